@@ -0,0 +1,3 @@
+(preproc_include path: (string_literal) @import.relative)
+
+(preproc_include path: (system_lib_string) @import)
@@ -0,0 +1,64 @@
+(annotation_type_declaration
+  (modifiers
+    [
+      (marker_annotation name: (_) @annotation.name)
+      (annotation name: (_) @annotation.name)
+    ] @annotation)
+  name: (identifier) @annotation.target)
+
+(constructor_declaration
+  (modifiers
+    [
+      (marker_annotation name: (_) @annotation.name)
+      (annotation name: (_) @annotation.name)
+    ] @annotation)
+  name: (identifier) @annotation.target)
+
+(class_declaration
+  (modifiers
+    [
+      (marker_annotation name: (_) @annotation.name)
+      (annotation name: (_) @annotation.name)
+    ] @annotation)
+  name: (identifier) @annotation.target)
+
+(enum_declaration
+  (modifiers
+    [
+      (marker_annotation name: (_) @annotation.name)
+      (annotation name: (_) @annotation.name)
+    ] @annotation)
+  name: (identifier) @annotation.target)
+
+(field_declaration
+  (modifiers
+    [
+      (marker_annotation name: (_) @annotation.name)
+      (annotation name: (_) @annotation.name)
+    ] @annotation)
+  declarator: (variable_declarator
+    name: (identifier) @annotation.target))
+
+(interface_declaration
+  (modifiers
+    [
+      (marker_annotation name: (_) @annotation.name)
+      (annotation name: (_) @annotation.name)
+    ] @annotation)
+  name: (identifier) @annotation.target)
+
+(method_declaration
+  (modifiers
+    [
+      (marker_annotation name: (_) @annotation.name)
+      (annotation name: (_) @annotation.name)
+    ] @annotation)
+  name: (identifier) @annotation.target)
+
+(record_declaration
+  (modifiers
+    [
+      (marker_annotation name: (_) @annotation.name)
+      (annotation name: (_) @annotation.name)
+    ] @annotation)
+  name: (identifier) @annotation.target)
@@ -1 +1,15 @@
-(method_invocation) @dep.Call
\ No newline at end of file
+(import_declaration) @dep.Import
+
+(superclass (_) @dep.Extend)
+
+(extends_interfaces (_) @dep.Extend)
+
+(super_interfaces (_) @dep.Implement)
+
+(object_creation_expression type: (_) @dep.Create)
+
+(method_invocation name: (identifier) @dep.Call)
+
+(cast_expression type: (_) @dep.Cast)
+
+(instanceof_expression right: (_) @dep.Cast)
@@ -0,0 +1 @@
+(import_declaration [(identifier) (scoped_identifier)] @import)
@@ -46,3 +46,8 @@
   (record_declaration
     name: (identifier) @name) @tag.Record
 )
+
+(object_creation_expression
+  (class_body)) @tag.Class
+
+(lambda_expression) @tag.Lambda
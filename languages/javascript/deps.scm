@@ -0,0 +1,9 @@
+(import_statement) @dep.Import
+
+(class_heritage (_) @dep.Extend)
+
+(new_expression constructor: (_) @dep.Create)
+
+(call_expression function: (identifier) @dep.Call)
+
+(call_expression function: (member_expression property: (property_identifier) @dep.Call))
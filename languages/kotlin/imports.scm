@@ -0,0 +1 @@
+(import_header (identifier) @import)
@@ -0,0 +1,26 @@
+(decorated_definition
+  (decorator
+    [
+      (identifier)
+      (attribute)
+    ] @annotation.name) @annotation
+  definition: [
+    (class_definition
+      name: (identifier) @annotation.target)
+    (function_definition
+      name: (identifier) @annotation.target)
+  ])
+
+(decorated_definition
+  (decorator
+    (call
+      function: [
+        (identifier)
+        (attribute)
+      ] @annotation.name)) @annotation
+  definition: [
+    (class_definition
+      name: (identifier) @annotation.target)
+    (function_definition
+      name: (identifier) @annotation.target)
+  ])
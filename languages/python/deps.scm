@@ -0,0 +1,9 @@
+(import_statement) @dep.Import
+
+(import_from_statement) @dep.Import
+
+(class_definition superclasses: (argument_list [(identifier) (attribute)] @dep.Extend))
+
+(call function: (identifier) @dep.Call)
+
+(call function: (attribute attribute: (identifier) @dep.Call))
@@ -0,0 +1,11 @@
+(import_statement name: (dotted_name) @import)
+
+(import_statement name: (aliased_import name: (dotted_name) @import))
+
+(import_from_statement module_name: (_) @import.module name: (dotted_name) @import.member)
+
+(import_from_statement
+  module_name: (_) @import.module
+  name: (aliased_import name: (dotted_name) @import.member))
+
+(import_from_statement module_name: (_) @import.module (wildcard_import))
@@ -0,0 +1,18 @@
+(class_definition
+  name: (identifier) @name) @tag.Class
+
+(class_definition
+  body: (block
+    (function_definition
+      name: (identifier) @name) @tag.Method))
+
+(class_definition
+  body: (block
+    (decorated_definition
+      definition: (function_definition
+        name: (identifier) @name) @tag.Method)))
+
+(function_definition
+  name: (identifier) @name) @tag.Function
+
+(lambda) @tag.Lambda
@@ -0,0 +1,3 @@
+(superclass (_) @dep.Extend)
+
+(call method: (identifier) @dep.Call)
@@ -0,0 +1,9 @@
+(call
+  method: (identifier) @_method
+  arguments: (argument_list . (string (string_content) @import))
+  (#eq? @_method "require"))
+
+(call
+  method: (identifier) @_method
+  arguments: (argument_list . (string (string_content) @import.relative))
+  (#eq? @_method "require_relative"))
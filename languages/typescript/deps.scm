@@ -0,0 +1,13 @@
+(import_statement) @dep.Import
+
+(extends_clause value: (_) @dep.Extend)
+
+(extends_type_clause (_) @dep.Extend)
+
+(implements_clause (_) @dep.Implement)
+
+(new_expression constructor: (_) @dep.Create)
+
+(call_expression function: (identifier) @dep.Call)
+
+(call_expression function: (member_expression property: (property_identifier) @dep.Call))
@@ -0,0 +1,10 @@
+(import_statement source: (string) @import.relative)
+
+(export_statement source: (string) @import.relative)
+
+(call_expression
+  function: (identifier) @_function
+  arguments: (arguments . (string) @import.relative)
+  (#eq? @_function "require"))
+
+(call_expression function: (import) arguments: (arguments . (string) @import.relative))
@@ -0,0 +1,118 @@
+//! Compare the deps between entities with how often they change together.
+//!
+//! A pair of entities with deps that never change together may be coupled
+//! only nominally, while a pair that often changes together without any deps
+//! may share an undeclared assumption. Deps and changes are collected with
+//! [AlignmentBuilder::add_dep] and [AlignmentBuilder::add_change] as they are
+//! extracted and compared once extraction has finished.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use counter::Counter;
+use itertools::Itertools;
+
+use crate::core::Alignment;
+use crate::core::AlignmentKind;
+use crate::core::Change;
+use crate::core::CommitId;
+use crate::core::EntityDep;
+use crate::core::EntityId;
+use crate::core::SimpleEntityId;
+
+#[derive(Debug, Default)]
+pub struct AlignmentBuilder {
+    deps: Mutex<Counter<(EntityId, EntityId)>>,
+    changes: Mutex<HashMap<CommitId, HashSet<SimpleEntityId>>>,
+}
+
+impl AlignmentBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_dep(&self, dep: &EntityDep) {
+        self.deps.lock().unwrap()[&(dep.src, dep.tgt)] += 1;
+    }
+
+    pub fn add_change(&self, change: &Change) {
+        let mut changes = self.changes.lock().unwrap();
+        changes.entry(change.commit_id).or_default().insert(change.simple_id);
+    }
+
+    /// Compare every pair of entities that has deps or has changed together.
+    ///
+    /// Only entities in `simple_ids` are considered. Pairs where one entity
+    /// contains the other (according to `parents`) are skipped, since these
+    /// almost always change together.
+    pub fn build(
+        self,
+        simple_ids: &HashMap<EntityId, SimpleEntityId>,
+        parents: &HashMap<SimpleEntityId, SimpleEntityId>,
+    ) -> Vec<Alignment> {
+        let mut deps = Counter::<(SimpleEntityId, SimpleEntityId)>::new();
+
+        for ((src, tgt), count) in self.deps.into_inner().unwrap() {
+            if let (Some(&src), Some(&tgt)) = (simple_ids.get(&src), simple_ids.get(&tgt)) {
+                deps[&to_pair(src, tgt)] += count;
+            }
+        }
+
+        let known: HashSet<_> = simple_ids.values().copied().collect();
+        let mut cochanges = Counter::<(SimpleEntityId, SimpleEntityId)>::new();
+
+        for changed in self.changes.into_inner().unwrap().into_values() {
+            let changed = changed.into_iter().filter(|s| known.contains(s)).sorted().collect_vec();
+
+            for (i, &a) in changed.iter().enumerate() {
+                for &b in &changed[(i + 1)..] {
+                    cochanges[&(a, b)] += 1;
+                }
+            }
+        }
+
+        let pairs: HashSet<_> = deps.keys().chain(cochanges.keys()).copied().collect();
+
+        pairs
+            .into_iter()
+            .filter(|&(a, b)| a != b && !is_ancestor(parents, a, b) && !is_ancestor(parents, b, a))
+            .map(|(a, b)| {
+                let (deps, cochanges) = (deps[&(a, b)], cochanges[&(a, b)]);
+                let kind = match (deps > 0, cochanges > 0) {
+                    (true, true) => AlignmentKind::Aligned,
+                    (true, false) => AlignmentKind::DepOnly,
+                    _ => AlignmentKind::CochangeOnly,
+                };
+                Alignment { a, b, deps, cochanges, kind }
+            })
+            .sorted()
+            .collect()
+    }
+}
+
+fn to_pair(a: SimpleEntityId, b: SimpleEntityId) -> (SimpleEntityId, SimpleEntityId) {
+    if a <= b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+/// Does `ancestor` (transitively) contain `entity`?
+fn is_ancestor(
+    parents: &HashMap<SimpleEntityId, SimpleEntityId>,
+    ancestor: SimpleEntityId,
+    entity: SimpleEntityId,
+) -> bool {
+    let mut curr = entity;
+
+    while let Some(&parent) = parents.get(&curr) {
+        if parent == ancestor {
+            return true;
+        }
+        curr = parent;
+    }
+
+    false
+}
@@ -0,0 +1,80 @@
+//! Find the annotations (or decorators) applied to each entity.
+//!
+//! Frameworks often mark the code they manage with annotations (e.g. `@Entity`
+//! in JPA or `@pytest.fixture` in pytest), so these tell a lot about the role
+//! of an entity. Annotations are found with the annotation query of each
+//! language, which captures each annotation as `@annotation`, its name as
+//! `@annotation.name`, and the name of the declaration it is applied to as
+//! `@annotation.target`. Each annotation belongs to the innermost entity that
+//! contains its target.
+
+use tree_sitter::Language;
+use tree_sitter::Query;
+use tree_sitter::QueryCursor;
+
+use crate::core::Position;
+use crate::core::Span;
+use crate::tagging;
+
+/// An annotation found by an [AnnotationFinder].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoundAnnotation {
+    /// The name of the annotation without the `@` or any arguments (e.g.
+    /// `pytest.fixture`).
+    pub name: String,
+
+    /// The whole annotation as written.
+    pub text: String,
+
+    /// Where the annotation starts.
+    pub start: Position,
+
+    /// Where the name of the declaration it is applied to starts.
+    pub target: Position,
+}
+
+#[derive(Debug)]
+pub struct AnnotationFinder {
+    language: Language,
+    query: Query,
+}
+
+impl AnnotationFinder {
+    pub fn new(language: Language, annotation_query: &str) -> Self {
+        Self { language, query: Query::new(language, annotation_query).unwrap() }
+    }
+
+    /// Find each annotation of a file.
+    pub fn find(&self, content: &str) -> Vec<FoundAnnotation> {
+        let Ok(tree) = tagging::parse(self.language, content) else {
+            return Vec::new();
+        };
+
+        let names = self.query.capture_names();
+        let mut cursor = QueryCursor::new();
+        let mut annotations = Vec::new();
+
+        for m in cursor.matches(&self.query, tree.root_node(), content.as_bytes()) {
+            let capture = |name: &str| {
+                m.captures.iter().find(|c| names[c.index as usize] == name).map(|c| c.node)
+            };
+
+            let (Some(annotation), Some(name), Some(target)) =
+                (capture("annotation"), capture("annotation.name"), capture("annotation.target"))
+            else {
+                continue;
+            };
+
+            let text = |node: tree_sitter::Node| content[node.byte_range()].to_string();
+
+            annotations.push(FoundAnnotation {
+                name: text(name),
+                text: text(annotation),
+                start: Span::from_ts(annotation.range()).start,
+                target: Span::from_ts(target.range()).start,
+            });
+        }
+
+        annotations
+    }
+}
@@ -0,0 +1,235 @@
+//! Replace identifying names with keyed hashes so a dataset can be shared.
+//!
+//! Every filename, entity name, symbol, annotation, revspec (and ref name),
+//! diagnostic message, commit author and committer (name and email, also on
+//! changes), and commit subject is replaced by the first 16 hex digits of its
+//! HMAC-SHA256 under a secret key. The same name always gets the same hash
+//! (with the same key), so structure and history survive: IDs are untouched,
+//! each component of a path is hashed on its own (keeping the directory tree
+//! and the file extension), and an entity keeps its hash across versions.
+//! Without the key, the names cannot be recovered short of guessing them.
+//! Contents are never written, as they would give everything away.
+
+use std::path::Path;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use itertools::Itertools;
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::core::Alignment;
+use crate::core::Annotation;
+use crate::core::Change;
+use crate::core::Commit;
+use crate::core::CommitFile;
+use crate::core::Content;
+use crate::core::DepChange;
+use crate::core::Diagnostic;
+use crate::core::Entity;
+use crate::core::EntityDep;
+use crate::core::EntityKind;
+use crate::core::EntityMatch;
+use crate::core::ExternalDep;
+use crate::core::Metric;
+use crate::core::Revspec;
+use crate::core::Supertype;
+use crate::core::UnresolvedRef;
+use crate::output::Resource;
+use crate::output::Writer;
+
+/// The block size of SHA-256, as used by HMAC.
+const BLOCK_SIZE: usize = 64;
+
+/// Hashes names with a secret key.
+#[derive(Debug, Clone)]
+pub struct Anonymizer {
+    key: Vec<u8>,
+}
+
+impl Anonymizer {
+    /// Read the key from a file.
+    ///
+    /// Leading and trailing whitespace (like a final newline) is ignored.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let key = std::fs::read(path)
+            .with_context(|| format!("failed to read key from {}", path.display()))?;
+        let key = key.trim_ascii().to_vec();
+
+        if key.is_empty() {
+            bail!("The key in {} is empty", path.display());
+        }
+
+        Ok(Self { key })
+    }
+
+    /// Hash a single name.
+    pub fn name(&self, name: &str) -> String {
+        hex::encode(&hmac(&self.key, name.as_bytes())[..8])
+    }
+
+    /// Hash each component of a path, keeping the extension of the last one.
+    pub fn path(&self, path: &str) -> String {
+        let (dirs, filename) = match path.rsplit_once('/') {
+            Some((dirs, filename)) => (Some(dirs), filename),
+            None => (None, path),
+        };
+
+        let filename = match filename.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => format!("{}.{}", self.name(stem), ext),
+            _ => self.name(filename),
+        };
+
+        match dirs {
+            Some(dirs) => {
+                let prefix = if dirs.starts_with('/') { "/" } else { "" };
+                let dirs = dirs.split('/').filter(|d| !d.is_empty()).map(|d| self.name(d));
+                format!("{}{}", prefix, dirs.chain([filename]).join("/"))
+            }
+            None => filename,
+        }
+    }
+}
+
+/// Compute the HMAC-SHA256 of `message` (see RFC 2104).
+fn hmac(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut block = [0u8; BLOCK_SIZE];
+
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect_vec();
+    let inner = Sha256::new().chain_update(pad(0x36)).chain_update(message).finalize();
+    Sha256::new().chain_update(pad(0x5c)).chain_update(inner).finalize().to_vec()
+}
+
+/// A [Writer] that anonymizes each record before handing it to another writer.
+pub struct AnonymizingWriter {
+    inner: Box<dyn Writer + Sync>,
+    anonymizer: Anonymizer,
+}
+
+impl AnonymizingWriter {
+    pub fn new(inner: Box<dyn Writer + Sync>, anonymizer: Anonymizer) -> Self {
+        Self { inner, anonymizer }
+    }
+}
+
+impl Writer for AnonymizingWriter {
+    fn supports(&self, resource: Resource) -> bool {
+        resource != Resource::Contents && self.inner.supports(resource)
+    }
+
+    fn is_single_structure(&self) -> bool {
+        self.inner.is_single_structure()
+    }
+
+    fn write_entity(&self, mut value: Entity) -> Result<()> {
+        value.name = match value.kind {
+            EntityKind::File => self.anonymizer.path(&value.name),
+            _ => self.anonymizer.name(&value.name),
+        };
+        value.qualified_name = match value.kind {
+            EntityKind::File => self.anonymizer.path(&value.qualified_name),
+            _ => self.anonymizer.name(&value.qualified_name),
+        };
+        value.filename = value.filename.map(|f| self.anonymizer.path(&f));
+        self.inner.write_entity(value)
+    }
+
+    fn write_dep(&self, mut value: EntityDep) -> Result<()> {
+        if let Some(location) = &mut value.location {
+            location.src_filename = self.anonymizer.path(&location.src_filename);
+            location.tgt_filename = self.anonymizer.path(&location.tgt_filename);
+            location.src_name = location.src_name.as_deref().map(|n| self.anonymizer.name(n));
+            location.tgt_name = location.tgt_name.as_deref().map(|n| self.anonymizer.name(n));
+        }
+        self.inner.write_dep(value)
+    }
+
+    fn write_change(&self, mut value: Change) -> Result<()> {
+        value.author_name = self.anonymizer.name(&value.author_name);
+        value.author_email = self.anonymizer.name(&value.author_email);
+        self.inner.write_change(value)
+    }
+
+    fn write_content(&self, _: Content) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_dep_change(&self, value: DepChange) -> Result<()> {
+        self.inner.write_dep_change(value)
+    }
+
+    fn write_alignment(&self, value: Alignment) -> Result<()> {
+        self.inner.write_alignment(value)
+    }
+
+    fn write_revspec(&self, mut value: Revspec) -> Result<()> {
+        value.revspec = self.anonymizer.name(&value.revspec);
+        self.inner.write_revspec(value)
+    }
+
+    fn write_unresolved_ref(&self, mut value: UnresolvedRef) -> Result<()> {
+        value.filename = self.anonymizer.path(&value.filename);
+        value.text = self.anonymizer.name(&value.text);
+        self.inner.write_unresolved_ref(value)
+    }
+
+    fn write_entity_match(&self, value: EntityMatch) -> Result<()> {
+        self.inner.write_entity_match(value)
+    }
+
+    fn write_metric(&self, value: Metric) -> Result<()> {
+        self.inner.write_metric(value)
+    }
+
+    fn write_external_dep(&self, mut value: ExternalDep) -> Result<()> {
+        value.symbol = self.anonymizer.name(&value.symbol);
+        self.inner.write_external_dep(value)
+    }
+
+    fn write_supertype(&self, value: Supertype) -> Result<()> {
+        self.inner.write_supertype(value)
+    }
+
+    fn write_diagnostic(&self, mut value: Diagnostic) -> Result<()> {
+        value.filename = value.filename.map(|f| self.anonymizer.path(&f));
+        value.message = self.anonymizer.name(&value.message);
+        self.inner.write_diagnostic(value)
+    }
+
+    fn write_annotation(&self, mut value: Annotation) -> Result<()> {
+        value.name = self.anonymizer.name(&value.name);
+        value.text = self.anonymizer.name(&value.text);
+        self.inner.write_annotation(value)
+    }
+
+    fn write_file(&self, mut value: CommitFile) -> Result<()> {
+        value.filename = self.anonymizer.path(&value.filename);
+        self.inner.write_file(value)
+    }
+
+    fn write_commit(&self, mut value: Commit) -> Result<()> {
+        value.author_name = self.anonymizer.name(&value.author_name);
+        value.author_email = self.anonymizer.name(&value.author_email);
+        value.committer_name = self.anonymizer.name(&value.committer_name);
+        value.committer_email = self.anonymizer.name(&value.committer_email);
+        value.subject = self.anonymizer.name(&value.subject);
+        value.ref_name = value.ref_name.map(|r| self.anonymizer.name(&r));
+        self.inner.write_commit(value)
+    }
+
+    fn write_value(&self, resource: Resource, value: serde_json::Value) -> Result<()> {
+        self.inner.write_value(resource, value)
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.inner.finalize()
+    }
+}
@@ -0,0 +1,384 @@
+//! Find the deps that cross from one language to another.
+//!
+//! Resolvers only ever see the files of one language at a time, so they miss
+//! the places where a project calls into its own code written in another
+//! language. Two bridges are recognized, each reported as a [DepKind::Link]:
+//!
+//! - A Java `native` method is linked to the C or C++ function that implements
+//!   it, which JNI finds by a mangled name (e.g. `Java_com_foo_Bar_baz`).
+//! - A call like `lib.foo_bar(...)` in a Python file that imports `ctypes` or
+//!   `cffi` is linked to the C or C++ function `foo_bar`, if it is the only
+//!   function with that name. As the receiver is never checked, these deps are
+//!   marked [Confidence::Heuristic].
+//!
+//! Protobuf services are not linked, as `.proto` files are never scanned.
+
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use rayon::prelude::*;
+use tree_sitter::Language;
+use tree_sitter::Node;
+use tree_sitter::Parser;
+use tree_sitter::Query;
+use tree_sitter::QueryCursor;
+
+use crate::core::Confidence;
+use crate::core::Dep;
+use crate::core::DepKind;
+use crate::core::FileDep;
+use crate::core::FileEndpoint;
+use crate::core::FileKey;
+use crate::core::MultiFileSet;
+use crate::core::PartialPosition;
+use crate::core::Position;
+use crate::core::PseudoCommitId;
+use crate::core::Span;
+use crate::filesystem::FileReader;
+use crate::languages::Lang;
+
+/// Finds the names of the functions defined in a C or C++ file.
+const FUNCTION_QUERY: &str = "
+    (function_definition
+        declarator: (function_declarator declarator: (identifier) @name))
+    (function_definition
+        declarator: (pointer_declarator
+            declarator: (function_declarator declarator: (identifier) @name)))";
+
+lazy_static! {
+    static ref C_QUERY: Query = Query::new(tree_sitter_c::language(), FUNCTION_QUERY).unwrap();
+    static ref CPP_QUERY: Query = Query::new(tree_sitter_cpp::language(), FUNCTION_QUERY).unwrap();
+    static ref JAVA_QUERY: Query = Query::new(
+        tree_sitter_java::language(),
+        "(package_declaration (_) @package)
+         (method_declaration
+            (modifiers \"native\")
+            name: (identifier) @native
+            parameters: (formal_parameters) @params)"
+    )
+    .unwrap();
+    static ref PYTHON_QUERY: Query = Query::new(
+        tree_sitter_python::language(),
+        "(import_statement name: (dotted_name) @module)
+         (import_statement name: (aliased_import name: (dotted_name) @module))
+         (import_from_statement module_name: (dotted_name) @module)
+         (call function: (attribute attribute: (identifier) @call))"
+    )
+    .unwrap();
+}
+
+/// The kinds of nodes that declare a named Java class.
+const JAVA_CLASSES: &[&str] =
+    &["class_declaration", "enum_declaration", "interface_declaration", "record_declaration"];
+
+/// The Python modules that load and call into C libraries.
+const FOREIGN_MODULES: &[&str] = &["ctypes", "cffi"];
+
+/// The names that one side of a bridge defines or refers to, with where each
+/// name appears.
+#[derive(Debug, Default)]
+struct Scan {
+    /// The functions defined in a C or C++ file.
+    functions: Vec<(String, Position)>,
+
+    /// The native methods declared in a Java file.
+    natives: Vec<Native>,
+
+    /// The methods called in a Python file that imports a foreign module.
+    foreign_calls: Vec<(String, Position)>,
+}
+
+/// A Java native method.
+#[derive(Debug)]
+struct Native {
+    /// The name of the C function that implements it (without a signature).
+    name: String,
+
+    /// The types of the parameters as a JNI type signature, but with classes
+    /// named by their simple names (e.g. `ILString;` for `(int, String)`).
+    params: String,
+
+    position: Position,
+}
+
+impl Native {
+    /// Is this implemented by the C function named `function`?
+    ///
+    /// Functions that implement overloaded native methods have the mangled
+    /// signature of the parameters after a `__`.
+    fn is_implemented_by(&self, function: &str) -> bool {
+        match function.strip_prefix(&self.name) {
+            Some("") => true,
+            Some(rest) => {
+                rest.strip_prefix("__").is_some_and(|s| simplify(&demangle(s)) == self.params)
+            }
+            None => false,
+        }
+    }
+}
+
+/// Find the deps across languages for each version.
+pub fn resolve<R: FileReader>(reader: &R, files: &MultiFileSet) -> Vec<FileDep> {
+    files
+        .iter()
+        .flat_map(|(&commit_id, file_set)| {
+            let scans: Vec<_> = file_set
+                .iter()
+                .filter_map(|f| Some((f, Lang::of(&f.filename)?)))
+                .filter(|(_, l)| matches!(l, Lang::C | Lang::Cpp | Lang::Java | Lang::Python))
+                .collect_vec()
+                .into_par_iter()
+                .filter_map(|(f, l)| Some((f, scan(l, &reader.read(f.content_id).ok()?)?)))
+                .collect();
+
+            let functions = scans
+                .iter()
+                .flat_map(|(f, s)| s.functions.iter().map(move |(n, p)| (n.as_str(), (*f, *p))))
+                .into_group_map();
+
+            let jni = scans.iter().flat_map(|(f, s)| s.natives.iter().map(move |n| (*f, n)));
+            let jni = jni.flat_map(|(f, native)| {
+                functions
+                    .iter()
+                    .filter(|(n, _)| native.is_implemented_by(n))
+                    .flat_map(|(_, defs)| defs)
+                    .map(|&(tgt, tgt_p)| to_dep(f, native.position, tgt, tgt_p, commit_id))
+                    .collect_vec()
+            });
+
+            let foreign = scans
+                .iter()
+                .flat_map(|(f, s)| s.foreign_calls.iter().map(move |c| (*f, c)))
+                .filter_map(|(f, (name, p))| match functions.get(name.as_str())?.as_slice() {
+                    [(tgt, tgt_p)] => Some(to_dep(f, *p, tgt, *tgt_p, commit_id)),
+                    _ => None,
+                })
+                .map(|d| d.with_confidence(Confidence::Heuristic));
+
+            jni.chain(foreign).collect_vec()
+        })
+        .collect()
+}
+
+fn to_dep(
+    src: &FileKey,
+    src_position: Position,
+    tgt: &FileKey,
+    tgt_position: Position,
+    commit_id: PseudoCommitId,
+) -> FileDep {
+    let position = PartialPosition::Whole(src_position);
+    let src = FileEndpoint::new(src.clone(), position);
+    let tgt = FileEndpoint::new(tgt.clone(), PartialPosition::Whole(tgt_position));
+    Dep::new(src, tgt, DepKind::Link, position, commit_id).with_resolver("cross-language")
+}
+
+/// Find the names that `content` defines or refers to across languages.
+///
+/// Returns None if the file could not be parsed.
+fn scan(lang: Lang, content: &str) -> Option<Scan> {
+    let (language, query): (Language, &Query) = match lang {
+        Lang::C => (tree_sitter_c::language(), &C_QUERY),
+        Lang::Cpp => (tree_sitter_cpp::language(), &CPP_QUERY),
+        Lang::Java => (tree_sitter_java::language(), &JAVA_QUERY),
+        Lang::Python => (tree_sitter_python::language(), &PYTHON_QUERY),
+        _ => return None,
+    };
+
+    let mut parser = Parser::new();
+    parser.set_language(language).ok()?;
+    let tree = parser.parse(content, None)?;
+    let names = query.capture_names();
+
+    let mut result = Scan::default();
+    let mut package = None;
+    let mut modules = Vec::new();
+    let mut cursor = QueryCursor::new();
+
+    for m in cursor.matches(query, tree.root_node(), content.as_bytes()) {
+        let capture = |name: &str| {
+            m.captures.iter().find(|c| names[c.index as usize] == name).map(|c| c.node)
+        };
+        let text = |node: Node| node.utf8_text(content.as_bytes()).unwrap_or_default();
+        let start = |node: Node| Span::from_ts(node.range()).start;
+
+        if let Some(name) = capture("name") {
+            result.functions.push((text(name).to_string(), start(name)));
+        } else if let Some(name) = capture("package") {
+            package = Some(text(name));
+        } else if let (Some(name), Some(params)) = (capture("native"), capture("params")) {
+            if let Some(class) = enclosing_class(name, content) {
+                let class = match package {
+                    Some(package) => format!("{}.{}", package, class),
+                    None => class,
+                };
+                result.natives.push(Native {
+                    name: format!("Java_{}_{}", mangle(&class), mangle(text(name))),
+                    params: param_types(params, content),
+                    position: start(name),
+                });
+            }
+        } else if let Some(name) = capture("module") {
+            modules.push(text(name));
+        } else if let Some(name) = capture("call") {
+            result.foreign_calls.push((text(name).to_string(), start(name)));
+        }
+    }
+
+    let is_foreign = |m: &&str| FOREIGN_MODULES.contains(&m.split('.').next().unwrap_or(m));
+
+    if !modules.iter().any(is_foreign) {
+        result.foreign_calls.clear();
+    }
+
+    Some(result)
+}
+
+/// The binary name of the class that encloses `node` (without the package),
+/// with nested classes separated by `$` (e.g. `Outer$Inner`).
+///
+/// Returns None if one of the classes has no name.
+fn enclosing_class(node: Node, content: &str) -> Option<String> {
+    let mut classes = Vec::new();
+    let mut curr = node.parent();
+
+    while let Some(node) = curr {
+        if JAVA_CLASSES.contains(&node.kind()) {
+            let name = node.child_by_field_name("name")?;
+            classes.push(name.utf8_text(content.as_bytes()).ok()?);
+        }
+
+        curr = node.parent();
+    }
+
+    Some(classes.into_iter().rev().join("$"))
+}
+
+/// The types of the parameters of a Java method as a type signature (see
+/// [Native::params]).
+fn param_types(params: Node, content: &str) -> String {
+    let mut cursor = params.walk();
+    let mut types = String::new();
+
+    for param in params.named_children(&mut cursor) {
+        let (ty, variadic) = match param.kind() {
+            "formal_parameter" => (param.child_by_field_name("type"), false),
+            "spread_parameter" => {
+                let mut cursor = param.walk();
+                let ty = param.named_children(&mut cursor).find(|c| c.kind() != "modifiers");
+                (ty, true)
+            }
+            _ => continue,
+        };
+
+        let text = ty.and_then(|t| t.utf8_text(content.as_bytes()).ok()).unwrap_or_default();
+        let text = strip_type_args(text);
+        let dims = text.matches('[').count() + variadic as usize;
+        let name = text.split('[').next().unwrap_or_default().trim();
+        let name = name.rsplit('.').next().unwrap_or(name);
+
+        types.push_str(&"[".repeat(dims));
+
+        match name {
+            "boolean" => types.push('Z'),
+            "byte" => types.push('B'),
+            "char" => types.push('C'),
+            "short" => types.push('S'),
+            "int" => types.push('I'),
+            "long" => types.push('J'),
+            "float" => types.push('F'),
+            "double" => types.push('D'),
+            name => types.push_str(&format!("L{};", name)),
+        }
+    }
+
+    types
+}
+
+/// Remove the type arguments (e.g. `<String>`) and whitespace from a type.
+fn strip_type_args(ty: &str) -> String {
+    let mut depth = 0;
+    let mut stripped = String::new();
+
+    for c in ty.chars() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            c if depth == 0 && !c.is_whitespace() => stripped.push(c),
+            _ => {}
+        }
+    }
+
+    stripped
+}
+
+/// Name the classes of a type signature by their simple names (e.g.
+/// `Ljava/lang/String;` becomes `LString;`).
+fn simplify(signature: &str) -> String {
+    let mut simplified = String::new();
+    let mut rest = signature;
+
+    while let Some(c) = rest.chars().next() {
+        match c {
+            'L' => {
+                let end = rest.find(';').unwrap_or(rest.len());
+                let name = rest[1..end].rsplit(['/', '$']).next().unwrap_or_default();
+                simplified.push_str(&format!("L{};", name));
+                rest = &rest[(end + 1).min(rest.len())..];
+            }
+            c => {
+                simplified.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+
+    simplified
+}
+
+/// Reverse [mangle].
+fn demangle(name: &str) -> String {
+    let mut demangled = String::new();
+    let mut chars = name.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '_' {
+            demangled.push(c);
+            continue;
+        }
+
+        let rest = chars.as_str();
+        let escaped = match rest.chars().next() {
+            Some('1') => Some('_'),
+            Some('2') => Some(';'),
+            Some('3') => Some('['),
+            _ => None,
+        };
+
+        if let Some(escaped) = escaped {
+            demangled.push(escaped);
+            chars.next();
+        } else if let Some(hex) = rest.strip_prefix('0').and_then(|r| r.get(..4)) {
+            demangled.extend(u32::from_str_radix(hex, 16).ok().and_then(char::from_u32));
+            chars.nth(4);
+        } else {
+            demangled.push('/');
+        }
+    }
+
+    demangled
+}
+
+/// Mangle a qualified Java name the way JNI does for the names of native
+/// functions.
+fn mangle(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '.' | '/' => "_".to_string(),
+            '_' => "_1".to_string(),
+            ';' => "_2".to_string(),
+            '[' => "_3".to_string(),
+            c if c.is_ascii_alphanumeric() => c.to_string(),
+            c => format!("_0{:04x}", c as u32),
+        })
+        .collect()
+}
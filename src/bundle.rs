@@ -0,0 +1,209 @@
+//! Package an output together with everything needed to reproduce it.
+//!
+//! A bundle is a zstd-compressed tar archive that holds:
+//!
+//! - `manifest.json`: How the output was made (see [Manifest]).
+//! - `output/`: The output itself, either a single file or every file of an
+//!   output directory.
+//! - `queries/LANG/`: The tree-sitter queries and stack graph rules of every
+//!   language, exactly as compiled into this build.
+//!
+//! Only the small subset of the ustar format needed for regular files is
+//! written, so no file may be 8 GiB or larger.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use itertools::Itertools;
+use strum::IntoEnumIterator;
+use walkdir::WalkDir;
+
+use crate::core::Revspec;
+use crate::languages::Lang;
+
+const BLOCK_SIZE: usize = 512;
+
+/// A record of a single run of Neodepends.
+#[derive(Debug, Clone)]
+#[derive(serde::Serialize)]
+pub struct Manifest {
+    /// The version of Neodepends.
+    pub tool_version: &'static str,
+
+    /// The command-line arguments, including the name of the executable.
+    ///
+    /// Empty if the output was anonymized, as they usually name the project.
+    pub args: Vec<String>,
+
+    /// The absolute path of the project that was scanned (hashed like a
+    /// filename if the output was anonymized).
+    pub input: String,
+
+    /// The format of the output.
+    pub format: String,
+
+    /// The commit that each revspec given on the command-line resolved to.
+    pub revspecs: Vec<Revspec>,
+
+    /// The name and version of each enabled resolver, in order of priority.
+    pub resolvers: Vec<ResolverVersion>,
+
+    /// When the run started and finished, in seconds since the Unix epoch.
+    pub started_at: u64,
+    pub finished_at: u64,
+}
+
+#[derive(Debug, Clone)]
+#[derive(serde::Serialize)]
+pub struct ResolverVersion {
+    pub name: &'static str,
+    pub version: String,
+}
+
+/// Write a bundle of `output` to `path`.
+pub fn write_bundle<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    output: Q,
+    manifest: &Manifest,
+) -> Result<()> {
+    let output = output.as_ref();
+    let file = File::create(path.as_ref()).context("failed to create bundle")?;
+    let mut tar = TarWriter::new(zstd::Encoder::new(BufWriter::new(file), 0)?);
+    tar.append("manifest.json", &serde_json::to_vec_pretty(manifest)?, manifest.finished_at)?;
+
+    if output.is_dir() {
+        let files = WalkDir::new(output)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_ok(|e| e.file_type().is_file())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for entry in files {
+            let relative = entry.path().strip_prefix(output)?;
+            let name = relative.components().map(|c| c.as_os_str().to_string_lossy()).join("/");
+            tar.append_path(&format!("output/{}", name), entry.path(), manifest.finished_at)?;
+        }
+    } else {
+        let name = output.file_name().context("output has no filename")?.to_string_lossy();
+        tar.append_path(&format!("output/{}", name), output, manifest.finished_at)?;
+    }
+
+    for lang in Lang::iter() {
+        for (filename, query) in lang.queries() {
+            let name = format!("queries/{}/{}", lang, filename);
+            tar.append(&name, query.as_bytes(), manifest.finished_at)?;
+        }
+    }
+
+    tar.finish()?.finish()?.flush()?;
+    Ok(())
+}
+
+/// Writes regular files to a tar archive.
+struct TarWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Add a file named `name` (with `/` as the separator) to the archive.
+    fn append(&mut self, name: &str, content: &[u8], mtime: u64) -> Result<()> {
+        self.append_reader(name, content, content.len() as u64, mtime)
+    }
+
+    /// Add the file at `path` to the archive under `name`, without reading it
+    /// all into memory.
+    fn append_path(&mut self, name: &str, path: &Path, mtime: u64) -> Result<()> {
+        let file = File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+        let len = file.metadata()?.len();
+        self.append_reader(name, file, len, mtime)
+    }
+
+    /// Add a file of `len` bytes read from `content` to the archive.
+    fn append_reader<R: Read>(
+        &mut self,
+        name: &str,
+        content: R,
+        len: u64,
+        mtime: u64,
+    ) -> Result<()> {
+        let mut header = [0u8; BLOCK_SIZE];
+        let (prefix, short_name) = split_name(name)?;
+        header[..short_name.len()].copy_from_slice(short_name.as_bytes());
+        write_octal(&mut header[100..108], 0o644)?;
+        write_octal(&mut header[108..116], 0)?;
+        write_octal(&mut header[116..124], 0)?;
+        write_octal(&mut header[124..136], len)
+            .with_context(|| format!("file is too large to be added to a bundle: {}", name))?;
+        write_octal(&mut header[136..148], mtime)?;
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+        // The checksum is computed as if its own field were all spaces
+        header[148..156].fill(b' ');
+        let checksum = header.iter().map(|&b| b as u64).sum::<u64>();
+        write_octal(&mut header[148..155], checksum)?;
+
+        self.inner.write_all(&header)?;
+        let copied = std::io::copy(&mut content.take(len), &mut self.inner)?;
+
+        if copied != len {
+            bail!("file changed while being added to a bundle: {}", name);
+        }
+
+        self.inner.write_all(&vec![0; padding(len)])?;
+        Ok(())
+    }
+
+    /// End the archive with two empty blocks and return the inner writer.
+    fn finish(mut self) -> Result<W> {
+        self.inner.write_all(&[0; 2 * BLOCK_SIZE])?;
+        Ok(self.inner)
+    }
+}
+
+/// Split a name into the prefix and name fields of a ustar header.
+fn split_name(name: &str) -> Result<(&str, &str)> {
+    if name.len() <= 100 {
+        return Ok(("", name));
+    }
+
+    for (i, _) in name.match_indices('/') {
+        let (prefix, rest) = (&name[..i], &name[i + 1..]);
+
+        if prefix.len() <= 155 && rest.len() <= 100 {
+            return Ok((prefix, rest));
+        }
+    }
+
+    bail!("path is too long to be added to a bundle: {}", name)
+}
+
+/// Write `value` as zero-padded octal followed by a NUL to fill `field`, or
+/// fail if it has too many digits.
+fn write_octal(field: &mut [u8], value: u64) -> Result<()> {
+    let digits = format!("{:0width$o}\0", value, width = field.len() - 1);
+
+    if digits.len() != field.len() {
+        bail!("{} does not fit in a tar header field of {} bytes", value, field.len());
+    }
+
+    field.copy_from_slice(digits.as_bytes());
+    Ok(())
+}
+
+/// The number of zero bytes needed to fill out the last block of a file.
+fn padding(len: u64) -> usize {
+    ((BLOCK_SIZE as u64 - len % BLOCK_SIZE as u64) % BLOCK_SIZE as u64) as usize
+}
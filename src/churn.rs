@@ -0,0 +1,98 @@
+//! Find the deps that were added and deleted between versions of a project.
+//!
+//! Deps are collected with [DepChurn::add] as they are extracted and compared
+//! once extraction has finished. Entities are matched across versions by their
+//! [SimpleEntityId], since an [EntityId] changes whenever its content does.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use counter::Counter;
+use itertools::Itertools;
+
+use crate::core::DepChange;
+use crate::core::DepKind;
+use crate::core::EntityDep;
+use crate::core::EntityId;
+use crate::core::PseudoCommitId;
+use crate::core::SimpleEntityId;
+
+type DepCounts<E> = Counter<(E, E, DepKind)>;
+
+#[derive(Debug, Default)]
+pub struct DepChurn {
+    counts: Mutex<HashMap<PseudoCommitId, DepCounts<EntityId>>>,
+}
+
+impl DepChurn {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, dep: &EntityDep) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.entry(dep.commit_id).or_default()[&(dep.src, dep.tgt, dep.kind)] += 1;
+    }
+
+    /// Compare each commit with the commit listed after it.
+    ///
+    /// This matches the order of `git rev-list`, where each commit is
+    /// followed by its parent. Deps with an endpoint missing from `simple_ids`
+    /// are ignored.
+    pub fn into_dep_changes(
+        self,
+        commits: &[PseudoCommitId],
+        simple_ids: &HashMap<EntityId, SimpleEntityId>,
+    ) -> Vec<DepChange> {
+        let mut counts = self.counts.into_inner().unwrap();
+
+        let counts = commits
+            .iter()
+            .map(|c| to_simple_counts(counts.remove(c).unwrap_or_default(), simple_ids))
+            .collect_vec();
+
+        commits
+            .iter()
+            .zip(&counts)
+            .tuple_windows()
+            .flat_map(|((new_id, new), (old_id, old))| diff(*old_id, old, *new_id, new))
+            .collect()
+    }
+}
+
+/// Find the deps that differ between two commits.
+fn diff(
+    old_commit_id: PseudoCommitId,
+    old: &DepCounts<SimpleEntityId>,
+    new_commit_id: PseudoCommitId,
+    new: &DepCounts<SimpleEntityId>,
+) -> Vec<DepChange> {
+    let keys: HashSet<_> = old.keys().chain(new.keys()).copied().collect();
+
+    keys.into_iter()
+        .filter(|k| old[k] != new[k])
+        .map(|(src, tgt, kind)| {
+            let (old, new) = (old[&(src, tgt, kind)], new[&(src, tgt, kind)]);
+            let adds = new.saturating_sub(old);
+            let dels = old.saturating_sub(new);
+            DepChange { src, tgt, kind, old_commit_id, new_commit_id, adds, dels }
+        })
+        .sorted()
+        .collect()
+}
+
+fn to_simple_counts(
+    counts: DepCounts<EntityId>,
+    simple_ids: &HashMap<EntityId, SimpleEntityId>,
+) -> DepCounts<SimpleEntityId> {
+    let mut simple_counts = DepCounts::new();
+
+    for ((src, tgt, kind), count) in counts {
+        if let (Some(&src), Some(&tgt)) = (simple_ids.get(&src), simple_ids.get(&tgt)) {
+            simple_counts[&(src, tgt, kind)] += count;
+        }
+    }
+
+    simple_counts
+}
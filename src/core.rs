@@ -9,11 +9,14 @@ use std::path::Path;
 use anyhow::bail;
 use anyhow::Result;
 use itertools::Itertools;
+use lazy_static::lazy_static;
 use rusqlite::types::ToSqlOutput;
 use rusqlite::ToSql;
 use sha1::Digest;
 use sha1::Sha1;
 
+use crate::languages::Lang;
+
 /// A 160 bit SHA-1 hash.
 #[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Sha1Hash([u8; 20]);
@@ -97,7 +100,7 @@ pub struct CommitId(pub Sha1Hash);
 
 impl Display for CommitId {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.to_string())
+        f.write_str(&self.0.to_string())
     }
 }
 
@@ -139,6 +142,15 @@ impl serde::Serialize for PseudoCommitId {
     }
 }
 
+impl Display for PseudoCommitId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PseudoCommitId::CommitId(commit_id) => f.write_str(&commit_id.to_string()),
+            PseudoCommitId::WorkDir => f.write_str("WORKDIR"),
+        }
+    }
+}
+
 impl ToSql for PseudoCommitId {
     fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
         match self {
@@ -159,6 +171,15 @@ impl ContentId {
     pub fn from_content(content: &str) -> ContentId {
         git2::Oid::hash_object(git2::ObjectType::Blob, content.as_bytes()).unwrap().into()
     }
+
+    /// Is this the ID of an empty file?
+    pub fn is_empty(&self) -> bool {
+        *self == *EMPTY_CONTENT_ID
+    }
+}
+
+lazy_static! {
+    static ref EMPTY_CONTENT_ID: ContentId = ContentId::from_content("");
 }
 
 impl From<git2::Oid> for ContentId {
@@ -179,6 +200,28 @@ impl ToSql for ContentId {
     }
 }
 
+/// A hash of the code of an [Entity] with whitespace normalized.
+///
+/// Entities with the same code have the same ID, no matter which file they are
+/// in or how they are indented, so moved and copy-pasted code can be found.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+pub struct EntityContentId(pub Sha1Hash);
+
+impl EntityContentId {
+    /// Each run of whitespace counts as a single space, and leading and
+    /// trailing whitespace is ignored.
+    pub fn from_code(code: &str) -> Self {
+        Self(Sha1Hash::hash(code.split_whitespace().join(" ").as_bytes()))
+    }
+}
+
+impl ToSql for EntityContentId {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        self.0.to_sql()
+    }
+}
+
 /// A unique identifier for a particular version of a file.
 ///
 /// Ordinarily, [ContentId] would be good enough to uniquely identify a file,
@@ -250,6 +293,48 @@ impl FileSet {
             .into_iter()
             .flat_map(|x| x.iter().map(|&i| self.file_keys[i].filename.as_str()))
     }
+
+    /// Iterate over each group of filenames that share the same non-empty
+    /// [ContentId].
+    ///
+    /// These are usually vendored copies of the same file. The canonical
+    /// filename (see [Self::is_canonical]) always comes first in each group.
+    pub fn iter_duplicates(&self) -> impl Iterator<Item = Vec<&str>> {
+        self.content_ids
+            .iter()
+            .filter(|(&content_id, indices)| indices.len() > 1 && !content_id.is_empty())
+            .map(|(_, indices)| {
+                indices
+                    .iter()
+                    .map(|&i| self.file_keys[i].filename.as_str())
+                    .sorted_by_key(|&f| canonical_key(f))
+                    .collect()
+            })
+    }
+
+    /// Is this file the canonical copy of its content?
+    ///
+    /// When several filenames share the same content, the one with the fewest
+    /// path components is canonical. Ties are broken by length and then
+    /// lexicographically. Empty files are always considered canonical.
+    pub fn is_canonical(&self, file_key: &FileKey) -> bool {
+        if file_key.content_id.is_empty() {
+            return true;
+        }
+
+        self.get_filenames(file_key.content_id).min_by_key(|&f| canonical_key(f))
+            == Some(file_key.filename.as_str())
+    }
+
+    /// Create a new [FileSet] containing only the canonical copy of each
+    /// [ContentId].
+    pub fn without_duplicates(&self) -> FileSet {
+        FileSet::new(self.iter().filter(|f| self.is_canonical(f)).cloned())
+    }
+}
+
+fn canonical_key(filename: &str) -> (usize, usize, &str) {
+    (filename.split('/').count(), filename.len(), filename)
 }
 
 /// A collection of [FileSet]s.
@@ -281,6 +366,11 @@ impl MultiFileSet {
     pub fn iter(&self) -> impl Iterator<Item = (&PseudoCommitId, &FileSet)> {
         self.file_sets.iter()
     }
+
+    /// Create a new [MultiFileSet] by applying `f` to each [FileSet].
+    pub fn map<F: Fn(&FileSet) -> FileSet>(&self, f: F) -> MultiFileSet {
+        Self::new(self.file_sets.iter().map(|(&c, s)| (c, f(s))).collect())
+    }
 }
 
 /// A position (i.e. index) within a file.
@@ -480,11 +570,22 @@ pub enum EntityKind {
     Class,
     Enum,
     Field,
+    Function,
     Interface,
+    Lambda,
     Method,
+    Module,
+    Package,
     Record,
 }
 
+impl EntityKind {
+    /// Is this a kind of type (e.g. a class or an interface)?
+    pub fn is_type(&self) -> bool {
+        matches!(self, Self::Annotation | Self::Class | Self::Enum | Self::Interface | Self::Record)
+    }
+}
+
 impl ToSql for EntityKind {
     fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
         self.as_ref().to_sql()
@@ -497,17 +598,26 @@ impl ToSql for EntityKind {
 /// This is how we correlate entities from different versions. Unfortunately,
 /// entities in the same version may sometimes re-use the same
 /// `SimpleEntityId``. For instance, overloaded Java methods will all be given
-/// the same `SimpleEntityId`.
+/// the same `SimpleEntityId` (unless signatures are included, see
+/// `--signature-ids`).
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[derive(serde::Serialize)]
 pub struct SimpleEntityId(pub Sha1Hash);
 
 impl SimpleEntityId {
-    pub fn new(parent_id: Option<SimpleEntityId>, name: &str, kind: EntityKind) -> Self {
+    /// The `signature` (e.g. `(int,String)`) tells overloaded methods apart.
+    /// Without it, the ID is the same as in earlier versions of Neodepends.
+    pub fn new(
+        parent_id: Option<SimpleEntityId>,
+        name: &str,
+        kind: EntityKind,
+        signature: Option<&str>,
+    ) -> Self {
         let mut bytes = Vec::new();
         bytes.extend(parent_id.unwrap_or_default().0.as_ref());
         bytes.extend(name.as_bytes());
         bytes.extend(kind.as_ref().as_bytes());
+        bytes.extend(signature.unwrap_or_default().as_bytes());
         Self(Sha1Hash::hash(&bytes))
     }
 }
@@ -583,9 +693,67 @@ pub struct Entity {
     pub name: String,
     pub kind: EntityKind,
     pub code: Span,
+
+    /// The comment right before the code (like a Javadoc comment), if the tag
+    /// query of the language captures comments.
     pub comment: Option<Span>,
     pub content_id: ContentId,
     pub simple_id: SimpleEntityId,
+
+    /// The position of this entity among its siblings, ordered by where their
+    /// code starts (and then by where it ends). Starts at 0 and is unique among
+    /// siblings, so the layout of a file can be rebuilt (and reordered members
+    /// detected) without parsing its content again.
+    ///
+    /// Not part of [Self::id]. Set when the entities of a file are collected
+    /// (see [crate::tagging::EntitySet]).
+    pub ordinal: usize,
+
+    /// The name of the package (if any), the names of the ancestors below the
+    /// file, and the name of this entity joined with ".", followed by the
+    /// parameter types of a method or constructor (e.g.
+    /// `com.foo.Bar.baz(int)`). The filename for a file.
+    ///
+    /// Not part of [Self::id]. Set when the entities of a file are collected
+    /// (see [crate::tagging::EntitySet]).
+    pub qualified_name: String,
+
+    /// See [EntityContentId]. Zero for entities without code (like packages).
+    ///
+    /// Not part of [Self::id]. Set when the entities of a file are collected
+    /// (see [crate::tagging::EntitySet]).
+    pub entity_content_id: EntityContentId,
+
+    /// Is this test code? See [crate::testcode].
+    ///
+    /// Not part of [Self::id].
+    pub is_test: bool,
+
+    /// The length of [Self::code] in bytes, leaving out the comment.
+    pub byte_size: usize,
+
+    /// The number of tokens (the leaves of the syntax tree besides comments)
+    /// in [Self::code]. None if the file was not parsed (e.g. because its
+    /// language has no tag query).
+    ///
+    /// Not part of [Self::id]. Set when the entities of a file are collected
+    /// (see [crate::tagging::EntitySet]).
+    pub token_count: Option<usize>,
+
+    /// The language of the file holding this entity. None for files of no
+    /// known language and for packages (which are shared by every file in
+    /// them).
+    ///
+    /// Not part of [Self::id]. Set when the entities of a file are collected
+    /// (see [crate::tagging::EntitySet]).
+    pub lang: Option<Lang>,
+
+    /// The name of the file holding this entity. None for packages (which are
+    /// shared by every file in them).
+    ///
+    /// Not part of [Self::id]. Set when the entities of a file are collected
+    /// (see [crate::tagging::EntitySet]).
+    pub filename: Option<String>,
 }
 
 impl Entity {
@@ -599,7 +767,25 @@ impl Entity {
         simple_id: SimpleEntityId,
     ) -> Self {
         let id = EntityId::new(parent_id, &name, kind, code, comment, content_id, simple_id);
-        Self { id, parent_id, name, kind, code, comment, content_id, simple_id }
+        let qualified_name = name.clone();
+        Self {
+            id,
+            parent_id,
+            name,
+            kind,
+            code,
+            comment,
+            content_id,
+            simple_id,
+            ordinal: 0,
+            qualified_name,
+            entity_content_id: EntityContentId::default(),
+            is_test: false,
+            byte_size: code.end.byte - code.start.byte,
+            token_count: None,
+            lang: None,
+            filename: None,
+        }
     }
 
     pub fn location(&self) -> Span {
@@ -631,7 +817,7 @@ impl Content {
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[derive(serde::Deserialize, serde::Serialize)]
-#[derive(strum::AsRefStr, strum::Display, strum::EnumIs, strum::EnumString)]
+#[derive(strum::AsRefStr, strum::Display, strum::EnumIs, strum::EnumString, strum::IntoStaticStr)]
 pub enum DepKind {
     Annotation,
     Call,
@@ -660,6 +846,25 @@ impl ToSql for DepKind {
     }
 }
 
+/// How sure a resolver is about a [Dep].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(strum::AsRefStr, strum::EnumIs)]
+pub enum Confidence {
+    /// The resolver found the definition that the reference points to.
+    #[default]
+    Resolved,
+
+    /// The definition was guessed (e.g. by matching names alone).
+    Heuristic,
+}
+
+impl ToSql for Confidence {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        self.as_ref().to_sql()
+    }
+}
+
 /// A syntactic dependency between two files.
 ///
 /// [Self::position] refers to the location of the dependency within the source
@@ -672,6 +877,19 @@ pub struct Dep<E> {
     pub kind: DepKind,
     pub position: PartialPosition,
     pub commit_id: PseudoCommitId,
+    pub confidence: Confidence,
+
+    /// The name of the resolver (or other pass) that found this dep.
+    pub resolver: &'static str,
+
+    /// The number of identical deps that this one stands for. Only more than
+    /// one if deps were deduplicated (see [crate::dedup]).
+    pub count: usize,
+
+    /// The files of the endpoints and where the dep points to in the target
+    /// file. A [FileDep] keeps these in its endpoints, so this is only set once
+    /// they have been mapped to entities (see [FileDep::to_entity_dep]).
+    pub location: Option<DepLocation>,
 }
 
 impl<E> Dep<E> {
@@ -682,10 +900,64 @@ impl<E> Dep<E> {
         position: PartialPosition,
         commit_id: PseudoCommitId,
     ) -> Self {
-        Self { src, tgt, kind, position, commit_id }
+        Self {
+            src,
+            tgt,
+            kind,
+            position,
+            commit_id,
+            confidence: Confidence::default(),
+            resolver: "",
+            count: 1,
+            location: None,
+        }
+    }
+
+    pub fn with_confidence(self, confidence: Confidence) -> Self {
+        Self { confidence, ..self }
+    }
+
+    pub fn with_resolver(self, resolver: &'static str) -> Self {
+        Self { resolver, ..self }
+    }
+
+    /// Replace the endpoints while keeping everything else.
+    pub fn with_endpoints<F>(self, src: F, tgt: F) -> Dep<F> {
+        Dep {
+            src,
+            tgt,
+            kind: self.kind,
+            position: self.position,
+            commit_id: self.commit_id,
+            confidence: self.confidence,
+            resolver: self.resolver,
+            count: self.count,
+            location: self.location,
+        }
+    }
+
+    pub fn with_location(self, location: DepLocation) -> Self {
+        Self { location: Some(location), ..self }
     }
 }
 
+/// Where the endpoints of a [Dep] are.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+pub struct DepLocation {
+    pub src_filename: String,
+    pub tgt_filename: String,
+
+    /// Where the target is in its file, like the declaration of a called
+    /// method (the position of the source is [Dep::position]).
+    pub tgt_position: PartialPosition,
+
+    /// The symbols that the resolver says are involved, if it says (see
+    /// [FileEndpoint::name]).
+    pub src_name: Option<String>,
+    pub tgt_name: Option<String>,
+}
+
 impl<E: Eq> Dep<E> {
     pub fn is_loop(&self) -> bool {
         self.src == self.tgt
@@ -699,11 +971,17 @@ impl<E: Eq> Dep<E> {
 pub struct FileEndpoint {
     pub file_key: FileKey,
     pub position: PartialPosition,
+
+    /// The qualified name of the entity, if the resolver knows it.
+    ///
+    /// Takes priority over [Self::position] when mapping the endpoint to an
+    /// entity. See [crate::tagging::EntitySet::find_id_by_name].
+    pub name: Option<String>,
 }
 
 impl FileEndpoint {
     pub fn new(file_key: FileKey, position: PartialPosition) -> Self {
-        Self { file_key, position }
+        Self { file_key, position, name: None }
     }
 }
 
@@ -717,16 +995,22 @@ impl FileEndpoint {
 pub struct FilenameEndpoint {
     pub filename: String,
     pub position: PartialPosition,
+    pub name: Option<String>,
 }
 
 impl FilenameEndpoint {
     pub fn new(filename: String, position: PartialPosition) -> Self {
-        Self { filename, position }
+        Self { filename, position, name: None }
+    }
+
+    pub fn with_name(self, name: Option<String>) -> Self {
+        Self { name, ..self }
     }
 
     pub fn into_file_endpoint(self, file_set: &FileSet) -> Option<FileEndpoint> {
-        let content_id = file_set.get_content_id(&self.filename);
-        content_id.map(|c| FileEndpoint::new(FileKey::new(self.filename, c), self.position))
+        let content_id = file_set.get_content_id(&self.filename)?;
+        let file_key = FileKey::new(self.filename, content_id);
+        Some(FileEndpoint { file_key, position: self.position, name: self.name })
     }
 }
 
@@ -741,9 +1025,9 @@ pub type EntityDep = Dep<EntityId>;
 
 impl FilenameDep {
     pub fn into_file_dep(self, file_set: &FileSet) -> Option<FileDep> {
-        let src = self.src.into_file_endpoint(file_set)?;
-        let tgt = self.tgt.into_file_endpoint(file_set)?;
-        Some(Dep::new(src, tgt, self.kind, self.position, self.commit_id))
+        let src = self.src.clone().into_file_endpoint(file_set)?;
+        let tgt = self.tgt.clone().into_file_endpoint(file_set)?;
+        Some(self.with_endpoints(src, tgt))
     }
 }
 
@@ -768,7 +1052,8 @@ impl ToSql for ChangeKind {
 ///
 /// The number of lines added and deleted are stored in [Self::adds] and
 /// [Self::dels] respectively. Be careful not to count these more than once when
-/// mapping [SimpleEntityId] back to [EntityId].
+/// mapping [SimpleEntityId] back to [EntityId]. The author is the author of the
+/// commit (not its committer), as given by git.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[derive(serde::Serialize)]
 pub struct Change {
@@ -777,20 +1062,323 @@ pub struct Change {
     pub kind: ChangeKind,
     pub adds: usize,
     pub dels: usize,
+    pub author_name: String,
+    pub author_email: String,
 }
 
 impl Change {
     pub fn new(
         simple_id: SimpleEntityId,
-        commit_id: CommitId,
+        diff: &Diff,
         kind: ChangeKind,
         adds: usize,
         dels: usize,
     ) -> Self {
-        Self { simple_id, commit_id, kind, adds, dels }
+        Self {
+            simple_id,
+            commit_id: diff.commit_id,
+            kind,
+            adds,
+            dels,
+            author_name: diff.author_name.clone(),
+            author_email: diff.author_email.clone(),
+        }
+    }
+}
+
+/// A record of the deps between a pair of entities changing between two
+/// versions of a project.
+///
+/// Deps are matched across versions by the [SimpleEntityId]s of their
+/// endpoints and by their [DepKind]. [Self::adds] and [Self::dels] count how
+/// many of these deps were added and deleted going from [Self::old_commit_id]
+/// to [Self::new_commit_id].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+pub struct DepChange {
+    pub src: SimpleEntityId,
+    pub tgt: SimpleEntityId,
+    pub kind: DepKind,
+    pub old_commit_id: PseudoCommitId,
+    pub new_commit_id: PseudoCommitId,
+    pub adds: usize,
+    pub dels: usize,
+}
+
+/// How the structural and evolutionary coupling of a pair of entities agree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+#[derive(strum::AsRefStr, strum::EnumIs)]
+pub enum AlignmentKind {
+    /// The pair has deps and has changed together.
+    Aligned,
+
+    /// The pair has deps but has never changed together.
+    DepOnly,
+
+    /// The pair has changed together but has no deps.
+    CochangeOnly,
+}
+
+impl ToSql for AlignmentKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        self.as_ref().to_sql()
     }
 }
 
+/// A record comparing the deps of a pair of entities with the number of
+/// commits that changed both of them.
+///
+/// Pairs are unordered, so [Self::deps] counts deps in either direction and
+/// [Self::a] is always less than [Self::b].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+pub struct Alignment {
+    pub a: SimpleEntityId,
+    pub b: SimpleEntityId,
+    pub deps: usize,
+    pub cochanges: usize,
+    pub kind: AlignmentKind,
+}
+
+/// A record of a revspec given on the command-line and the commit it resolved
+/// to.
+///
+/// Branches and tags can move between runs, so this makes it possible to tell
+/// which commit was actually scanned. [Self::resolved_at] is in seconds since
+/// the Unix epoch.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+pub struct Revspec {
+    pub revspec: String,
+    pub commit_id: PseudoCommitId,
+    pub resolved_at: u64,
+}
+
+impl Revspec {
+    pub fn new(revspec: String, commit_id: PseudoCommitId, resolved_at: u64) -> Self {
+        Self { revspec, commit_id, resolved_at }
+    }
+}
+
+/// The metadata of a commit, as given by `git log`.
+///
+/// Dates are in seconds since the Unix epoch. [Self::parent_ids] are separated
+/// by spaces (like `%P` in `git log --format`). [Self::ref_name] is the ref
+/// that the commit was reached from (like `git log --source`), if it was found
+/// by walking refs rather than given as a revspec.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+pub struct Commit {
+    pub commit_id: CommitId,
+    pub author_name: String,
+    pub author_email: String,
+    pub author_date: i64,
+    pub committer_name: String,
+    pub committer_email: String,
+    pub commit_date: i64,
+    pub subject: String,
+    pub parent_ids: String,
+    pub ref_name: Option<String>,
+}
+
+/// A reference that a resolver found but could not match to a definition.
+///
+/// Useful for measuring the recall of a resolver and for finding files that are
+/// missing from the project. [Self::row] and [Self::column] start at 0.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+pub struct UnresolvedRef {
+    pub filename: String,
+    pub row: usize,
+    pub column: usize,
+    pub text: String,
+    pub commit_id: PseudoCommitId,
+}
+
+impl UnresolvedRef {
+    pub fn new(
+        filename: String,
+        row: usize,
+        column: usize,
+        text: String,
+        commit_id: PseudoCommitId,
+    ) -> Self {
+        Self { filename, row, column, text, commit_id }
+    }
+}
+
+/// The step of extraction at which a [Diagnostic] was raised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+#[derive(strum::AsRefStr, strum::EnumIs)]
+pub enum DiagnosticStage {
+    /// The entities of a file could not be tagged, so it fell back to a single
+    /// file-level entity.
+    Tag,
+
+    /// A resolver failed, so some deps of its files are missing.
+    Resolve,
+
+    /// The content of a file could not be read.
+    Read,
+}
+
+impl ToSql for DiagnosticStage {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        self.as_ref().to_sql()
+    }
+}
+
+/// A failure that left part of a version out of the output.
+///
+/// Useful for auditing how much of a project was actually covered by a run.
+/// [Self::filename] is None if the failure is not about a single file (e.g. a
+/// resolver that failed for every file of a language).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+pub struct Diagnostic {
+    pub filename: Option<String>,
+    pub commit_id: PseudoCommitId,
+    pub stage: DiagnosticStage,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(
+        filename: Option<String>,
+        commit_id: PseudoCommitId,
+        stage: DiagnosticStage,
+        message: String,
+    ) -> Self {
+        Self { filename, commit_id, stage, message }
+    }
+}
+
+/// A file of a particular version.
+///
+/// An entity is in every version holding a file with its [Entity::content_id],
+/// so these say which versions each entity is in.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+pub struct CommitFile {
+    pub filename: String,
+    pub commit_id: PseudoCommitId,
+    pub content_id: ContentId,
+}
+
+impl CommitFile {
+    pub fn new(filename: String, commit_id: PseudoCommitId, content_id: ContentId) -> Self {
+        Self { filename, commit_id, content_id }
+    }
+}
+
+/// How an entity differs from the entity it was matched to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+#[derive(strum::AsRefStr, strum::EnumIs)]
+pub enum MatchKind {
+    /// Both entities have the same [EntityId].
+    Unchanged,
+
+    /// The code of the entity is the same but has moved within its file (or
+    /// something else in the file has changed).
+    Moved,
+
+    /// The code of the entity has changed.
+    Modified,
+}
+
+impl ToSql for MatchKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        self.as_ref().to_sql()
+    }
+}
+
+/// A record that an entity of [Self::old_commit_id] and an entity of
+/// [Self::new_commit_id] are the same entity.
+///
+/// Since an [EntityId] changes whenever the content of its file does, this is
+/// what ties together the entities of different versions of a project. See
+/// [crate::matching].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+pub struct EntityMatch {
+    pub old_id: EntityId,
+    pub new_id: EntityId,
+    pub old_commit_id: PseudoCommitId,
+    pub new_commit_id: PseudoCommitId,
+    pub kind: MatchKind,
+}
+
+/// A record of an import of something outside of the project, such as a
+/// third-party library or the standard library.
+///
+/// [Self::symbol] is the imported name as written in the source, except that
+/// Python modules are joined to their members with a ".".
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+pub struct ExternalDep {
+    pub src: EntityId,
+    pub symbol: String,
+    pub row: usize,
+    pub commit_id: PseudoCommitId,
+}
+
+/// A record of an annotation (or decorator) applied to an entity.
+///
+/// [Self::name] is the name of the annotation without the `@` or any arguments
+/// (e.g. `pytest.fixture` for `@pytest.fixture(scope="module")`) and
+/// [Self::text] is the whole annotation as written. [Self::row] is where the
+/// annotation starts and starts at 0. See [crate::annotations].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+pub struct Annotation {
+    pub entity_id: EntityId,
+    pub name: String,
+    pub text: String,
+    pub row: usize,
+}
+
+/// A record of how many distinct entities an entity depends on (its fan-out)
+/// and how many depend on it (its fan-in) in a particular version, along with
+/// the size and complexity of its code.
+///
+/// Deps of an entity on itself are not counted. See [crate::metrics].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+pub struct Metric {
+    pub entity_id: EntityId,
+    pub commit_id: PseudoCommitId,
+    pub fan_in: usize,
+    pub fan_out: usize,
+    pub lines: usize,
+    pub logical_lines: usize,
+    pub complexity: usize,
+    pub max_nesting: usize,
+
+    /// None if the entity has no parameter list (e.g. a class).
+    pub params: Option<usize>,
+}
+
+/// A record that one type is a subtype of another in a particular version,
+/// either directly or through a chain of [DepKind::Extend] and
+/// [DepKind::Implement] deps.
+///
+/// [Self::kind] is [DepKind::Extend] if the supertype can be reached through
+/// Extend deps alone and [DepKind::Implement] otherwise. [Self::depth] is the
+/// fewest deps needed to reach the supertype (1 for a direct supertype). See
+/// [crate::hierarchy].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize)]
+pub struct Supertype {
+    pub subtype: EntityId,
+    pub supertype: EntityId,
+    pub commit_id: PseudoCommitId,
+    pub kind: DepKind,
+    pub depth: usize,
+}
+
 /// A record of a block of text that has been changed.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Hunk {
@@ -802,11 +1390,13 @@ pub struct Hunk {
 }
 
 impl Hunk {
+    /// Git reports a start of zero for the empty side of an added or deleted
+    /// file, so the conversion to zero-based rows saturates.
     pub fn from_git(hunk: &git2::DiffHunk) -> Self {
-        let old_start = usize::try_from(hunk.old_start()).unwrap() - 1;
+        let old_start = usize::try_from(hunk.old_start()).unwrap().saturating_sub(1);
         let old_end = old_start + usize::try_from(hunk.old_lines()).unwrap();
         let old = PartialSpan::Row(old_start, old_end);
-        let new_start = usize::try_from(hunk.new_start()).unwrap() - 1;
+        let new_start = usize::try_from(hunk.new_start()).unwrap().saturating_sub(1);
         let new_end = new_start + usize::try_from(hunk.new_lines()).unwrap();
         let new = PartialSpan::Row(new_start, new_end);
         Self { old, new }
@@ -820,11 +1410,22 @@ pub struct Diff {
     pub old: Option<FileKey>,
     pub new: Option<FileKey>,
     pub hunks: Vec<Hunk>,
+
+    /// The author of the commit, so that each [Change] can be attributed.
+    pub author_name: String,
+    pub author_email: String,
 }
 
 impl Diff {
     pub fn new(id: CommitId, old: Option<FileKey>, new: Option<FileKey>, hunks: Vec<Hunk>) -> Self {
-        Self { commit_id: id, old, new, hunks }
+        let (author_name, author_email) = Default::default();
+        Self { commit_id: id, old, new, hunks, author_name, author_email }
+    }
+
+    pub fn with_author(mut self, name: String, email: String) -> Self {
+        self.author_name = name;
+        self.author_email = email;
+        self
     }
 
     pub fn change_kind(&self) -> ChangeKind {
@@ -840,6 +1441,10 @@ impl Diff {
         self.old.as_ref().into_iter().chain(self.new.as_ref().into_iter())
     }
 
+    pub fn iter_file_keys_mut(&mut self) -> impl Iterator<Item = &mut FileKey> {
+        self.old.as_mut().into_iter().chain(self.new.as_mut())
+    }
+
     pub fn iter_old_spans(&self) -> impl Iterator<Item = PartialSpan> + '_ {
         self.hunks.iter().map(|h| h.old)
     }
@@ -0,0 +1,58 @@
+//! Collapse identical deps into a single dep with a count.
+//!
+//! The same call from one method to another often occurs many times, once for
+//! each row it is made on. Deps with the same source, target, kind, version,
+//! and resolver are merged into the first of them (by position), whose
+//! [crate::core::Dep::count] says how many there were. A merged dep takes the
+//! best confidence among the deps it stands for.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::core::DepKind;
+use crate::core::EntityDep;
+use crate::core::EntityId;
+use crate::core::PseudoCommitId;
+
+type DepKey = (EntityId, EntityId, DepKind, PseudoCommitId, &'static str);
+
+#[derive(Debug, Default)]
+pub struct DepCollapser {
+    deps: Mutex<HashMap<DepKey, EntityDep>>,
+}
+
+impl DepCollapser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, dep: EntityDep) {
+        let key = (dep.src, dep.tgt, dep.kind, dep.commit_id, dep.resolver);
+
+        match self.deps.lock().unwrap().entry(key) {
+            Entry::Vacant(entry) => {
+                entry.insert(dep);
+            }
+            Entry::Occupied(mut entry) => {
+                let kept = entry.get_mut();
+                let count = kept.count + dep.count;
+                let confidence = kept.confidence.min(dep.confidence);
+
+                if dep.position < kept.position {
+                    *kept = dep;
+                }
+
+                kept.count = count;
+                kept.confidence = confidence;
+            }
+        }
+    }
+
+    /// The collapsed deps, ordered by version, source, and position.
+    pub fn build(self) -> Vec<EntityDep> {
+        let mut deps: Vec<_> = self.deps.into_inner().unwrap().into_values().collect();
+        deps.sort_by_key(|d| (d.commit_id, d.src, d.position, d.tgt, d.kind));
+        deps
+    }
+}
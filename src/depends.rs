@@ -9,14 +9,18 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::sync::RwLock;
 
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
-use itertools::Itertools;
 use serde::Deserialize;
+use sha2::Digest;
+use sha2::Sha256;
 use subprocess::Exec;
 use subprocess::Redirection;
+use tempfile::NamedTempFile;
 use tempfile::TempDir;
 
+use crate::core::Confidence;
 use crate::core::FileDep;
 use crate::core::FileKey;
 use crate::core::FileSet;
@@ -28,6 +32,21 @@ use crate::languages::Lang;
 use crate::resolution::Resolver;
 use crate::resolution::ResolverFactory;
 
+/// Where to download depends.jar from when using `--depends-install`.
+///
+/// Each release of Neodepends attaches the depends.jar it was tested against.
+const DEPENDS_JAR_URL: &str = concat!(
+    "https://github.com/jlefever/neodepends/releases/download/v",
+    env!("CARGO_PKG_VERSION"),
+    "/depends.jar"
+);
+
+/// The SHA-256 checksum of the depends.jar found at [DEPENDS_JAR_URL].
+const DEPENDS_JAR_SHA256: &str = "b5c1a91d17233c9dc0d492c73f913cc6066c4e26a988e2b3012389c615983a82";
+
+/// The oldest Java that can run depends.jar (class file version 52).
+const MIN_JAVA_VERSION: u32 = 8;
+
 /// All options needed to run Depends.
 #[derive(Debug, Clone)]
 pub struct DependsConfig {
@@ -39,17 +58,77 @@ pub struct DependsConfig {
 
     /// The "-Xmx" value to be passed to the Java executable.
     xmx: Option<String>,
+
+    /// Where to create the temporary directories that Depends runs in.
+    scratch_dir: Option<PathBuf>,
 }
 
 impl DependsConfig {
-    pub fn new(jar: Option<PathBuf>, java: Option<PathBuf>, xmx: Option<String>) -> Self {
-        Self { jar, java, xmx }
+    pub fn new(
+        jar: Option<PathBuf>,
+        java: Option<PathBuf>,
+        xmx: Option<String>,
+        scratch_dir: Option<PathBuf>,
+    ) -> Self {
+        Self { jar, java, xmx, scratch_dir }
+    }
+
+    /// Check that Java can be run and is new enough for depends.jar.
+    ///
+    /// Meant to be called before any resolution begins so that a missing or
+    /// outdated Java is reported up front rather than as a failed subprocess.
+    pub fn check_java(&self) -> Result<()> {
+        let java = self.java();
+        let path = resolve_executable(&java).unwrap_or(java.clone());
+        let hint = "Install Java or pass --depends-java with the path to a Java executable.";
+
+        let capture = Exec::cmd(&java)
+            .arg("-version")
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Merge)
+            .capture()
+            .with_context(|| {
+                format!(
+                    "could not run Java at {}. Depends requires Java {} or newer. {}",
+                    path.display(),
+                    MIN_JAVA_VERSION,
+                    hint
+                )
+            })?;
+
+        let output = capture.stdout_str();
+        let version = parse_java_version(&output);
+
+        match version {
+            Some(v) if v >= MIN_JAVA_VERSION => {
+                log::debug!("Using Java {} at {}", v, path.display());
+                Ok(())
+            }
+            Some(v) => bail!(
+                "Java {} at {} is too old. Depends requires Java {} or newer. {}",
+                v,
+                path.display(),
+                MIN_JAVA_VERSION,
+                hint
+            ),
+            None => bail!(
+                "could not determine the version of Java at {} from {:?}. {}",
+                path.display(),
+                output.lines().next().unwrap_or_default(),
+                hint
+            ),
+        }
+    }
+
+    fn java(&self) -> PathBuf {
+        self.java.clone().unwrap_or("java".into())
     }
 }
 
 /// A Depends resolver.
 ///
-/// Works by using a temporary directory.
+/// Works by using a temporary directory, created under the scratch directory
+/// if one is configured.
 ///
 /// See [Resolver].
 #[derive(Debug)]
@@ -63,40 +142,66 @@ pub struct DependsResolver {
 
 impl DependsResolver {
     fn new(commit_id: PseudoCommitId, depends_lang: String, config: DependsConfig) -> Self {
-        Self {
-            commit_id,
-            depends_lang,
-            config,
-            temp_dir: TempDir::new().unwrap(),
-            files: Default::default(),
+        let temp_dir = match &config.scratch_dir {
+            Some(dir) => TempDir::new_in(dir).unwrap(),
+            None => TempDir::new().unwrap(),
+        };
+
+        Self { commit_id, depends_lang, config, temp_dir, files: Default::default() }
+    }
+}
+
+impl DependsResolver {
+    /// Check that the file is new and create its parent directories in the
+    /// temp dir.
+    fn prepare(&self, file_key: &FileKey) -> PathBuf {
+        if self.files.read().unwrap().contains(file_key) {
+            panic!("attempted to add two files with identical filenames");
         }
+
+        let path = self.temp_dir.as_ref().join(&file_key.filename);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        path
     }
 }
 
 impl Resolver for DependsResolver {
     fn add_file(&self, filename: &str, content: &str) {
         let file_key = FileKey::from_content(filename.to_string(), content);
+        let path = self.prepare(&file_key);
+        std::fs::File::create(path).unwrap().write_all(content.as_bytes()).unwrap();
+        self.files.write().unwrap().insert(file_key);
+    }
 
-        if self.files.read().unwrap().contains(&file_key) {
-            panic!("attempted to add two files with identical filenames");
+    /// Hard link to the file on disk. If that fails (e.g. because the temp dir
+    /// is on another device), fall back to [std::fs::copy], which will clone
+    /// the file on filesystems that support copy-on-write.
+    fn add_file_on_disk(&self, file: &FileKey, path: &Path) -> bool {
+        let target = self.prepare(file);
+
+        if std::fs::hard_link(path, &target).is_err() && std::fs::copy(path, &target).is_err() {
+            return false;
         }
 
-        let path = self.temp_dir.as_ref().join(filename);
-        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
-        std::fs::File::create(path).unwrap().write_all(content.as_bytes()).unwrap();
-        self.files.write().unwrap().insert(file_key);
+        self.files.write().unwrap().insert(file.clone());
+        true
     }
 
-    fn resolve(&self) -> Vec<FileDep> {
+    fn resolve(&self) -> Result<Vec<FileDep>> {
         let file_set = FileSet::new(self.files.read().unwrap().iter().map(|x| x.clone()));
         log::info!("Running Depends on {} file(s)...", &self.depends_lang);
-        run(&self.config, &self.temp_dir, &self.depends_lang).unwrap();
+        run(&self.config, &self.temp_dir, &self.depends_lang)?;
         log::info!("Loading Depends {} output...", &self.depends_lang);
         load_depends_output(&self.temp_dir, &self.depends_lang)
-            .unwrap()
+            .context("failed to load the output of Depends")?
             .iter_filename_deps(self.commit_id)
-            .map(|d| d.into_file_dep(&file_set).unwrap())
-            .collect_vec()
+            .map(|d| {
+                let filenames = (d.src.filename.clone(), d.tgt.filename.clone());
+                d.into_file_dep(&file_set).with_context(|| {
+                    format!("Depends found a dep between unknown files {:?}", filenames)
+                })
+            })
+            .collect()
     }
 }
 
@@ -115,6 +220,29 @@ impl DependsResolverFactory {
 }
 
 impl ResolverFactory for DependsResolverFactory {
+    fn name(&self) -> &'static str {
+        "depends"
+    }
+
+    fn supports(&self, lang: Lang) -> bool {
+        lang.depends_lang().is_some()
+    }
+
+    fn is_heavyweight(&self) -> bool {
+        true
+    }
+
+    /// The SHA-256 checksum of the depends.jar that will be run.
+    ///
+    /// Depends has no version flag, and a jar given with --depends-jar may
+    /// differ from the one that is downloaded.
+    fn version(&self) -> Result<String> {
+        let jar = get_depends_jar(self.config.jar.clone())?;
+        let bytes =
+            std::fs::read(&jar).with_context(|| format!("failed to read {}", jar.display()))?;
+        Ok(format!("sha256:{}", hex::encode(Sha256::digest(bytes))))
+    }
+
     fn try_create(&self, commit_id: PseudoCommitId, lang: Lang) -> Option<Box<dyn Resolver>> {
         lang.depends_lang().map(|l| {
             Box::new(DependsResolver::new(commit_id, l.to_string(), self.config.clone()))
@@ -124,7 +252,7 @@ impl ResolverFactory for DependsResolverFactory {
 }
 
 fn run<P: AsRef<Path>>(config: &DependsConfig, dir: P, depends_lang: &str) -> Result<()> {
-    let mut cmd = Exec::cmd(config.java.clone().unwrap_or("java".into()));
+    let mut cmd = Exec::cmd(config.java());
 
     if let Some(xmx) = &config.xmx {
         cmd = cmd.arg(format!("-Xmx{xmx}"));
@@ -159,15 +287,88 @@ fn load_depends_output<P: AsRef<Path>>(dir: P, depends_lang: &str) -> Result<Dep
     Ok(serde_json::from_slice(&buffer)?)
 }
 
+/// Download the pinned depends.jar into the cache directory.
+///
+/// Does nothing if depends.jar can already be found next to this executable or
+/// in the cache. The download is verified against [DEPENDS_JAR_SHA256] before
+/// it is moved into place, so a partial or tampered download is never used.
+pub fn install_depends_jar() -> Result<PathBuf> {
+    if let Some(jar) = find_depends_jar() {
+        return Ok(jar);
+    }
+
+    let path = cached_depends_jar().context("could not determine a cache directory")?;
+    let dir = path.parent().unwrap();
+    std::fs::create_dir_all(dir)?;
+
+    log::info!("Downloading depends.jar from {}...", DEPENDS_JAR_URL);
+    let mut bytes = Vec::new();
+    let response = ureq::get(DEPENDS_JAR_URL).call().context("failed to download depends.jar")?;
+    response.into_reader().read_to_end(&mut bytes)?;
+
+    let checksum = hex::encode(Sha256::digest(&bytes));
+
+    if checksum != DEPENDS_JAR_SHA256 {
+        bail!("checksum mismatch for depends.jar (expected {DEPENDS_JAR_SHA256}, got {checksum})");
+    }
+
+    let mut file = NamedTempFile::new_in(dir)?;
+    file.write_all(&bytes)?;
+    file.persist(&path)?;
+    log::info!("Installed depends.jar to {}", path.display());
+    Ok(path)
+}
+
 fn get_depends_jar(jar: Option<PathBuf>) -> Result<PathBuf> {
-    Ok(jar.or_else(find_depends_jar).context("could not find depends.jar")?.canonicalize()?)
+    let jar = jar.or_else(find_depends_jar);
+    let jar = jar.context("could not find depends.jar (try --depends-jar or --depends-install)")?;
+    Ok(jar.canonicalize()?)
 }
 
+/// Look for depends.jar next to this executable and then in the cache.
 fn find_depends_jar() -> Option<PathBuf> {
     std::env::current_exe()
         .ok()
-        .and_then(|e| e.parent().map(|p| p.to_path_buf()))
-        .map(|p| p.join("depends.jar"))
+        .and_then(|e| e.parent().map(|p| p.join("depends.jar")))
+        .into_iter()
+        .chain(cached_depends_jar())
+        .find(|p| p.is_file())
+}
+
+/// The location of depends.jar after running [install_depends_jar].
+///
+/// The checksum is part of the path so that upgrading Neodepends never picks
+/// up a stale jar.
+fn cached_depends_jar() -> Option<PathBuf> {
+    let name = format!("depends-{}", &DEPENDS_JAR_SHA256[..12]);
+    dirs::cache_dir().map(|d| d.join("neodepends").join(name).join("depends.jar"))
+}
+
+/// Parse the major version from the output of `java -version`.
+///
+/// Handles both the legacy scheme (`"1.8.0_292"`) and the modern scheme
+/// (`"17.0.2"`).
+fn parse_java_version(output: &str) -> Option<u32> {
+    let quoted = output.lines().find_map(|l| l.split('"').nth(1))?;
+    let mut parts = quoted.split(|c: char| !c.is_ascii_digit());
+
+    match parts.next()?.parse().ok()? {
+        1 => parts.next()?.parse().ok(),
+        major => Some(major),
+    }
+}
+
+/// Find the absolute path of an executable the way a shell would.
+fn resolve_executable(program: &Path) -> Option<PathBuf> {
+    if program.components().count() > 1 {
+        return program.canonicalize().ok();
+    }
+
+    let names = [program.to_path_buf(), program.with_extension(std::env::consts::EXE_EXTENSION)];
+
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .flat_map(|dir| names.iter().map(move |n| dir.join(n)))
+        .find(|p| p.is_file())
 }
 
 #[derive(Debug, Deserialize)]
@@ -213,8 +414,16 @@ impl DependsDetail {
         let src = self.src.into_filename_endpoint()?;
         let tgt = self.tgt.into_filename_endpoint()?;
         let position = src.position;
-        let kind = self.kind.strip_suffix("(possible)").unwrap_or(&self.kind);
-        Some(FilenameDep::new(src, tgt, kind.try_into().unwrap(), position, commit_id))
+
+        // Depends marks the deps it is unsure about (e.g. a call on a receiver
+        // whose type could not be inferred) with a "(possible)" suffix
+        let (kind, confidence) = match self.kind.strip_suffix("(possible)") {
+            Some(kind) => (kind, Confidence::Heuristic),
+            None => (self.kind.as_str(), Confidence::Resolved),
+        };
+
+        let dep = FilenameDep::new(src, tgt, kind.try_into().unwrap(), position, commit_id);
+        Some(dep.with_confidence(confidence))
     }
 }
 
@@ -225,6 +434,10 @@ struct DependsEndpoint {
 
     #[serde(rename = "lineNumber")]
     line: usize,
+
+    /// The qualified name of the entity (e.g. `com.foo.Bar.baz`)
+    #[serde(rename = "object")]
+    name: Option<String>,
 }
 
 impl DependsEndpoint {
@@ -233,7 +446,7 @@ impl DependsEndpoint {
             // The "line number" from Depends starts counting at 1, whereas our "row" starts
             // counting at 0.
             let row = PartialPosition::Row(self.line - 1);
-            Some(FilenameEndpoint::new(self.filename, row))
+            Some(FilenameEndpoint::new(self.filename, row).with_name(self.name))
         } else {
             // A line number of 0 means Depends does not know the line number. We ignore
             // these.
@@ -0,0 +1,52 @@
+//! Assign entities to the directories that hold their files.
+//!
+//! A DSM of a repository with tens of thousands of files is too large to read.
+//! For an architecture-scale view, every entity can instead be merged into the
+//! directory of its file, cut down to its first N components. With N = 2,
+//! `src/app/ui/Button.java` belongs to `src/app`, while `src/Main.java` belongs
+//! to `src` (there being no deeper directory). Files at the root of the project
+//! belong to `.`.
+
+use std::collections::HashMap;
+
+use crate::core::Entity;
+use crate::core::EntityId;
+
+/// The name of the directory that holds files at the root of the project.
+const ROOT: &str = ".";
+
+/// Find the directory of each of `entities`, keeping at most `depth`
+/// components (or all of them if None).
+///
+/// Entities outside of any file (e.g. packages) are left out.
+pub fn directories(entities: &[Entity], depth: Option<usize>) -> HashMap<EntityId, String> {
+    let by_id: HashMap<_, _> = entities.iter().map(|e| (e.id, e)).collect();
+
+    entities
+        .iter()
+        .filter_map(|entity| {
+            let mut curr = entity;
+
+            while !curr.kind.is_file() {
+                curr = by_id.get(&curr.parent_id?)?;
+            }
+
+            Some((entity.id, directory(&curr.name, depth)))
+        })
+        .collect()
+}
+
+/// The directory of `filename`, keeping at most `depth` components.
+fn directory(filename: &str, depth: Option<usize>) -> String {
+    let mut parts = filename.split('/').collect::<Vec<_>>();
+    parts.pop();
+
+    if let Some(depth) = depth {
+        parts.truncate(depth);
+    }
+
+    match parts.is_empty() {
+        true => ROOT.to_string(),
+        false => parts.join("/"),
+    }
+}
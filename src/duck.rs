@@ -0,0 +1,125 @@
+//! Guess the deps of Python attributes whose receiver has no known type.
+//!
+//! In `def f(x): x.frobnicate()`, nothing says what `x` is, so a resolver
+//! cannot find the definition of `frobnicate`. But if only one function or
+//! method in the project is named `frobnicate`, it is probably the one being
+//! called. Deps found this way are marked [Confidence::Heuristic]. Attributes
+//! that were already resolved and names starting with `__` (like `__init__`)
+//! are skipped.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use rayon::prelude::*;
+use tree_sitter::Parser;
+use tree_sitter::Query;
+use tree_sitter::QueryCursor;
+
+use crate::core::Confidence;
+use crate::core::Dep;
+use crate::core::DepKind;
+use crate::core::FileDep;
+use crate::core::FileEndpoint;
+use crate::core::MultiFileSet;
+use crate::core::PartialPosition;
+use crate::core::Position;
+use crate::core::Span;
+use crate::filesystem::FileReader;
+use crate::languages::Lang;
+
+lazy_static! {
+    static ref PYTHON_QUERY: Query = Query::new(
+        tree_sitter_python::language(),
+        "(function_definition name: (identifier) @def)
+         (attribute attribute: (identifier) @attr)"
+    )
+    .unwrap();
+}
+
+/// The names defined and accessed in a Python file.
+#[derive(Debug, Default)]
+struct Scan {
+    defs: Vec<(String, Position)>,
+    attrs: Vec<(String, Position)>,
+}
+
+/// Find heuristic deps for the Python files of each version.
+///
+/// `resolved` holds the deps that were already found, so the same reference is
+/// not matched twice.
+pub fn resolve<R: FileReader>(
+    reader: &R,
+    files: &MultiFileSet,
+    resolved: &[FileDep],
+) -> Vec<FileDep> {
+    let resolved: HashSet<_> = resolved
+        .iter()
+        .filter_map(|d| Some((&d.src.file_key.filename, d.position.byte()?)))
+        .collect();
+
+    files
+        .iter()
+        .flat_map(|(&commit_id, file_set)| {
+            let scans: Vec<_> = file_set
+                .iter()
+                .filter(|f| Lang::of(&f.filename) == Some(Lang::Python))
+                .collect_vec()
+                .into_par_iter()
+                .filter_map(|f| Some((f, scan(&reader.read(f.content_id).ok()?)?)))
+                .collect();
+
+            let counts = scans.iter().flat_map(|(_, s)| &s.defs).map(|(n, _)| n).counts();
+            let unique: HashMap<_, _> = scans
+                .iter()
+                .flat_map(|(f, s)| s.defs.iter().map(move |(n, p)| (n, (*f, *p))))
+                .filter(|(n, _)| counts[n] == 1)
+                .collect();
+
+            scans
+                .iter()
+                .flat_map(|(f, s)| s.attrs.iter().map(move |(n, p)| (*f, n, *p)))
+                .filter(|(f, n, p)| {
+                    !n.starts_with("__") && !resolved.contains(&(&f.filename, p.byte))
+                })
+                .filter_map(|(f, n, p)| {
+                    let (tgt, tgt_position) = unique.get(n)?;
+                    let src = FileEndpoint::new(f.clone(), PartialPosition::Whole(p));
+                    let tgt =
+                        FileEndpoint::new((*tgt).clone(), PartialPosition::Whole(*tgt_position));
+                    let position = PartialPosition::Whole(p);
+                    let dep = Dep::new(src, tgt, DepKind::Use, position, commit_id);
+                    Some(dep.with_confidence(Confidence::Heuristic).with_resolver("duck-typing"))
+                })
+                .collect_vec()
+        })
+        .collect()
+}
+
+/// Find the names of the functions defined in a file and of the attributes it
+/// accesses.
+///
+/// Returns None if the file could not be parsed.
+fn scan(content: &str) -> Option<Scan> {
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_python::language()).ok()?;
+    let tree = parser.parse(content, None)?;
+    let ix_def = PYTHON_QUERY.capture_index_for_name("def")?;
+
+    let mut result = Scan::default();
+    let mut cursor = QueryCursor::new();
+
+    for (m, i) in cursor.captures(&PYTHON_QUERY, tree.root_node(), content.as_bytes()) {
+        let capture = m.captures[i];
+        let name = capture.node.utf8_text(content.as_bytes()).ok()?.to_string();
+        let position = Span::from_ts(capture.node.range()).start;
+
+        match capture.index == ix_def {
+            true => result.defs.push((name, position)),
+            false => result.attrs.push((name, position)),
+        }
+    }
+
+    Some(result)
+}
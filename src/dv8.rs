@@ -0,0 +1,90 @@
+//! Export a design structure matrix in the JSON format ingested by DV8.
+//!
+//! See https://archdia.com/. Unlike [crate::matrix::dsm_v2], DV8 identifies
+//! variables by name alone, so each entity is named by its path from the root
+//! of the project (e.g. `src/Foo.java/Foo/bar`). Entities that share a path
+//! (such as overloaded methods) are merged into a single variable. If the
+//! entities have owners, each cell also has a "SameTeam" value of 1 or 0.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+use anyhow::Result;
+use itertools::Itertools;
+
+use crate::core::Entity;
+use crate::matrix::qualified_names;
+use crate::matrix::Tally;
+use crate::ownership::Ownership;
+use crate::taxonomy::Taxonomy;
+
+/// A DSM that serializes to the dv8-json format.
+#[derive(Debug, Clone)]
+#[derive(serde::Serialize)]
+pub struct Dv8Matrix {
+    #[serde(rename = "@schemaVersion")]
+    schema_version: String,
+    name: String,
+    variables: Vec<String>,
+    cells: Vec<Dv8Cell>,
+}
+
+impl Dv8Matrix {
+    /// Build a matrix whose cells count deps by kind and co-changes.
+    ///
+    /// Deps are counted under their label in `taxonomy`. Co-changes are counted
+    /// under the "Cochange" key, once for each commit that touched both
+    /// variables (so merged entities do not inflate the count). Cells on the
+    /// diagonal are omitted.
+    pub fn build(
+        name: &str,
+        entities: &[Entity],
+        tally: &Tally,
+        taxonomy: &Taxonomy,
+        ownership: &Ownership,
+    ) -> Result<Self> {
+        let names = qualified_names(entities);
+        let variables = names.values().cloned().unique().sorted().collect_vec();
+        let positions: HashMap<_, _> = variables.iter().enumerate().map(|(i, v)| (v, i)).collect();
+        let indices: HashMap<_, _> = names.iter().map(|(id, n)| (*id, positions[n])).collect();
+
+        let teams = variables.iter().map(|v| ownership.team(v)).collect_vec();
+        let same_team = |src: usize, tgt: usize| Some(teams[src]? == teams[tgt]?);
+
+        let cells = tally
+            .cells(entities, taxonomy, |id| indices.get(&id).copied())?
+            .into_iter()
+            .filter(|((src, tgt), _)| src != tgt)
+            .map(|((src, tgt), values)| Dv8Cell::new(src, tgt, values, same_team(src, tgt)))
+            .sorted_by_key(|c| (c.src, c.tgt))
+            .collect();
+
+        Ok(Self { schema_version: "1.0".to_string(), name: name.to_string(), variables, cells })
+    }
+}
+
+#[derive(Debug, Clone)]
+#[derive(serde::Serialize)]
+struct Dv8Cell {
+    src: usize,
+    #[serde(rename = "dest")]
+    tgt: usize,
+    values: BTreeMap<String, f64>,
+}
+
+impl Dv8Cell {
+    fn new(
+        src: usize,
+        tgt: usize,
+        values: BTreeMap<String, usize>,
+        same_team: Option<bool>,
+    ) -> Self {
+        let mut values: BTreeMap<_, _> = values.into_iter().map(|(k, c)| (k, c as f64)).collect();
+
+        if let Some(same_team) = same_team {
+            values.insert("SameTeam".to_string(), same_team as u8 as f64);
+        }
+
+        Self { src, tgt, values }
+    }
+}
@@ -1,98 +1,869 @@
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::sync::Mutex;
 use std::sync::RwLock;
 
+use anyhow::bail;
+use anyhow::Result;
 use counter::Counter;
 use itertools::Itertools;
+use rayon::iter::Either;
 use rayon::prelude::*;
 
+use crate::core::Annotation;
 use crate::core::Change;
 use crate::core::ChangeKind;
+use crate::core::CommitFile;
 use crate::core::Content;
+use crate::core::ContentId;
+use crate::core::Dep;
+use crate::core::DepKind;
+use crate::core::DepLocation;
+use crate::core::Diagnostic;
+use crate::core::DiagnosticStage;
 use crate::core::Diff;
 use crate::core::Entity;
 use crate::core::EntityDep;
+use crate::core::EntityId;
+use crate::core::EntityKind;
+use crate::core::ExternalDep;
 use crate::core::FileKey;
+use crate::core::FileSet;
+use crate::core::MultiFileSet;
+use crate::core::PartialPosition;
+use crate::core::PartialSpan;
+use crate::core::PseudoCommitId;
+use crate::core::Sha1Hash;
 use crate::core::SimpleEntityId;
+use crate::core::UnresolvedRef;
 use crate::filesystem::FileReader;
 use crate::filesystem::FileSystem;
+use crate::filesystem::MergePolicy;
+use crate::imports::ImportIndex;
 use crate::languages::Lang;
+use crate::manifests;
+use crate::metrics;
+use crate::metrics::CodeMetrics;
 use crate::resolution::ResolverManager;
 use crate::spec::Filespec;
+use crate::tagging;
 use crate::tagging::EntitySet;
+use crate::tagging::Granularity;
+use crate::tagging::Tagger;
+use crate::testcode::TestDetector;
+use crate::worddiff;
+
+/// What to do with files whose content appears under more than one path.
+///
+/// These are usually vendored copies of a library. Left alone, each copy
+/// contributes its own entities and deps, which inflates coupling.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(strum::Display, strum::EnumIs, strum::EnumString, strum::VariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub enum DuplicatePolicy {
+    /// Scan every copy.
+    #[default]
+    Keep,
+
+    /// Only scan the canonical copy. See [FileSet::is_canonical].
+    Canonical,
+
+    /// Scan every copy but log a warning for each group of duplicates.
+    Warn,
+}
+
+/// How the lines of a diff are attributed to entities when extracting changes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(strum::Display, strum::EnumIs, strum::EnumString, strum::VariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ChangeAttribution {
+    /// Each changed line counts for the innermost entity on that line.
+    #[default]
+    Rows,
+
+    /// Each changed line counts for every entity whose bytes changed on that
+    /// line. Slower, since every diff is narrowed down to the changed words
+    /// (see [crate::worddiff]).
+    Bytes,
+}
 
 pub struct Extractor {
     fs: FileSystem,
-    file_level: bool,
+    granularity: Granularity,
+    duplicates: DuplicatePolicy,
+    attribution: ChangeAttribution,
+    merges: MergePolicy,
+    ignore_whitespace: bool,
+    ignore_comments: bool,
+    contain_deps: bool,
+    build_manifests: bool,
+    signature_ids: bool,
+    packages: bool,
+    tests: TestDetector,
     resolver: ResolverManager,
+    scope: Vec<String>,
     entity_sets: RwLock<HashMap<FileKey, EntitySet>>,
+
+    /// The package entities of each file (outermost first). Only filled if
+    /// [Self::set_packages] is set.
+    file_packages: RwLock<HashMap<FileKey, Vec<Entity>>>,
+    reported_duplicates: Mutex<HashSet<ContentId>>,
+    unresolved_refs: Mutex<Vec<UnresolvedRef>>,
+
+    /// Why each file whose entities fell back to the file level did so.
+    tag_failures: Mutex<HashMap<FileKey, String>>,
+
+    /// Why each file that could not be read could not be. Such a file is
+    /// treated as if it were empty.
+    read_failures: Mutex<HashMap<FileKey, String>>,
 }
 
 impl Extractor {
-    pub fn new(fs: FileSystem, file_level: bool) -> Self {
-        Self { fs, file_level, resolver: ResolverManager::empty(), entity_sets: Default::default() }
+    pub fn new(fs: FileSystem, granularity: Granularity) -> Self {
+        Self {
+            fs,
+            granularity,
+            duplicates: DuplicatePolicy::default(),
+            attribution: ChangeAttribution::default(),
+            merges: MergePolicy::default(),
+            ignore_whitespace: false,
+            ignore_comments: false,
+            contain_deps: false,
+            build_manifests: false,
+            signature_ids: false,
+            packages: false,
+            tests: TestDetector::default(),
+            resolver: ResolverManager::empty(),
+            scope: Vec::new(),
+            entity_sets: Default::default(),
+            file_packages: Default::default(),
+            reported_duplicates: Default::default(),
+            unresolved_refs: Default::default(),
+            tag_failures: Default::default(),
+            read_failures: Default::default(),
+        }
     }
 
     pub fn set_resolver(&mut self, resolver: ResolverManager) {
         self.resolver = resolver;
     }
 
+    pub fn set_duplicate_policy(&mut self, duplicates: DuplicatePolicy) {
+        self.duplicates = duplicates;
+    }
+
+    pub fn set_change_attribution(&mut self, attribution: ChangeAttribution) {
+        self.attribution = attribution;
+    }
+
+    pub fn set_merge_policy(&mut self, merges: MergePolicy) {
+        self.merges = merges;
+    }
+
+    /// Leave out changes to whitespace and blank lines when finding changes.
+    pub fn set_ignore_whitespace(&mut self, ignore_whitespace: bool) {
+        self.ignore_whitespace = ignore_whitespace;
+    }
+
+    /// Leave out hunks that only touch the comments of entities (see
+    /// [Entity::comment]) when finding changes.
+    pub fn set_ignore_comment_only_changes(&mut self, ignore_comments: bool) {
+        self.ignore_comments = ignore_comments;
+    }
+
+    /// Also emit a [DepKind::Contain] dep from each entity to its children.
+    pub fn set_contain_deps(&mut self, contain_deps: bool) {
+        self.contain_deps = contain_deps;
+    }
+
+    /// Give each build manifest a module entity. See [crate::manifests].
+    pub fn set_build_manifests(&mut self, build_manifests: bool) {
+        self.build_manifests = build_manifests;
+    }
+
+    /// Include the parameter types of methods and constructors in their
+    /// [SimpleEntityId], so overloads are not conflated.
+    pub fn set_signature_ids(&mut self, signature_ids: bool) {
+        self.signature_ids = signature_ids;
+    }
+
+    /// Put each file entity inside an entity for the package its file declares
+    /// (e.g. `package com.foo;`). See [tagging::to_package_entities].
+    ///
+    /// Only the parent of each file entity changes, not its ID.
+    pub fn set_packages(&mut self, packages: bool) {
+        self.packages = packages;
+    }
+
+    /// Flag test code with this detector. See [crate::testcode].
+    pub fn set_test_detector(&mut self, tests: TestDetector) {
+        self.tests = tests;
+    }
+
+    /// Only resolve deps from files under these directories (or files).
+    ///
+    /// Paths are relative to the root of the project. See
+    /// [Self::narrow_to_scope].
+    pub fn set_scope(&mut self, scope: Vec<String>) {
+        self.scope = scope.into_iter().map(|s| s.trim_matches('/').to_string()).collect();
+    }
+
+    /// Fail if any file could not be read or if its entities fell back to a
+    /// single file-level entity.
+    ///
+    /// Entities fall back when a file has syntax errors or its language has no
+    /// tag query. Files without a known language (e.g. extra files) are always
+    /// file-level, so they are not checked for that. Neither are any files
+    /// when reporting at the file level.
+    pub fn check_entities(&self, spec: &Filespec) -> Result<()> {
+        let files = self.list(spec);
+        self.ensure_entity_sets(files.files().clone());
+        let read_failures = self.read_failures.lock().unwrap();
+        let tag_failures = self.tag_failures.lock().unwrap();
+
+        for (commit_id, file_set) in files.iter().sorted_by_key(|(&c, _)| c) {
+            for f in file_set.iter().sorted() {
+                if let Some(failure) = read_failures.get(f) {
+                    bail!("Failed to read {} at {}: {}", f.filename, commit_id, failure);
+                }
+
+                if let Some(failure) = tag_failures.get(f) {
+                    bail!(
+                        "Failed to extract the entities of {} at {}: {}",
+                        f.filename,
+                        commit_id,
+                        failure
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn extract_entities(&self, spec: &Filespec) -> impl ParallelIterator<Item = Entity> + '_ {
-        let files = self.fs.list(spec);
+        let files = self.list(spec);
         self.ensure_entity_sets(files.files().iter().sorted().cloned().collect());
 
-        files.into_files().into_par_iter().flat_map(|f| {
-            self.entity_sets.read().unwrap().get(&f).unwrap().clone().into_entities_vec()
-        })
+        let packages = self.package_entities(files.files());
+
+        packages.into_par_iter().chain(files.into_files().into_par_iter().flat_map(|f| {
+            let mut entities =
+                self.entity_sets.read().unwrap().get(&f).unwrap().clone().into_entities_vec();
+            self.enclose(&f, &mut entities);
+            entities
+        }))
     }
 
+    /// Find the entities changed by each commit of a [Filespec].
+    ///
+    /// Renames are followed back through the commits (like `git log
+    /// --follow`), so the changes made to a file before it was renamed are
+    /// given under the [SimpleEntityId]s it has after.
     pub fn extract_changes(&self, spec: &Filespec) -> impl ParallelIterator<Item = Change> + '_ {
-        let diffs: Vec<_> = spec
+        let commits = spec
             .commits
-            .par_iter()
+            .iter()
             .filter_map(|c| c.try_as_commit_id())
-            .flat_map(|c| self.fs.diff(c, &spec.pathspec).unwrap())
+            .sorted_by_cached_key(|&c| (Reverse(self.fs.commit_time(c).unwrap()), c))
+            .collect_vec();
+        let mut diffs: Vec<_> = commits
+            .par_iter()
+            .map(|&c| self.fs.diff(c, &spec.pathspec, self.merges, self.ignore_whitespace).unwrap())
             .collect();
+        follow_renames(&mut diffs);
+        let diffs = diffs.into_iter().flatten().collect_vec();
         let files = diffs.iter().flat_map(|d| d.iter_file_keys().cloned()).collect();
         self.ensure_entity_sets(files);
-        diffs.into_par_iter().flat_map(move |d| calc_changes(&self.entity_sets.read().unwrap(), &d))
+        let changes = diffs.into_par_iter().flat_map(move |mut d| {
+            if self.ignore_comments {
+                self.drop_comment_only_hunks(&mut d);
+            }
+
+            let (old_ids, new_ids) = self.count_changed_rows(&d);
+            calc_changes(&d, old_ids, new_ids)
+        });
+
+        match self.merges.is_all_parents() {
+            true => Either::Left(sum_changes(changes.collect()).into_par_iter()),
+            false => Either::Right(changes),
+        }
     }
 
     pub fn extract_deps(&self, spec: &Filespec) -> impl ParallelIterator<Item = EntityDep> + '_ {
-        let files = self.fs.list(spec);
+        let files = match self.scope.is_empty() {
+            true => self.list(spec),
+            false => self.list(spec).map(|f| self.narrow_to_scope(f)),
+        };
         self.ensure_entity_sets(files.files().iter().cloned().collect());
-        self.resolver
-            .resolve(&self.fs, &files)
-            .into_par_iter()
+        let (deps, unresolved) = self.resolver.resolve(&self.fs, &files);
+        let unresolved = unresolved.into_iter().filter(|r| self.in_scope(&r.filename));
+        self.unresolved_refs.lock().unwrap().extend(unresolved);
+        let contain_deps = match self.contain_deps {
+            true => self.find_contain_deps(&files),
+            false => Vec::new(),
+        };
+        deps.into_par_iter()
+            .filter(|d| self.in_scope(&d.src.file_key.filename))
             .map(move |d| d.to_entity_dep(&self.entity_sets.read().unwrap()).unwrap())
             .filter(|d| !d.is_loop())
+            .chain(contain_deps)
     }
 
+    /// Find the imports of things outside of the project.
+    ///
+    /// An import is external if it is not relative and cannot be mapped to a
+    /// file of the same version (see [ImportIndex]). Imports are found with
+    /// the import query of each language, like the imports resolver.
+    pub fn extract_external_deps(&self, spec: &Filespec) -> Vec<ExternalDep> {
+        let files = self.list(spec);
+        self.ensure_entity_sets(files.files().iter().cloned().collect());
+        let entity_sets = self.entity_sets.read().unwrap();
+
+        files
+            .iter()
+            .flat_map(|(&commit_id, file_set)| {
+                let index = ImportIndex::new(file_set);
+                let entity_sets = &entity_sets;
+
+                file_set
+                    .iter()
+                    .filter(|f| self.in_scope(&f.filename))
+                    .filter_map(|f| Some((f, Lang::of(&f.filename)?)))
+                    .filter(|(_, lang)| lang.import_finder().is_some())
+                    .collect_vec()
+                    .into_par_iter()
+                    .flat_map_iter(|(f, lang)| {
+                        let content = self.fs.read(f.content_id).unwrap();
+                        let finder = lang.import_finder().unwrap();
+                        finder
+                            .find(lang, &content)
+                            .into_iter()
+                            .filter(|(_, i)| {
+                                !i.relative && index.resolve(&f.filename, i).is_empty()
+                            })
+                            .filter_map(|(position, i)| {
+                                let src =
+                                    entity_sets[f].find_id(PartialPosition::Whole(position))?;
+                                let symbol = i.symbol(lang);
+                                Some(ExternalDep { src, symbol, row: position.row, commit_id })
+                            })
+                            .collect_vec()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Find the annotations of the entities of a [Filespec]. See
+    /// [crate::annotations].
+    ///
+    /// Nothing is found when reporting at the file level.
+    pub fn extract_annotations(&self, spec: &Filespec) -> Vec<Annotation> {
+        if self.granularity.is_file() {
+            return Vec::new();
+        }
+
+        let files = self.list(spec);
+        self.ensure_entity_sets(files.files().clone());
+        let entity_sets = self.entity_sets.read().unwrap();
+
+        files
+            .into_files()
+            .into_iter()
+            .filter_map(|f| Some((Lang::of(&f.filename)?, f)))
+            .filter(|(lang, _)| lang.annotation_finder().is_some())
+            .collect_vec()
+            .into_par_iter()
+            .flat_map_iter(|(lang, f)| {
+                let content = self.fs.read(f.content_id).unwrap_or_default();
+                let finder = lang.annotation_finder().unwrap();
+                let entity_set = &entity_sets[&f];
+                finder
+                    .find(&content)
+                    .into_iter()
+                    .filter_map(|a| {
+                        let entity_id = entity_set.find_id(PartialPosition::Whole(a.target))?;
+                        Some(Annotation { entity_id, name: a.name, text: a.text, row: a.start.row })
+                    })
+                    .collect_vec()
+            })
+            .collect()
+    }
+
+    /// Fail if any resolver failed as a whole while running
+    /// [Self::extract_deps]. See [ResolverManager::check_failures].
+    pub fn check_resolvers(&self) -> Result<()> {
+        self.resolver.check_failures()
+    }
+
+    /// Take the references that [Self::extract_deps] could not resolve.
+    pub fn take_unresolved_refs(&self) -> Vec<UnresolvedRef> {
+        std::mem::take(&mut self.unresolved_refs.lock().unwrap())
+    }
+
+    /// Take the failures to read or tag the files of a [Filespec] (one per
+    /// version of each file) along with the failures of the resolvers.
+    pub fn take_diagnostics(&self, spec: &Filespec) -> Vec<Diagnostic> {
+        let files = self.list(spec);
+        self.ensure_entity_sets(files.files().clone());
+        let read_failures = self.read_failures.lock().unwrap();
+        let tag_failures = self.tag_failures.lock().unwrap();
+        let stages =
+            [(&read_failures, DiagnosticStage::Read), (&tag_failures, DiagnosticStage::Tag)];
+
+        let mut diagnostics = files
+            .iter()
+            .flat_map(|(&commit_id, file_set)| file_set.iter().map(move |f| (commit_id, f)))
+            .flat_map(|(commit_id, f)| {
+                stages.iter().filter_map(move |(failures, stage)| {
+                    let message = failures.get(f)?.clone();
+                    Some(Diagnostic::new(Some(f.filename.clone()), commit_id, *stage, message))
+                })
+            })
+            .collect_vec();
+
+        diagnostics.extend(self.resolver.take_diagnostics());
+        diagnostics.sort();
+        diagnostics
+    }
+
+    /// Read the content of each file of a [Filespec]. Files that cannot be
+    /// read (e.g. binary files) are left out.
     pub fn extract_contents(&self, spec: &Filespec) -> impl ParallelIterator<Item = Content> + '_ {
         let content_ids: HashSet<_> =
-            self.fs.list(spec).files().iter().map(|f| f.content_id).collect();
-        content_ids.into_par_iter().map(|id| Content::new(id, self.fs.read(id).unwrap()))
+            self.list(spec).files().iter().map(|f| f.content_id).collect();
+        content_ids.into_par_iter().filter_map(|id| Some(Content::new(id, self.fs.read(id).ok()?)))
+    }
+
+    /// Map the ID of every entity of a [Filespec] to its [SimpleEntityId].
+    pub fn simple_ids(&self, spec: &Filespec) -> HashMap<EntityId, SimpleEntityId> {
+        self.flat_map_entity_sets(spec, |s| {
+            s.iter_entities().map(|e| (e.id, e.simple_id)).collect_vec()
+        })
+    }
+
+    /// Map the [SimpleEntityId] of every entity of a [Filespec] to the
+    /// [SimpleEntityId] of its parent (if it has one).
+    pub fn simple_parents(&self, spec: &Filespec) -> HashMap<SimpleEntityId, SimpleEntityId> {
+        self.flat_map_entity_sets(spec, |s| {
+            s.iter_entities()
+                .filter_map(|e| Some((e.simple_id, s.get(e.parent_id?)?.simple_id)))
+                .collect_vec()
+        })
+    }
+
+    /// Map the ID of every entity of a [Filespec] to its path. See
+    /// [EntitySet::path].
+    pub fn entity_paths(&self, spec: &Filespec) -> HashMap<EntityId, String> {
+        self.flat_map_entity_sets(spec, |s| {
+            s.iter_entities().map(|e| (e.id, s.path(e.id).unwrap())).collect_vec()
+        })
+    }
+
+    /// Map the [SimpleEntityId] of every entity of a [Filespec] to its path.
+    ///
+    /// Entities with the same [SimpleEntityId] always have the same path.
+    pub fn simple_paths(&self, spec: &Filespec) -> HashMap<SimpleEntityId, String> {
+        self.flat_map_entity_sets(spec, |s| {
+            s.iter_entities().map(|e| (e.simple_id, s.path(e.id).unwrap())).collect_vec()
+        })
+    }
+
+    /// List the entities of each commit of a [Filespec].
+    pub fn entities_by_commit(&self, spec: &Filespec) -> HashMap<PseudoCommitId, Vec<Entity>> {
+        let files = self.list(spec);
+        self.ensure_entity_sets(files.files().clone());
+        let entity_sets = self.entity_sets.read().unwrap();
+
+        files
+            .iter()
+            .map(|(c, s)| {
+                let mut entities = self.package_entities(s.iter());
+
+                for f in s.iter() {
+                    let start = entities.len();
+                    entities.extend(entity_sets[f].iter_entities().cloned());
+                    self.enclose(f, &mut entities[start..]);
+                }
+
+                (*c, entities)
+            })
+            .collect()
+    }
+
+    /// List the files of each version of a [Filespec].
+    pub fn extract_files(&self, spec: &Filespec) -> Vec<CommitFile> {
+        self.list(spec)
+            .iter()
+            .flat_map(|(&c, s)| {
+                s.iter().map(move |f| CommitFile::new(f.filename.clone(), c, f.content_id))
+            })
+            .sorted()
+            .collect()
+    }
+
+    /// Hash the code of each entity, so that code that has moved within its
+    /// file (and so has a new [EntityId]) can still be recognized.
+    ///
+    /// Packages have no code, so they are left out.
+    pub fn code_hashes<'a, I>(&self, entities: I) -> HashMap<EntityId, Sha1Hash>
+    where
+        I: IntoIterator<Item = &'a Entity>,
+    {
+        entities
+            .into_iter()
+            .filter(|e| !e.kind.is_package())
+            .unique_by(|e| e.id)
+            .into_group_map_by(|e| e.content_id)
+            .into_par_iter()
+            .flat_map_iter(|(content_id, entities)| {
+                let content = self.fs.read(content_id).unwrap_or_default();
+                entities
+                    .into_iter()
+                    .map(|e| {
+                        let code = content.as_bytes().get(e.code.start.byte..e.code.end.byte);
+                        (e.id, Sha1Hash::hash(code.unwrap_or_default()))
+                    })
+                    .collect_vec()
+            })
+            .collect()
+    }
+
+    /// Measure the code of each entity (except packages). See
+    /// [metrics::measure].
+    pub fn code_metrics<'a, I>(&self, entities: I) -> HashMap<EntityId, CodeMetrics>
+    where
+        I: IntoIterator<Item = &'a Entity>,
+    {
+        entities
+            .into_iter()
+            .filter(|e| !e.kind.is_package())
+            .unique_by(|e| e.id)
+            .into_group_map_by(|e| e.content_id)
+            .into_par_iter()
+            .flat_map_iter(|(content_id, entities)| {
+                let content = self.fs.read(content_id).unwrap_or_default();
+                let lang = entities
+                    .iter()
+                    .filter(|e| e.kind == EntityKind::File)
+                    .find_map(|e| Lang::of(&e.name));
+                metrics::measure(lang.map(|l| l.language()), &content, entities)
+            })
+            .collect()
+    }
+
+    /// List the files of a [Filespec] while respecting the [DuplicatePolicy].
+    fn list(&self, spec: &Filespec) -> MultiFileSet {
+        let files = self.fs.list(spec);
+
+        if self.duplicates.is_keep() {
+            return files;
+        }
+
+        for (_, file_set) in files.iter() {
+            for filenames in file_set.iter_duplicates() {
+                self.report_duplicates(file_set, &filenames);
+            }
+        }
+
+        match self.duplicates {
+            DuplicatePolicy::Canonical => files.map(FileSet::without_duplicates),
+            _ => files,
+        }
+    }
+
+    /// Keep only the files in scope and the files that they directly import.
+    ///
+    /// Imports are found the same way as by the imports resolver (see
+    /// [crate::import_resolver]), so this may keep slightly more or fewer
+    /// files than another resolver would need.
+    fn narrow_to_scope(&self, file_set: &FileSet) -> FileSet {
+        let index = ImportIndex::new(file_set);
+        let in_scope = file_set.iter().filter(|f| self.in_scope(&f.filename)).collect_vec();
+
+        let imported: HashSet<String> = in_scope
+            .par_iter()
+            .filter_map(|f| Lang::of(&f.filename).map(|lang| (f, lang)))
+            .flat_map_iter(|(f, lang)| {
+                let content = self.fs.read(f.content_id).unwrap();
+                let imports = match lang.import_finder() {
+                    Some(finder) => finder.find(lang, &content),
+                    None => Vec::new(),
+                };
+                imports
+                    .iter()
+                    .flat_map(|(_, i)| index.resolve(&f.filename, i))
+                    .chain(index.implicit(&f.filename))
+                    .map(|f| f.to_string())
+                    .collect_vec()
+            })
+            .collect();
+
+        log::debug!("Scope has {} file(s) which import {} file(s)", in_scope.len(), imported.len());
+
+        let filter = |f: &&FileKey| self.in_scope(&f.filename) || imported.contains(&f.filename);
+        FileSet::new(file_set.iter().filter(filter).cloned())
+    }
+
+    /// Is this file under one of the paths given to [Self::set_scope]?
+    fn in_scope(&self, filename: &str) -> bool {
+        self.scope.is_empty()
+            || self.scope.iter().any(|s| {
+                s.is_empty()
+                    || filename == s
+                    || (filename.starts_with(s.as_str()) && filename[s.len()..].starts_with('/'))
+            })
+    }
+
+    /// Log a group of duplicate files, but only the first time it is seen.
+    fn report_duplicates(&self, file_set: &FileSet, filenames: &[&str]) {
+        let content_id = file_set.get_content_id(filenames[0]).unwrap();
+
+        if self.reported_duplicates.lock().unwrap().insert(content_id) {
+            let (canonical, copies) = filenames.split_first().unwrap();
+            log::warn!(
+                "Found {} duplicate(s) of {}: {}",
+                copies.len(),
+                canonical,
+                copies.join(", ")
+            );
+        }
+    }
+
+    /// The package entities of `files`, without duplicates.
+    fn package_entities<'a, I>(&self, files: I) -> Vec<Entity>
+    where
+        I: IntoIterator<Item = &'a FileKey>,
+    {
+        let file_packages = self.file_packages.read().unwrap();
+        files
+            .into_iter()
+            .flat_map(|f| file_packages.get(f).into_iter().flatten())
+            .unique_by(|e| e.id)
+            .cloned()
+            .collect()
+    }
+
+    /// Put the file entity among the entities of `file` inside its innermost
+    /// package (if any).
+    fn enclose(&self, file: &FileKey, entities: &mut [Entity]) {
+        let file_packages = self.file_packages.read().unwrap();
+
+        if let Some(package) = file_packages.get(file).and_then(|p| p.last()) {
+            for entity in entities.iter_mut().filter(|e| e.parent_id.is_none()) {
+                entity.parent_id = Some(package.id);
+            }
+        }
+    }
+
+    fn flat_map_entity_sets<T, F, C>(&self, spec: &Filespec, f: F) -> C
+    where
+        F: Fn(&EntitySet) -> Vec<T>,
+        C: FromIterator<T>,
+    {
+        let files = self.list(spec).into_files();
+        self.ensure_entity_sets(files.clone());
+        let entity_sets = self.entity_sets.read().unwrap();
+        files.iter().flat_map(|file| f(&entity_sets[file])).collect()
+    }
+
+    /// Turn the parent of each entity into a [DepKind::Contain] dep from the
+    /// parent to the entity.
+    ///
+    /// The position of the dep is the start of the entity.
+    fn find_contain_deps(&self, files: &MultiFileSet) -> Vec<EntityDep> {
+        let entity_sets = self.entity_sets.read().unwrap();
+
+        files
+            .iter()
+            .flat_map(|(&commit_id, file_set)| {
+                file_set
+                    .iter()
+                    .filter(|f| self.in_scope(&f.filename))
+                    .flat_map(|f| entity_sets[f].iter_entities().map(move |e| (f, e)))
+                    .filter_map(move |(f, e)| {
+                        let position = PartialPosition::Whole(e.location().start);
+                        let location = DepLocation {
+                            src_filename: f.filename.clone(),
+                            tgt_filename: f.filename.clone(),
+                            tgt_position: position,
+                            src_name: None,
+                            tgt_name: None,
+                        };
+                        Some(
+                            Dep::new(e.parent_id?, e.id, DepKind::Contain, position, commit_id)
+                                .with_resolver("contain")
+                                .with_location(location),
+                        )
+                    })
+            })
+            .collect()
     }
 
     fn ensure_entity_sets(&self, files: HashSet<FileKey>) {
         files.into_par_iter().for_each(|f| {
             if !self.entity_sets.read().unwrap().contains_key(&f) {
-                let content = self.fs.read(f.content_id).unwrap();
-                let lang = Lang::of(&f.filename).unwrap();
-                let entity_set = lang.tagger().tag(&f.filename, &content, self.file_level);
+                let content = self.fs.read(f.content_id).unwrap_or_else(|err| {
+                    self.read_failures.lock().unwrap().insert(f.clone(), err.to_string());
+                    String::new()
+                });
+                let file_level = self.granularity.is_file();
+                let mut entity_set = match Lang::of(&f.filename).filter(|_| !file_level) {
+                    Some(lang) => lang
+                        .tagger()
+                        .try_tag(&f.filename, &content, self.signature_ids, self.granularity)
+                        .unwrap_or_else(|err| {
+                            self.tag_failures.lock().unwrap().insert(f.clone(), err.to_string());
+                            Tagger::FileLevel.tag(&f.filename, &content, Granularity::File)
+                        }),
+                    None if self.build_manifests && !file_level => {
+                        manifests::tag(&f.filename, &content).unwrap_or_else(|| {
+                            Tagger::FileLevel.tag(&f.filename, &content, Granularity::File)
+                        })
+                    }
+                    None => Tagger::FileLevel.tag(&f.filename, &content, Granularity::File),
+                };
+                self.tests.mark(&f.filename, &content, &mut entity_set);
+                if self.packages {
+                    let package = Lang::of(&f.filename)
+                        .and_then(|lang| tagging::parse_package(lang.language(), &content));
+                    let entities = package.map(|p| tagging::to_package_entities(&p));
+                    self.file_packages
+                        .write()
+                        .unwrap()
+                        .insert(f.clone(), entities.unwrap_or_default());
+                }
                 self.entity_sets.write().unwrap().insert(f, entity_set);
             }
         })
     }
+
+    /// Remove each hunk of `diff` whose rows (on both sides) are all within the
+    /// comments of entities.
+    fn drop_comment_only_hunks(&self, diff: &mut Diff) {
+        let entity_sets = self.entity_sets.read().unwrap();
+        let comment_rows = |k: &FileKey| {
+            entity_sets[k]
+                .iter_entities()
+                .filter_map(|e| e.comment)
+                .map(|c| match c.end.column == 0 && c.end.row > c.start.row {
+                    // The comment ends with (and includes) a newline
+                    true => (c.start.row, c.end.row),
+                    false => (c.start.row, c.end.row + 1),
+                })
+                .collect_vec()
+        };
+        let old_rows = diff.old.as_ref().map(comment_rows).unwrap_or_default();
+        let new_rows = diff.new.as_ref().map(comment_rows).unwrap_or_default();
+        let in_comments = |span: PartialSpan, rows: &[(usize, usize)]| match span {
+            PartialSpan::Row(start, end) => {
+                (start..end).all(|r| rows.iter().any(|&(s, e)| s <= r && r < e))
+            }
+            PartialSpan::Whole(_) => false,
+        };
+
+        diff.hunks.retain(|h| !(in_comments(h.old, &old_rows) && in_comments(h.new, &new_rows)));
+    }
+
+    /// Count the changed rows of each entity on both sides of a diff.
+    ///
+    /// A side is None if the file does not exist there.
+    fn count_changed_rows(
+        &self,
+        diff: &Diff,
+    ) -> (Option<Counter<SimpleEntityId>>, Option<Counter<SimpleEntityId>>) {
+        let entity_sets = self.entity_sets.read().unwrap();
+        let old_entity_set = diff.old.as_ref().map(|k| entity_sets.get(k).unwrap());
+        let new_entity_set = diff.new.as_ref().map(|k| entity_sets.get(k).unwrap());
+
+        if self.attribution.is_rows() {
+            let old_ids = old_entity_set.map(|s| s.count_simple_ids(diff.iter_old_spans()));
+            let new_ids = new_entity_set.map(|s| s.count_simple_ids(diff.iter_new_spans()));
+            return (old_ids, new_ids);
+        }
+
+        let read = |k: &FileKey| self.fs.read(k.content_id).unwrap_or_default();
+        let old = diff.old.as_ref().map(read).unwrap_or_default();
+        let new = diff.new.as_ref().map(read).unwrap_or_default();
+        let (old_spans, new_spans) = worddiff::changed_spans(&diff.hunks, &old, &new);
+
+        let old_ids = old_entity_set.map(|s| s.count_simple_ids_per_row(old_spans));
+        let new_ids = new_entity_set.map(|s| s.count_simple_ids_per_row(new_spans));
+        (old_ids, new_ids)
+    }
 }
 
-fn calc_changes(entity_sets: &HashMap<FileKey, EntitySet>, diff: &Diff) -> Vec<Change> {
-    let old_entity_set = diff.old.as_ref().map(|k| entity_sets.get(&k).unwrap());
-    let new_entity_set = diff.new.as_ref().map(|k| entity_sets.get(&k).unwrap());
+/// Give each file of `diffs` the name it has in the newest commit, following
+/// renames back through older commits.
+///
+/// The diffs of each commit must be together, newest commit first. Both sides
+/// of a renamed file take the new name, so its entities are modified rather
+/// than deleted and added.
+fn follow_renames(diffs: &mut [Vec<Diff>]) {
+    // The newest name of each file renamed by a newer commit, by its name as of
+    // the commit being visited
+    let mut names: HashMap<String, String> = HashMap::new();
 
-    let old_ids = old_entity_set.map(|s| s.count_simple_ids(diff.iter_old_spans()));
-    let new_ids = new_entity_set.map(|s| s.count_simple_ids(diff.iter_new_spans()));
+    for commit_diffs in diffs {
+        let mut created = Vec::new();
+        let mut renamed = Vec::new();
 
+        for diff in commit_diffs.iter_mut() {
+            let old_name = diff.old.as_ref().map(|k| k.filename.clone());
+            let new_name = diff.new.as_ref().map(|k| k.filename.clone());
+            let newest = |n: &String| names.get(n).unwrap_or(n).clone();
+
+            let name = match (&old_name, &new_name) {
+                (_, Some(new_name)) => newest(new_name),
+                (Some(old_name), None) => newest(old_name),
+                (None, None) => continue,
+            };
+
+            // Before this commit, there was no file here (or it was another)
+            if let Some(new_name) = new_name.filter(|n| old_name.as_ref() != Some(n)) {
+                created.push(new_name);
+
+                if let Some(old_name) = old_name {
+                    renamed.push((old_name, name.clone()));
+                }
+            }
+
+            diff.iter_file_keys_mut().for_each(|k| k.filename = name.clone());
+        }
+
+        for filename in created {
+            names.remove(&filename);
+        }
+
+        names.extend(renamed);
+    }
+}
+
+/// Add up the changes to each entity in each commit. An entity may be changed
+/// more than once by a merge commit that is diffed against each parent.
+fn sum_changes(changes: Vec<Change>) -> Vec<Change> {
+    changes
+        .into_iter()
+        .into_grouping_map_by(|c| (c.simple_id, c.commit_id))
+        .fold_first(|mut sum, _, c| {
+            if sum.kind != c.kind {
+                sum.kind = ChangeKind::Modified;
+            }
+            sum.adds += c.adds;
+            sum.dels += c.dels;
+            sum
+        })
+        .into_values()
+        .collect()
+}
+
+fn calc_changes(
+    diff: &Diff,
+    old_ids: Option<Counter<SimpleEntityId>>,
+    new_ids: Option<Counter<SimpleEntityId>>,
+) -> Vec<Change> {
     let mut ids = HashSet::new();
     ids.extend(old_ids.iter().flat_map(|x| x.keys()));
     ids.extend(new_ids.iter().flat_map(|x| x.keys()));
@@ -116,8 +887,6 @@ fn calc_changes(entity_sets: &HashMap<FileKey, EntitySet>, diff: &Diff) -> Vec<C
     let new_counts: Counter<SimpleEntityId> = new_ids.into_iter().flatten().collect();
 
     ids.iter()
-        .map(|id| {
-            Change::new(*id, diff.commit_id, change_kinds[id], old_counts[id], new_counts[id])
-        })
+        .map(|id| Change::new(*id, diff, change_kinds[id], old_counts[id], new_counts[id]))
         .collect()
 }
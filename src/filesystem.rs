@@ -1,5 +1,5 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::collections::HashSet;
 use std::fmt::Debug;
 use std::fs::File;
 use std::io::Read;
@@ -16,6 +16,7 @@ use itertools::Itertools;
 use rayon::prelude::*;
 use walkdir::WalkDir;
 
+use crate::core::Commit;
 use crate::core::CommitId;
 use crate::core::ContentId;
 use crate::core::Diff;
@@ -27,6 +28,22 @@ use crate::core::PseudoCommitId;
 use crate::spec::Filespec;
 use crate::spec::Pathspec;
 
+/// How commits with more than one parent are diffed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(strum::Display, strum::EnumIs, strum::EnumString, strum::VariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub enum MergePolicy {
+    /// Diff against the first parent only (like `git log --first-parent`).
+    FirstParent,
+
+    /// Diff against each parent (like `git log -m`).
+    AllParents,
+
+    /// Leave merge commits out.
+    #[default]
+    Skip,
+}
+
 /// The central way to interact with the filesystem inside Neodepends.
 #[derive(Debug, Clone)]
 pub struct FileSystem {
@@ -87,6 +104,36 @@ impl FileSystem {
         }
     }
 
+    /// Attempt to parse a revspec as one or more [PseudoCommitId]s.
+    ///
+    /// Unlike [Self::parse_as_commit], this revspec may also be a range (e.g.
+    /// `v1.0..v2.0` or `A...B`), which is walked like `git log` would. The
+    /// commits of a range are returned newest first.
+    pub fn parse_as_commits(&self, revspec: &str) -> Result<Vec<PseudoCommitId>> {
+        if revspec == "WORKDIR" || !revspec.contains("..") {
+            return Ok(vec![self.parse_as_commit(revspec)?]);
+        }
+
+        let repo = self.repo.as_ref().context("cannot parse range in disk-only mode")?;
+        let repo = repo.repo.lock().unwrap();
+        let range = repo.revparse(revspec)?;
+        let from = range.from().context("range has no start")?.peel_to_commit()?.id();
+        let to = range.to().context("range has no end")?.peel_to_commit()?.id();
+
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+        walk.push(to)?;
+
+        if range.mode().contains(git2::RevparseMode::MERGE_BASE) {
+            walk.push(from)?;
+            walk.hide(repo.merge_base(from, to)?)?;
+        } else {
+            walk.hide(from)?;
+        }
+
+        walk.map(|id| Ok(PseudoCommitId::CommitId(id?.into()))).collect()
+    }
+
     /// Walk the commits and files reachable from the given [Filespec],
     /// returning the results as a [MultiFileSet].
     pub fn list(&self, spec: &Filespec) -> MultiFileSet {
@@ -112,18 +159,111 @@ impl FileSystem {
         MultiFileSet::new(map)
     }
 
+    /// Read the metadata of a commit.
+    pub fn commit(&self, commit_id: CommitId) -> Result<Commit> {
+        let repo = self.repo.as_ref().context("cannot read commit in disk-only mode")?;
+        let repo = repo.repo.lock().unwrap();
+        let commit = repo.find_commit(commit_id.into())?;
+        let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+        let (author, committer) = (commit.author(), commit.committer());
+
+        Ok(Commit {
+            commit_id,
+            author_name: text(author.name_bytes()),
+            author_email: text(author.email_bytes()),
+            author_date: author.when().seconds(),
+            committer_name: text(committer.name_bytes()),
+            committer_email: text(committer.email_bytes()),
+            commit_date: committer.when().seconds(),
+            subject: text(commit.summary_bytes().unwrap_or_default()),
+            parent_ids: commit.parent_ids().map(|id| CommitId::from(id).to_string()).join(" "),
+            ref_name: None,
+        })
+    }
+
+    /// Walk the commits reachable from the refs whose full names (e.g.
+    /// `refs/heads/main`) match any of `globs`, like `git log --glob` would.
+    ///
+    /// Each commit is returned once, with the first ref (by name) that reaches
+    /// it. Refs that do not point to a commit are ignored.
+    pub fn walk_refs(&self, globs: &[String]) -> Result<Vec<(String, CommitId)>> {
+        let repo = self.repo.as_ref().context("cannot walk refs in disk-only mode")?;
+        let repo = repo.repo.lock().unwrap();
+        let mut tips = BTreeMap::new();
+
+        for glob in globs {
+            for reference in repo.references_glob(glob)? {
+                let reference = reference?;
+
+                if let (Some(name), Ok(commit)) = (reference.name(), reference.peel_to_commit()) {
+                    tips.insert(name.to_string(), commit.id());
+                }
+            }
+        }
+
+        let mut walked = Vec::new();
+        let mut seen = Vec::new();
+
+        for (name, tip) in tips {
+            let mut walk = repo.revwalk()?;
+            walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+            walk.push(tip)?;
+
+            // Anything reachable from an earlier ref was already walked
+            for &id in &seen {
+                walk.hide(id)?;
+            }
+
+            for id in walk {
+                walked.push((name.clone(), id?.into()));
+            }
+
+            seen.push(tip);
+        }
+
+        Ok(walked)
+    }
+
+    /// Get the time of a commit in seconds since the Unix epoch.
+    pub fn commit_time(&self, commit_id: CommitId) -> Result<i64> {
+        let repo = self.repo.as_ref().context("cannot read commit in disk-only mode")?;
+        let repo = repo.repo.lock().unwrap();
+        let time = repo.find_commit(commit_id.into())?.time();
+        Ok(time.seconds())
+    }
+
     /// Compares the given commit against its parent and produces a vec of
     /// [Diff]s.
     ///
-    /// One Diff per touched file.
-    pub fn diff(&self, commit_id: CommitId, pathspec: &Pathspec) -> Result<Vec<Diff>> {
+    /// One Diff per touched file (and per parent, if a merge commit is diffed
+    /// against each of its parents). A renamed file is a single Diff whose old
+    /// and new [FileKey]s have different filenames. If `ignore_whitespace`,
+    /// changes to whitespace and blank lines are left out (like `git diff -w
+    /// --ignore-blank-lines`).
+    pub fn diff(
+        &self,
+        commit_id: CommitId,
+        pathspec: &Pathspec,
+        merges: MergePolicy,
+        ignore_whitespace: bool,
+    ) -> Result<Vec<Diff>> {
         if let Some(repo) = &self.repo {
-            repo.diff(commit_id, pathspec)
+            repo.diff(commit_id, pathspec, merges, ignore_whitespace)
         } else {
             bail!("attempted to diff while in disk-only mode")
         }
     }
 
+    /// Get the size of a file in bytes, without reading it.
+    ///
+    /// Like [Self::read_buf], looks in the git repository before the disk.
+    pub fn size(&self, content_id: ContentId) -> Result<usize> {
+        match self.repo.as_ref().map(|r| r.size(content_id)) {
+            Some(Ok(size)) => Ok(size),
+            _ => self.disk.size(content_id),
+        }
+    }
+
     /// Read the contents of a file as a UTF-8 String.
     fn read_to_string(&self, content_id: ContentId) -> Result<String> {
         String::from_utf8(self.read_to_vec(content_id)?).context("invalid UTF-8")
@@ -155,12 +295,22 @@ impl FileSystem {
 /// not explicitly depend on FileSystem.
 pub trait FileReader: Send + Sync {
     fn read(&self, content_id: ContentId) -> Result<String>;
+
+    /// Find a file on disk whose content is exactly that of `file`.
+    ///
+    /// Returns [None] if the file only exists in the git repository or if the
+    /// copy in the working directory has different content.
+    fn path_on_disk(&self, file: &FileKey) -> Option<PathBuf>;
 }
 
 impl FileReader for FileSystem {
     fn read(&self, content_id: ContentId) -> Result<String> {
         self.read_to_string(content_id)
     }
+
+    fn path_on_disk(&self, file: &FileKey) -> Option<PathBuf> {
+        self.disk.path_on_disk(file)
+    }
 }
 
 /// A wrapper around [`git2::Repository`].
@@ -216,6 +366,12 @@ impl Repository {
         Ok(FileSet::new(walk_commits(self.repo.lock().unwrap(), vec![commit], pathspec)?))
     }
 
+    /// Get the size of a blob from its header in the object database.
+    fn size<B: Into<git2::Oid>>(&self, blob_id: B) -> Result<usize> {
+        let (size, _) = self.repo.lock().unwrap().odb()?.read_header(blob_id.into())?;
+        Ok(size)
+    }
+
     /// Read the contents of a blob into the provided buffer.
     fn read_buf<B: Into<git2::Oid>>(&self, blob_id: B, buf: &mut Vec<u8>) -> Result<()> {
         buf.extend_from_slice(self.repo.lock().unwrap().find_blob(blob_id.into())?.content());
@@ -223,11 +379,18 @@ impl Repository {
     }
 
     /// Collect all [FileKey]s that changed between this commit and its parent.
-    fn diff<C>(&self, commit_id: C, pathspec: &Pathspec) -> Result<Vec<Diff>>
+    fn diff<C>(
+        &self,
+        commit_id: C,
+        pathspec: &Pathspec,
+        merges: MergePolicy,
+        ignore_whitespace: bool,
+    ) -> Result<Vec<Diff>>
     where
         C: Into<git2::Oid>,
     {
-        diff_with_parent(self.repo.lock().unwrap(), commit_id, pathspec)
+        let repo = self.repo.lock().unwrap();
+        diff_with_parents(repo, commit_id, pathspec, merges, ignore_whitespace)
     }
 }
 
@@ -263,6 +426,24 @@ impl Disk {
         self.read_buf_by_filename(filename, buf)
     }
 
+    fn size(&self, content_id: ContentId) -> Result<usize> {
+        let filename = self
+            .file_set
+            .get_filenames(content_id)
+            .next()
+            .context("could not find file with matching id")?;
+        Ok(std::fs::metadata(self.root.join(filename))?.len() as usize)
+    }
+
+    fn path_on_disk(&self, file: &FileKey) -> Option<PathBuf> {
+        match self.file_set.get_content_id(&file.filename) {
+            Some(content_id) if content_id == file.content_id => {
+                Some(self.root.join(&file.filename))
+            }
+            _ => None,
+        }
+    }
+
     fn read_buf_by_filename<P: AsRef<Path>>(&self, filename: P, buf: &mut Vec<u8>) -> Result<()> {
         File::open(self.root.join(filename))?.read_to_end(buf)?;
         Ok(())
@@ -306,7 +487,6 @@ where
     I: IntoIterator<Item = C>,
 {
     let mut keys = Vec::new();
-    let mut visited = HashSet::new();
 
     // TODO: Allow None to be passed in for commit_id, then read from the working
     // tree instead. This would let us respect the .gitignore rules. libgit2 doesn't
@@ -316,12 +496,13 @@ where
     for id in commit_ids {
         let commit = repo.find_commit(id.into())?;
 
+        // Identical blobs (and trees) may appear under several paths, so every entry
+        // is visited. See [crate::extraction::DuplicatePolicy].
         commit.tree()?.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
-            if visited.contains(&entry.id()) {
-                return git2::TreeWalkResult::Skip;
+            if entry.kind() != Some(git2::ObjectType::Blob) {
+                return git2::TreeWalkResult::Ok;
             }
 
-            visited.insert(entry.id());
             let path = dir.to_string() + entry.name().unwrap();
 
             // TODO: Consider using `.matches_tree` of `git2::Pathspec` for potential
@@ -337,39 +518,74 @@ where
     Ok(keys)
 }
 
-fn diff_with_parent<R, C>(repo: R, commit_id: C, pathspec: &Pathspec) -> Result<Vec<Diff>>
+fn diff_with_parents<R, C>(
+    repo: R,
+    commit_id: C,
+    pathspec: &Pathspec,
+    merges: MergePolicy,
+    ignore_whitespace: bool,
+) -> Result<Vec<Diff>>
 where
     R: Deref<Target = git2::Repository>,
     C: Into<git2::Oid>,
 {
     let commit_id: git2::Oid = commit_id.into();
     let commit = repo.find_commit(commit_id)?;
-    let parents = commit.parents().collect_vec();
     let new_tree = commit.tree()?;
-
     let mut opts = git2::DiffOptions::new();
     opts.ignore_filemode(true);
+    opts.ignore_whitespace(ignore_whitespace);
+    opts.ignore_blank_lines(ignore_whitespace);
     opts.context_lines(0);
 
-    let diff = match parents.len() {
-        0 => repo.diff_tree_to_tree(None, Some(&new_tree), Some(&mut opts)),
-        1 => {
-            let parent = parents.get(0).unwrap();
-            let old_tree = parent.tree()?;
-            repo.diff_tree_to_tree(Some(&old_tree), Some(&new_tree), Some(&mut opts))
-        }
-        _ => return Ok(Vec::new()),
-    }?;
+    let parents = match (commit.parent_count(), merges) {
+        (0, _) => return diff_trees(&repo, &commit, None, &new_tree, pathspec, &mut opts),
+        (1, _) | (_, MergePolicy::FirstParent) => vec![commit.parent(0)?],
+        (_, MergePolicy::AllParents) => commit.parents().collect_vec(),
+        (_, MergePolicy::Skip) => return Ok(Vec::new()),
+    };
+
+    let mut diffs = Vec::new();
+
+    for parent in parents {
+        let old_tree = parent.tree()?;
+        diffs.extend(diff_trees(&repo, &commit, Some(&old_tree), &new_tree, pathspec, &mut opts)?);
+    }
+
+    diffs.sort();
+    Ok(diffs)
+}
+
+fn diff_trees(
+    repo: &git2::Repository,
+    commit: &git2::Commit,
+    old_tree: Option<&git2::Tree>,
+    new_tree: &git2::Tree,
+    pathspec: &Pathspec,
+    opts: &mut git2::DiffOptions,
+) -> Result<Vec<Diff>> {
+    let mut diff = repo.diff_tree_to_tree(old_tree, Some(new_tree), Some(opts))?;
+
+    diff.find_similar(Some(git2::DiffFindOptions::new().renames(true)))?;
 
     let mut diffs = HashMap::new();
 
+    // A file that was renamed without being changed has no hunks
+    for delta in diff.deltas().filter(|d| d.status() == git2::Delta::Renamed) {
+        let keys = to_file_keys(&delta, pathspec);
+
+        if keys.0.is_some() || keys.1.is_some() {
+            diffs.entry(keys).or_insert(Vec::new());
+        }
+    }
+
     diff.foreach(
         &mut |_, _| true,
         None,
         Some(&mut |delta, hunk| {
-            let filename = diff_delta_filename(&delta);
+            let (old, new) = to_file_keys(&delta, pathspec);
 
-            if !pathspec.matches(filename) {
+            if old.is_none() && new.is_none() {
                 return true;
             }
 
@@ -377,37 +593,45 @@ where
                 git2::Delta::Added => (),
                 git2::Delta::Deleted => (),
                 git2::Delta::Modified => (),
+                git2::Delta::Renamed => (),
                 _ => panic!("unsupported diff status: {:?}", &delta.status()),
             };
 
-            let old = to_file_key(filename, delta.old_file().id());
-            let new = to_file_key(filename, delta.new_file().id());
             diffs.entry((old, new)).or_insert(Vec::new()).push(Hunk::from_git(&hunk));
             true
         }),
         None,
     )?;
 
-    Ok(diffs.into_iter().map(|((x, y), z)| Diff::new(commit_id.into(), x, y, z)).sorted().collect())
+    let author = commit.author();
+    let name = String::from_utf8_lossy(author.name_bytes());
+    let email = String::from_utf8_lossy(author.email_bytes());
+
+    Ok(diffs
+        .into_iter()
+        .map(|((x, y), z)| Diff::new(commit.id().into(), x, y, z))
+        .map(|d| d.with_author(name.to_string(), email.to_string()))
+        .sorted()
+        .collect())
 }
 
-fn diff_delta_filename<'a>(diff_delta: &'a git2::DiffDelta) -> &'a str {
-    let old_path = diff_delta.old_file().path();
-    let new_path = diff_delta.new_file().path();
-
-    let path = match (old_path, new_path) {
-        (None, None) => panic!("expected at least one side of diff to be non-empty"),
-        (None, Some(path)) => path,
-        (Some(path), None) => path,
-        (Some(old_path), Some(new_path)) => {
-            if old_path != new_path {
-                panic!("expected no renames or moves");
-            }
-            old_path
-        }
+/// The [FileKey]s of the old and new sides of a delta, each under its own
+/// path. A side is None if it is empty (like the old side of an added file) or
+/// does not match the pathspec, so a file renamed into (or out of) the
+/// pathspec is added (or deleted).
+///
+/// The pathspec is only applied here (rather than also given to git) so that
+/// paths are matched the same way as when listing files.
+fn to_file_keys(
+    delta: &git2::DiffDelta,
+    pathspec: &Pathspec,
+) -> (Option<FileKey>, Option<FileKey>) {
+    let to_side = |file: git2::DiffFile| {
+        let filename = file.path()?.to_str()?;
+        to_file_key(filename, file.id()).filter(|_| pathspec.matches(filename))
     };
 
-    path.to_str().unwrap()
+    (to_side(delta.old_file()), to_side(delta.new_file()))
 }
 
 fn to_file_key(filename: &str, oid: git2::Oid) -> Option<FileKey> {
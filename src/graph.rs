@@ -0,0 +1,374 @@
+//! Render entities and deps as a graph for visualization tools.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use itertools::Itertools;
+
+use crate::core::DepKind;
+use crate::core::Entity;
+use crate::core::EntityDep;
+use crate::core::EntityId;
+use crate::core::EntityKind;
+use crate::taxonomy::Taxonomy;
+
+/// Render a GraphML document where nodes are entities and edges are deps.
+///
+/// Deps with an endpoint that is not among `entities` are skipped. See
+/// http://graphml.graphdrawing.org/.
+pub fn graphml(entities: &[Entity], deps: &[EntityDep], taxonomy: &Taxonomy) -> String {
+    let ids: HashSet<_> = entities.iter().map(|e| e.id).collect();
+    let mut text = String::new();
+
+    text.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    text.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+    text.push_str(GRAPHML_KEYS);
+    text.push_str("  <graph id=\"G\" edgedefault=\"directed\">\n");
+
+    for entity in entities {
+        writeln!(text, "    <node id=\"{}\">", entity.id.0.to_string()).unwrap();
+        write_data(&mut text, "label", &entity.name);
+        write_data(&mut text, "name", &entity.name);
+        write_data(&mut text, "kind", entity.kind.as_ref());
+
+        if let Some(parent_id) = entity.parent_id {
+            write_data(&mut text, "parent_id", &parent_id.0.to_string());
+        }
+
+        text.push_str("    </node>\n");
+    }
+
+    for (i, dep) in deps.iter().filter(|d| ids.contains(&d.src) && ids.contains(&d.tgt)).enumerate()
+    {
+        let (src, tgt) = (dep.src.0.to_string(), dep.tgt.0.to_string());
+        writeln!(text, "    <edge id=\"e{}\" source=\"{}\" target=\"{}\">", i, src, tgt).unwrap();
+        write_data(&mut text, "dep_kind", taxonomy.label(dep.kind));
+        write_data(&mut text, "row", &dep.position.row().to_string());
+        text.push_str("    </edge>\n");
+    }
+
+    text.push_str("  </graph>\n");
+    text.push_str("</graphml>\n");
+    text
+}
+
+/// Render a Graphviz DOT document where nodes are entities and edges are deps.
+///
+/// Entities are grouped into a cluster per file. If every entity is a file
+/// (e.g. with `--file-level`), then files are instead grouped into a cluster
+/// per directory. Parallel deps between the same pair of entities are merged
+/// into a single edge labeled with each distinct kind.
+pub fn dot(entities: &[Entity], deps: &[EntityDep], taxonomy: &Taxonomy) -> String {
+    let by_id: HashMap<_, _> = entities.iter().map(|e| (e.id, e)).collect();
+    let file_level = entities.iter().all(|e| e.kind.is_file() || e.kind.is_package());
+
+    let clusters = entities.iter().into_group_map_by(|e| match file_level {
+        true => dirname(&e.name).to_string(),
+        false => find_file(&by_id, e).name.clone(),
+    });
+
+    let mut text = String::new();
+    text.push_str("digraph G {\n");
+    text.push_str("  node [shape=box];\n");
+
+    for (i, (label, members)) in clusters.into_iter().sorted().enumerate() {
+        writeln!(text, "  subgraph cluster_{} {{", i).unwrap();
+        writeln!(text, "    label={};", quote(&label)).unwrap();
+
+        for entity in members {
+            let id = quote(&entity.id.0.to_string());
+            let (name, kind) = (quote(&entity.name), quote(entity.kind.as_ref()));
+            writeln!(text, "    {} [label={}, tooltip={}];", id, name, kind).unwrap();
+        }
+
+        text.push_str("  }\n");
+    }
+
+    let edges = deps
+        .iter()
+        .filter(|d| by_id.contains_key(&d.src) && by_id.contains_key(&d.tgt))
+        .map(|d| ((d.src, d.tgt), taxonomy.label(d.kind)))
+        .into_group_map();
+
+    for ((src, tgt), kinds) in edges.into_iter().sorted_by_key(|(k, _)| *k) {
+        let label = kinds.into_iter().unique().sorted().join(", ");
+        let (src, tgt) = (quote(&src.0.to_string()), quote(&tgt.0.to_string()));
+        writeln!(text, "  {} -> {} [label={}];", src, tgt, quote(&label)).unwrap();
+    }
+
+    text.push_str("}\n");
+    text
+}
+
+/// Render a Structurizr DSL workspace where containers are modules and
+/// relationships are the deps between them.
+///
+/// A file belongs to the module of the nearest enclosing directory that holds a
+/// build file (e.g. pom.xml, when scanned with `--include-extra-files`), or
+/// else to the module of its own directory. Deps within a module are left out
+/// and the rest are merged into a single relationship per pair of modules,
+/// described by each distinct kind. See https://docs.structurizr.com/dsl.
+pub fn structurizr(
+    name: &str,
+    entities: &[Entity],
+    deps: &[EntityDep],
+    taxonomy: &Taxonomy,
+) -> String {
+    let by_id: HashMap<_, _> = entities.iter().map(|e| (e.id, e)).collect();
+    let files = entities.iter().filter(|e| e.kind.is_file()).map(|e| e.name.as_str());
+    let build_dirs: HashSet<_> =
+        files.clone().filter(|f| is_build_file(basename(f))).map(dirname).collect();
+
+    let modules: HashMap<_, _> = files.map(|f| (f, find_module(&build_dirs, f))).collect();
+    let names = modules.values().copied().unique().sorted().collect_vec();
+    let indices: HashMap<_, _> = names.iter().enumerate().map(|(i, m)| (*m, i)).collect();
+    let module_of = |id| modules.get(find_file(&by_id, by_id.get(&id)?).name.as_str());
+
+    let mut text = String::new();
+    writeln!(text, "workspace {} {{", quote(name)).unwrap();
+    text.push_str("  model {\n");
+    writeln!(text, "    system = softwareSystem {} {{", quote(name)).unwrap();
+
+    for (i, module) in names.iter().enumerate() {
+        let count = modules.values().filter(|m| *m == module).count();
+        let description = format!("{} file{}", count, if count == 1 { "" } else { "s" });
+        writeln!(text, "      m{} = container {} {}", i, quote(module), quote(&description))
+            .unwrap();
+    }
+
+    text.push_str("    }\n");
+
+    let relationships = deps
+        .iter()
+        .filter_map(|d| Some(((indices[module_of(d.src)?], indices[module_of(d.tgt)?]), d)))
+        .filter(|((src, tgt), _)| src != tgt)
+        .map(|(k, d)| (k, taxonomy.label(d.kind)))
+        .into_group_map();
+
+    for ((src, tgt), kinds) in relationships.into_iter().sorted_by_key(|(k, _)| *k) {
+        let label = kinds.into_iter().unique().sorted().join(", ");
+        writeln!(text, "    m{} -> m{} {}", src, tgt, quote(&label)).unwrap();
+    }
+
+    text.push_str("  }\n");
+    text.push_str("  views {\n");
+    text.push_str("    container system {\n");
+    text.push_str("      include *\n");
+    text.push_str("      autoLayout\n");
+    text.push_str("    }\n");
+    text.push_str("  }\n");
+    text.push_str("}\n");
+    text
+}
+
+/// Render a PlantUML class diagram of the types in files under any of the paths
+/// in `scope` (or every file if `scope` is empty).
+///
+/// Each class, interface, enum, record, and annotation is drawn with its
+/// fields and methods. Deps are lifted to the types that enclose their
+/// endpoints, so a call between two methods becomes a dependency between their
+/// classes. Extend and implement deps are drawn as generalizations and
+/// realizations, while every other kind is drawn as a plain dependency (unless
+/// the pair is already related). See https://plantuml.com/class-diagram.
+pub fn plantuml(entities: &[Entity], deps: &[EntityDep], scope: &[String]) -> String {
+    let by_id: HashMap<_, _> = entities.iter().map(|e| (e.id, e)).collect();
+    let in_scope = |e: &Entity| {
+        let filename = &find_file(&by_id, e).name;
+        scope.is_empty() || scope.iter().any(|s| is_under(filename, s))
+    };
+
+    let types = entities
+        .iter()
+        .filter(|e| is_type(e) && in_scope(e))
+        .sorted_by_key(|e| (&find_file(&by_id, e).name, e.code.start.byte))
+        .collect_vec();
+    let aliases: HashMap<_, _> =
+        types.iter().enumerate().map(|(i, e)| (e.id, format!("c{}", i))).collect();
+    let members = entities
+        .iter()
+        .filter(|e| e.parent_id.is_some_and(|p| aliases.contains_key(&p)))
+        .sorted_by_key(|e| e.code.start.byte)
+        .into_group_map_by(|e| e.parent_id.unwrap());
+
+    let mut text = String::new();
+    text.push_str("@startuml\n");
+
+    for entity in &types {
+        let (keyword, stereotype) = match entity.kind {
+            EntityKind::Annotation => ("annotation", ""),
+            EntityKind::Enum => ("enum", ""),
+            EntityKind::Interface => ("interface", ""),
+            EntityKind::Record => ("class", " <<record>>"),
+            _ => ("class", ""),
+        };
+
+        let name = quote(&type_name(&by_id, entity));
+        write!(text, "{} {} as {}{}", keyword, name, aliases[&entity.id], stereotype).unwrap();
+
+        let lines = members
+            .get(&entity.id)
+            .into_iter()
+            .flatten()
+            .filter_map(|m| match m.kind {
+                EntityKind::Field => Some(m.name.clone()),
+                EntityKind::Method | EntityKind::Constructor => Some(format!("{}()", m.name)),
+                _ => None,
+            })
+            .unique()
+            .collect_vec();
+
+        if lines.is_empty() {
+            text.push('\n');
+        } else {
+            text.push_str(" {\n");
+            lines.iter().for_each(|l| writeln!(text, "  {}", l).unwrap());
+            text.push_str("}\n");
+        }
+    }
+
+    let find_type = |id| {
+        let mut curr = by_id.get(&id).copied();
+
+        while let Some(entity) = curr.filter(|e| !aliases.contains_key(&e.id)) {
+            curr = entity.parent_id.and_then(|id| by_id.get(&id).copied());
+        }
+
+        Some(&aliases[&curr?.id])
+    };
+
+    let relations = deps
+        .iter()
+        .filter_map(|d| Some((find_type(d.src)?, find_type(d.tgt)?, d.kind)))
+        .filter(|(src, tgt, _)| src != tgt)
+        .map(|(src, tgt, kind)| ((src, tgt), kind))
+        .into_group_map();
+
+    for ((src, tgt), kinds) in relations.into_iter().sorted() {
+        if kinds.contains(&DepKind::Extend) {
+            writeln!(text, "{} --|> {}", src, tgt).unwrap();
+        }
+
+        if kinds.contains(&DepKind::Implement) {
+            writeln!(text, "{} ..|> {}", src, tgt).unwrap();
+        }
+
+        if kinds.iter().all(|k| !matches!(k, DepKind::Extend | DepKind::Implement)) {
+            writeln!(text, "{} ..> {}", src, tgt).unwrap();
+        }
+    }
+
+    text.push_str("@enduml\n");
+    text
+}
+
+fn is_type(entity: &Entity) -> bool {
+    entity.kind.is_type()
+}
+
+fn is_under(filename: &str, dir: &str) -> bool {
+    let dir = dir.trim_end_matches('/');
+    dir.is_empty() || filename == dir || filename.starts_with(&format!("{}/", dir))
+}
+
+/// The names of a type and the types that enclose it joined by dots.
+fn type_name(by_id: &HashMap<EntityId, &Entity>, entity: &Entity) -> String {
+    let mut names = vec![entity.name.as_str()];
+    let mut curr = entity;
+
+    while let Some(parent) = curr.parent_id.and_then(|id| by_id.get(&id)).filter(|e| is_type(e)) {
+        names.push(&parent.name);
+        curr = parent;
+    }
+
+    names.into_iter().rev().join(".")
+}
+
+/// Find the module of a file given the directories that hold a build file.
+fn find_module<'a>(build_dirs: &HashSet<&'a str>, filename: &'a str) -> &'a str {
+    let mut dir = dirname(filename);
+
+    while !build_dirs.contains(dir) {
+        match dir.rsplit_once('/') {
+            Some((parent, _)) => dir = parent,
+            None if build_dirs.contains(".") => return ".",
+            None => return dirname(filename),
+        }
+    }
+
+    dir
+}
+
+fn is_build_file(basename: &str) -> bool {
+    BUILD_FILES.contains(&basename) || basename.ends_with(".csproj")
+}
+
+const BUILD_FILES: &[&str] = &[
+    "pom.xml",
+    "build.gradle",
+    "build.gradle.kts",
+    "build.xml",
+    "Cargo.toml",
+    "CMakeLists.txt",
+    "go.mod",
+    "package.json",
+    "pyproject.toml",
+    "setup.py",
+    "Gemfile",
+];
+
+/// Follow the parents of an entity until reaching its file entity.
+fn find_file<'a>(by_id: &HashMap<EntityId, &'a Entity>, entity: &'a Entity) -> &'a Entity {
+    let mut curr = entity;
+
+    while let Some(parent) = curr.parent_id.and_then(|id| by_id.get(&id)) {
+        // Files may be inside packages (see --packages)
+        if curr.kind.is_file() {
+            break;
+        }
+        curr = parent;
+    }
+
+    curr
+}
+
+fn dirname(filename: &str) -> &str {
+    filename.rsplit_once('/').map_or(".", |(dir, _)| dir)
+}
+
+fn basename(filename: &str) -> &str {
+    filename.rsplit_once('/').map_or(filename, |(_, base)| base)
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+const GRAPHML_KEYS: &str = r#"  <key id="label" for="node" attr.name="label" attr.type="string"/>
+  <key id="name" for="node" attr.name="name" attr.type="string"/>
+  <key id="kind" for="node" attr.name="kind" attr.type="string"/>
+  <key id="parent_id" for="node" attr.name="parent_id" attr.type="string"/>
+  <key id="dep_kind" for="edge" attr.name="kind" attr.type="string"/>
+  <key id="row" for="edge" attr.name="row" attr.type="int"/>
+"#;
+
+fn write_data(text: &mut String, key: &str, value: &str) {
+    writeln!(text, "      <data key=\"{}\">{}</data>", key, escape_xml(value)).unwrap();
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
@@ -0,0 +1,104 @@
+//! Compute the transitive type hierarchy of each version.
+//!
+//! Consumers that ask "is A a subtype of B?" would otherwise have to walk the
+//! Extend and Implement deps themselves for every query. Deps are collected
+//! with [HierarchyBuilder::add_dep] as they are extracted, and the closure is
+//! computed once extraction has finished. Only deps between types (classes,
+//! interfaces, enums, records, and annotations) take part, so a dep from a
+//! method or a file is ignored.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::core::DepKind;
+use crate::core::Entity;
+use crate::core::EntityDep;
+use crate::core::EntityId;
+use crate::core::PseudoCommitId;
+use crate::core::Supertype;
+
+#[derive(Debug, Default)]
+pub struct HierarchyBuilder {
+    edges: Mutex<HashSet<(PseudoCommitId, EntityId, EntityId, DepKind)>>,
+}
+
+impl HierarchyBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_dep(&self, dep: &EntityDep) {
+        if matches!(dep.kind, DepKind::Extend | DepKind::Implement) && dep.src != dep.tgt {
+            self.edges.lock().unwrap().insert((dep.commit_id, dep.src, dep.tgt, dep.kind));
+        }
+    }
+
+    /// Find every supertype of every type of every version.
+    pub fn build(self, entities: &HashMap<PseudoCommitId, Vec<Entity>>) -> Vec<Supertype> {
+        let types: HashSet<_> = entities
+            .iter()
+            .flat_map(|(&commit_id, entities)| entities.iter().map(move |e| (commit_id, e)))
+            .filter(|(_, e)| e.kind.is_type())
+            .map(|(commit_id, e)| (commit_id, e.id))
+            .collect();
+
+        let mut graphs: HashMap<PseudoCommitId, HashMap<EntityId, Vec<(EntityId, DepKind)>>> =
+            HashMap::new();
+
+        for (commit_id, src, tgt, kind) in self.edges.into_inner().unwrap() {
+            if types.contains(&(commit_id, src)) && types.contains(&(commit_id, tgt)) {
+                graphs.entry(commit_id).or_default().entry(src).or_default().push((tgt, kind));
+            }
+        }
+
+        let mut supertypes = graphs
+            .iter()
+            .flat_map(|(&commit_id, graph)| {
+                graph.keys().flat_map(move |&subtype| {
+                    let depths = search(graph, subtype, |_| true);
+                    let extended = search(graph, subtype, |k| k == DepKind::Extend);
+                    depths.into_iter().map(move |(supertype, depth)| Supertype {
+                        subtype,
+                        supertype,
+                        commit_id,
+                        kind: match extended.contains_key(&supertype) {
+                            true => DepKind::Extend,
+                            false => DepKind::Implement,
+                        },
+                        depth,
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+
+        supertypes.sort();
+        supertypes
+    }
+}
+
+/// Find the types reachable from `start` through deps of the kinds allowed by
+/// `follow`, with the fewest deps needed to reach each.
+fn search<F>(
+    graph: &HashMap<EntityId, Vec<(EntityId, DepKind)>>,
+    start: EntityId,
+    follow: F,
+) -> HashMap<EntityId, usize>
+where
+    F: Fn(DepKind) -> bool,
+{
+    let mut depths = HashMap::new();
+    let mut queue = VecDeque::from([(start, 0)]);
+
+    while let Some((id, depth)) = queue.pop_front() {
+        for &(tgt, kind) in graph.get(&id).into_iter().flatten() {
+            if tgt != start && follow(kind) && !depths.contains_key(&tgt) {
+                depths.insert(tgt, depth + 1);
+                queue.push_back((tgt, depth + 1));
+            }
+        }
+    }
+
+    depths
+}
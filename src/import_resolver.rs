@@ -0,0 +1,296 @@
+//! Resolve deps from import statements alone.
+//!
+//! Import statements (and includes, requires, etc.) are found with the
+//! tree-sitter query of each language (`languages/*/imports.scm`) and mapped
+//! to the files of the project using their paths and package conventions (see
+//! [ImportIndex]). Every dep is an [DepKind::Import] from the import statement
+//! to the imported file, or to the imported class or function when the import
+//! names one (e.g. `import com.foo.Bar;` or `from foo import bar`). This misses
+//! every dep that is not written as an import, but it needs no Java and runs
+//! in a fraction of the time of the other resolvers.
+
+use std::sync::Mutex;
+
+use anyhow::Result;
+use itertools::Itertools;
+use tree_sitter::Language;
+use tree_sitter::Node;
+use tree_sitter::Parser;
+use tree_sitter::Query;
+use tree_sitter::QueryCursor;
+
+use crate::core::Confidence;
+use crate::core::Dep;
+use crate::core::DepKind;
+use crate::core::FileDep;
+use crate::core::FileEndpoint;
+use crate::core::FileKey;
+use crate::core::FileSet;
+use crate::core::PartialPosition;
+use crate::core::Position;
+use crate::core::PseudoCommitId;
+use crate::core::Span;
+use crate::imports::Import;
+use crate::imports::ImportIndex;
+use crate::languages::Lang;
+use crate::resolution::Resolver;
+use crate::resolution::ResolverFactory;
+
+/// Finds the import statements of a file with a tree-sitter query.
+///
+/// The query captures the imported path as `@import` (or as `@import.relative`
+/// if the path is relative to the importing file). Python's `from module import
+/// member` is captured as `@import.module` and `@import.member` in the same
+/// match. Captures starting with `_` are only used by predicates.
+#[derive(Debug)]
+pub struct ImportFinder {
+    language: Language,
+    query: Query,
+}
+
+impl ImportFinder {
+    pub fn new(language: Language, import_query: &str) -> Self {
+        Self { language, query: Query::new(language, import_query).unwrap() }
+    }
+
+    /// Find each import of a file and where its path starts.
+    pub fn find(&self, lang: Lang, content: &str) -> Vec<(Position, Import)> {
+        let mut parser = Parser::new();
+        parser.set_language(self.language).unwrap();
+
+        let Some(tree) = parser.parse(content, None) else {
+            return Vec::new();
+        };
+
+        let names = self.query.capture_names();
+        let mut cursor = QueryCursor::new();
+        let mut imports = Vec::new();
+
+        for m in cursor.matches(&self.query, tree.root_node(), content.as_bytes()) {
+            let capture = |name: &str| {
+                m.captures.iter().find(|c| names[c.index as usize] == name).map(|c| c.node)
+            };
+
+            let found = match (capture("import"), capture("import.relative")) {
+                (Some(path), _) => to_import(lang, content, path, false),
+                (_, Some(path)) => to_import(lang, content, path, true),
+                _ => capture("import.module")
+                    .and_then(|module| to_python_import(content, module, capture("import.member"))),
+            };
+
+            imports.extend(found);
+        }
+
+        imports
+    }
+}
+
+/// A file added to an [ImportResolver].
+#[derive(Debug)]
+struct ScannedFile {
+    file_key: FileKey,
+    imports: Vec<(Position, Import)>,
+    end: Position,
+}
+
+/// A resolver that only looks at import statements.
+///
+/// See [Resolver] and [crate::import_resolver].
+#[derive(Debug)]
+pub struct ImportResolver {
+    commit_id: PseudoCommitId,
+    lang: Lang,
+    include_dirs: Vec<String>,
+    files: Mutex<Vec<ScannedFile>>,
+}
+
+impl ImportResolver {
+    fn new(commit_id: PseudoCommitId, lang: Lang, include_dirs: Vec<String>) -> Self {
+        Self { commit_id, lang, include_dirs, files: Default::default() }
+    }
+
+    fn to_dep(
+        &self,
+        src: &ScannedFile,
+        position: Position,
+        import: &Import,
+        tgt: &ScannedFile,
+    ) -> FileDep {
+        let position = PartialPosition::Whole(position);
+        let src = FileEndpoint::new(src.file_key.clone(), position);
+
+        // The end of the file belongs to the file entity, so imports that do
+        // not name anything inside the file are attributed to the file itself
+        let mut tgt = FileEndpoint::new(tgt.file_key.clone(), PartialPosition::Whole(tgt.end));
+
+        if names_entity(self.lang, import) {
+            tgt.name = Some(import.name.clone());
+        }
+
+        Dep::new(src, tgt, DepKind::Import, position, self.commit_id)
+    }
+
+    /// Could an import that resolved to `count` files have meant only one of
+    /// them?
+    ///
+    /// Wildcard imports and Go packages name every file they resolve to.
+    fn is_ambiguous(&self, import: &Import, count: usize) -> bool {
+        count > 1 && self.lang != Lang::Go && !import.name.ends_with('*')
+    }
+}
+
+impl Resolver for ImportResolver {
+    fn add_file(&self, filename: &str, content: &str) {
+        let file_key = FileKey::from_content(filename.to_string(), content);
+        let finder = self.lang.import_finder().unwrap();
+        let imports = finder.find(self.lang, content);
+        let file = ScannedFile { file_key, imports, end: end_of(content) };
+        self.files.lock().unwrap().push(file);
+    }
+
+    fn resolve(&self) -> Result<Vec<FileDep>> {
+        let files = self.files.lock().unwrap();
+        let mut index = ImportIndex::new(&FileSet::new(files.iter().map(|f| f.file_key.clone())));
+        index.set_include_dirs(self.include_dirs.clone());
+        let by_filename = files.iter().into_group_map_by(|f| f.file_key.filename.as_str());
+
+        let deps = files
+            .iter()
+            .flat_map(|src| {
+                src.imports.iter().flat_map(|(position, import)| {
+                    let tgts = index
+                        .resolve(&src.file_key.filename, import)
+                        .into_iter()
+                        .filter(|&f| f != src.file_key.filename)
+                        .collect_vec();
+
+                    let confidence = match self.is_ambiguous(import, tgts.len()) {
+                        true => Confidence::Heuristic,
+                        false => Confidence::Resolved,
+                    };
+
+                    tgts.into_iter()
+                        .map(|f| by_filename[f][0])
+                        .map(|tgt| self.to_dep(src, *position, import, tgt))
+                        .map(|dep| dep.with_confidence(confidence))
+                        .collect_vec()
+                })
+            })
+            .collect();
+        Ok(deps)
+    }
+}
+
+/// An [ImportResolver] factory.
+///
+/// See [ResolverFactory].
+#[derive(Debug)]
+pub struct ImportResolverFactory {
+    include_dirs: Vec<String>,
+}
+
+impl ImportResolverFactory {
+    /// Create a factory whose resolvers search `include_dirs` for C and C++
+    /// includes (see [ImportIndex::set_include_dirs]).
+    pub fn new(include_dirs: Vec<String>) -> Self {
+        Self { include_dirs }
+    }
+}
+
+impl ResolverFactory for ImportResolverFactory {
+    fn name(&self) -> &'static str {
+        "imports"
+    }
+
+    fn supports(&self, lang: Lang) -> bool {
+        lang.import_finder().is_some()
+    }
+
+    fn try_create(&self, commit_id: PseudoCommitId, lang: Lang) -> Option<Box<dyn Resolver>> {
+        match self.supports(lang) {
+            true => Some(Box::new(ImportResolver::new(commit_id, lang, self.include_dirs.clone()))),
+            false => None,
+        }
+    }
+}
+
+/// Turn the captured path of an import into an [Import].
+///
+/// JavaScript and TypeScript imports of packages (paths that do not start with
+/// a ".") are never part of the project, but are kept so they can be reported
+/// as external (see [crate::core::ExternalDep]).
+fn to_import(lang: Lang, content: &str, path: Node, relative: bool) -> Option<(Position, Import)> {
+    let text = path.utf8_text(content.as_bytes()).ok()?;
+    let text = text.trim_matches(|c| matches!(c, '"' | '\'' | '`' | '<' | '>'));
+    let position = Span::from_ts(path.range()).start;
+
+    let name = match lang {
+        Lang::Java | Lang::Kotlin | Lang::Python => {
+            let name = text.replace('.', "/");
+
+            match is_wildcard(content, path) {
+                true => name + "/*",
+                false => name,
+            }
+        }
+        Lang::JavaScript | Lang::TypeScript => {
+            return Some((
+                position,
+                Import::new(position.row, text.to_string(), text.starts_with('.')),
+            ));
+        }
+        _ => text.to_string(),
+    };
+
+    Some((position, Import::new(position.row, name, relative)))
+}
+
+/// Turn the module and (unless it is a wildcard import) member of a Python
+/// `from module import member` statement into an [Import].
+fn to_python_import(
+    content: &str,
+    module: Node,
+    member: Option<Node>,
+) -> Option<(Position, Import)> {
+    let module_text = module.utf8_text(content.as_bytes()).ok()?;
+    let dots = module_text.len() - module_text.trim_start_matches('.').len();
+    let module_path = module_text[dots..].replace('.', "/");
+    let member_path = match member {
+        Some(member) => member.utf8_text(content.as_bytes()).ok()?.replace('.', "/"),
+        None => String::new(),
+    };
+
+    let path = [module_path, member_path].into_iter().filter(|s| !s.is_empty()).join("/");
+    let prefix = "../".repeat(dots.saturating_sub(1));
+    let position = Span::from_ts(member.unwrap_or(module).range()).start;
+    Some((position, Import::new(position.row, prefix + path.as_str(), dots > 0)))
+}
+
+/// Is the path followed by a `*` (e.g. `import com.foo.*;` in Java)?
+fn is_wildcard(content: &str, path: Node) -> bool {
+    let mut sibling = path.next_sibling();
+
+    while let Some(node) = sibling {
+        if node.utf8_text(content.as_bytes()).is_ok_and(|t| t.ends_with('*')) {
+            return true;
+        }
+        sibling = node.next_sibling();
+    }
+
+    false
+}
+
+/// Can the name of an import be matched with an entity of the imported file?
+///
+/// True for the package-based imports of Java, Kotlin, and Python, but not for
+/// wildcards or imports of paths.
+fn names_entity(lang: Lang, import: &Import) -> bool {
+    matches!(lang, Lang::Java | Lang::Kotlin | Lang::Python) && !import.name.ends_with('*')
+}
+
+/// The position just past the last character of a file.
+fn end_of(content: &str) -> Position {
+    let row = content.matches('\n').count();
+    let column = content.len() - content.rfind('\n').map_or(0, |i| i + 1);
+    Position::new(content.len(), row, column)
+}
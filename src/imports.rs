@@ -0,0 +1,269 @@
+//! Cheaply find the files a source file imports.
+//!
+//! Import statements are found by an [crate::import_resolver::ImportFinder],
+//! and imported names are mapped to files using only the layout of the
+//! project. This is far less accurate than a real resolver, but it needs no
+//! Java and runs in a fraction of the time, which makes it useful for deciding
+//! which files are worth resolving.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use itertools::Itertools;
+
+use crate::core::FileSet;
+use crate::languages::Lang;
+
+/// An import statement found in a source file.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Import {
+    /// The zero-based row of the import statement.
+    pub row: usize,
+
+    /// The imported name as a "/"-separated path (e.g. `com/foo/Bar`).
+    ///
+    /// Does not include a file extension unless one was written in the source
+    /// (e.g. C includes). Java and Kotlin wildcard imports end with `*`.
+    pub name: String,
+
+    /// Is [Self::name] relative to the directory of the importing file?
+    pub relative: bool,
+}
+
+impl Import {
+    pub fn new(row: usize, name: String, relative: bool) -> Self {
+        Self { row, name, relative }
+    }
+
+    /// The imported name as it would be written in `lang` (e.g. `com.foo.Bar`
+    /// rather than `com/foo/Bar`).
+    pub fn symbol(&self, lang: Lang) -> String {
+        match lang {
+            Lang::Java | Lang::Kotlin | Lang::Python => self.name.replace('/', "."),
+            _ => self.name.clone(),
+        }
+    }
+}
+
+/// Maps imported names to the files of a single version of a project.
+#[derive(Debug, Clone)]
+pub struct ImportIndex {
+    /// Every filename.
+    files: HashSet<String>,
+
+    /// Every filename keyed by each of its suffixes that starts after a "/".
+    suffixes: HashMap<String, Vec<String>>,
+
+    /// The files directly inside each directory, keyed by each suffix of the
+    /// directory path that starts after a "/".
+    dirs: HashMap<String, Vec<String>>,
+
+    /// The directories searched for C and C++ includes, like `-I` of a
+    /// compiler.
+    include_dirs: Vec<String>,
+}
+
+impl ImportIndex {
+    pub fn new(file_set: &FileSet) -> Self {
+        let mut files = HashSet::new();
+        let mut suffixes: HashMap<_, Vec<_>> = HashMap::new();
+        let mut dirs: HashMap<_, Vec<_>> = HashMap::new();
+
+        for file in file_set.iter() {
+            let filename = &file.filename;
+            files.insert(filename.clone());
+
+            for suffix in iter_suffixes(filename) {
+                suffixes.entry(suffix.to_string()).or_default().push(filename.clone());
+            }
+
+            for suffix in iter_suffixes(dirname(filename)) {
+                dirs.entry(suffix.to_string()).or_default().push(filename.clone());
+            }
+        }
+
+        Self { files, suffixes, dirs, include_dirs: Vec::new() }
+    }
+
+    /// Search these directories (relative to the root of the project) for C
+    /// and C++ includes.
+    ///
+    /// Once set, includes are only resolved like a compiler would: quoted
+    /// includes are looked for next to the including file and then in each of
+    /// these directories, and angle-bracket includes only in these directories.
+    /// Otherwise, an include is matched to every file whose path ends with it.
+    pub fn set_include_dirs(&mut self, include_dirs: Vec<String>) {
+        self.include_dirs = include_dirs.iter().map(|d| normalize(d, "")).collect();
+    }
+
+    /// Find the files that an import (found in `filename`) may refer to.
+    ///
+    /// Returns an empty list if the import most likely refers to something
+    /// outside the project, such as the standard library.
+    pub fn resolve(&self, filename: &str, import: &Import) -> Vec<&str> {
+        let Some(lang) = Lang::of(filename) else {
+            return Vec::new();
+        };
+
+        if import.relative {
+            let path = normalize(dirname(filename), &import.name);
+            let found = match lang {
+                Lang::Python => shorten(&path, |p| self.resolve_exact(lang, p)),
+                _ => self.resolve_exact(lang, &path),
+            };
+
+            if !found.is_empty() || !matches!(lang, Lang::C | Lang::Cpp) {
+                return found;
+            }
+        }
+
+        match lang {
+            Lang::C | Lang::Cpp if !self.include_dirs.is_empty() => {
+                self.resolve_include(&import.name)
+            }
+            Lang::Go => self.resolve_go(&import.name),
+            Lang::JavaScript | Lang::TypeScript => Vec::new(),
+            Lang::Java | Lang::Kotlin | Lang::Python => {
+                shorten(&import.name, |n| self.resolve_suffix(lang, n))
+            }
+            _ => self.resolve_suffix(lang, &import.name),
+        }
+    }
+
+    /// Find the files that `filename` can refer to without an import.
+    ///
+    /// In Go, Java, and Kotlin, these are the other files of the same package,
+    /// which is assumed to be the same directory.
+    pub fn implicit(&self, filename: &str) -> Vec<&str> {
+        let (Some(lang), dir) = (Lang::of(filename), dirname(filename)) else {
+            return Vec::new();
+        };
+
+        match lang {
+            Lang::Go | Lang::Java | Lang::Kotlin => self
+                .files_in_dir(lang, dir)
+                .into_iter()
+                .filter(|&f| f != filename && dirname(f) == dir)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Find a file at exactly `path` (after adding an extension).
+    fn resolve_exact(&self, lang: Lang, path: &str) -> Vec<&str> {
+        extensions(lang)
+            .iter()
+            .map(|e| format!("{}{}", path, e))
+            .find_map(|p| self.files.get(&p))
+            .map(|f| f.as_str())
+            .into_iter()
+            .collect()
+    }
+
+    /// Find every file whose path ends with `name` (after adding an extension).
+    ///
+    /// If `name` ends with `/*`, then find every file of the same language
+    /// directly inside a directory whose path ends with the rest of `name`.
+    fn resolve_suffix(&self, lang: Lang, name: &str) -> Vec<&str> {
+        if let Some(dir) = name.strip_suffix("/*") {
+            return self.files_in_dir(lang, dir);
+        }
+
+        extensions(lang)
+            .iter()
+            .find_map(|e| self.suffixes.get(&format!("{}{}", name, e)))
+            .into_iter()
+            .flatten()
+            .map(|f| f.as_str())
+            .collect()
+    }
+
+    /// Find the file of the first include directory that has `name`.
+    fn resolve_include(&self, name: &str) -> Vec<&str> {
+        self.include_dirs
+            .iter()
+            .find_map(|dir| self.files.get(&normalize(dir, name)))
+            .map(|f| f.as_str())
+            .into_iter()
+            .collect()
+    }
+
+    /// Find the files of the directory with the longest path that is a suffix
+    /// of the import path of a Go package.
+    fn resolve_go(&self, name: &str) -> Vec<&str> {
+        iter_suffixes(name)
+            .map(|dir| self.files_in_dir(Lang::Go, dir))
+            .find(|files| !files.is_empty())
+            .unwrap_or_default()
+    }
+
+    fn files_in_dir(&self, lang: Lang, dir: &str) -> Vec<&str> {
+        self.dirs
+            .get(dir)
+            .into_iter()
+            .flatten()
+            .filter(|f| Lang::of(f) == Some(lang))
+            .map(|f| f.as_str())
+            .collect()
+    }
+}
+
+/// Call `resolve` with `name`, dropping trailing components until it finds
+/// something.
+///
+/// Handles imports of members, such as static imports in Java and
+/// `from module import function` in Python.
+fn shorten<'a, F: Fn(&str) -> Vec<&'a str>>(name: &str, resolve: F) -> Vec<&'a str> {
+    let mut name = name;
+
+    loop {
+        let found = resolve(name);
+
+        match (found.is_empty(), name.rsplit_once('/')) {
+            (true, Some((parent, _))) => name = parent,
+            _ => return found,
+        }
+    }
+}
+
+/// Iterate over `path` followed by each of its suffixes that starts after a
+/// "/", from longest to shortest.
+fn iter_suffixes(path: &str) -> impl Iterator<Item = &str> {
+    std::iter::once(path).chain(path.match_indices('/').map(move |(i, _)| &path[(i + 1)..]))
+}
+
+/// The extensions to try (in order) when mapping an imported name to a file.
+fn extensions(lang: Lang) -> &'static [&'static str] {
+    match lang {
+        Lang::C | Lang::Cpp => &[""],
+        Lang::Go => &[".go"],
+        Lang::Java => &[".java"],
+        Lang::Kotlin => &[".kt"],
+        Lang::Python => &[".py", "/__init__.py"],
+        Lang::Ruby => &[".rb", ""],
+        Lang::JavaScript | Lang::TypeScript => {
+            &["", ".ts", ".tsx", ".js", ".jsx", ".mjs", ".cjs", "/index.ts", "/index.js"]
+        }
+    }
+}
+
+pub fn dirname(filename: &str) -> &str {
+    filename.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+/// Join `path` onto `dir` and resolve any "." or ".." components.
+pub fn normalize(dir: &str, path: &str) -> String {
+    let mut parts = dir.split('/').filter(|p| !p.is_empty()).collect_vec();
+
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+
+    parts.join("/")
+}
@@ -0,0 +1,343 @@
+//! Find the deps between the methods and fields of the same Java file.
+//!
+//! Resolvers are not good at these. Depends only reports the row of each dep,
+//! so when a row holds several entities the dep can land on the wrong one. But
+//! a method or field of a class is usually referenced by its simple name alone
+//! (or through `this`), so a syntactic pass over each file finds most of them.
+//! A reference is matched with the innermost enclosing class that declares a
+//! method (with a compatible number of parameters) or field of that name,
+//! unless a local variable or parameter shadows it. Inherited members and
+//! members accessed through other objects are left to the resolvers.
+
+use std::collections::HashSet;
+
+use itertools::Itertools;
+use rayon::prelude::*;
+use tree_sitter::Node;
+use tree_sitter::Parser;
+
+use crate::core::Dep;
+use crate::core::DepKind;
+use crate::core::FileDep;
+use crate::core::FileEndpoint;
+use crate::core::FileKey;
+use crate::core::MultiFileSet;
+use crate::core::PartialPosition;
+use crate::core::Position;
+use crate::core::PseudoCommitId;
+use crate::core::Span;
+use crate::filesystem::FileReader;
+use crate::languages::Lang;
+
+/// The kinds of nodes that hold the members of a class.
+const CLASS_BODIES: &[&str] =
+    &["class_body", "enum_body", "interface_body", "annotation_type_body"];
+
+/// The kinds of nodes that declare a local variable or parameter (as their
+/// `name` field).
+const LOCAL_DECLARATIONS: &[&str] = &[
+    "catch_formal_parameter",
+    "enhanced_for_statement",
+    "formal_parameter",
+    "resource",
+    "variable_declarator",
+];
+
+/// The kinds of nodes whose identifiers never refer to a variable.
+const NOT_VARIABLES: &[&str] = &[
+    "break_statement",
+    "continue_statement",
+    "element_value_pair",
+    "import_declaration",
+    "inferred_parameters",
+    "labeled_statement",
+    "lambda_expression",
+    "method_reference",
+    "package_declaration",
+    "scoped_identifier",
+];
+
+/// A method or field declared in a file.
+#[derive(Debug)]
+struct Member {
+    name: String,
+
+    /// Where the name of the member starts.
+    position: Position,
+
+    /// The number of parameters and whether the last one is variadic. None for
+    /// fields.
+    params: Option<(usize, bool)>,
+
+    /// The bytes of the body of the class that declares this member.
+    class: (usize, usize),
+}
+
+/// A reference to a method or field by its simple name.
+#[derive(Debug)]
+struct Reference {
+    name: String,
+    position: Position,
+    kind: DepKind,
+
+    /// The number of arguments. None for fields.
+    args: Option<usize>,
+}
+
+/// The members declared and referenced in a Java file.
+#[derive(Debug, Default)]
+struct Scan {
+    members: Vec<Member>,
+    references: Vec<Reference>,
+}
+
+impl Scan {
+    /// Find the member that a reference refers to, if there is exactly one.
+    fn find(&self, reference: &Reference) -> Option<&Member> {
+        let byte = reference.position.byte;
+        let candidates = self
+            .members
+            .iter()
+            .filter(|m| m.name == reference.name && m.class.0 <= byte && byte < m.class.1)
+            .filter(|m| match (m.params, reference.args) {
+                (Some((n, true)), Some(args)) => args + 1 >= n,
+                (Some((n, false)), Some(args)) => args == n,
+                (None, None) => true,
+                _ => false,
+            })
+            .max_set_by_key(|m| m.class.0);
+
+        match candidates.as_slice() {
+            [member] => Some(member),
+            _ => None,
+        }
+    }
+}
+
+/// Find the deps within each Java file of each version.
+///
+/// `resolved` holds the deps that were already found, so the same reference is
+/// not matched twice.
+pub fn resolve<R: FileReader>(
+    reader: &R,
+    files: &MultiFileSet,
+    resolved: &[FileDep],
+) -> Vec<FileDep> {
+    let resolved: HashSet<_> = resolved
+        .iter()
+        .filter(|d| d.src.file_key == d.tgt.file_key)
+        .filter_map(|d| Some((&d.src.file_key.filename, d.position.byte()?)))
+        .collect();
+
+    files
+        .iter()
+        .flat_map(|(&commit_id, file_set)| {
+            file_set
+                .iter()
+                .filter(|f| Lang::of(&f.filename) == Some(Lang::Java))
+                .collect_vec()
+                .into_par_iter()
+                .filter_map(|f| Some((f, scan(&reader.read(f.content_id).ok()?)?)))
+                .flat_map_iter(|(f, s)| {
+                    s.references
+                        .iter()
+                        .filter(|r| !resolved.contains(&(&f.filename, r.position.byte)))
+                        .filter_map(|r| Some(to_dep(f, r, s.find(r)?, commit_id)))
+                        .collect_vec()
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn to_dep(
+    file: &FileKey,
+    reference: &Reference,
+    member: &Member,
+    commit_id: PseudoCommitId,
+) -> FileDep {
+    let position = PartialPosition::Whole(reference.position);
+    let src = FileEndpoint::new(file.clone(), position);
+    let tgt = FileEndpoint::new(file.clone(), PartialPosition::Whole(member.position));
+    Dep::new(src, tgt, reference.kind, position, commit_id).with_resolver("intra-file")
+}
+
+/// Find the methods and fields declared in a file and the references to them.
+///
+/// Returns None if the file could not be parsed.
+fn scan(content: &str) -> Option<Scan> {
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_java::language()).ok()?;
+    let tree = parser.parse(content, None)?;
+
+    let mut result = Scan::default();
+    visit(tree.root_node(), content, None, &HashSet::new(), &mut result);
+    Some(result)
+}
+
+/// Add the members and references of `node` and its descendants to `scan`.
+///
+/// `class` is the innermost class body that contains `node` and `locals` are
+/// the names of the local variables and parameters that may be in scope.
+fn visit<'a>(
+    node: Node,
+    content: &'a str,
+    class: Option<(usize, usize)>,
+    locals: &HashSet<&'a str>,
+    scan: &mut Scan,
+) {
+    let class = match CLASS_BODIES.contains(&node.kind()) {
+        true => Some((node.start_byte(), node.end_byte())),
+        false => class,
+    };
+
+    // Each method, constructor, or initializer brings its own locals
+    let member_locals: HashSet<_>;
+    let locals = match node.parent().is_some_and(|p| CLASS_BODIES.contains(&p.kind())) {
+        true if node.kind() != "field_declaration" => {
+            member_locals = locals.iter().copied().chain(declared_names(node, content)).collect();
+            &member_locals
+        }
+        _ => locals,
+    };
+
+    if let Some(class) = class {
+        let text = |n: Node| n.utf8_text(content.as_bytes()).unwrap_or_default().to_string();
+        let start = |n: Node| Span::from_ts(n.range()).start;
+
+        match node.kind() {
+            "method_declaration" => {
+                if let (Some(name), Some(params)) =
+                    (node.child_by_field_name("name"), node.child_by_field_name("parameters"))
+                {
+                    scan.members.push(Member {
+                        name: text(name),
+                        position: start(name),
+                        params: Some(count_params(params)),
+                        class,
+                    });
+                }
+            }
+            "variable_declarator" if is_field(node) => {
+                if let Some(name) = node.child_by_field_name("name") {
+                    let (name, position) = (text(name), start(name));
+                    scan.members.push(Member { name, position, params: None, class });
+                }
+            }
+            "method_invocation" if node.child_by_field_name("object").is_none_or(is_this) => {
+                if let (Some(name), Some(args)) =
+                    (node.child_by_field_name("name"), node.child_by_field_name("arguments"))
+                {
+                    scan.references.push(Reference {
+                        name: text(name),
+                        position: start(name),
+                        kind: DepKind::Call,
+                        args: Some(args.named_child_count()),
+                    });
+                }
+            }
+            "field_access" if node.child_by_field_name("object").is_some_and(is_this) => {
+                if let Some(field) = node.child_by_field_name("field") {
+                    scan.references.push(Reference {
+                        name: text(field),
+                        position: start(field),
+                        kind: access_kind(node),
+                        args: None,
+                    });
+                }
+            }
+            "identifier" if is_variable(node) && !locals.contains(text(node).as_str()) => {
+                scan.references.push(Reference {
+                    name: text(node),
+                    position: start(node),
+                    kind: access_kind(node),
+                    args: None,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let mut cursor = node.walk();
+
+    for child in node.named_children(&mut cursor) {
+        visit(child, content, class, locals, scan);
+    }
+}
+
+/// Find the names of the local variables and parameters declared anywhere
+/// inside `node`.
+fn declared_names<'a>(node: Node, content: &'a str) -> Vec<&'a str> {
+    let mut names = Vec::new();
+    let mut stack = vec![node];
+
+    while let Some(node) = stack.pop() {
+        let declared = match node.kind() {
+            "variable_declarator" if is_field(node) => None,
+            kind if LOCAL_DECLARATIONS.contains(&kind) => node.child_by_field_name("name"),
+            "lambda_expression" => node.child_by_field_name("parameters"),
+            _ => None,
+        };
+
+        if let Some(declared) = declared {
+            let mut cursor = declared.walk();
+            let identifiers = match declared.kind() {
+                "identifier" => vec![declared],
+                "inferred_parameters" => declared.named_children(&mut cursor).collect(),
+                _ => Vec::new(),
+            };
+
+            names.extend(identifiers.iter().filter_map(|i| i.utf8_text(content.as_bytes()).ok()));
+        }
+
+        let mut cursor = node.walk();
+        stack.extend(node.named_children(&mut cursor));
+    }
+
+    names
+}
+
+/// The number of parameters of a method and whether the last one is variadic.
+fn count_params(params: Node) -> (usize, bool) {
+    let mut cursor = params.walk();
+    let params = params
+        .named_children(&mut cursor)
+        .filter(|p| matches!(p.kind(), "formal_parameter" | "spread_parameter"))
+        .collect_vec();
+    (params.len(), params.last().is_some_and(|p| p.kind() == "spread_parameter"))
+}
+
+/// Is this the declarator of a field (rather than a local variable)?
+fn is_field(declarator: Node) -> bool {
+    declarator
+        .parent()
+        .is_some_and(|p| matches!(p.kind(), "field_declaration" | "constant_declaration"))
+}
+
+/// Could this identifier be a reference to a variable?
+fn is_variable(identifier: Node) -> bool {
+    let Some(parent) = identifier.parent() else {
+        return false;
+    };
+
+    !NOT_VARIABLES.contains(&parent.kind())
+        && parent.child_by_field_name("name") != Some(identifier)
+        && parent.child_by_field_name("field") != Some(identifier)
+}
+
+fn is_this(node: Node) -> bool {
+    node.kind() == "this"
+}
+
+/// Is the variable at `node` being assigned to or only read?
+fn access_kind(node: Node) -> DepKind {
+    match node.parent() {
+        Some(p) if p.kind() == "assignment_expression" => {
+            match p.child_by_field_name("left") == Some(node) {
+                true => DepKind::Set,
+                false => DepKind::Use,
+            }
+        }
+        _ => DepKind::Use,
+    }
+}
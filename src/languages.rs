@@ -4,15 +4,22 @@ use std::sync::Arc;
 
 use itertools::Itertools;
 use lazy_static::lazy_static;
+use rusqlite::types::ToSqlOutput;
+use rusqlite::ToSql;
 use tree_sitter::Language;
 use tree_sitter_stack_graphs::StackGraphLanguage;
 
+use crate::annotations::AnnotationFinder;
+use crate::import_resolver::ImportFinder;
 use crate::spec::Pathspec;
+use crate::tagging::DepTagger;
 use crate::tagging::Tagger;
 
 /// Each programming language supported by Neodepends.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-#[derive(strum::Display, strum::EnumString, strum::VariantNames)]
+#[derive(serde::Serialize)]
+#[derive(strum::AsRefStr, strum::Display, strum::EnumIter, strum::EnumString, strum::VariantNames)]
+#[serde(rename_all = "lowercase")]
 #[strum(serialize_all = "lowercase")]
 pub enum Lang {
     C,
@@ -26,6 +33,12 @@ pub enum Lang {
     TypeScript,
 }
 
+impl ToSql for Lang {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        self.as_ref().to_sql()
+    }
+}
+
 impl Lang {
     /// Get the language for a filename.
     pub fn of<S: AsRef<str>>(filename: S) -> Option<Self> {
@@ -43,11 +56,31 @@ impl Lang {
         Pathspec::new(LANG_TABLE.patterns(langs))
     }
 
+    /// Get the tree-sitter grammar of this language.
+    pub fn language(&self) -> Language {
+        self.config().language
+    }
+
     /// Get the [Tagger] associated with this language.
     pub fn tagger(&self) -> &Tagger {
         &self.config().tagger
     }
 
+    /// Get the [DepTagger] associated with this language, if any.
+    pub fn dep_tagger(&self) -> Option<&DepTagger> {
+        self.config().dep_tagger.as_ref()
+    }
+
+    /// Get the [ImportFinder] associated with this language, if any.
+    pub fn import_finder(&self) -> Option<&ImportFinder> {
+        self.config().import_finder.as_ref()
+    }
+
+    /// Get the [AnnotationFinder] associated with this language, if any.
+    pub fn annotation_finder(&self) -> Option<&AnnotationFinder> {
+        self.config().annotation_finder.as_ref()
+    }
+
     /// Get the [StackGraphLanguage] associated with this language.
     pub fn sgl(&self) -> Option<Arc<StackGraphLanguage>> {
         self.config().sgl.clone()
@@ -60,6 +93,40 @@ impl Lang {
         self.config().depends_lang
     }
 
+    /// Get the queries and stack graph rules of this language, each with the
+    /// name of the file it came from (e.g. "tags.scm").
+    pub fn queries(&self) -> &[(&'static str, &'static str)] {
+        &self.config().queries
+    }
+
+    /// Get the features Neodepends supports for this language.
+    pub fn capabilities(&self) -> Capabilities {
+        let config = self.config();
+        Capabilities {
+            lang: self.to_string(),
+            entities: config.tagger.is_entity_level(),
+            imports: config.import_finder.is_some(),
+            stackgraphs: config.sgl.is_some(),
+            depends: config.depends_lang,
+            pyright: *self == Lang::Python,
+        }
+    }
+
+    /// Get the name of this language according to GitHub's linguist.
+    pub fn linguist_name(&self) -> &'static str {
+        match self {
+            Lang::C => "C",
+            Lang::Cpp => "C++",
+            Lang::Go => "Go",
+            Lang::Java => "Java",
+            Lang::JavaScript => "JavaScript",
+            Lang::Kotlin => "Kotlin",
+            Lang::Python => "Python",
+            Lang::Ruby => "Ruby",
+            Lang::TypeScript => "TypeScript",
+        }
+    }
+
     fn config(&self) -> &LangConfig {
         match &self {
             Lang::C => &C,
@@ -75,24 +142,81 @@ impl Lang {
     }
 }
 
+/// The features Neodepends supports for a particular [Lang].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(serde::Serialize)]
+pub struct Capabilities {
+    /// The name of the language.
+    pub lang: String,
+
+    /// Can entities finer than files (classes, methods, etc.) be extracted?
+    pub entities: bool,
+
+    /// Can deps be resolved from import statements alone?
+    pub imports: bool,
+
+    /// Can deps be resolved with Stack Graphs?
+    pub stackgraphs: bool,
+
+    /// The name of the language according to Depends, if supported.
+    pub depends: Option<&'static str>,
+
+    /// Can deps be resolved with Pyright?
+    pub pyright: bool,
+}
+
 struct LangConfig {
+    language: Language,
     pathspec: Pathspec,
     tagger: Tagger,
+    dep_tagger: Option<DepTagger>,
+    import_finder: Option<ImportFinder>,
+    annotation_finder: Option<AnnotationFinder>,
     sgl: Option<Arc<StackGraphLanguage>>,
     depends_lang: Option<&'static str>,
+    queries: Vec<(&'static str, &'static str)>,
 }
 
 impl LangConfig {
     fn new(
         language: Language,
         pathspec: Pathspec,
-        tag_query: Option<&str>,
-        tsg: Option<&str>,
+        tag_query: Option<&'static str>,
+        dep_query: Option<&'static str>,
+        import_query: Option<&'static str>,
+        tsg: Option<&'static str>,
         depends_lang: Option<&'static str>,
     ) -> Self {
         let tagger = Tagger::new(Some(language), tag_query);
+        let dep_tagger = dep_query.map(|q| DepTagger::new(language, q));
+        let import_finder = import_query.map(|q| ImportFinder::new(language, q));
         let sgl = tsg.map(|x| Arc::new(StackGraphLanguage::from_str(language, &x).unwrap()));
-        Self { pathspec, tagger, sgl, depends_lang }
+        let queries = [
+            ("tags.scm", tag_query),
+            ("deps.scm", dep_query),
+            ("imports.scm", import_query),
+            ("stack-graphs.tsg", tsg),
+        ];
+        let queries = queries.into_iter().filter_map(|(f, q)| Some((f, q?))).collect();
+        Self {
+            language,
+            pathspec,
+            tagger,
+            dep_tagger,
+            import_finder,
+            annotation_finder: None,
+            sgl,
+            depends_lang,
+            queries,
+        }
+    }
+
+    /// Also find the annotations of entities with this query. See
+    /// [crate::annotations].
+    fn with_annotation_query(mut self, annotation_query: &'static str) -> Self {
+        self.annotation_finder = Some(AnnotationFinder::new(self.language, annotation_query));
+        self.queries.push(("annotations.scm", annotation_query));
+        self
     }
 }
 
@@ -153,6 +277,7 @@ lazy_static! {
     static ref LANG_TABLE: LangLookupTable = {
         let mut table = LangLookupTable::new();
         table.insert_extension(Lang::C, "c");
+        table.insert_extension(Lang::C, "h");
         table.insert_extension(Lang::Cpp, "c++");
         table.insert_extension(Lang::Cpp, "cc");
         table.insert_extension(Lang::Cpp, "cpp");
@@ -176,6 +301,8 @@ lazy_static! {
         LANG_TABLE.pathspec(Lang::C),
         None,
         None,
+        Some(include_str!("../languages/c/imports.scm")),
+        None,
         Some("cpp")
     );
     static ref CPP: LangConfig = LangConfig::new(
@@ -183,6 +310,8 @@ lazy_static! {
         LANG_TABLE.pathspec(Lang::Cpp),
         None,
         None,
+        Some(include_str!("../languages/cpp/imports.scm")),
+        None,
         Some("cpp")
     );
     static ref GO: LangConfig = LangConfig::new(
@@ -190,19 +319,26 @@ lazy_static! {
         LANG_TABLE.pathspec(Lang::Go),
         None,
         None,
+        Some(include_str!("../languages/go/imports.scm")),
+        None,
         Some("go")
     );
     static ref JAVA: LangConfig = LangConfig::new(
         tree_sitter_java::language(),
         LANG_TABLE.pathspec(Lang::Java),
         Some(include_str!("../languages/java/tags.scm")),
+        Some(include_str!("../languages/java/deps.scm")),
+        Some(include_str!("../languages/java/imports.scm")),
         Some(include_str!("../languages/java/stack-graphs.tsg")),
         Some("java")
-    );
+    )
+    .with_annotation_query(include_str!("../languages/java/annotations.scm"));
     static ref JAVASCRIPT: LangConfig = LangConfig::new(
         tree_sitter_javascript::language(),
         LANG_TABLE.pathspec(Lang::JavaScript),
         None,
+        Some(include_str!("../languages/javascript/deps.scm")),
+        Some(include_str!("../languages/javascript/imports.scm")),
         Some(include_str!("../languages/javascript/stack-graphs.tsg")),
         None
     );
@@ -211,19 +347,26 @@ lazy_static! {
         LANG_TABLE.pathspec(Lang::Kotlin),
         None,
         None,
+        Some(include_str!("../languages/kotlin/imports.scm")),
+        None,
         Some("kotlin")
     );
     static ref PYTHON: LangConfig = LangConfig::new(
         tree_sitter_python::language(),
         LANG_TABLE.pathspec(Lang::Python),
-        None,
+        Some(include_str!("../languages/python/tags.scm")),
+        Some(include_str!("../languages/python/deps.scm")),
+        Some(include_str!("../languages/python/imports.scm")),
         Some(include_str!("../languages/python/stack-graphs.tsg")),
         Some("python")
-    );
+    )
+    .with_annotation_query(include_str!("../languages/python/annotations.scm"));
     static ref RUBY: LangConfig = LangConfig::new(
         tree_sitter_ruby::language(),
         LANG_TABLE.pathspec(Lang::Ruby),
         None,
+        Some(include_str!("../languages/ruby/deps.scm")),
+        Some(include_str!("../languages/ruby/imports.scm")),
         Some(include_str!("../languages/ruby/stack-graphs.tsg")),
         Some("ruby")
     );
@@ -231,6 +374,8 @@ lazy_static! {
         tree_sitter_typescript::language_typescript(),
         LANG_TABLE.pathspec(Lang::TypeScript),
         None,
+        Some(include_str!("../languages/typescript/deps.scm")),
+        Some(include_str!("../languages/typescript/imports.scm")),
         Some(include_str!("../languages/typescript/stack-graphs.tsg")),
         None
     );
@@ -1,53 +1,134 @@
 #[macro_use]
 extern crate derive_builder;
 
+use core::Commit;
+use core::CommitId;
 use core::PseudoCommitId;
+use core::Revspec;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 use clap::arg;
+use clap::parser::ValueSource;
 use clap::ArgMatches;
 use clap::Args;
 use clap::CommandFactory;
 use clap::FromArgMatches;
 use clap::Parser;
+use clap::Subcommand;
 use clap_verbosity_flag::InfoLevel;
 use clap_verbosity_flag::Verbosity;
+use depends::install_depends_jar;
 use depends::DependsConfig;
 use itertools::Itertools;
 use languages::Lang;
+use output::Compression;
+use output::Level;
 use output::OutputFormat;
+use output::OutputOptions;
 use output::Resource;
 use rayon::prelude::*;
+use regex::Regex;
 use resolution::ResolverManager;
+use resolution::ResolverMode;
+use resolution::ScratchPolicy;
 use spec::Pathspec;
-
+use strum::IntoEnumIterator;
+
+use crate::alignment::AlignmentBuilder;
+use crate::anonymize::Anonymizer;
+use crate::anonymize::AnonymizingWriter;
+use crate::bundle::write_bundle;
+use crate::bundle::Manifest;
+use crate::bundle::ResolverVersion;
+use crate::churn::DepChurn;
+use crate::dedup::DepCollapser;
 use crate::depends::DependsResolverFactory;
+use crate::extraction::ChangeAttribution;
+use crate::extraction::DuplicatePolicy;
 use crate::extraction::Extractor;
 use crate::filesystem::FileSystem;
+use crate::filesystem::MergePolicy;
+use crate::hierarchy::HierarchyBuilder;
+use crate::import_resolver::ImportResolverFactory;
+use crate::matching::match_versions;
+use crate::metrics::FanCounter;
+use crate::name_match::NameMatchResolverFactory;
+use crate::ownership::Ownership;
+use crate::plugin::PluginCommand;
+use crate::plugin::PluginResolverFactory;
+use crate::pyright::PyrightResolverFactory;
+use crate::pyright::DEFAULT_PYRIGHT_CMD;
 use crate::resolution::ResolverFactory;
+use crate::scripting::ScriptedWriter;
 use crate::spec::Filespec;
 use crate::stackgraphs::StackGraphsResolverFactory;
-
+use crate::storage::Storage;
+use crate::suppression::Suppressions;
+use crate::tagging::Granularity;
+use crate::taxonomy::Taxonomy;
+use crate::testcode::TestDetector;
+
+mod alignment;
+mod annotations;
+mod anonymize;
+mod bridges;
+mod bundle;
+mod churn;
 mod core;
+mod dedup;
 mod depends;
+mod directories;
+mod duck;
+mod dv8;
 mod extraction;
 mod filesystem;
+mod graph;
+mod hierarchy;
+mod import_resolver;
+mod imports;
+mod intrafile;
 mod languages;
+mod manifests;
+mod markdown;
+mod matching;
 mod matrix;
+mod metrics;
+mod name_match;
 mod output;
+mod ownership;
+mod plugin;
+mod proto;
+mod pyright;
+mod reflection;
 mod resolution;
+mod scip;
+mod scripting;
 mod sparse_vec;
 mod spec;
 mod stackgraphs;
+mod stats;
+mod storage;
+mod suppression;
+mod table;
 mod tagging;
+mod taxonomy;
+mod testcode;
+mod understand;
+mod worddiff;
 
 /// Allow an enum to be used on the command-line as long as the enum implements
 /// [`strum::EnumString`] and [`strum::VariantNames`].
@@ -73,10 +154,38 @@ macro_rules! strum_parser {
 ///
 /// - Deps: Syntactic dependencies between entities (like method calls)
 ///
-/// - Changes: Records of a particular commit changing a particular entity
+/// - Changes: Records of a particular commit changing a particular entity, with
+///   the author of the commit
+///
+/// - Dep changes: Deps added or deleted between consecutive --structure commits
 ///
 /// - Contents: Textual content of source files
 ///
+/// - Alignments: Pairs of entities that have deps but never change together (or
+///   vice versa)
+///
+/// - Revspecs: The commit that each revspec given on the command-line resolved
+///   to
+///
+/// - Unresolved refs: References that the resolver could not match to a
+///   definition (only found by --stackgraphs)
+///
+/// - Entity matches: The entities of each version that are the same as an
+///   entity of the next version, with their IDs in both (only written when
+///   entities come from more than one commit, e.g. with --all-entities)
+///
+/// - Annotations: The annotations (or decorators) applied to each entity
+///
+/// - Diagnostics: Files that could not be read or tagged (and so fell back to a
+///   single file-level entity) and resolvers that failed
+///
+/// - Files: The filename and content of each file of each version whose
+///   entities are written, which places each entity (by its content) in the
+///   versions that hold it
+///
+/// - Commits: The author, committer, dates, subject, and parents of each commit
+///   that was scanned
+///
 /// Entities, deps, and contents and considered "structural" resources, while
 /// changes are considered "historical" resources.
 ///
@@ -88,12 +197,14 @@ macro_rules! strum_parser {
 /// from the working directory (WORKDIR). If the project is a git repository,
 /// Neodepends can also extract resources from one or more commits. For example,
 ///
-/// $ neodepends --output=out.jsonl --format=jsonl --depends $(git rev-list HEAD -n 100)
+/// $ neodepends --output=out.jsonl --format=jsonl --depends $(git rev-list HEAD
+/// -n 100)
 ///
-/// will scan the most recent 100 commits reachable from HEAD. By default, entities,
-/// deps, and contents will only be extracted from the fist commit specified. The
-/// remaining commits are used to calculate changes. If this info is desired for
-/// more than the first commit, use the --structure argument.
+/// will scan the most recent 100 commits reachable from HEAD. By default,
+/// entities, deps, and contents will only be extracted from the fist commit
+/// specified. The remaining commits are used to calculate changes. If this info
+/// is desired for more than the first commit, use the --structure argument (or
+/// --all-deps for every commit).
 ///
 /// Instead of providing the commits directly on the command line, Neodepends
 /// can also take commits as a text file. For example,
@@ -105,15 +216,23 @@ macro_rules! strum_parser {
 /// This is useful in some shells where subcommands are not available.
 ///
 /// Dependency resolution can be done with Stack Graphs (--stackgraphs),
-/// Depends (--depends), or both. If both are enabled, Neodepends will
-/// determine which one to use for a particular language by using whichever one
-/// is specified first on the command-line. This is useful when a language is
-/// supported by both Stack Graphs and Depends.
+/// Depends (--depends), import statements alone (--imports), an external
+/// command (--resolver-cmd), or any combination. If several are enabled,
+/// Neodepends will determine which one to use for a particular language by
+/// using whichever one is specified first on the command-line. This is useful
+/// when a language is supported by both Stack Graphs and Depends.
 ///
-/// If --format=csvs or --format=parquets, then a directory will be created with
-/// a .csv or .parquet file for each table requested. All other formats will
+/// If --format=csvs, --format=jsonls, --format=parquets, or --format=arrow,
+/// then a directory will be created with a .csv, .jsonl, .parquet, or .arrow
+/// file for each table requested. If --format=neo4j, then a directory will be
+/// created with a nodes.csv and relationships.csv. If --format=mtx, then a
+/// directory will be created with the DSM as a deps.mtx and a cochanges.mtx (in
+/// Matrix Market format) along with a variables.csv. All other formats will
 /// result in a single file.
 ///
+/// If --format=proto, then the file is a stream of length-delimited Record
+/// messages as defined by proto/neodepends.proto.
+///
 /// A design structure matrix (DSM) has a list of `variables` (entities) and a
 /// list of `cells` that indicate relations between pairs of variables. At
 /// minimum, these cells indicate syntactic dependencies between pairs of
@@ -121,10 +240,19 @@ macro_rules! strum_parser {
 /// pair of entities have changed together in the same commit (co-change).
 #[derive(Debug, Parser)]
 #[command(author, version, about)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Opts {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// The path of the output file or directory.
-    #[arg(short, long)]
-    output: PathBuf,
+    ///
+    /// The output is built at a hidden temporary path in the same directory and
+    /// only moved here once complete, so a failed run never leaves partial
+    /// output behind. Pressing Ctrl-C stops extraction early but still
+    /// finalizes whatever has been written (press it twice to quit at once).
+    #[arg(short, long, required = true)]
+    output: Option<PathBuf>,
 
     /// Overwrite the output file or directory if it already exists.
     ///
@@ -132,6 +260,17 @@ struct Opts {
     #[arg(short, long)]
     force: bool,
 
+    /// Add to the output if it already exists instead of failing.
+    ///
+    /// Only supported with --format=sqlite. Rows already in the database are
+    /// skipped, as are the deps of any commit that already has deps. Useful
+    /// for cheaply scanning new commits into a long-lived database. There is
+    /// no parquet output, and the Arrow output cannot be appended to: its
+    /// files are IPC streams, which would have to be read back in full to skip
+    /// rows that are already present.
+    #[arg(long, conflicts_with = "force")]
+    append: bool,
+
     /// The root of the project/repository to scan.
     ///
     /// If not specified, will use the current working directory. If no git
@@ -147,6 +286,46 @@ struct Opts {
     #[arg(long, value_parser = strum_parser!(OutputFormat))]
     format: Option<OutputFormat>,
 
+    /// Compress the output as it is written.
+    ///
+    /// Only supported with --format=jsonl and --format=jsonls. If not
+    /// specified, will try to infer from a .gz or .zst file extension.
+    #[arg(long, value_parser = strum_parser!(Compression))]
+    compress: Option<Compression>,
+
+    /// Wrap each row as {"type": ..., "v": ..., "data": ...}.
+    ///
+    /// Only supported with --format=jsonl. The type is the singular name of the
+    /// resource (e.g. "entity" or "dep_change") and v is the version of the
+    /// row schema, so consumers can reliably split a single stream.
+    #[arg(long)]
+    envelope: bool,
+
+    /// Write JSON without indentation or line breaks.
+    ///
+    /// Only supported with --format=dsm-v1, dsm-v2, or dv8. Whitespace makes
+    /// up most of a pretty-printed method-level matrix.
+    #[arg(long)]
+    minify: bool,
+
+    /// Write identical deps as a single dep with a count
+    ///
+    /// Deps with the same source, target, kind, version, and resolver are
+    /// written once, at the first row they occur on, and the count column says
+    /// how many there were. Without this, every dep has a count of 1.
+    #[arg(long)]
+    dedup_deps: bool,
+
+    /// Also package the output into this .tar.zst archive
+    ///
+    /// The archive holds the output, a manifest.json recording how it was made
+    /// (the arguments, the commit each revspec resolved to, and the version of
+    /// Neodepends and of each enabled resolver), and the exact language
+    /// queries used. Written once the output is complete, so published
+    /// datasets are self-describing and can be re-run.
+    #[arg(long, value_name = "PATH")]
+    bundle: Option<PathBuf>,
+
     /// Extract and export the provided resources.
     ///
     /// If not provided, all supported resources will be exported.
@@ -154,16 +333,281 @@ struct Opts {
     resources: Vec<Resource>,
 
     /// Extract entities from historical commits in addition to structural.
+    ///
+    /// Entity matches then cover every consecutive pair of these commits, in
+    /// the order they were given (structural first).
     #[arg(long)]
     all_entities: bool,
 
+    /// Extract deps from historical commits in addition to structural.
+    ///
+    /// Every historical commit is treated as if it were also given with
+    /// --structure, so a time series of deps can be extracted in one run.
+    /// Files whose content is unchanged between commits are only parsed once.
+    /// Implies --all-entities.
+    #[arg(long)]
+    all_deps: bool,
+
     /// Always report at the file-level, even when more fine-grain info is
     /// available.
     ///
-    /// Implied if --format=dsm-v1.
-    #[arg(long)]
+    /// Implied if --format=dsm-v1. Same as --level=file or
+    /// --granularity=file.
+    #[arg(long, conflicts_with = "level")]
     file_level: bool,
 
+    /// How fine-grained the entities found within each file are.
+    ///
+    /// With "file", each file is a single entity (see --file-level). With
+    /// "type", files hold only their types (e.g. classes and interfaces) and
+    /// the members of each type (e.g. methods and fields) are folded into it,
+    /// so their deps and changes count for the type. With "member", every
+    /// entity is found.
+    #[arg(
+        long,
+        default_value_t,
+        conflicts_with = "file_level",
+        value_parser = strum_parser!(Granularity)
+    )]
+    granularity: Granularity,
+
+    /// The granularity of the entities that are reported.
+    ///
+    /// With "file", only files are reported (see --file-level). With "team",
+    /// the files owned by each team (see --owners) are merged into a single
+    /// entity named after the team, giving a team-to-team matrix of deps and
+    /// co-changes. Files without an owner are left out. With "dir", the files
+    /// of each directory are merged (see --dir-level). Only supported with DSM
+    /// formats.
+    #[arg(long, default_value_t, value_parser = strum_parser!(Level))]
+    level: Level,
+
+    /// Merge the files of each directory into a single entity, keeping only the
+    /// first N components of each directory.
+    ///
+    /// With N = 2, `src/app/ui/Button.java` belongs to `src/app`. Without N,
+    /// every directory is kept. Gives an architecture-scale matrix of deps and
+    /// co-changes for projects too large to read file by file. Only supported
+    /// with DSM formats.
+    #[arg(
+        long,
+        value_name = "N",
+        require_equals = true,
+        conflicts_with_all = ["level", "file_level"]
+    )]
+    dir_level: Option<Option<usize>>,
+
+    /// How to handle files whose content appears under more than one path.
+    ///
+    /// Vendored copies of a library will otherwise double the entities and
+    /// inflate coupling. With "canonical", only the copy with the shortest path
+    /// is scanned. With "warn", every copy is scanned but each group of
+    /// duplicates is logged. Empty files are never considered duplicates.
+    #[arg(long, default_value_t, value_parser = strum_parser!(DuplicatePolicy))]
+    duplicates: DuplicatePolicy,
+
+    /// How the changed lines of a commit are attributed to entities.
+    ///
+    /// With "rows", each changed line counts only for the innermost entity on
+    /// it, which undercounts when several small entities share a line (e.g.
+    /// fields declared on one line). With "bytes", each diff is narrowed down
+    /// to the words that changed (like `git diff --word-diff`) and each line
+    /// counts for every entity whose code changed on it. Slower.
+    #[arg(long, default_value_t, value_parser = strum_parser!(ChangeAttribution))]
+    change_attribution: ChangeAttribution,
+
+    /// How the changes of merge commits are found.
+    ///
+    /// With "first-parent", a merge commit is diffed against its first parent,
+    /// so it holds every change brought in by the merged branch. With
+    /// "all-parents", it is diffed against each parent and the changes to an
+    /// entity are added up. With "skip", merge commits have no changes.
+    #[arg(long, default_value_t, value_parser = strum_parser!(MergePolicy))]
+    merges: MergePolicy,
+
+    /// Only scan the history of commits whose author matches PATTERN
+    ///
+    /// Like `git log --author`, PATTERN is a regular expression matched
+    /// against "NAME <EMAIL>". If given more than once, a commit is scanned if
+    /// any pattern matches. Does not apply to --structure commits.
+    #[arg(long, value_name = "PATTERN")]
+    author: Vec<String>,
+
+    /// Leave merge commits out of the history (like `git log --no-merges`)
+    #[arg(long, conflicts_with = "only_merges")]
+    no_merges: bool,
+
+    /// Only scan the history of merge commits (like `git log --merges`)
+    ///
+    /// Requires --merges=first-parent or --merges=all-parents, as merge
+    /// commits otherwise have no changes.
+    #[arg(long)]
+    only_merges: bool,
+
+    /// Do not count changes that only touch whitespace or blank lines
+    ///
+    /// Like `git diff -w --ignore-blank-lines`, so reformatting a file does
+    /// not change its entities.
+    #[arg(long)]
+    ignore_whitespace: bool,
+
+    /// Do not count changes that only touch the comments of entities
+    ///
+    /// A hunk is left out if every line it touches (before and after) is within
+    /// the comment of an entity (like a Javadoc comment). Comments that are not
+    /// captured by the tag query of a language (e.g. within a method body) are
+    /// treated as code.
+    #[arg(long)]
+    ignore_comment_only_changes: bool,
+
+    /// Count the changes to files matching these patterns instead
+    ///
+    /// The history pathspec otherwise is the same as the structure pathspec
+    /// (see PATH). This replaces it, so it may narrow it (e.g. `src/core/*` to
+    /// compute co-change from only the history of a subsystem) or cover other
+    /// files (e.g. `pom.xml` to count co-change on build files). Unlike PATH,
+    /// the patterns are not limited to the scanned languages. A file renamed
+    /// into (or out of) these patterns is added (or deleted). See
+    /// https://git-scm.com/docs/gitglossary#def_pathspec.
+    #[arg(long, value_name = "PATTERN")]
+    history_path: Vec<String>,
+
+    /// Also scan files matching these patterns, even if they are not source
+    /// code.
+    ///
+    /// Useful for build and configuration files (e.g. "*pom.xml" or
+    /// "*.gradle"). Each matched file becomes a single file entity that takes
+    /// part in changes and co-change, but never has deps. Files that are not
+    /// UTF-8 text are treated as empty. See
+    /// https://git-scm.com/docs/gitglossary#def_pathspec.
+    #[arg(long, value_name = "PATTERN", value_delimiter = ',')]
+    include_extra_files: Vec<String>,
+
+    /// Also scan every file that is not source code
+    ///
+    /// Same as `--include-extra-files='*'`. Config files, SQL, protos, docs,
+    /// and so on each become a single file entity that takes part in changes
+    /// and co-change. Combine with the positional patterns to leave files out
+    /// (e.g. `-- '!*.png'`).
+    #[arg(long)]
+    include_non_source: bool,
+
+    /// Also scan build manifests and turn each into a module
+    ///
+    /// Every pom.xml, build.gradle, build.gradle.kts, package.json,
+    /// Cargo.toml, and requirements*.txt file gets an entity of kind "Module"
+    /// inside its file entity. Modules have "Extend" deps to the modules they
+    /// inherit from (e.g. a Maven parent or a Cargo workspace) and "Dependency"
+    /// deps to the modules of this project they depend on. These deps have a
+    /// resolver of "manifests" and are found even if no resolver is enabled.
+    #[arg(long)]
+    build_manifests: bool,
+
+    /// Tell overloaded methods apart when matching entities across commits
+    ///
+    /// By default, overloads (e.g. `foo(int)` and `foo(String)`) share the same
+    /// simple ID, so their changes are attributed to each other. With this, the
+    /// parameter types of each method and constructor are part of its simple
+    /// ID. Off by default because it changes the simple ID of every method
+    /// and constructor from earlier versions of Neodepends.
+    #[arg(long)]
+    signature_ids: bool,
+
+    /// Put each file inside entities for the package it declares
+    ///
+    /// A file declaring `package com.foo` (in Java, Kotlin, or Go) becomes a
+    /// child of a "Package" entity named `foo`, which is a child of a "Package"
+    /// entity named `com`. Useful for aggregating a DSM at the package level.
+    /// Files without a package declaration have no parent, as before. The IDs
+    /// of entities do not change. Not supported with --format=dsm-v1.
+    #[arg(long)]
+    packages: bool,
+
+    /// Patterns of the files that hold test code.
+    ///
+    /// Every entity in a matching file is flagged as test code (see the
+    /// is_test field of entities), as is every method or function annotated
+    /// like a test (e.g. `@Test`) or named like one (e.g. `test_foo`), every
+    /// class named like a test (e.g. `FooTest`), and everything inside of
+    /// these. Replaces the default patterns, so --test-files= leaves only the
+    /// heuristics. See https://git-scm.com/docs/gitglossary#def_pathspec.
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        value_delimiter = ',',
+        default_values = testcode::DEFAULT_TEST_PATTERNS
+    )]
+    test_files: Vec<String>,
+
+    /// Fail rather than silently fall back when something cannot be extracted
+    ///
+    /// Without this, a source file with syntax errors (or whose language has
+    /// no entity support) is reported as a single file entity, a file that
+    /// cannot be read is treated as empty, a resolver that fails (e.g. because
+    /// Depends crashed) contributes no deps, and a language that no enabled
+    /// resolver supports simply has no deps. With this, each is an error
+    /// naming the file, commit, resolver or language, so the output is known
+    /// to be complete. Implies --strict-coverage. Entities are checked before
+    /// anything is written, which means every scanned version is parsed up
+    /// front. Resolvers can only be checked once the deps have been written.
+    #[arg(long)]
+    strict: bool,
+
+    /// Leave out deps between entities that match the rules in this file.
+    ///
+    /// Each line has the form "SRC -> TGT", where SRC and TGT are pathspecs
+    /// matched against the path of each entity (e.g. "src/Foo.java/Foo/bar").
+    /// Matching deps are also left out of dep changes and alignments, so only
+    /// new or unaccepted coupling is reported. Lines starting with "#" are
+    /// ignored.
+    #[arg(long, value_name = "FILE")]
+    suppressions: Option<PathBuf>,
+
+    /// Rename and merge dep kinds according to the rules in this file.
+    ///
+    /// Each line has the form "KIND, ... -> LABEL" (e.g. "Create, Call, Use ->
+    /// Runtime"). Deps of the listed kinds are written with LABEL as their
+    /// kind, which also merges them in the cells of a DSM. Kinds without a
+    /// rule keep their own name. Lines starting with "#" are ignored.
+    #[arg(long, value_name = "FILE")]
+    taxonomy: Option<PathBuf>,
+
+    /// Mark each DSM cell with whether both entities are owned by one team.
+    ///
+    /// Each line of this file has the form "PATHSPEC -> TEAM" (e.g.
+    /// "src/billing -> payments"). If several rules match an entity, the last
+    /// one wins. Cells are only marked if both entities have an owner. Useful
+    /// for checking whether coupling follows team boundaries (Conway's law).
+    ///
+    /// A .yaml or .yml file may instead map each team to a list of pathspecs.
+    /// Required by --level=team.
+    #[arg(long, value_name = "FILE")]
+    owners: Option<PathBuf>,
+
+    /// Pass every record through the hooks of this Rhai script before writing.
+    ///
+    /// The script may define a function named after the singular of any
+    /// resource (entity, dep, change, content, dep_change, alignment, or
+    /// revspec). It is called with each record as a map and returns true to
+    /// keep it, false to drop it, or a map to write instead. Records replaced
+    /// by a map can only be written with --format=jsonl or --format=jsonls.
+    /// See https://rhai.rs.
+    #[arg(long, value_name = "FILE")]
+    post_script: Option<PathBuf>,
+
+    /// Replace names with keyed hashes so the output can be shared
+    ///
+    /// Filenames (each directory and the name of each file, but not its
+    /// extension), entity names, symbols, revspecs, and the authors and
+    /// subjects of commits are replaced by a hash under the secret key in this
+    /// file. The same name always gets the same hash, so structure and history
+    /// are kept, but the names cannot be recovered without the key. Contents
+    /// are never written. Runs after --post-script, which sees the hashed
+    /// names. A --bundle leaves out the command-line arguments and hashes the
+    /// input path.
+    #[arg(long, value_name = "KEY_FILE", conflicts_with = "owners")]
+    anonymize: Option<PathBuf>,
+
     /// Scan these commits for structural data (entities, deps, and contents).
     ///
     /// If not provided, these will only be extracted from the first COMMIT
@@ -174,10 +618,26 @@ struct Opts {
     ///
     /// Defaults to WORKDIR if not specified. If input is a bare repository,
     /// then it will default to HEAD. Entities, deps, and contents will only be
-    /// extracted from the first commit.
+    /// extracted from the first commit. A range (e.g. `v1.0..v2.0` or
+    /// `HEAD~100..HEAD`) is expanded to the commits `git log` would list,
+    /// newest first.
     #[arg(value_name = "COMMIT")]
     revspecs: Vec<String>,
 
+    /// Also scan every commit reachable from any ref (like `git log --all`)
+    ///
+    /// Each of these commits is recorded (in commits and revspecs) with the
+    /// first ref, by name, that reaches it.
+    #[arg(long)]
+    all_refs: bool,
+
+    /// Also scan every commit reachable from the branches matching GLOB
+    ///
+    /// Like `git log --branches=GLOB`, so `release/*` would scan the
+    /// maintenance lines under `refs/heads/release/`. May be repeated.
+    #[arg(long, value_name = "GLOB")]
+    branches: Vec<String>,
+
     #[clap(flatten)]
     pathspec_opts: PathspecOpts,
 
@@ -192,6 +652,23 @@ struct Opts {
 }
 
 impl Opts {
+    /// The pathspec of scanned files, including any extra files.
+    fn pathspec(&self) -> Result<Pathspec> {
+        let extra = &self.include_extra_files;
+        let extra_pathspec = Pathspec::try_from_vec(extra.clone())
+            .with_context(|| format!("failed to parse extra file patterns: {:?}", extra))?;
+        let mut pathspec = self.pathspec_opts.pathspec()?.merge(&extra_pathspec);
+
+        if self.include_non_source {
+            pathspec = pathspec.merge(&Pathspec::new(["*"]));
+        }
+
+        match self.build_manifests {
+            true => Ok(pathspec.merge(&manifests::pathspec())),
+            false => Ok(pathspec),
+        }
+    }
+
     fn contains(&self, table: Resource) -> bool {
         if self.resources.is_empty() {
             true
@@ -201,18 +678,57 @@ impl Opts {
     }
 
     fn absolute_input(&self) -> PathBuf {
-        if let Some(input) = self.input.clone() {
-            if input.is_absolute() {
-                input
-            } else {
-                std::env::current_dir().unwrap().join(input)
-            }
-        } else {
-            std::env::current_dir().unwrap()
-        }
+        to_absolute(self.input.clone())
     }
 }
 
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Report the number of files and bytes of each language.
+    ///
+    /// Only considers files matched by the pathspec at a single commit. The
+    /// output is similar to that of GitHub's linguist. Useful to check that a
+    /// pathspec selects what you expect before running a full scan.
+    Langs(LangsOpts),
+
+    /// Report which features are supported for each language.
+    ///
+    /// Shows whether entities finer than files can be extracted and which
+    /// dependency resolvers are available. Useful to verify support before
+    /// running a full scan.
+    Capabilities(CapabilitiesOpts),
+}
+
+#[derive(Debug, Args)]
+struct CapabilitiesOpts {
+    /// Print the report as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Args)]
+struct LangsOpts {
+    /// The root of the project/repository to scan.
+    ///
+    /// If not specified, will use the current working directory.
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+
+    /// Print the report as JSON.
+    #[arg(long)]
+    json: bool,
+
+    /// The commit to report on.
+    ///
+    /// Defaults to WORKDIR if not specified. If input is a bare repository,
+    /// then it will default to HEAD.
+    #[arg(value_name = "COMMIT")]
+    commit: Option<String>,
+
+    #[clap(flatten)]
+    pathspec_opts: PathspecOpts,
+}
+
 #[derive(Debug, Args)]
 struct LoggingOpts {
     #[command(flatten)]
@@ -224,10 +740,18 @@ struct DependsOpts {
     /// Path to the depends.jar that is used for Depends dependency resolution.
     ///
     /// If not provided, will look for depends.jar in the same directory as this
-    /// executable.
+    /// executable and then in the directory used by --depends-install.
     #[arg(long, global = true)]
     depends_jar: Option<PathBuf>,
 
+    /// Download depends.jar if it cannot be found.
+    ///
+    /// The version of depends.jar released alongside this version of
+    /// Neodepends is downloaded to the user's cache directory and its checksum
+    /// is verified. Later runs will find it there without this flag.
+    #[arg(long, global = true, conflicts_with = "depends_jar")]
+    depends_install: bool,
+
     /// Java executable used for running depends.jar.
     ///
     /// If not provided, will assume "java" is on the system path.
@@ -241,15 +765,41 @@ struct DependsOpts {
     /// gigabyte memory allocation pool.
     #[arg(long, global = true)]
     depends_xmx: Option<String>,
+
+    /// Directory in which Depends writes a copy of each scanned file.
+    ///
+    /// If not provided, will use the system's temporary directory. Useful when
+    /// that directory is on a small partition.
+    #[arg(long, global = true)]
+    scratch_dir: Option<PathBuf>,
+
+    /// When to write scanned files to the scratch directory.
+    ///
+    /// "batch" writes the files of every commit and language before running
+    /// Depends on any of them. "stream" writes, resolves, and deletes one
+    /// commit and language at a time, which bounds disk usage at the cost of
+    /// parallelism.
+    #[arg(long, global = true, default_value_t, value_parser = strum_parser!(ScratchPolicy))]
+    scratch_policy: ScratchPolicy,
 }
 
 impl DependsOpts {
-    fn to_depends_config(&self) -> DependsConfig {
-        DependsConfig::new(
-            self.depends_jar.clone(),
+    fn to_depends_config(&self) -> Result<DependsConfig> {
+        let jar = match self.depends_install {
+            true => Some(install_depends_jar()?),
+            false => self.depends_jar.clone(),
+        };
+
+        if let Some(scratch_dir) = &self.scratch_dir {
+            std::fs::create_dir_all(scratch_dir).context("failed to create scratch directory")?;
+        }
+
+        Ok(DependsConfig::new(
+            jar,
             self.depends_java.clone(),
             self.depends_xmx.clone(),
-        )
+            self.scratch_dir.clone(),
+        ))
     }
 }
 
@@ -286,37 +836,262 @@ struct ResolverOpts {
     #[arg(short = 'S', long)]
     stackgraphs: bool,
 
+    /// Keep the stack graphs of scanned files in this directory
+    ///
+    /// Building the stack graph of a file is the slowest part of --stackgraphs.
+    /// With a cache directory, the stack graph of a file is only built the
+    /// first time that file (with the same name and content) is scanned, even
+    /// across runs. The cache is invalidated when Neodepends is upgraded.
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
     /// Enable dependency resolution using Depends
     ///
     /// When a both tools support a language, Depends will take priority over
     /// Stack Graphs if specified first on the command line.
     #[arg(short = 'D', long)]
     depends: bool,
+
+    /// Enable dependency resolution using only import statements
+    ///
+    /// Import, include, and require statements are mapped to the files of the
+    /// project by their paths and package names. Much less complete than the
+    /// other resolvers (only imports are found), but supports every language
+    /// and needs no Java. An import that matches several files has a dep to
+    /// each of them with a confidence of "Heuristic". When several resolvers
+    /// support a language, the one specified first on the command line takes
+    /// priority.
+    #[arg(short = 'I', long)]
+    imports: bool,
+
+    /// Enable dependency resolution using an external command for a language
+    ///
+    /// Given as LANG=CMD (e.g. "java=python3 resolve.py"). CMD is run through
+    /// the shell once for each version of the project. It is sent each file as
+    /// a line of JSON on stdin and writes each dep it finds as a line of JSON
+    /// to stdout (see src/plugin.rs). Can be given more than once, for
+    /// different languages. Takes priority over the other resolvers like they
+    /// do over each other, by where it is first given on the command line.
+    #[arg(long, value_name = "LANG=CMD")]
+    resolver_cmd: Vec<String>,
+
+    /// Enable dependency resolution of Python using Pyright
+    ///
+    /// Pyright infers the types of receivers (e.g. attributes assigned in
+    /// __init__), so it finds calls that the other resolvers miss. It is run
+    /// as a language server once for each version of the project and needs
+    /// to be installed separately (e.g. with `npm install -g pyright`). When
+    /// several resolvers support Python, the one specified first on the
+    /// command line takes priority.
+    #[arg(long)]
+    pyright: bool,
+
+    /// Enable dependency resolution by matching names alone
+    ///
+    /// Each identifier is linked to the only entity in the project with the
+    /// same name (or, for languages without entities finer than files, the
+    /// only file with that name before its extension). Far less precise than
+    /// the other resolvers, but supports every language. These are "Use" deps
+    /// with a confidence of "Heuristic". Specify it last to only use it for
+    /// the languages that no other enabled resolver supports.
+    #[arg(long)]
+    name_match: bool,
+
+    /// The command to run for --pyright
+    ///
+    /// Run through the shell. Any language server for Python that supports go
+    /// to definition over stdio can be used.
+    #[arg(long, value_name = "CMD", default_value = DEFAULT_PYRIGHT_CMD)]
+    pyright_cmd: String,
+
+    /// Search this directory for the includes of C and C++ files
+    ///
+    /// Used by --imports. The path is relative to the root of the project and
+    /// is searched like the -I option of a compiler: a quoted include is looked
+    /// for next to the including file first, then in each include directory in
+    /// the order given. Without any include directories, an include is matched
+    /// to every file whose path ends with it. Can be given more than once.
+    #[arg(long, value_name = "DIR")]
+    include_dir: Vec<String>,
+
+    /// How to resolve a language that several enabled resolvers support
+    ///
+    /// With "priority", only the resolver specified first on the command line
+    /// is used. With "union", all of them are used and every dep is reported
+    /// once per resolver that found it. The resolver column of each dep tells
+    /// them apart, so the resolvers can be compared.
+    #[arg(long, default_value_t, value_parser = strum_parser!(ResolverMode))]
+    resolver_mode: ResolverMode,
+
+    /// Fail if a scanned language is not supported by any enabled resolver
+    ///
+    /// Otherwise, a warning is logged and no deps are extracted for that
+    /// language. Only checked when at least one resolver is enabled.
+    #[arg(long)]
+    strict_coverage: bool,
+
+    /// Guess the deps of Python attributes that could not be resolved
+    ///
+    /// An attribute like `x.frobnicate` whose receiver has no known type is
+    /// matched to the only function or method in the project with that name,
+    /// if there is exactly one. These deps have a confidence of "Heuristic"
+    /// rather than "Resolved".
+    #[arg(long)]
+    duck_typing: bool,
+
+    /// Find the deps between the methods and fields of the same Java file
+    ///
+    /// Calls and field accesses by simple name (or through `this`) are matched
+    /// to the members of the enclosing classes with a syntactic pass, which
+    /// keeps deps between entities on the same row apart. These replace the
+    /// deps within the same file that a resolver only found the row of. Found
+    /// even if no resolver is enabled.
+    #[arg(long)]
+    intra_file: bool,
+
+    /// Find the deps from one language to another
+    ///
+    /// Java native methods are linked to the C and C++ functions that
+    /// implement them (by their JNI names). Calls in Python files that import
+    /// ctypes or cffi are linked to the only C or C++ function with the same
+    /// name, with a confidence of "Heuristic". These are "Link" deps with a
+    /// resolver of "cross-language". Found even if no resolver is enabled.
+    #[arg(long)]
+    cross_language: bool,
+
+    /// Guess the deps that Java code makes through reflection
+    ///
+    /// Class names passed to Class.forName or loadClass, bean names passed to
+    /// getBean (matched to Spring beans declared with annotations like
+    /// @Service or @Bean), and any other string literal that is the qualified
+    /// name of a class are matched to the only class (or bean) in the project
+    /// with that name. These are "Use" deps with a confidence of "Heuristic"
+    /// and a resolver of "reflection". Found even if no resolver is enabled.
+    #[arg(long)]
+    reflection: bool,
+
+    /// Also report containment as deps
+    ///
+    /// Each entity gets a "Contain" dep from its parent (e.g. from a class to
+    /// each of its methods), for tools that do not read the parent_id of
+    /// entities. These deps are reported even if no resolver is enabled.
+    #[arg(long)]
+    emit_contain_deps: bool,
+
+    /// Only resolve deps from files under this path
+    ///
+    /// The path is relative to the root of the project. Files elsewhere that
+    /// are imported by files under this path (found the same way as by
+    /// --imports) are still given to the resolver so that deps leaving the
+    /// path are found. Can be given more than once. With
+    /// --format=plantuml, only the classes under this path are drawn.
+    #[arg(long, value_name = "PATH")]
+    scope: Vec<String>,
 }
 
 fn main() -> Result<()> {
     let matches = Opts::command().get_matches();
     let opts = Opts::from_arg_matches(&matches)?;
     env_logger::Builder::new().filter_level(opts.logging_opts.verbose.log_level_filter()).init();
+
+    if let Some(command) = &opts.command {
+        return match command {
+            Command::Langs(langs_opts) => run_langs(langs_opts),
+            Command::Capabilities(capabilities_opts) => run_capabilities(capabilities_opts),
+        };
+    }
+
+    let output = opts.output.as_ref().unwrap();
     let fs = FileSystem::open(opts.absolute_input())?;
-    let pathspec = opts.pathspec_opts.pathspec()?;
-    let depends_config = opts.depends_opts.to_depends_config();
+    let pathspec = opts.pathspec()?;
+    let depends_config = opts.depends_opts.to_depends_config()?;
 
     let format = match opts.format {
         Some(format) => format,
-        None => infer_format(&opts.output)?,
+        None => infer_format(output)?,
     };
 
-    let file_level = match format {
-        OutputFormat::DsmV1 => true,
-        _ => opts.file_level,
+    let compression = opts.compress.or_else(|| infer_compression(output));
+
+    let suppressions = match &opts.suppressions {
+        Some(path) => Suppressions::read(path)?,
+        None => Suppressions::default(),
     };
 
-    let mut extractor = Extractor::new(fs.clone(), file_level);
-    extractor.set_resolver(create_resolver(&matches, depends_config));
+    let taxonomy = match &opts.taxonomy {
+        Some(path) => Taxonomy::read(path)?,
+        None => Taxonomy::default(),
+    };
 
-    let mut structure_commits = try_parse_revspecs(&fs, &opts.structure)?;
-    let history_commits = try_parse_revspecs(&fs, &opts.revspecs)?;
+    let ownership = match &opts.owners {
+        Some(path) => Ownership::read(path)?,
+        None => Ownership::default(),
+    };
+
+    let level = match opts.dir_level {
+        Some(depth) => Level::Dir(depth),
+        None => opts.level,
+    };
+
+    if level.is_team() && opts.owners.is_none() {
+        bail!("--level=team requires --owners");
+    }
+
+    if opts.only_merges && opts.merges.is_skip() {
+        bail!("--only-merges requires --merges=first-parent or --merges=all-parents");
+    }
+
+    if opts.packages && format == OutputFormat::DsmV1 {
+        bail!("--packages is not supported with --format=dsm-v1");
+    }
+
+    let granularity = match format {
+        OutputFormat::DsmV1 => Granularity::File,
+        _ if opts.file_level || !level.is_entity() => Granularity::File,
+        _ => opts.granularity,
+    };
+
+    let mut extractor = Extractor::new(fs.clone(), granularity);
+    extractor.set_duplicate_policy(opts.duplicates);
+    extractor.set_change_attribution(opts.change_attribution);
+    extractor.set_merge_policy(opts.merges);
+    extractor.set_ignore_whitespace(opts.ignore_whitespace);
+    extractor.set_ignore_comment_only_changes(opts.ignore_comment_only_changes);
+
+    extractor.set_scope(opts.resolver_opts.scope.clone());
+    extractor.set_contain_deps(opts.resolver_opts.emit_contain_deps);
+    extractor.set_build_manifests(opts.build_manifests);
+    extractor.set_signature_ids(opts.signature_ids);
+    extractor.set_packages(opts.packages);
+    extractor.set_test_detector(TestDetector::new(opts.test_files.clone())?);
+    let include_dirs = opts.resolver_opts.include_dir.clone();
+    let plugins = opts.resolver_opts.resolver_cmd.iter().map(|s| PluginCommand::parse(s));
+    let plugins = plugins.collect::<Result<Vec<_>>>().context("failed to parse --resolver-cmd")?;
+    let pyright_cmd = opts.resolver_opts.pyright_cmd.clone();
+    let storage = match &opts.resolver_opts.cache_dir {
+        Some(dir) => Some(Arc::new(Storage::open(dir)?)),
+        None => None,
+    };
+    let mut resolver = create_resolver(
+        &matches,
+        depends_config.clone(),
+        include_dirs,
+        plugins,
+        pyright_cmd,
+        storage,
+    );
+    resolver.set_scratch_policy(opts.depends_opts.scratch_policy);
+    resolver.set_mode(opts.resolver_opts.resolver_mode);
+    resolver.set_duck_typing(opts.resolver_opts.duck_typing);
+    resolver.set_intra_file(opts.resolver_opts.intra_file);
+    resolver.set_cross_language(opts.resolver_opts.cross_language);
+    resolver.set_reflection(opts.resolver_opts.reflection);
+    resolver.set_build_manifests(opts.build_manifests);
+
+    let structure_revspecs = try_parse_revspecs(&fs, &opts.structure)?;
+    let mut history_revspecs = try_parse_revspecs(&fs, &opts.revspecs)?;
+    let mut structure_commits = to_commits(&structure_revspecs);
+    let mut history_commits = to_commits(&history_revspecs);
 
     if structure_commits.is_empty() {
         if history_commits.is_empty() {
@@ -330,8 +1105,92 @@ fn main() -> Result<()> {
         }
     }
 
-    prepare_output(&opts.output, opts.force)?;
-    let mut writer = format.open(&opts.output)?;
+    // Walked after the default structure commit is picked, so that it is not
+    // the tip of whichever ref happens to sort first
+    let ref_names = walk_refs(&fs, &opts)?;
+    history_revspecs.extend(
+        ref_names.iter().map(|(name, id)| {
+            Revspec::new(name.clone(), PseudoCommitId::CommitId(*id), unix_time())
+        }),
+    );
+    history_commits = to_commits(&history_revspecs);
+    let ref_names: HashMap<_, _> = ref_names.into_iter().map(|(name, id)| (id, name)).collect();
+
+    if !opts.author.is_empty() {
+        let patterns = opts
+            .author
+            .iter()
+            .map(|p| Regex::new(p).with_context(|| format!("invalid author pattern: {}", p)))
+            .collect::<Result<Vec<_>>>()?;
+        history_revspecs = filter_commits(&fs, history_revspecs, |c| {
+            let author = format!("{} <{}>", c.author_name, c.author_email);
+            patterns.iter().any(|p| p.is_match(&author))
+        })?;
+        history_commits = to_commits(&history_revspecs);
+    }
+
+    if opts.no_merges || opts.only_merges {
+        history_revspecs = filter_commits(&fs, history_revspecs, |c| {
+            let is_merge = c.parent_ids.split_whitespace().count() > 1;
+            is_merge == opts.only_merges
+        })?;
+        history_commits = to_commits(&history_revspecs);
+    }
+
+    if opts.all_deps {
+        structure_commits.extend(history_commits.iter().copied());
+        structure_commits = structure_commits.into_iter().unique().collect();
+    }
+
+    let anonymizer = opts.anonymize.as_ref().map(Anonymizer::open).transpose()?;
+
+    if anonymizer.is_some() && opts.resources.contains(&Resource::Contents) {
+        bail!("Contents cannot be written with --anonymize");
+    }
+
+    let root = match &anonymizer {
+        Some(anonymizer) => anonymizer.path(&opts.absolute_input().to_string_lossy()).into(),
+        None => opts.absolute_input(),
+    };
+    let args: Vec<String> = match &anonymizer {
+        Some(_) => Vec::new(),
+        None => std::env::args().collect(),
+    };
+    let scope = match &anonymizer {
+        Some(anonymizer) => opts.resolver_opts.scope.iter().map(|s| anonymizer.path(s)).collect(),
+        None => opts.resolver_opts.scope.clone(),
+    };
+
+    prepare_output(output, opts.force, opts.append)?;
+
+    if let Some(bundle) = &opts.bundle {
+        if to_absolute(Some(bundle.clone())).starts_with(to_absolute(Some(output.clone()))) {
+            bail!("The bundle cannot be written inside of the output");
+        }
+
+        prepare_output(bundle, opts.force, false)?;
+    }
+
+    let mut writer = format.open(output, OutputOptions {
+        compression,
+        append: opts.append,
+        envelope: opts.envelope,
+        minify: opts.minify,
+        scope,
+        taxonomy: taxonomy.clone(),
+        ownership,
+        level,
+        root: root.clone(),
+        args: args.clone(),
+    })?;
+
+    if let Some(post_script) = &opts.post_script {
+        writer = Box::new(ScriptedWriter::open(post_script, writer, taxonomy)?);
+    }
+
+    if let Some(anonymizer) = &anonymizer {
+        writer = Box::new(AnonymizingWriter::new(writer, anonymizer.clone()));
+    }
 
     if structure_commits.len() > 1 && writer.is_single_structure() {
         bail!("Selected output format can only take the structural information of a single commit")
@@ -339,64 +1198,439 @@ fn main() -> Result<()> {
 
     let mut union_commits = structure_commits.clone();
     union_commits.extend(history_commits.clone());
+    let entity_commits = match opts.all_entities {
+        true => union_commits.iter().copied().unique().collect_vec(),
+        false => structure_commits.clone(),
+    };
     let union_filespec = Filespec::new(union_commits, pathspec.clone());
-    let structure_filespec = Filespec::new(structure_commits, pathspec.clone());
-    let history_filespec = Filespec::new(history_commits, pathspec);
+    let structure_filespec = Filespec::new(structure_commits.clone(), pathspec.clone());
+    let history_pathspec = match opts.history_path.is_empty() {
+        true => pathspec,
+        false => Pathspec::try_from_vec(opts.history_path.clone())
+            .with_context(|| format!("failed to parse patterns: {:?}", opts.history_path))?,
+    };
+    let history_filespec = Filespec::new(history_commits, history_pathspec);
+    let entity_filespec = match opts.all_entities {
+        true => &union_filespec,
+        false => &structure_filespec,
+    };
     let start = Instant::now();
+    let started_at = unix_time();
+    let revspecs = structure_revspecs.into_iter().chain(history_revspecs).unique().collect_vec();
+
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+
+        log::warn!("Interrupted. Finalizing what has been written so far...");
+    })?;
+
+    let written: HashMap<_, _> = Resource::iter().map(|r| (r, AtomicUsize::new(0))).collect();
+    let wrote = |resource| written[&resource].fetch_add(1, Ordering::Relaxed);
 
     let should_extract = |resource: Resource| writer.supports(resource) && opts.contains(resource);
+    let write_deps = should_extract(Resource::Deps);
+    let write_changes = should_extract(Resource::Changes);
+    let churn = match should_extract(Resource::DepChanges) && structure_commits.len() > 1 {
+        true => Some(DepChurn::new()),
+        false => None,
+    };
+    let alignment = match should_extract(Resource::Alignments) {
+        true => Some(AlignmentBuilder::new()),
+        false => None,
+    };
+    let fans = match should_extract(Resource::Metrics) {
+        true => Some(FanCounter::new()),
+        false => None,
+    };
+    let hierarchy = match should_extract(Resource::Hierarchy) {
+        true => Some(HierarchyBuilder::new()),
+        false => None,
+    };
+    let collapser = match write_deps && opts.dedup_deps {
+        true => Some(DepCollapser::new()),
+        false => None,
+    };
+    let write_unresolved_refs = should_extract(Resource::UnresolvedRefs);
+    let extract_deps = write_deps
+        || write_unresolved_refs
+        || churn.is_some()
+        || alignment.is_some()
+        || fans.is_some()
+        || hierarchy.is_some();
+
+    if extract_deps && !resolver.is_empty() {
+        let langs = match opts.pathspec_opts.langs.is_empty() {
+            true => fs
+                .list(&structure_filespec)
+                .files()
+                .iter()
+                .filter_map(|f| Lang::of(&f.filename))
+                .unique()
+                .sorted()
+                .collect(),
+            false => opts.pathspec_opts.langs.clone(),
+        };
+        let strict = opts.strict || opts.resolver_opts.strict_coverage;
+        check_coverage(&resolver, &langs, strict)?;
+
+        if langs.iter().any(|&l| resolver.resolver_names(l).contains(&"depends")) {
+            depends_config.check_java()?;
+        }
+    }
 
-    if should_extract(Resource::Entities) {
+    let resolvers = match opts.bundle.is_some() {
+        true => resolver.versions()?,
+        false => Vec::new(),
+    };
+    extractor.set_resolver(resolver);
+
+    if opts.strict {
+        log::info!("Checking that the entities of every file can be extracted...");
+        extractor.check_entities(&union_filespec)?;
+    }
+
+    if should_extract(Resource::Entities) && !interrupted() {
         log::info!("Extracting and writing entities...");
-        let filespec = match opts.all_entities {
-            true => &union_filespec,
-            false => &structure_filespec,
-        };
-        extractor.extract_entities(filespec).for_each(|v| {
-            writer.write_entity(v).unwrap();
-        });
+        extractor.extract_entities(entity_filespec).take_any_while(|_| !interrupted()).for_each(
+            |v| {
+                writer.write_entity(v).unwrap();
+                wrote(Resource::Entities);
+            },
+        );
     }
 
-    if should_extract(Resource::Deps) {
+    if should_extract(Resource::Annotations) && !interrupted() {
+        log::info!("Extracting and writing annotations...");
+        for value in extractor.extract_annotations(entity_filespec) {
+            writer.write_annotation(value)?;
+            wrote(Resource::Annotations);
+        }
+    }
+
+    if should_extract(Resource::Files) && !interrupted() {
+        log::info!("Writing files...");
+        for value in extractor.extract_files(entity_filespec) {
+            writer.write_file(value)?;
+            wrote(Resource::Files);
+        }
+    }
+
+    if should_extract(Resource::EntityMatches) && entity_commits.len() > 1 && !interrupted() {
+        log::info!("Matching and writing entities across versions...");
+        let mut entities = extractor.entities_by_commit(entity_filespec);
+        let versions = entity_commits
+            .iter()
+            .map(|c| (*c, entities.remove(c).unwrap_or_default()))
+            .collect_vec();
+        let code_hashes = extractor.code_hashes(versions.iter().flat_map(|(_, e)| e));
+        for value in match_versions(&versions, &code_hashes) {
+            writer.write_entity_match(value)?;
+            wrote(Resource::EntityMatches);
+        }
+    }
+
+    if extract_deps && !interrupted() {
         log::info!("Extracting and writing deps...");
-        extractor.extract_deps(&structure_filespec).for_each(|v| {
-            writer.write_dep(v).unwrap();
-        });
+        let paths = match suppressions.is_empty() {
+            true => HashMap::new(),
+            false => extractor.entity_paths(&structure_filespec),
+        };
+        extractor.extract_deps(&structure_filespec).take_any_while(|_| !interrupted()).for_each(
+            |v| {
+                if suppressions.suppresses(&paths, &v.src, &v.tgt) {
+                    return;
+                }
+                if let Some(churn) = &churn {
+                    churn.add(&v);
+                }
+                if let Some(alignment) = &alignment {
+                    alignment.add_dep(&v);
+                }
+                if let Some(fans) = &fans {
+                    fans.add_dep(&v);
+                }
+                if let Some(hierarchy) = &hierarchy {
+                    hierarchy.add_dep(&v);
+                }
+                if let Some(collapser) = &collapser {
+                    collapser.add(v);
+                } else if write_deps {
+                    writer.write_dep(v).unwrap();
+                    wrote(Resource::Deps);
+                }
+            },
+        );
+
+        if opts.strict {
+            extractor.check_resolvers()?;
+        }
     }
 
-    if should_extract(Resource::Changes) {
+    if let Some(collapser) = collapser.filter(|_| !interrupted()) {
+        for value in collapser.build() {
+            writer.write_dep(value)?;
+            wrote(Resource::Deps);
+        }
+    }
+
+    if write_unresolved_refs && !interrupted() {
+        log::info!("Writing unresolved refs...");
+        for value in extractor.take_unresolved_refs() {
+            writer.write_unresolved_ref(value)?;
+            wrote(Resource::UnresolvedRefs);
+        }
+    }
+
+    if should_extract(Resource::ExternalDeps) && !interrupted() {
+        log::info!("Extracting and writing external deps...");
+        for value in extractor.extract_external_deps(&structure_filespec) {
+            writer.write_external_dep(value)?;
+            wrote(Resource::ExternalDeps);
+        }
+    }
+
+    if (write_changes || alignment.is_some()) && !interrupted() {
         log::info!("Extracting and writing changes...");
-        extractor.extract_changes(&history_filespec).for_each(|v| {
-            writer.write_change(v).unwrap();
-        });
+        extractor.extract_changes(&history_filespec).take_any_while(|_| !interrupted()).for_each(
+            |v| {
+                if let Some(alignment) = &alignment {
+                    alignment.add_change(&v);
+                }
+                if write_changes {
+                    writer.write_change(v).unwrap();
+                    wrote(Resource::Changes);
+                }
+            },
+        );
     }
 
-    if should_extract(Resource::Contents) {
+    if should_extract(Resource::Contents) && !interrupted() {
         log::info!("Extracting and writing contents...");
-        extractor.extract_contents(&structure_filespec).for_each(|v| {
-            writer.write_content(v).unwrap();
-        });
+        extractor
+            .extract_contents(&structure_filespec)
+            .take_any_while(|_| !interrupted())
+            .for_each(|v| {
+                writer.write_content(v).unwrap();
+                wrote(Resource::Contents);
+            });
+    }
+
+    let simple_paths = match suppressions.is_empty() {
+        true => HashMap::new(),
+        false => extractor.simple_paths(&structure_filespec),
+    };
+
+    if let Some(churn) = churn.filter(|_| !interrupted()) {
+        log::info!("Writing dep changes...");
+        let simple_ids = extractor.simple_ids(&structure_filespec);
+        for value in churn.into_dep_changes(&structure_commits, &simple_ids) {
+            if !suppressions.suppresses(&simple_paths, &value.src, &value.tgt) {
+                writer.write_dep_change(value)?;
+                wrote(Resource::DepChanges);
+            }
+        }
+    }
+
+    if let Some(alignment) = alignment.filter(|_| !interrupted()) {
+        log::info!("Writing alignments...");
+        let simple_ids = extractor.simple_ids(&structure_filespec);
+        let parents = extractor.simple_parents(&structure_filespec);
+        for value in alignment.build(&simple_ids, &parents) {
+            if !suppressions.suppresses(&simple_paths, &value.a, &value.b)
+                && !suppressions.suppresses(&simple_paths, &value.b, &value.a)
+            {
+                writer.write_alignment(value)?;
+                wrote(Resource::Alignments);
+            }
+        }
+    }
+
+    if let Some(fans) = fans.filter(|_| !interrupted()) {
+        log::info!("Writing metrics...");
+        let entities = extractor.entities_by_commit(&structure_filespec);
+        let code = extractor.code_metrics(entities.values().flatten());
+        for value in fans.build(&entities, &code) {
+            writer.write_metric(value)?;
+            wrote(Resource::Metrics);
+        }
+    }
+
+    if let Some(hierarchy) = hierarchy.filter(|_| !interrupted()) {
+        log::info!("Writing hierarchy...");
+        let entities = extractor.entities_by_commit(&structure_filespec);
+        for value in hierarchy.build(&entities) {
+            writer.write_supertype(value)?;
+            wrote(Resource::Hierarchy);
+        }
+    }
+
+    if should_extract(Resource::Diagnostics) && !interrupted() {
+        log::info!("Writing diagnostics...");
+        for value in extractor.take_diagnostics(&structure_filespec) {
+            writer.write_diagnostic(value)?;
+            wrote(Resource::Diagnostics);
+        }
+    }
+
+    if should_extract(Resource::Commits) && !interrupted() {
+        log::info!("Writing commits...");
+        for commit_id in union_filespec.commits.iter().filter_map(|c| c.try_as_commit_id()).sorted()
+        {
+            let mut commit = fs.commit(commit_id)?;
+            commit.ref_name = ref_names.get(&commit_id).cloned();
+            writer.write_commit(commit)?;
+            wrote(Resource::Commits);
+        }
+    }
+
+    if should_extract(Resource::Revspecs) && !interrupted() {
+        log::info!("Writing revspecs...");
+        for revspec in revspecs.iter().cloned() {
+            writer.write_revspec(revspec)?;
+            wrote(Resource::Revspecs);
+        }
     }
 
     writer.finalize()?;
+
+    if interrupted() {
+        let counts = written
+            .iter()
+            .map(|(r, n)| (r, n.load(Ordering::Relaxed)))
+            .filter(|(_, n)| *n > 0)
+            .sorted()
+            .map(|(r, n)| format!("{} {}", n, r))
+            .join(", ");
+        bail!("Interrupted after writing {}", if counts.is_empty() { "nothing" } else { &counts });
+    }
+
+    if let Some(bundle) = &opts.bundle {
+        log::info!("Writing bundle to {}...", bundle.display());
+        let manifest = Manifest {
+            tool_version: env!("CARGO_PKG_VERSION"),
+            args,
+            input: root.display().to_string(),
+            format: format.to_string(),
+            revspecs: match &anonymizer {
+                Some(anonymizer) => revspecs
+                    .into_iter()
+                    .map(|r| Revspec { revspec: anonymizer.name(&r.revspec), ..r })
+                    .collect(),
+                None => revspecs,
+            },
+            resolvers: resolvers
+                .into_iter()
+                .map(|(name, version)| ResolverVersion { name, version })
+                .collect(),
+            started_at,
+            finished_at: unix_time(),
+        };
+        write_bundle(bundle, output, &manifest)?;
+    }
+
     log::info!("Finished in {}ms", start.elapsed().as_millis());
     Ok(())
 }
 
+/// Set by the Ctrl-C handler to stop extraction early.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+fn run_langs(opts: &LangsOpts) -> Result<()> {
+    let fs = FileSystem::open(to_absolute(opts.input.clone()))?;
+    let pathspec = opts.pathspec_opts.pathspec()?;
+
+    let commit = match &opts.commit {
+        Some(revspec) => fs.parse_as_commit(revspec)?,
+        None if fs.is_bare_repo() => fs.head(),
+        None => PseudoCommitId::WorkDir,
+    };
+
+    let stats = stats::lang_stats(&fs, &Filespec::new([commit], pathspec))?;
+
+    match opts.json {
+        true => println!("{}", stats::to_json(&stats)),
+        false => print!("{}", stats::to_text(&stats)),
+    }
+
+    Ok(())
+}
+
+fn run_capabilities(opts: &CapabilitiesOpts) -> Result<()> {
+    let capabilities = Lang::iter().map(|l| l.capabilities()).collect_vec();
+
+    if opts.json {
+        println!("{}", serde_json::to_string_pretty(&capabilities)?);
+        return Ok(());
+    }
+
+    let header = ["lang", "entities", "imports", "stackgraphs", "depends", "pyright"];
+    let rows = capabilities
+        .into_iter()
+        .map(|c| {
+            vec![
+                c.lang,
+                table::yes_or_no(c.entities),
+                table::yes_or_no(c.imports),
+                table::yes_or_no(c.stackgraphs),
+                c.depends.unwrap_or("-").to_string(),
+                table::yes_or_no(c.pyright),
+            ]
+        })
+        .collect_vec();
+
+    print!("{}", table::render(&header, &rows));
+    Ok(())
+}
+
+fn to_absolute(path: Option<PathBuf>) -> PathBuf {
+    if let Some(path) = path {
+        if path.is_absolute() {
+            path
+        } else {
+            std::env::current_dir().unwrap().join(path)
+        }
+    } else {
+        std::env::current_dir().unwrap()
+    }
+}
+
+fn infer_compression<P: AsRef<Path>>(output: P) -> Option<Compression> {
+    let extension = output.as_ref().extension()?.to_ascii_lowercase();
+    Compression::iter().find(|c| extension.to_str() == Some(c.extension()))
+}
+
 fn infer_format<P: AsRef<Path>>(output: P) -> Result<OutputFormat> {
+    let output = match infer_compression(&output) {
+        Some(_) => Path::new(output.as_ref().file_stem().unwrap()),
+        None => output.as_ref(),
+    };
+
     output
-        .as_ref()
         .extension()
         .and_then(|e| match e.to_ascii_lowercase().to_str() {
             Some("db") => Some(OutputFormat::Sqlite),
+            Some("dot") | Some("gv") => Some(OutputFormat::Dot),
+            Some("graphml") => Some(OutputFormat::Graphml),
             Some("json") => Some(OutputFormat::DsmV2),
             Some("jsonl") => Some(OutputFormat::Jsonl),
+            Some("md") => Some(OutputFormat::Markdown),
+            Some("puml") => Some(OutputFormat::Plantuml),
+            Some("binpb") | Some("pb") => Some(OutputFormat::Proto),
+            Some("scip") => Some(OutputFormat::Scip),
+            Some("dsl") => Some(OutputFormat::Structurizr),
+            Some("xlsx") => Some(OutputFormat::Xlsx),
             _ => None,
         })
         .context("Could not infer file format. Use --format to specify.")
 }
 
-fn prepare_output<P: AsRef<Path>>(output: P, force: bool) -> Result<()> {
+fn prepare_output<P: AsRef<Path>>(output: P, force: bool, append: bool) -> Result<()> {
     let path = output.as_ref();
     let path_str = path.to_string_lossy();
 
@@ -407,6 +1641,11 @@ fn prepare_output<P: AsRef<Path>>(output: P, force: bool) -> Result<()> {
         return Ok(());
     }
 
+    if append {
+        log::info!("Appending to existing output at {}", &path_str);
+        return Ok(());
+    }
+
     if !force {
         bail!("Output path ({}) already exists. Use --force to overwrite it.", &path_str);
     }
@@ -436,34 +1675,33 @@ fn prepare_output<P: AsRef<Path>>(output: P, force: bool) -> Result<()> {
     Ok(())
 }
 
-fn try_parse_revspecs(fs: &FileSystem, revspecs: &[String]) -> Result<Vec<PseudoCommitId>> {
-    let mut ids = Vec::with_capacity(revspecs.len());
+fn try_parse_revspecs(fs: &FileSystem, revspecs: &[String]) -> Result<Vec<Revspec>> {
+    let mut parsed = Vec::with_capacity(revspecs.len());
 
     for revspec in revspecs {
-        if let Ok(id) = fs.parse_as_commit(revspec) {
-            ids.push(id);
+        if let Ok(ids) = fs.parse_as_commits(revspec) {
+            parsed.extend(ids.into_iter().map(|id| Revspec::new(revspec.clone(), id, unix_time())));
         } else {
-            ids.extend(try_read_file_revspecs(fs, revspec)?);
+            parsed.extend(try_read_file_revspecs(fs, revspec)?);
         }
     }
 
-    let ids = ids.into_iter().unique().collect_vec();
-
-    Ok(ids)
+    Ok(parsed)
 }
 
-fn try_read_file_revspecs(fs: &FileSystem, path: &str) -> Result<Vec<PseudoCommitId>> {
+fn try_read_file_revspecs(fs: &FileSystem, path: &str) -> Result<Vec<Revspec>> {
     let mut buf = String::new();
 
     File::open(path)
         .and_then(|mut f| f.read_to_string(&mut buf))
         .with_context(|| format!("'{}' is not a commit in this repository or a file", path))?;
 
-    let mut ids = Vec::new();
+    let mut parsed = Vec::new();
 
     for (i, line) in buf.lines().enumerate() {
-        if let Ok(id) = fs.parse_as_commit(&line) {
-            ids.push(id);
+        if let Ok(ids) = fs.parse_as_commits(line) {
+            parsed
+                .extend(ids.into_iter().map(|id| Revspec::new(line.to_string(), id, unix_time())));
         } else {
             let path = std::fs::canonicalize(path).unwrap_or(path.into());
             let path = path.to_string_lossy();
@@ -471,13 +1709,86 @@ fn try_read_file_revspecs(fs: &FileSystem, path: &str) -> Result<Vec<PseudoCommi
         }
     }
 
-    Ok(ids)
+    Ok(parsed)
 }
 
-fn create_resolver(matches: &ArgMatches, config: DependsConfig) -> ResolverManager {
+/// The commits reachable from the refs chosen by --all-refs and --branches.
+fn walk_refs(fs: &FileSystem, opts: &Opts) -> Result<Vec<(String, CommitId)>> {
+    let mut globs = opts.branches.iter().map(|b| format!("refs/heads/{}", b)).collect_vec();
+
+    if opts.all_refs {
+        globs = vec!["refs/*".to_string()];
+    }
+
+    if globs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    fs.walk_refs(&globs)
+}
+
+/// Keep the revspecs of the commits for which `keep` is true. WORKDIR is never
+/// kept, as it is not a commit.
+fn filter_commits<F>(fs: &FileSystem, revspecs: Vec<Revspec>, keep: F) -> Result<Vec<Revspec>>
+where
+    F: Fn(&Commit) -> bool,
+{
+    let mut filtered = Vec::with_capacity(revspecs.len());
+
+    for revspec in revspecs {
+        if let Some(commit_id) = revspec.commit_id.try_as_commit_id() {
+            if keep(&fs.commit(commit_id)?) {
+                filtered.push(revspec);
+            }
+        }
+    }
+
+    Ok(filtered)
+}
+
+fn to_commits(revspecs: &[Revspec]) -> Vec<PseudoCommitId> {
+    revspecs.iter().map(|r| r.commit_id).unique().collect_vec()
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Warn (or fail if `strict`) when a language is not supported by any resolver.
+fn check_coverage(resolver: &ResolverManager, langs: &[Lang], strict: bool) -> Result<()> {
+    let gaps = langs.iter().filter(|&&l| resolver.resolver_names(l).is_empty()).join(", ");
+
+    if gaps.is_empty() {
+        return Ok(());
+    }
+
+    for line in resolver.coverage_table(langs).lines() {
+        log::warn!("{}", line);
+    }
+
+    if strict {
+        bail!("No enabled resolver supports the following language(s): {}", gaps);
+    }
+
+    log::warn!("No deps will be extracted for: {}. Use --strict-coverage to fail instead.", gaps);
+    Ok(())
+}
+
+fn create_resolver(
+    matches: &ArgMatches,
+    config: DependsConfig,
+    include_dirs: Vec<String>,
+    plugins: Vec<PluginCommand>,
+    pyright_cmd: String,
+    storage: Option<Arc<Storage>>,
+) -> ResolverManager {
     let mut map: HashMap<&str, Box<dyn ResolverFactory>> = HashMap::new();
-    map.insert("stackgraphs", Box::new(StackGraphsResolverFactory::new()));
+    map.insert("stackgraphs", Box::new(StackGraphsResolverFactory::new(storage)));
     map.insert("depends", Box::new(DependsResolverFactory::new(config)));
+    map.insert("imports", Box::new(ImportResolverFactory::new(include_dirs)));
+    map.insert("resolver_cmd", Box::new(PluginResolverFactory::new(plugins)));
+    map.insert("pyright", Box::new(PyrightResolverFactory::new(pyright_cmd)));
+    map.insert("name_match", Box::new(NameMatchResolverFactory::new()));
     ResolverManager::new(sort_by_flag_index(matches, map))
 }
 
@@ -489,10 +1800,11 @@ fn sort_by_flag_index<V>(matches: &ArgMatches, map: HashMap<&str, V>) -> Vec<V>
         .collect()
 }
 
+/// Where a flag (or the first occurrence of an option) was given on the
+/// command line, if at all.
 fn get_flag_index(matches: &ArgMatches, flag: &str) -> Option<usize> {
-    if matches.get_flag(flag) {
-        Some(matches.index_of(flag).unwrap())
-    } else {
-        None
+    match matches.value_source(flag) {
+        Some(ValueSource::CommandLine) => matches.index_of(flag),
+        _ => None,
     }
 }
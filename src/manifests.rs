@@ -0,0 +1,564 @@
+//! Turn build manifests into modules so the build structure of a project shows
+//! up next to its code.
+//!
+//! Each manifest becomes a [EntityKind::Module] entity inside its file entity,
+//! named like the build tool names it:
+//!
+//! - Maven (`pom.xml`): `groupId:artifactId`, with the group inherited from the
+//!   parent if not given.
+//! - npm (`package.json`): The `name` field.
+//! - Cargo (`Cargo.toml`): The name of the `[package]`.
+//! - Gradle (`build.gradle` and `build.gradle.kts`) and pip
+//!   (`requirements*.txt`), as well as manifests without a name (like a virtual
+//!   Cargo workspace): The directory of the manifest.
+//!
+//! Between modules, two kinds of deps are found:
+//!
+//! - [DepKind::Extend]: A Maven module to its `<parent>`, a Cargo package that
+//!   inherits anything with `workspace = true` to its workspace, a Gradle
+//!   project to the closest project above it that configures `subprojects` or
+//!   `allprojects`, and a requirements file to those it includes with `-r`.
+//! - [DepKind::Dependency]: A module to the modules of the project it declares
+//!   as dependencies. Maven, npm, and Cargo match dependencies by name. Gradle
+//!   matches `project(":a:b")` to the project in the directory `a/b` below the
+//!   topmost Gradle project. Pip matches local paths (e.g. `-e ./libs/foo`) to
+//!   the only manifest in that directory.
+//!
+//! Only dependencies on modules of the same project are found, and a name only
+//! matches if exactly one module of that kind has it. The manifests are read
+//! with simple scanners rather than full parsers, so unusual layouts (like
+//! dependencies declared through a variable) are missed.
+//!
+//! [EntityKind::Module]: crate::core::EntityKind::Module
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use rayon::prelude::*;
+use regex::Regex;
+
+use crate::core::Dep;
+use crate::core::DepKind;
+use crate::core::FileDep;
+use crate::core::FileEndpoint;
+use crate::core::FileKey;
+use crate::core::MultiFileSet;
+use crate::core::PartialPosition;
+use crate::core::PseudoCommitId;
+use crate::filesystem::FileReader;
+use crate::imports::dirname;
+use crate::imports::normalize;
+use crate::spec::Pathspec;
+use crate::tagging::to_module_entity_set;
+use crate::tagging::EntitySet;
+
+lazy_static! {
+    static ref XML_TOKEN: Regex =
+        Regex::new(r"(?s)<!--.*?-->|<!\[CDATA\[.*?\]\]>|<[?!][^>]*>|<(/?)([^\s/>]+)[^>]*?(/?)>")
+            .unwrap();
+    static ref GRADLE_PROJECT: Regex = Regex::new(
+        r#"(?m)^[ \t]*[A-Za-z_]\w*[ \t]*\(?[ \t]*project[ \t]*\([ \t]*(?:path[ \t]*[:=][ \t]*)?["']([^"']+)["']"#
+    )
+    .unwrap();
+    static ref GRADLE_ROOT: Regex = Regex::new(r"\b(?:subprojects|allprojects)\s*\{").unwrap();
+    static ref CARGO_WORKSPACE: Regex = Regex::new(r"\bworkspace\s*=\s*true\b").unwrap();
+    static ref CARGO_PACKAGE: Regex = Regex::new(r#"\bpackage\s*=\s*"([^"]+)""#).unwrap();
+}
+
+/// The file patterns of every kind of manifest.
+const PATTERNS: &[&str] = &[
+    "pom.xml",
+    "*/pom.xml",
+    "build.gradle",
+    "*/build.gradle",
+    "build.gradle.kts",
+    "*/build.gradle.kts",
+    "package.json",
+    "*/package.json",
+    "Cargo.toml",
+    "*/Cargo.toml",
+    "requirements*.txt",
+    "*/requirements*.txt",
+];
+
+/// The sections of a `package.json` that list dependencies.
+const NPM_SECTIONS: &[&str] =
+    &["dependencies", "devDependencies", "peerDependencies", "optionalDependencies"];
+
+/// The build tool that reads a manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Format {
+    Cargo,
+    Gradle,
+    Maven,
+    Npm,
+    Pip,
+}
+
+impl Format {
+    fn of(filename: &str) -> Option<Self> {
+        let (dir, basename) = filename.rsplit_once('/').unwrap_or(("", filename));
+
+        match basename {
+            "pom.xml" => Some(Self::Maven),
+            "build.gradle" | "build.gradle.kts" => Some(Self::Gradle),
+            "package.json" => Some(Self::Npm),
+            "Cargo.toml" => Some(Self::Cargo),
+            _ if !basename.ends_with(".txt") => None,
+            _ if basename.starts_with("requirements") => Some(Self::Pip),
+            _ if dir.rsplit('/').next() == Some("requirements") => Some(Self::Pip),
+            _ => None,
+        }
+    }
+}
+
+/// A module declared by a manifest.
+#[derive(Debug)]
+struct Manifest {
+    format: Format,
+    name: String,
+
+    /// Can the modules below this one extend it? See [Target::Root].
+    is_root: bool,
+
+    refs: Vec<Ref>,
+}
+
+/// A reference from a manifest to another module.
+#[derive(Debug)]
+struct Ref {
+    kind: DepKind,
+    target: Target,
+    row: usize,
+}
+
+/// How a [Ref] names the module it refers to.
+#[derive(Debug)]
+enum Target {
+    /// The module of the same format with this name.
+    Name(String),
+
+    /// The manifest at this path.
+    File(String),
+
+    /// The only manifest in this directory.
+    Dir(String),
+
+    /// The Gradle project with this path (e.g. `:a:b`).
+    GradleProject(String),
+
+    /// The closest manifest of the same format in a directory above this one
+    /// that is a root.
+    Root,
+}
+
+/// The pathspec of every kind of manifest.
+pub fn pathspec() -> Pathspec {
+    Pathspec::new(PATTERNS.iter().copied())
+}
+
+/// Tag a manifest as a file entity holding a single module entity.
+///
+/// Returns None if the file is not a manifest or could not be read.
+pub fn tag(filename: &str, content: &str) -> Option<EntitySet> {
+    let manifest = parse(filename, content)?;
+    Some(to_module_entity_set(filename, content, &manifest.name))
+}
+
+/// Find the deps between the modules of each version.
+pub fn resolve<R: FileReader>(reader: &R, files: &MultiFileSet) -> Vec<FileDep> {
+    files
+        .iter()
+        .flat_map(|(&commit_id, file_set)| {
+            let manifests: Vec<_> = file_set
+                .iter()
+                .filter(|f| Format::of(&f.filename).is_some())
+                .collect_vec()
+                .into_par_iter()
+                .filter_map(|f| Some((f, parse(&f.filename, &reader.read(f.content_id).ok()?)?)))
+                .collect();
+
+            let by_file: HashMap<_, _> =
+                manifests.iter().map(|(f, m)| (f.filename.as_str(), (*f, m))).collect();
+            let by_name = unique(manifests.iter().map(|(f, m)| ((m.format, m.name.as_str()), *f)));
+            let by_dir = unique(manifests.iter().map(|(f, _)| (dirname(&f.filename), *f)));
+
+            let find = |src: &FileKey, manifest: &Manifest, target: &Target| match target {
+                Target::Name(name) => by_name.get(&(manifest.format, name.as_str())).copied(),
+                Target::File(path) => by_file.get(path.as_str()).map(|(f, _)| *f),
+                Target::Dir(dir) => by_dir.get(dir.as_str()).copied(),
+                Target::GradleProject(path) => {
+                    let top = ancestors(dirname(&src.filename))
+                        .filter(|d| is_gradle_dir(&by_file, d))
+                        .last()?;
+                    let dir = match path.strip_prefix(':') {
+                        Some(path) => normalize(top, &path.replace(':', "/")),
+                        None => normalize(dirname(&src.filename), &path.replace(':', "/")),
+                    };
+                    gradle_file(&by_file, &dir)
+                }
+                Target::Root => ancestors(dirname(&src.filename))
+                    .skip(1)
+                    .find_map(|dir| {
+                        by_file.values().find(|(f, m)| {
+                            m.format == manifest.format && m.is_root && dirname(&f.filename) == dir
+                        })
+                    })
+                    .map(|(f, _)| *f),
+            };
+
+            manifests
+                .iter()
+                .flat_map(|(src, manifest)| {
+                    manifest.refs.iter().filter_map(move |r| {
+                        let tgt = find(src, manifest, &r.target).filter(|&t| t != *src)?;
+                        Some(to_dep(src, tgt, r, commit_id))
+                    })
+                })
+                .collect_vec()
+        })
+        .collect()
+}
+
+/// Map each key to its manifest, leaving out keys shared by several manifests.
+fn unique<'a, K, I>(keys: I) -> HashMap<K, &'a FileKey>
+where
+    K: Eq + std::hash::Hash,
+    I: Iterator<Item = (K, &'a FileKey)>,
+{
+    keys.into_group_map()
+        .into_iter()
+        .filter_map(|(k, files)| match files.as_slice() {
+            [file] => Some((k, *file)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A directory followed by each of the directories above it, ending with "".
+fn ancestors(dir: &str) -> impl Iterator<Item = &str> {
+    let mut next = Some(dir);
+
+    std::iter::from_fn(move || {
+        let curr = next?;
+        next = match curr {
+            "" => None,
+            _ => Some(dirname(curr)),
+        };
+        Some(curr)
+    })
+}
+
+fn is_gradle_dir(by_file: &HashMap<&str, (&FileKey, &Manifest)>, dir: &str) -> bool {
+    gradle_file(by_file, dir).is_some()
+}
+
+/// The Gradle build file in a directory, if any.
+fn gradle_file<'a>(
+    by_file: &HashMap<&str, (&'a FileKey, &Manifest)>,
+    dir: &str,
+) -> Option<&'a FileKey> {
+    ["build.gradle", "build.gradle.kts"]
+        .iter()
+        .find_map(|name| by_file.get(normalize(dir, name).as_str()))
+        .map(|(f, _)| *f)
+}
+
+fn to_dep(src: &FileKey, tgt: &FileKey, r: &Ref, commit_id: PseudoCommitId) -> FileDep {
+    let position = PartialPosition::Row(r.row);
+    let src = FileEndpoint::new(src.clone(), position);
+    let tgt = FileEndpoint::new(tgt.clone(), PartialPosition::Row(0));
+    Dep::new(src, tgt, r.kind, position, commit_id).with_resolver("manifests")
+}
+
+/// Read the module declared by a manifest.
+///
+/// Returns None if the file is not a manifest or is malformed.
+fn parse(filename: &str, content: &str) -> Option<Manifest> {
+    let format = Format::of(filename)?;
+    let dir = dirname(filename);
+
+    let (name, is_root, refs) = match format {
+        Format::Cargo => parse_cargo(content),
+        Format::Gradle => parse_gradle(content),
+        Format::Maven => parse_maven(content)?,
+        Format::Npm => parse_npm(content)?,
+        Format::Pip => parse_pip(dir, content),
+    };
+
+    let name = name.unwrap_or_else(|| match dir {
+        "" => ".".to_string(),
+        dir => dir.to_string(),
+    });
+
+    Some(Manifest { format, name, is_root, refs })
+}
+
+type Parsed = (Option<String>, bool, Vec<Ref>);
+
+fn parse_cargo(content: &str) -> Parsed {
+    let mut name = None;
+    let mut is_root = false;
+    let mut refs = Vec::new();
+    let mut table = String::new();
+
+    for (row, line) in content.lines().enumerate() {
+        let line = line.split(" #").next().unwrap_or_default().trim();
+
+        if let Some(header) = line.strip_prefix('[') {
+            table = header.trim_start_matches('[').trim_end_matches(']').trim().to_string();
+            is_root |= table == "workspace";
+
+            if let Some(dep) = dependency_table(&table).and_then(|t| t.strip_prefix('.')) {
+                refs.push(Ref {
+                    kind: DepKind::Dependency,
+                    target: Target::Name(unquote(dep)),
+                    row,
+                });
+            }
+
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+
+        let key = key.trim();
+
+        if table == "package" && key == "name" {
+            name = Some(unquote(value.trim()));
+        } else if dependency_table(&table) == Some("") {
+            let dep = match CARGO_PACKAGE.captures(value) {
+                Some(package) => package[1].to_string(),
+                None => unquote(key.split('.').next().unwrap_or(key)),
+            };
+            refs.push(Ref { kind: DepKind::Dependency, target: Target::Name(dep), row });
+        }
+    }
+
+    if let Some(m) = CARGO_WORKSPACE.find(content).filter(|_| !is_root) {
+        refs.push(Ref {
+            kind: DepKind::Extend,
+            target: Target::Root,
+            row: row_of(content, m.start()),
+        });
+    }
+
+    (name, is_root, refs)
+}
+
+/// If a table lists dependencies, the rest of its name after `dependencies`
+/// (e.g. `.foo` for `dependencies.foo`).
+fn dependency_table(table: &str) -> Option<&str> {
+    if table.starts_with("workspace") {
+        return None;
+    }
+
+    ["dependencies", "dev-dependencies", "build-dependencies"].iter().find_map(|section| {
+        let rest = match table.find(section) {
+            Some(0) => &table[section.len()..],
+            Some(i) if table[..i].ends_with('.') => &table[i + section.len()..],
+            _ => return None,
+        };
+        (rest.is_empty() || rest.starts_with('.')).then_some(rest)
+    })
+}
+
+fn parse_gradle(content: &str) -> Parsed {
+    let refs = GRADLE_PROJECT
+        .captures_iter(content)
+        .map(|c| Ref {
+            kind: DepKind::Dependency,
+            target: Target::GradleProject(c[1].to_string()),
+            row: row_of(content, c.get(1).unwrap().start()),
+        })
+        .chain([Ref { kind: DepKind::Extend, target: Target::Root, row: 0 }])
+        .collect();
+
+    (None, GRADLE_ROOT.is_match(content), refs)
+}
+
+fn parse_maven(content: &str) -> Option<Parsed> {
+    let elements = parse_xml(content);
+    let text = |path: &str, within: Option<&XmlElement>| {
+        elements
+            .iter()
+            .filter(|e| within.is_none_or(|w| w.start <= e.start && e.end <= w.end))
+            .find(|e| e.path == path)
+            .and_then(|e| e.text.clone())
+    };
+
+    let parent = elements.iter().find(|e| e.path == "project/parent");
+    let parent_group = parent.and_then(|p| text("project/parent/groupId", Some(p)));
+    let group = text("project/groupId", None).or(parent_group.clone())?;
+    let artifact = text("project/artifactId", None)?;
+    let expand = |s: String| match s.as_str() {
+        "${project.groupId}" | "${pom.groupId}" | "${groupId}" => group.clone(),
+        "${project.parent.groupId}" => parent_group.clone().unwrap_or(s),
+        _ => s,
+    };
+
+    let mut refs = Vec::new();
+
+    if let Some(parent) = parent {
+        let group = text("project/parent/groupId", Some(parent));
+        let artifact = text("project/parent/artifactId", Some(parent));
+
+        if let (Some(group), Some(artifact)) = (group, artifact) {
+            refs.push(Ref {
+                kind: DepKind::Extend,
+                target: Target::Name(format!("{}:{}", group, artifact)),
+                row: row_of(content, parent.start),
+            });
+        }
+    }
+
+    for dep in elements.iter().filter(|e| e.path == "project/dependencies/dependency") {
+        let group = text("project/dependencies/dependency/groupId", Some(dep));
+        let artifact = text("project/dependencies/dependency/artifactId", Some(dep));
+
+        if let (Some(group), Some(artifact)) = (group, artifact) {
+            refs.push(Ref {
+                kind: DepKind::Dependency,
+                target: Target::Name(format!("{}:{}", expand(group), artifact)),
+                row: row_of(content, dep.start),
+            });
+        }
+    }
+
+    Some((Some(format!("{}:{}", group, artifact)), false, refs))
+}
+
+fn parse_npm(content: &str) -> Option<Parsed> {
+    let json: serde_json::Value = serde_json::from_str(content).ok()?;
+    let name = json.get("name").and_then(|n| n.as_str()).map(|n| n.to_string());
+    let mut refs = Vec::new();
+
+    for section in NPM_SECTIONS {
+        let Some(deps) = json.get(section).and_then(|d| d.as_object()) else {
+            continue;
+        };
+
+        let start = content.find(&format!("\"{}\"", section)).unwrap_or_default();
+
+        for dep in deps.keys() {
+            let at = content[start..].find(&format!("\"{}\"", dep)).map_or(start, |i| start + i);
+            let target = Target::Name(dep.to_string());
+            refs.push(Ref { kind: DepKind::Dependency, target, row: row_of(content, at) });
+        }
+    }
+
+    Some((name, false, refs))
+}
+
+fn parse_pip(dir: &str, content: &str) -> Parsed {
+    let mut refs = Vec::new();
+
+    for (row, line) in content.lines().enumerate() {
+        let line = line.split(" #").next().unwrap_or_default().trim();
+        let (option, arg) = match line.split_once([' ', '=']) {
+            Some((option, arg)) if option.starts_with('-') => (option, arg.trim()),
+            _ => ("", line),
+        };
+
+        let (kind, target) = match option {
+            "-r" | "--requirement" => (DepKind::Extend, Target::File(normalize(dir, arg))),
+            "" | "-e" | "--editable" => {
+                let path = arg.strip_prefix("file:").unwrap_or(arg);
+                let path = path.split(['#', '[']).next().unwrap_or_default();
+
+                if !path.starts_with("./") && !path.starts_with("../") && path != "." {
+                    continue;
+                }
+
+                (DepKind::Dependency, Target::Dir(normalize(dir, path)))
+            }
+            _ => continue,
+        };
+
+        refs.push(Ref { kind, target, row });
+    }
+
+    (None, false, refs)
+}
+
+/// An element of an XML document.
+#[derive(Debug)]
+struct XmlElement {
+    /// The local names of this element and its ancestors (e.g.
+    /// `project/parent`).
+    path: String,
+
+    /// The trimmed text of this element, if it has no child elements.
+    text: Option<String>,
+
+    /// The byte offsets of the start and end of this element.
+    start: usize,
+    end: usize,
+}
+
+/// Find the elements of an XML document, in the order they start.
+///
+/// Only as much of XML is understood as is needed to read build files.
+/// Malformed documents give partial results rather than errors.
+fn parse_xml(content: &str) -> Vec<XmlElement> {
+    let mut elements = Vec::new();
+
+    // The index of each open element and whether it has child elements
+    let mut stack: Vec<(usize, bool)> = Vec::new();
+
+    for token in XML_TOKEN.captures_iter(content) {
+        let (Some(close), Some(name), Some(empty)) = (token.get(1), token.get(2), token.get(3))
+        else {
+            continue;
+        };
+
+        let whole = token.get(0).unwrap();
+        let name = name.as_str().rsplit(':').next().unwrap_or_default();
+
+        if close.as_str() == "/" {
+            let Some((index, has_children)) = stack.pop() else {
+                continue;
+            };
+
+            let element: &mut XmlElement = &mut elements[index];
+            element.end = whole.end();
+
+            if !has_children {
+                let text = content[element.start..whole.start()].split_once('>');
+                element.text = text.map(|(_, t)| t.trim().to_string());
+            }
+
+            continue;
+        }
+
+        if let Some((_, has_children)) = stack.last_mut() {
+            *has_children = true;
+        }
+
+        let path = match stack.last() {
+            Some(&(parent, _)) => format!("{}/{}", elements[parent].path, name),
+            None => name.to_string(),
+        };
+
+        elements.push(XmlElement { path, text: None, start: whole.start(), end: whole.end() });
+
+        if empty.as_str().is_empty() {
+            stack.push((elements.len() - 1, false));
+        }
+    }
+
+    elements
+}
+
+/// Remove the quotes around a TOML key or string.
+fn unquote(s: &str) -> String {
+    s.trim_matches(|c| c == '"' || c == '\'').to_string()
+}
+
+/// The row (starting at 0) of a byte offset.
+fn row_of(content: &str, byte: usize) -> usize {
+    content[..byte].matches('\n').count()
+}
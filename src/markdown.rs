@@ -0,0 +1,228 @@
+//! Summarize a project as Markdown that can be pasted into PRs and wikis.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use itertools::Itertools;
+
+use crate::core::Change;
+use crate::core::Entity;
+use crate::core::EntityDep;
+use crate::core::EntityId;
+use crate::languages::Lang;
+use crate::matrix::qualified_names;
+
+/// The number of rows in each ranked table.
+const TOP: usize = 10;
+
+/// The most files drawn in a Mermaid flowchart.
+///
+/// Keeps the chart readable and well under the size limit of a GitHub comment.
+const MAX_NODES: usize = 40;
+
+/// The most edges drawn in a Mermaid flowchart.
+const MAX_EDGES: usize = 60;
+
+/// Render a report with the number of files and entities of each language, the
+/// most coupled pairs of files, the largest files, and the most changed
+/// entities.
+///
+/// Coupling counts both the deps between two files (in either direction) and
+/// the number of commits that changed both. Deps and changes of entities that
+/// are not among `entities` are skipped.
+pub fn report(entities: &[Entity], deps: &[EntityDep], changes: &[Change]) -> String {
+    let by_id: HashMap<_, _> = entities.iter().map(|e| (e.id, e)).collect();
+    let files: HashMap<_, _> = entities.iter().map(|e| (e.id, find_file(&by_id, e))).collect();
+    let by_simple_id: HashMap<_, _> = entities.iter().map(|e| (e.simple_id, e)).collect();
+
+    let mut text = String::new();
+    text.push_str("# Architecture summary\n");
+
+    text.push_str("\n## Languages\n\n");
+    text.push_str("| Language | Files | Entities |\n");
+    text.push_str("| --- | ---: | ---: |\n");
+
+    let langs = entities.iter().into_group_map_by(|e| Lang::of(&files[&e.id].name));
+
+    for (lang, members) in langs.into_iter().sorted_by_key(|(_, m)| std::cmp::Reverse(m.len())) {
+        let name = lang.map_or("Other", |l| l.linguist_name());
+        let file_count = members.iter().filter(|e| e.kind.is_file()).count();
+        writeln!(text, "| {} | {} | {} |", name, file_count, members.len()).unwrap();
+    }
+
+    text.push_str("\n## Most coupled files\n\n");
+    text.push_str("| File | File | Deps | Co-changes |\n");
+    text.push_str("| --- | --- | ---: | ---: |\n");
+
+    let dep_counts = deps
+        .iter()
+        .filter_map(|d| Some(((files.get(&d.src)?.id, files.get(&d.tgt)?.id), d.count)))
+        .filter(|((src, tgt), _)| src != tgt)
+        .map(|((src, tgt), count)| ((src.min(tgt), src.max(tgt)), count))
+        .into_grouping_map()
+        .sum();
+
+    let cochange_counts = changes
+        .iter()
+        .filter_map(|c| Some((c.commit_id, files[&by_simple_id.get(&c.simple_id)?.id].id)))
+        .into_group_map()
+        .into_values()
+        .flat_map(|f| f.into_iter().unique().sorted().tuple_combinations::<(_, _)>())
+        .counts();
+
+    let pairs: HashSet<_> = dep_counts.keys().chain(cochange_counts.keys()).collect();
+    let coupling = |p| (dep_counts.get(p).copied(), cochange_counts.get(p).copied());
+    let pairs = pairs
+        .into_iter()
+        .map(|p| (p, coupling(p)))
+        .sorted_by_key(|(p, (d, c))| (std::cmp::Reverse(d.unwrap_or(0) + c.unwrap_or(0)), **p))
+        .take(TOP);
+
+    for ((a, b), (deps, cochanges)) in pairs {
+        let (a, b) = (escape(&by_id[a].name), escape(&by_id[b].name));
+        let (deps, cochanges) = (deps.unwrap_or(0), cochanges.unwrap_or(0));
+        writeln!(text, "| {} | {} | {} | {} |", a, b, deps, cochanges).unwrap();
+    }
+
+    text.push_str("\n## Largest files\n\n");
+    text.push_str("| File | Lines |\n");
+    text.push_str("| --- | ---: |\n");
+
+    let largest = entities
+        .iter()
+        .filter(|e| e.kind.is_file())
+        .map(|e| (e, e.code.end.row - e.code.start.row + 1))
+        .sorted_by(|(a, x), (b, y)| y.cmp(x).then(a.name.cmp(&b.name)))
+        .take(TOP);
+
+    for (file, lines) in largest {
+        writeln!(text, "| {} | {} |", escape(&file.name), lines).unwrap();
+    }
+
+    text.push_str("\n## Most changed entities\n\n");
+    text.push_str("| Entity | Kind | Commits | Lines added | Lines deleted |\n");
+    text.push_str("| --- | --- | ---: | ---: | ---: |\n");
+
+    let names = qualified_names(entities);
+    let most_changed = changes
+        .iter()
+        .filter_map(|c| Some((*by_simple_id.get(&c.simple_id)?, c)))
+        .into_group_map_by(|(e, _)| e.id)
+        .into_values()
+        .map(|c| (c[0].0, c.iter().map(|(_, c)| c.commit_id).unique().count(), c))
+        .sorted_by(|(a, x, _), (b, y, _)| y.cmp(x).then(names[&a.id].cmp(&names[&b.id])))
+        .take(TOP);
+
+    for (entity, commits, changes) in most_changed {
+        let adds: usize = changes.iter().map(|(_, c)| c.adds).sum();
+        let dels: usize = changes.iter().map(|(_, c)| c.dels).sum();
+        let (name, kind) = (escape(&names[&entity.id]), entity.kind.as_ref());
+        writeln!(text, "| {} | {} | {} | {} | {} |", name, kind, commits, adds, dels).unwrap();
+    }
+
+    text
+}
+
+/// Render the dependency neighborhood of the changed files as a Mermaid
+/// flowchart in a fenced code block.
+///
+/// A file is changed if any of its entities has a change. Deps are merged into
+/// a single edge per pair of files (labeled with the number of deps) and only
+/// edges that touch a changed file are drawn. Changed files are highlighted. If
+/// the neighborhood is too big, the edges with the fewest deps are left out and
+/// a line below the block says how many.
+pub fn mermaid(entities: &[Entity], deps: &[EntityDep], changes: &[Change]) -> String {
+    let by_id: HashMap<_, _> = entities.iter().map(|e| (e.id, e)).collect();
+    let files: HashMap<_, _> = entities.iter().map(|e| (e.id, find_file(&by_id, e))).collect();
+    let by_simple_id: HashMap<_, _> = entities.iter().map(|e| (e.simple_id, e)).collect();
+
+    let changed: HashSet<_> = changes
+        .iter()
+        .filter_map(|c| Some(files[&by_simple_id.get(&c.simple_id)?.id].id))
+        .collect();
+
+    let edges = deps
+        .iter()
+        .filter_map(|d| Some((files.get(&d.src)?.id, files.get(&d.tgt)?.id)))
+        .filter(|(src, tgt)| src != tgt && (changed.contains(src) || changed.contains(tgt)))
+        .counts()
+        .into_iter()
+        .sorted_by_key(|((src, tgt), n)| {
+            (std::cmp::Reverse(*n), &by_id[src].name, &by_id[tgt].name)
+        })
+        .collect_vec();
+
+    let mut nodes = Vec::new();
+    let mut drawn = Vec::new();
+
+    for &((src, tgt), n) in &edges {
+        let added = [src, tgt].into_iter().filter(|f| !nodes.contains(f)).unique().collect_vec();
+
+        if drawn.len() < MAX_EDGES && nodes.len() + added.len() <= MAX_NODES {
+            nodes.extend(added);
+            drawn.push((src, tgt, n));
+        }
+    }
+
+    for file in changed.iter().sorted_by_key(|f| &by_id[f].name) {
+        if nodes.len() < MAX_NODES && !nodes.contains(file) {
+            nodes.push(*file);
+        }
+    }
+
+    let aliases: HashMap<_, _> =
+        nodes.iter().sorted_by_key(|f| &by_id[f].name).enumerate().map(|(i, f)| (*f, i)).collect();
+
+    let mut text = String::new();
+    text.push_str("```mermaid\n");
+    text.push_str("flowchart LR\n");
+    text.push_str("  classDef changed fill:#fff3b0,stroke:#d4a000\n");
+
+    for (file, i) in aliases.iter().sorted_by_key(|(_, i)| **i) {
+        let label = by_id[file].name.replace('"', "#quot;");
+        let class = if changed.contains(file) { ":::changed" } else { "" };
+        writeln!(text, "  n{}[\"{}\"]{}", i, label, class).unwrap();
+    }
+
+    for (src, tgt, n) in &drawn {
+        writeln!(text, "  n{} -->|{}| n{}", aliases[src], n, aliases[tgt]).unwrap();
+    }
+
+    text.push_str("```\n");
+
+    let missing_edges = edges.len() - drawn.len();
+    let missing_files = changed.iter().filter(|f| !nodes.contains(f)).count();
+
+    if missing_edges > 0 || missing_files > 0 {
+        let (edge_count, changed_count) = (edges.len(), changed.len());
+        writeln!(
+            text,
+            "\n_Left out {} of {} edges and {} of {} changed files._",
+            missing_edges, edge_count, missing_files, changed_count
+        )
+        .unwrap();
+    }
+
+    text
+}
+
+/// Follow the parents of an entity until reaching its file entity.
+fn find_file<'a>(by_id: &HashMap<EntityId, &'a Entity>, entity: &'a Entity) -> &'a Entity {
+    let mut curr = entity;
+
+    while let Some(parent) = curr.parent_id.and_then(|id| by_id.get(&id)) {
+        // Files may be inside packages (see --packages)
+        if curr.kind.is_file() {
+            break;
+        }
+        curr = parent;
+    }
+
+    curr
+}
+
+/// Keep a name from breaking out of its table cell.
+fn escape(name: &str) -> String {
+    name.replace('|', "\\|")
+}
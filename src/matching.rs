@@ -0,0 +1,106 @@
+//! Match the entities of one version of a project to those of the next.
+//!
+//! An [EntityId] changes whenever the content of its file does, so following
+//! an entity across versions means deciding which IDs belong together. Only
+//! entities with the same [SimpleEntityId] can match. When several entities
+//! share a [SimpleEntityId] (e.g. overloaded methods), pairs with the same code
+//! are preferred, then pairs whose code starts on the nearest line, then pairs
+//! whose code is closest in length. Each entity is matched at most once, so
+//! entities that were added or deleted are left unmatched.
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use crate::core::Entity;
+use crate::core::EntityId;
+use crate::core::EntityMatch;
+use crate::core::MatchKind;
+use crate::core::PseudoCommitId;
+use crate::core::Sha1Hash;
+
+/// Match each version with the version listed after it.
+///
+/// As with [crate::churn::DepChurn], this follows the order of `git rev-list`,
+/// where each commit is followed by its parent. `code_hashes` must have the
+/// hash of the code of every entity (see
+/// [crate::extraction::Extractor::code_hashes]).
+pub fn match_versions(
+    versions: &[(PseudoCommitId, Vec<Entity>)],
+    code_hashes: &HashMap<EntityId, Sha1Hash>,
+) -> Vec<EntityMatch> {
+    versions
+        .iter()
+        .tuple_windows()
+        .flat_map(|((new_id, new), (old_id, old))| {
+            match_entities(*old_id, old, *new_id, new, code_hashes)
+        })
+        .collect()
+}
+
+/// Match the entities of two versions.
+fn match_entities(
+    old_commit_id: PseudoCommitId,
+    old: &[Entity],
+    new_commit_id: PseudoCommitId,
+    new: &[Entity],
+    code_hashes: &HashMap<EntityId, Sha1Hash>,
+) -> Vec<EntityMatch> {
+    let mut new_groups = new.iter().into_group_map_by(|e| e.simple_id);
+    let mut matches = Vec::new();
+
+    for (simple_id, old_group) in old.iter().into_group_map_by(|e| e.simple_id) {
+        let Some(new_group) = new_groups.remove(&simple_id) else {
+            continue;
+        };
+
+        let candidates = old_group
+            .iter()
+            .cartesian_product(&new_group)
+            .sorted_by_key(|(o, n)| (distance(o, n, code_hashes), o.id, n.id));
+
+        let mut old_matched = Vec::new();
+        let mut new_matched = Vec::new();
+
+        for (o, n) in candidates {
+            if old_matched.contains(&o.id) || new_matched.contains(&n.id) {
+                continue;
+            }
+
+            old_matched.push(o.id);
+            new_matched.push(n.id);
+
+            let kind = if o.id == n.id {
+                MatchKind::Unchanged
+            } else if code_hashes.get(&o.id) == code_hashes.get(&n.id) {
+                MatchKind::Moved
+            } else {
+                MatchKind::Modified
+            };
+
+            matches.push(EntityMatch {
+                old_id: o.id,
+                new_id: n.id,
+                old_commit_id,
+                new_commit_id,
+                kind,
+            });
+        }
+    }
+
+    matches.sort();
+    matches
+}
+
+/// Rank a candidate pair. Lower is better.
+fn distance(
+    old: &Entity,
+    new: &Entity,
+    code_hashes: &HashMap<EntityId, Sha1Hash>,
+) -> (bool, usize, usize) {
+    let same_code = old.id == new.id || code_hashes.get(&old.id) == code_hashes.get(&new.id);
+    let rows = old.code.start.row.abs_diff(new.code.start.row);
+    let old_len = old.code.end.byte - old.code.start.byte;
+    let new_len = new.code.end.byte - new.code.start.byte;
+    (!same_code, rows, old_len.abs_diff(new_len))
+}
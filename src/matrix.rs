@@ -1,16 +1,290 @@
 use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::collections::HashSet;
+use std::hash::Hash;
+use std::str::FromStr;
 
+use anyhow::Result;
 use itertools::Itertools;
+use rusqlite::params;
+use rusqlite::Connection;
 
 use crate::core::Change;
+use crate::core::DepKind;
 use crate::core::Entity;
 use crate::core::EntityDep;
 use crate::core::EntityId;
 use crate::core::EntityKind;
+use crate::core::SimpleEntityId;
+use crate::ownership::same_team;
+use crate::ownership::Ownership;
+use crate::taxonomy::Taxonomy;
 
-pub fn dsm_v1(entities: &[Entity], deps: &[EntityDep], changes: &[Change]) -> String {
+/// The deps and changes that a matrix is built from.
+///
+/// Everything is kept in a temporary SQLite database on disk (deleted when the
+/// tally is dropped), so method-level runs over a long history are not bounded
+/// by memory. Deps are counted as they are added, one row per pair of entities
+/// and kind. Of each change, only the version in which the entity changed is
+/// kept. The cells are counted by the database, so only the entities and the
+/// finished matrix are held in memory.
+#[derive(Debug)]
+pub struct Tally {
+    conn: Connection,
+}
+
+const TALLY_INIT: &str = "
+PRAGMA journal_mode = OFF;
+PRAGMA synchronous = OFF;
+PRAGMA temp_store = FILE;
+
+CREATE TABLE keys (
+    id BLOB PRIMARY KEY,
+    simple_id BLOB NOT NULL,
+    key INTEGER NOT NULL
+) WITHOUT ROWID;
+
+CREATE INDEX keys_simple_id ON keys (simple_id);
+";
+
+const TALLY_TABLES: &str = "
+CREATE TABLE deps (
+    src BLOB NOT NULL,
+    tgt BLOB NOT NULL,
+    kind TEXT NOT NULL,
+    count INTEGER NOT NULL,
+    PRIMARY KEY (src, tgt, kind)
+) WITHOUT ROWID;
+
+CREATE TABLE changes (
+    simple_id BLOB NOT NULL,
+    commit_id BLOB NOT NULL,
+    PRIMARY KEY (simple_id, commit_id)
+) WITHOUT ROWID;
+";
+
+impl Tally {
+    pub fn new() -> Result<Self> {
+        // An empty path is a private database on disk that is deleted once the
+        // connection is closed
+        let conn = Connection::open("")?;
+        conn.execute_batch(TALLY_INIT)?;
+        conn.execute_batch(TALLY_TABLES)?;
+
+        // Nothing is ever committed, as the database does not outlive the tally
+        conn.execute_batch("BEGIN")?;
+        Ok(Self { conn })
+    }
+
+    pub fn add_dep(&self, dep: &EntityDep) -> Result<()> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO deps VALUES (?, ?, ?, ?)
+                 ON CONFLICT (src, tgt, kind) DO UPDATE SET count = count + excluded.count",
+            )?
+            .execute(params![dep.src, dep.tgt, dep.kind, dep.count])?;
+        Ok(())
+    }
+
+    pub fn add_change(&self, change: &Change) -> Result<()> {
+        self.conn
+            .prepare_cached("INSERT OR IGNORE INTO changes VALUES (?, ?)")?
+            .execute(params![change.simple_id, change.commit_id])?;
+        Ok(())
+    }
+
+    pub fn has_changes(&self) -> Result<bool> {
+        Ok(self.conn.query_row("SELECT EXISTS (SELECT 1 FROM changes)", [], |r| r.get(0))?)
+    }
+
+    /// Move the deps and changes to other entities.
+    ///
+    /// Deps that end up between the same pair of entities are added together.
+    /// Deps and changes of entities that are not mapped are dropped.
+    pub fn map(
+        &mut self,
+        ids: &HashMap<EntityId, EntityId>,
+        simple_ids: &HashMap<SimpleEntityId, SimpleEntityId>,
+    ) -> Result<()> {
+        self.conn.execute_batch(
+            "CREATE TABLE id_map (old BLOB PRIMARY KEY, new BLOB NOT NULL) WITHOUT ROWID;
+             CREATE TABLE simple_id_map (old BLOB PRIMARY KEY, new BLOB NOT NULL) WITHOUT ROWID;
+             ALTER TABLE deps RENAME TO unmapped_deps;
+             ALTER TABLE changes RENAME TO unmapped_changes;",
+        )?;
+        self.conn.execute_batch(TALLY_TABLES)?;
+
+        let mut insert = self.conn.prepare("INSERT INTO id_map VALUES (?, ?)")?;
+
+        for (old, new) in ids {
+            insert.execute(params![old, new])?;
+        }
+
+        let mut insert = self.conn.prepare("INSERT INTO simple_id_map VALUES (?, ?)")?;
+
+        for (old, new) in simple_ids {
+            insert.execute(params![old, new])?;
+        }
+
+        self.conn.execute_batch(
+            "INSERT INTO deps
+             SELECT s.new, t.new, d.kind, SUM(d.count)
+             FROM unmapped_deps d
+             JOIN id_map s ON s.old = d.src
+             JOIN id_map t ON t.old = d.tgt
+             GROUP BY s.new, t.new, d.kind;
+
+             INSERT OR IGNORE INTO changes
+             SELECT m.new, c.commit_id
+             FROM unmapped_changes c
+             JOIN simple_id_map m ON m.old = c.simple_id;
+
+             DROP TABLE unmapped_deps;
+             DROP TABLE unmapped_changes;
+             DROP TABLE id_map;
+             DROP TABLE simple_id_map;",
+        )?;
+        Ok(())
+    }
+
+    /// Count the deps of each kind (under their label in `taxonomy`) and the
+    /// co-changes (under "Cochange") between each pair of keys.
+    ///
+    /// Each of `entities` is given a key (e.g. its index in the matrix) by
+    /// `key`. Entities without a key are left out.
+    pub fn cells<K, F>(
+        &self,
+        entities: &[Entity],
+        taxonomy: &Taxonomy,
+        key: F,
+    ) -> Result<HashMap<(K, K), BTreeMap<String, usize>>>
+    where
+        K: Copy + Eq + Hash + Ord,
+        F: Fn(EntityId) -> Option<K>,
+    {
+        let keys = self.set_keys(entities, key)?;
+        let mut cells: HashMap<_, BTreeMap<_, _>> = HashMap::new();
+
+        self.count_deps(&keys, |pair, kind, count| {
+            let values = cells.entry(pair).or_default();
+            *values.entry(taxonomy.label(kind).to_string()).or_default() += count;
+        })?;
+
+        for (pair, count) in self.count_cochanges(&keys)? {
+            cells.entry(pair).or_default().insert("Cochange".to_string(), count);
+        }
+
+        Ok(cells)
+    }
+
+    /// Count the deps (of any kind) between each pair of keys.
+    ///
+    /// Keys are given as in [Tally::cells].
+    pub fn deps<K, F>(&self, entities: &[Entity], key: F) -> Result<HashMap<(K, K), usize>>
+    where
+        K: Copy + Eq + Hash + Ord,
+        F: Fn(EntityId) -> Option<K>,
+    {
+        let keys = self.set_keys(entities, key)?;
+        let mut counts = HashMap::new();
+
+        self.count_deps(&keys, |pair, _, count| *counts.entry(pair).or_default() += count)?;
+
+        Ok(counts)
+    }
+
+    /// Count the commits that changed both keys of each pair.
+    ///
+    /// Keys are given as in [Tally::cells]. Entities that share a key are
+    /// counted as one, so a commit that changed several of them only counts
+    /// once.
+    pub fn cochanges<K, F>(&self, entities: &[Entity], key: F) -> Result<HashMap<(K, K), usize>>
+    where
+        K: Copy + Eq + Hash + Ord,
+        F: Fn(EntityId) -> Option<K>,
+    {
+        let keys = self.set_keys(entities, key)?;
+        Ok(self.count_cochanges(&keys)?.into_iter().collect())
+    }
+
+    /// Store the key of each entity in the database as its position in the
+    /// returned list of distinct keys.
+    fn set_keys<K, F>(&self, entities: &[Entity], key: F) -> Result<Vec<K>>
+    where
+        K: Copy + Eq + Hash + Ord,
+        F: Fn(EntityId) -> Option<K>,
+    {
+        let keyed = entities.iter().filter_map(|e| Some((e, key(e.id)?))).collect_vec();
+        let keys = keyed.iter().map(|(_, k)| *k).unique().sorted().collect_vec();
+        let positions: HashMap<_, _> = keys.iter().enumerate().map(|(i, k)| (*k, i)).collect();
+
+        self.conn.execute("DELETE FROM keys", [])?;
+        let mut insert = self.conn.prepare("INSERT OR IGNORE INTO keys VALUES (?, ?, ?)")?;
+
+        for (entity, key) in keyed {
+            insert.execute(params![entity.id, entity.simple_id, positions[&key]])?;
+        }
+
+        Ok(keys)
+    }
+
+    /// Call `add` with the number of deps of each kind between each pair of
+    /// keys.
+    fn count_deps<K, F>(&self, keys: &[K], mut add: F) -> Result<()>
+    where
+        K: Copy,
+        F: FnMut((K, K), DepKind, usize),
+    {
+        let mut select = self.conn.prepare(
+            "SELECT s.key, t.key, d.kind, SUM(d.count)
+             FROM deps d
+             JOIN keys s ON s.id = d.src
+             JOIN keys t ON t.id = d.tgt
+             GROUP BY s.key, t.key, d.kind",
+        )?;
+        let mut rows = select.query([])?;
+
+        while let Some(row) = rows.next()? {
+            let (src, tgt): (usize, usize) = (row.get(0)?, row.get(1)?);
+            let kind = DepKind::from_str(&row.get::<_, String>(2)?)?;
+            add((keys[src], keys[tgt]), kind, row.get(3)?);
+        }
+
+        Ok(())
+    }
+
+    /// Each commit is only counted once per key, however many of the entities
+    /// with that key it changed.
+    fn count_cochanges<K: Copy>(&self, keys: &[K]) -> Result<Vec<((K, K), usize)>> {
+        let mut select = self.conn.prepare(
+            "WITH changed AS (
+                 SELECT DISTINCT k.key, c.commit_id
+                 FROM changes c
+                 JOIN keys k ON k.simple_id = c.simple_id
+             )
+             SELECT a.key, b.key, COUNT(*)
+             FROM changed a
+             JOIN changed b ON b.commit_id = a.commit_id AND b.key != a.key
+             GROUP BY a.key, b.key",
+        )?;
+        let mut rows = select.query([])?;
+        let mut counts = Vec::new();
+
+        while let Some(row) = rows.next()? {
+            let (a, b): (usize, usize) = (row.get(0)?, row.get(1)?);
+            counts.push(((keys[a], keys[b]), row.get(2)?));
+        }
+
+        Ok(counts)
+    }
+}
+
+/// Build a DSMv1 matrix, which serializes to JSON.
+pub fn dsm_v1<'a>(
+    entities: &'a [Entity],
+    tally: &Tally,
+    taxonomy: &Taxonomy,
+    ownership: &Ownership,
+) -> Result<impl serde::Serialize + 'a> {
     if entities.iter().any(|e| !e.kind.is_file()) {
         panic!("DSMv1 can only be made with files");
     }
@@ -20,49 +294,45 @@ pub fn dsm_v1(entities: &[Entity], deps: &[EntityDep], changes: &[Change]) -> St
     }
 
     let indices: HashMap<_, _> = entities.iter().enumerate().map(|(i, e)| (e.id, i)).collect();
+    let teams = ownership.teams(entities);
 
-    let cochanges = calc_cochanges(&entities, &changes)
-        .into_iter()
-        .map(|(a, b)| ((indices[&a], indices[&b]), "Cochange"));
-
-    let cells = deps
-        .iter()
-        .map(|d| ((indices[&d.src], indices[&d.tgt]), d.kind.as_ref()))
-        .chain(cochanges)
-        .into_group_map()
+    let cells = tally
+        .cells(entities, taxonomy, |id| indices.get(&id).copied())?
         .into_iter()
-        .map(|((src, tgt), kinds)| CellV1::new(src, tgt, kinds))
+        .map(|((src, tgt), values)| {
+            let same_team = same_team(&teams, entities[src].id, entities[tgt].id);
+            CellV1::new(src, tgt, values, same_team)
+        })
         .sorted_by_key(|c| c.as_pair())
         .collect();
 
-    let variables = entities.into_iter().map(|e| &e.name).collect();
-    let matrix = Matrix { schema: "1.0".to_string(), variables, cells };
-    serde_json::to_string_pretty(&matrix).unwrap()
+    let variables = entities.iter().map(|e| &e.name).collect();
+    Ok(Matrix { schema: "1.0".to_string(), variables, cells })
 }
 
-pub fn dsm_v2(entities: &[Entity], deps: &[EntityDep], changes: &[Change]) -> String {
+/// Build a DSMv2 matrix, which serializes to JSON.
+pub fn dsm_v2(
+    entities: &[Entity],
+    tally: &Tally,
+    taxonomy: &Taxonomy,
+    ownership: &Ownership,
+) -> Result<impl serde::Serialize> {
     if entities.len() != entities.iter().map(|e| &e.id).unique().count() {
         panic!("DSMv2 must have unique entity ids");
     }
 
     let indices: HashMap<_, _> = entities.iter().enumerate().map(|(i, e)| (e.id, i)).collect();
+    let teams = ownership.teams(entities);
 
-    let cochanges =
-        calc_cochanges(&entities, &changes).into_iter().map(|(a, b)| ((a, b), "Cochange"));
-
-    let cells = deps
-        .iter()
-        .map(|d| ((d.src, d.tgt), d.kind.as_ref()))
-        .chain(cochanges)
-        .into_group_map()
+    let cells = tally
+        .cells(entities, taxonomy, |id| indices.contains_key(&id).then_some(id))?
         .into_iter()
-        .map(|((src, tgt), kinds)| CellV2::new(src, tgt, kinds))
+        .map(|((src, tgt), values)| CellV2::new(src, tgt, values, same_team(&teams, src, tgt)))
         .sorted_by_key(|c| (indices[&c.src], indices[&c.tgt]))
         .collect();
 
-    let variables = entities.into_iter().map(|e| EntityVar::from(e.clone())).collect();
-    let matrix = Matrix { schema: "2.0".to_string(), variables, cells };
-    serde_json::to_string_pretty(&matrix).unwrap()
+    let variables = entities.iter().map(|e| EntityVar::from(e.clone())).collect();
+    Ok(Matrix { schema: "2.0".to_string(), variables, cells })
 }
 
 #[derive(Debug, Clone)]
@@ -100,8 +370,19 @@ struct CellV1 {
 }
 
 impl CellV1 {
-    fn new(src: usize, tgt: usize, kinds: Vec<&str>) -> Self {
-        let values = to_cell_values(kinds).into_iter().map(|(k, c)| (k, c as f64)).collect();
+    /// If `same_team` is known, it is stored as a "SameTeam" value of 1 or 0.
+    fn new(
+        src: usize,
+        tgt: usize,
+        values: BTreeMap<String, usize>,
+        same_team: Option<bool>,
+    ) -> Self {
+        let mut values: BTreeMap<_, _> = values.into_iter().map(|(k, c)| (k, c as f64)).collect();
+
+        if let Some(same_team) = same_team {
+            values.insert("SameTeam".to_string(), same_team as u8 as f64);
+        }
+
         Self { src, tgt, values }
     }
 
@@ -117,46 +398,83 @@ struct CellV2 {
     #[serde(rename = "dest")]
     tgt: EntityId,
     values: BTreeMap<String, usize>,
+
+    /// Are both entities owned by the same team? Omitted unless both have an
+    /// owner.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    same_team: Option<bool>,
 }
 
 impl CellV2 {
-    fn new(src: EntityId, tgt: EntityId, kinds: Vec<&str>) -> Self {
-        Self { src, tgt, values: to_cell_values(kinds) }
+    fn new(
+        src: EntityId,
+        tgt: EntityId,
+        values: BTreeMap<String, usize>,
+        same_team: Option<bool>,
+    ) -> Self {
+        Self { src, tgt, values, same_team }
     }
 }
 
-fn to_cell_values(kinds: Vec<&str>) -> BTreeMap<String, usize> {
-    kinds.into_iter().counts().into_iter().sorted().map(|(k, c)| (k.to_string(), c)).collect()
-}
+/// Name each entity by joining the names of its ancestors with "/".
+pub fn qualified_names(entities: &[Entity]) -> HashMap<EntityId, String> {
+    let by_id: HashMap<_, _> = entities.iter().map(|e| (e.id, e)).collect();
 
-fn calc_cochanges(entities: &[Entity], changes: &[Change]) -> Vec<(EntityId, EntityId)> {
-    let id_map = entities.iter().map(|e| (e.simple_id, e.id)).into_group_map();
-
-    let commits = changes
+    entities
         .iter()
-        .map(|c| (c.simple_id, c.commit_id))
-        .unique()
-        .filter_map(|(s, c)| id_map.get(&s).map(|es| (c, es)))
-        .flat_map(|(c, es)| es.iter().map(move |&e| (e, c)))
-        .into_grouping_map()
-        .collect::<HashSet<_>>();
-
-    let mut pairs = Vec::new();
-    let entity_ids = commits.keys().collect_vec();
-
-    for i in 0..entity_ids.len() {
-        let i_id = entity_ids[i];
-        let i_commits = &commits[i_id];
-        for j in (i + 1)..entity_ids.len() {
-            let j_id = entity_ids[j];
-            let j_commits = &commits[j_id];
-
-            for _ in i_commits.intersection(j_commits) {
-                pairs.push((*i_id, *j_id));
-                pairs.push((*j_id, *i_id));
+        .map(|entity| {
+            let mut parts = vec![entity.name.as_str()];
+            let mut curr = entity;
+
+            // Paths start at the file, even if it is inside a package
+            while let Some(parent) = curr.parent_id.and_then(|id| by_id.get(&id)) {
+                if curr.kind.is_file() {
+                    break;
+                }
+                parts.push(&parent.name);
+                curr = parent;
             }
-        }
+
+            (entity.id, parts.into_iter().rev().join("/"))
+        })
+        .collect()
+}
+
+/// Write the deps between entities as a sparse matrix in the Matrix Market
+/// exchange format.
+///
+/// Rows and columns are numbered (from 1) in the same order as `entities`.
+/// Each entry counts the deps (of any kind) from the row to the column.
+pub fn mtx_deps(entities: &[Entity], tally: &Tally) -> Result<String> {
+    let indices: HashMap<_, _> = entities.iter().enumerate().map(|(i, e)| (e.id, i)).collect();
+    let entries = tally.deps(entities, |id| indices.get(&id).copied())?;
+    Ok(to_mtx(entities.len(), entries, "general"))
+}
+
+/// Write the number of times each pair of entities changed together as a
+/// sparse matrix in the Matrix Market exchange format.
+///
+/// The matrix is symmetric, so only the entries below the diagonal are
+/// written.
+pub fn mtx_cochanges(entities: &[Entity], tally: &Tally) -> Result<String> {
+    let indices: HashMap<_, _> = entities.iter().enumerate().map(|(i, e)| (e.id, i)).collect();
+
+    let entries = tally
+        .cochanges(entities, |id| indices.get(&id).copied())?
+        .into_iter()
+        .filter(|((row, col), _)| row > col)
+        .collect();
+
+    Ok(to_mtx(entities.len(), entries, "symmetric"))
+}
+
+fn to_mtx(n: usize, entries: HashMap<(usize, usize), usize>, symmetry: &str) -> String {
+    let mut text = format!("%%MatrixMarket matrix coordinate integer {}\n", symmetry);
+    text.push_str(&format!("{} {} {}\n", n, n, entries.len()));
+
+    for ((row, col), count) in entries.into_iter().sorted() {
+        text.push_str(&format!("{} {} {}\n", row + 1, col + 1, count));
     }
 
-    pairs
+    text
 }
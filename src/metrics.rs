@@ -0,0 +1,241 @@
+//! Measure the size, complexity, and dependency partners of each entity.
+//!
+//! Almost every consumer of the deps computes the fan-in and fan-out of each
+//! entity right away, so it is cheaper to do it once here. Deps are collected
+//! with [FanCounter::add_dep] as they are extracted and counted once extraction
+//! has finished. Multiple deps between the same pair of entities (e.g. a call
+//! and a use) count only once.
+//!
+//! The code of each entity is measured with [measure] from its syntax tree,
+//! using node kinds that the tree-sitter grammars of every language share:
+//!
+//! - Lines: Every row of the entity, including blank lines and comments.
+//! - Logical lines: The rows that hold at least one token besides comments.
+//! - Complexity: One plus the number of branches (conditionals, loops, cases,
+//!   catches, and short-circuiting operators). The complexity of a class or
+//!   file includes that of everything in it.
+//! - Max nesting: The most branching statements that enclose one another.
+//! - Params: The number of parameters, for entities that have a parameter list.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use tree_sitter::Language;
+use tree_sitter::Node;
+
+use crate::core::Entity;
+use crate::core::EntityDep;
+use crate::core::EntityId;
+use crate::core::Metric;
+use crate::core::PseudoCommitId;
+use crate::tagging;
+
+/// The kinds of nodes that branch (see [CodeMetrics::complexity]).
+const BRANCHES: &[&str] = &[
+    "case_clause",
+    "catch_clause",
+    "conditional_expression",
+    "do_statement",
+    "elif_clause",
+    "enhanced_for_statement",
+    "except_clause",
+    "expression_case",
+    "for_in_statement",
+    "for_statement",
+    "if_expression",
+    "if_statement",
+    "rescue",
+    "switch_block_statement_group",
+    "switch_case",
+    "switch_label",
+    "ternary_expression",
+    "type_case",
+    "when",
+    "when_entry",
+    "while_statement",
+    "with_clause",
+];
+
+/// The operators that branch (see [CodeMetrics::complexity]).
+const BRANCHING_OPERATORS: &[&str] = &["&&", "||", "and", "or", "??"];
+
+/// The kinds of nodes that nest (see [CodeMetrics::max_nesting]).
+const NESTS: &[&str] = &[
+    "do_statement",
+    "enhanced_for_statement",
+    "for_in_statement",
+    "for_statement",
+    "if_expression",
+    "if_statement",
+    "switch_expression",
+    "switch_statement",
+    "try_statement",
+    "when_expression",
+    "while_statement",
+    "with_statement",
+];
+
+/// The size and complexity of the code of an entity. See [crate::metrics].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CodeMetrics {
+    pub lines: usize,
+    pub logical_lines: usize,
+    pub complexity: usize,
+    pub max_nesting: usize,
+    pub params: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct FanCounter {
+    pairs: Mutex<HashSet<(PseudoCommitId, EntityId, EntityId)>>,
+}
+
+impl FanCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_dep(&self, dep: &EntityDep) {
+        if dep.src != dep.tgt {
+            self.pairs.lock().unwrap().insert((dep.commit_id, dep.src, dep.tgt));
+        }
+    }
+
+    /// Count the fan-in and fan-out of every entity of every version and pair
+    /// them with the measurements of its code.
+    ///
+    /// Entities without any deps are included with a fan-in and fan-out of
+    /// zero.
+    pub fn build(
+        self,
+        entities: &HashMap<PseudoCommitId, Vec<Entity>>,
+        code: &HashMap<EntityId, CodeMetrics>,
+    ) -> Vec<Metric> {
+        let mut fans: HashMap<(PseudoCommitId, EntityId), (usize, usize)> = HashMap::new();
+
+        for (commit_id, src, tgt) in self.pairs.into_inner().unwrap() {
+            fans.entry((commit_id, src)).or_default().1 += 1;
+            fans.entry((commit_id, tgt)).or_default().0 += 1;
+        }
+
+        let mut metrics = entities
+            .iter()
+            .flat_map(|(&commit_id, entities)| entities.iter().map(move |e| (commit_id, e.id)))
+            .map(|(commit_id, entity_id)| {
+                let (fan_in, fan_out) =
+                    fans.get(&(commit_id, entity_id)).copied().unwrap_or_default();
+                let code = code.get(&entity_id).copied().unwrap_or_default();
+                Metric {
+                    entity_id,
+                    commit_id,
+                    fan_in,
+                    fan_out,
+                    lines: code.lines,
+                    logical_lines: code.logical_lines,
+                    complexity: code.complexity,
+                    max_nesting: code.max_nesting,
+                    params: code.params,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        metrics.sort();
+        metrics.dedup();
+        metrics
+    }
+}
+
+/// Measure the code of entities that share the same content.
+///
+/// Without a grammar, only [CodeMetrics::lines] is measured.
+pub fn measure<'a, I>(
+    language: Option<Language>,
+    content: &str,
+    entities: I,
+) -> Vec<(EntityId, CodeMetrics)>
+where
+    I: IntoIterator<Item = &'a Entity>,
+{
+    let tree = language.and_then(|l| tagging::parse(l, content).ok());
+
+    entities
+        .into_iter()
+        .map(|e| {
+            let mut metrics =
+                CodeMetrics { lines: e.code.end.row - e.code.start.row + 1, ..Default::default() };
+
+            if let Some(tree) = &tree {
+                let (start, end) = (e.code.start.byte, e.code.end.byte);
+                let node = tree.root_node().descendant_for_byte_range(start, end);
+
+                // The smallest node that covers the entity may be too small if
+                // a parent has the same range (e.g. a decorated definition)
+                let node = node.map(|mut n| {
+                    while let Some(p) = n.parent().filter(|p| p.byte_range() == n.byte_range()) {
+                        n = p;
+                    }
+                    n
+                });
+
+                if let Some(node) = node {
+                    let mut rows = HashSet::new();
+                    metrics.complexity = 1;
+                    visit(node, 0, &mut rows, &mut metrics);
+                    metrics.logical_lines = rows.len();
+                    metrics.params = params(node);
+                }
+            }
+
+            (e.id, metrics)
+        })
+        .collect()
+}
+
+/// Add the rows, branches, and nesting of `node` and its descendants to
+/// `metrics`, where `depth` is the nesting of `node`.
+fn visit(node: Node, depth: usize, rows: &mut HashSet<usize>, metrics: &mut CodeMetrics) {
+    if node.kind().contains("comment") {
+        return;
+    }
+
+    if node.child_count() == 0 {
+        rows.insert(node.start_position().row);
+
+        if BRANCHING_OPERATORS.contains(&node.kind()) && !node.is_named() {
+            metrics.complexity += 1;
+        }
+
+        return;
+    }
+
+    if BRANCHES.contains(&node.kind()) {
+        metrics.complexity += 1;
+    }
+
+    let depth = match NESTS.contains(&node.kind()) {
+        true => depth + 1,
+        false => depth,
+    };
+    metrics.max_nesting = metrics.max_nesting.max(depth);
+
+    let mut cursor = node.walk();
+
+    for child in node.children(&mut cursor) {
+        visit(child, depth, rows, metrics);
+    }
+}
+
+/// The number of parameters of a function-like node, if it has a parameter
+/// list.
+fn params(node: Node) -> Option<usize> {
+    let mut cursor = node.walk();
+    let list = node.child_by_field_name("parameters").or_else(|| {
+        node.children(&mut cursor)
+            .find(|c| matches!(c.kind(), "function_value_parameters" | "method_parameters"))
+    })?;
+
+    let mut cursor = list.walk();
+    let count = list.named_children(&mut cursor).filter(|c| !c.kind().contains("comment")).count();
+    Some(count)
+}
@@ -0,0 +1,177 @@
+//! Link identifiers to the entities with the same name, like grep would.
+//!
+//! Some languages have no resolver that does better than their imports. For
+//! studies that only need a rough structure (e.g. next to co-change), it is
+//! often enough to say that a file mentioning `frobnicate` depends on the only
+//! entity in the project named `frobnicate`. Every identifier in the syntax
+//! tree is matched this way, so comments and strings are skipped. For languages
+//! without entities finer than files, each file is named after its stem (e.g.
+//! `parser` for `src/parser.c`). Names declared more than once (in any file of
+//! the same language) are never matched.
+//!
+//! Deps found this way are "Use" deps marked [Confidence::Heuristic].
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use itertools::Itertools;
+use rayon::prelude::*;
+use tree_sitter::Node;
+
+use crate::core::Confidence;
+use crate::core::Dep;
+use crate::core::DepKind;
+use crate::core::EntityKind;
+use crate::core::FileDep;
+use crate::core::FileEndpoint;
+use crate::core::FileKey;
+use crate::core::PartialPosition;
+use crate::core::Position;
+use crate::core::PseudoCommitId;
+use crate::core::Span;
+use crate::languages::Lang;
+use crate::resolution::Resolver;
+use crate::resolution::ResolverFactory;
+use crate::tagging;
+use crate::tagging::Granularity;
+
+/// Names shorter than this are too likely to be matched by accident.
+const MIN_NAME_LEN: usize = 3;
+
+/// The names declared in a file and the identifiers it uses.
+#[derive(Debug, Default)]
+struct Scan {
+    defs: Vec<(String, Position)>,
+    uses: Vec<(String, Position)>,
+}
+
+/// A name-matching resolver.
+///
+/// See [Resolver].
+#[derive(Debug)]
+pub struct NameMatchResolver {
+    commit_id: PseudoCommitId,
+    lang: Lang,
+    files: Mutex<Vec<(FileKey, String)>>,
+}
+
+impl NameMatchResolver {
+    fn new(commit_id: PseudoCommitId, lang: Lang) -> Self {
+        Self { commit_id, lang, files: Default::default() }
+    }
+}
+
+impl Resolver for NameMatchResolver {
+    fn add_file(&self, filename: &str, content: &str) {
+        let file_key = FileKey::from_content(filename.to_string(), content);
+        self.files.lock().unwrap().push((file_key, content.to_string()));
+    }
+
+    fn resolve(&self) -> Result<Vec<FileDep>> {
+        let files = std::mem::take(&mut *self.files.lock().unwrap());
+        log::info!("Matching the names of {} {} file(s)...", files.len(), self.lang);
+
+        let scans: Vec<_> =
+            files.par_iter().map(|(f, content)| (f, scan(self.lang, f, content))).collect();
+
+        let counts = scans.iter().flat_map(|(_, s)| &s.defs).map(|(n, _)| n.as_str()).counts();
+        let unique: HashMap<_, _> = scans
+            .iter()
+            .flat_map(|(f, s)| s.defs.iter().map(move |(n, p)| (n.as_str(), (*f, *p))))
+            .filter(|(n, _)| counts[n] == 1)
+            .collect();
+
+        let deps = scans
+            .iter()
+            .flat_map(|(f, s)| s.uses.iter().map(move |(n, p)| (*f, n, *p)))
+            .filter_map(|(src, name, src_position)| {
+                let &(tgt, tgt_position) = unique.get(name.as_str())?;
+
+                // Skip the name of the declaration itself
+                if src == tgt && src_position.row == tgt_position.row {
+                    return None;
+                }
+
+                let position = PartialPosition::Whole(src_position);
+                let src = FileEndpoint::new(src.clone(), position);
+                let tgt = FileEndpoint::new(tgt.clone(), PartialPosition::Whole(tgt_position));
+                let dep = Dep::new(src, tgt, DepKind::Use, position, self.commit_id);
+                Some(dep.with_confidence(Confidence::Heuristic))
+            })
+            .collect();
+        Ok(deps)
+    }
+}
+
+/// A name-matching resolver factory.
+///
+/// See [ResolverFactory].
+#[derive(Debug, Clone, Default)]
+pub struct NameMatchResolverFactory;
+
+impl NameMatchResolverFactory {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl ResolverFactory for NameMatchResolverFactory {
+    fn name(&self) -> &'static str {
+        "name-match"
+    }
+
+    fn supports(&self, _: Lang) -> bool {
+        true
+    }
+
+    fn try_create(&self, commit_id: PseudoCommitId, lang: Lang) -> Option<Box<dyn Resolver>> {
+        Some(Box::new(NameMatchResolver::new(commit_id, lang)))
+    }
+}
+
+fn scan(lang: Lang, file_key: &FileKey, content: &str) -> Scan {
+    let mut result = Scan::default();
+
+    match lang.tagger().try_tag(&file_key.filename, content, false, Granularity::Member) {
+        Ok(entity_set) => {
+            let defs = entity_set
+                .iter_entities()
+                .filter(|e| e.kind != EntityKind::File)
+                .map(|e| (e.name.clone(), e.code.start));
+            result.defs.extend(defs);
+        }
+        Err(_) => {
+            let name = file_key.filename.rsplit('/').next().unwrap_or_default();
+            let stem = name.split('.').next().unwrap_or_default();
+            result.defs.push((stem.to_string(), Position::new(0, 0, 0)));
+        }
+    }
+
+    result.defs.retain(|(n, _)| n.len() >= MIN_NAME_LEN);
+
+    if let Ok(tree) = tagging::parse(lang.language(), content) {
+        visit(tree.root_node(), content, &mut result.uses);
+    }
+
+    result
+}
+
+/// Add each identifier under `node` (like `identifier`, `type_identifier`, or
+/// `field_identifier`) to `uses`.
+fn visit(node: Node, content: &str, uses: &mut Vec<(String, Position)>) {
+    if node.child_count() == 0 {
+        if node.kind().ends_with("identifier") || node.kind() == "constant" {
+            if let Ok(text) = node.utf8_text(content.as_bytes()) {
+                uses.push((text.to_string(), Span::from_ts(node.range()).start));
+            }
+        }
+        return;
+    }
+
+    let mut cursor = node.walk();
+
+    for child in node.children(&mut cursor) {
+        visit(child, content, uses);
+    }
+}
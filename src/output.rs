@@ -1,35 +1,122 @@
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fs::File;
+use std::io::BufWriter;
 use std::io::LineWriter;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::sync::Mutex;
 
+use anyhow::bail;
+use anyhow::Context;
 use anyhow::Result;
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::DataType;
+use arrow_schema::Field;
+use arrow_schema::FieldRef;
+use arrow_schema::Schema;
+use flate2::write::GzEncoder;
+use prost::Message;
 use rusqlite::params;
 use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use rust_xlsxwriter::Format;
+use rust_xlsxwriter::Workbook;
+use serde_arrow::ArrayBuilder;
+use strum::IntoEnumIterator;
 
+use crate::core::Alignment;
+use crate::core::Annotation;
 use crate::core::Change;
+use crate::core::Commit;
+use crate::core::CommitFile;
+use crate::core::CommitId;
+use crate::core::Confidence;
 use crate::core::Content;
 use crate::core::ContentId;
-use crate::core::DepKind;
+use crate::core::DepChange;
+use crate::core::Diagnostic;
 use crate::core::Entity;
+use crate::core::EntityContentId;
 use crate::core::EntityDep;
 use crate::core::EntityId;
 use crate::core::EntityKind;
+use crate::core::EntityMatch;
+use crate::core::ExternalDep;
+use crate::core::Metric;
 use crate::core::PseudoCommitId;
+use crate::core::Revspec;
 use crate::core::SimpleEntityId;
+use crate::core::Supertype;
+use crate::core::UnresolvedRef;
+use crate::directories::directories;
+use crate::dv8::Dv8Matrix;
+use crate::graph::dot;
+use crate::graph::graphml;
+use crate::graph::plantuml;
+use crate::graph::structurizr;
+use crate::languages::Lang;
+use crate::markdown::mermaid;
+use crate::markdown::report;
 use crate::matrix::dsm_v1;
 use crate::matrix::dsm_v2;
+use crate::matrix::mtx_cochanges;
+use crate::matrix::mtx_deps;
+use crate::matrix::Tally;
+use crate::ownership::aggregate_teams;
+use crate::ownership::Ownership;
+use crate::proto;
+use crate::scip::build_index;
+use crate::taxonomy::Taxonomy;
+use crate::understand::build_rows;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-#[derive(strum::Display, strum::EnumIs, strum::EnumString, strum::VariantNames)]
+#[derive(strum::Display, strum::EnumIs, strum::EnumIter, strum::EnumString, strum::VariantNames)]
 #[strum(serialize_all = "kebab-case")]
 pub enum Resource {
     Entities,
     Deps,
     Changes,
+    DepChanges,
     Contents,
+    Alignments,
+    Revspecs,
+    UnresolvedRefs,
+    EntityMatches,
+    Metrics,
+    ExternalDeps,
+    Hierarchy,
+    Diagnostics,
+    Annotations,
+    Files,
+    Commits,
+}
+
+impl Resource {
+    /// The name of a single record of this resource (e.g. "dep" for deps).
+    pub fn singular(&self) -> &'static str {
+        match self {
+            Resource::Entities => "entity",
+            Resource::Deps => "dep",
+            Resource::Changes => "change",
+            Resource::DepChanges => "dep_change",
+            Resource::Contents => "content",
+            Resource::Alignments => "alignment",
+            Resource::Revspecs => "revspec",
+            Resource::UnresolvedRefs => "unresolved_ref",
+            Resource::EntityMatches => "entity_match",
+            Resource::Metrics => "metric",
+            Resource::ExternalDeps => "external_dep",
+            Resource::Hierarchy => "supertype",
+            Resource::Diagnostics => "diagnostic",
+            Resource::Annotations => "annotation",
+            Resource::Files => "file",
+            Resource::Commits => "commit",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -38,20 +125,192 @@ pub enum Resource {
 pub enum OutputFormat {
     Csvs,
     Jsonl,
+    Jsonls,
     Sqlite,
     DsmV1,
     DsmV2,
+    Dv8,
+    Graphml,
+    Dot,
+    Neo4j,
+    Arrow,
+    Mtx,
+    Xlsx,
+    Proto,
+    Scip,
+    Structurizr,
+    Understand,
+    Markdown,
+    Plantuml,
+    Mermaid,
+}
+
+/// The granularity of the entities that are written.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(strum::Display, strum::EnumIs, strum::EnumString, strum::VariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Level {
+    /// Every entity that was extracted.
+    #[default]
+    Entity,
+
+    /// Only files.
+    File,
+
+    /// One entity per team, merging the files that each team owns.
+    Team,
+
+    /// One entity per directory, merging the files under each. Directories are
+    /// cut down to their first N components, if given. See
+    /// [crate::directories].
+    Dir(Option<usize>),
+}
+
+/// A compression codec applied to the output as it is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(strum::Display, strum::EnumIs, strum::EnumIter, strum::EnumString, strum::VariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The file extension conventionally used for this codec.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+}
+
+/// Options that change how an [OutputFormat] writes its resources.
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+    /// Compress the output as it is written (jsonl and jsonls only).
+    pub compression: Option<Compression>,
+
+    /// Add to the output if it already exists (sqlite only).
+    pub append: bool,
+
+    /// Wrap each row with its type and schema version (jsonl only).
+    pub envelope: bool,
+
+    /// Write JSON without indentation or line breaks (JSON DSMs only).
+    pub minify: bool,
+
+    /// Only include the files under these paths (plantuml only).
+    pub scope: Vec<String>,
+
+    /// How dep kinds are renamed and merged.
+    pub taxonomy: Taxonomy,
+
+    /// The teams that own each entity (DSMs only).
+    pub ownership: Ownership,
+
+    /// The granularity of the entities that are written.
+    pub level: Level,
+
+    /// The absolute path of the project that was scanned (hashed like a
+    /// filename if the output is anonymized). Only used to name the project.
+    pub root: PathBuf,
+
+    /// The command line recorded in the output (empty if the output is
+    /// anonymized since it names the input and other paths).
+    pub args: Vec<String>,
 }
 
 impl OutputFormat {
-    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<Box<dyn Writer + Sync>> {
-        Ok(match self {
-            OutputFormat::Csvs => Box::new(CsvsWriter::open(path)?),
-            OutputFormat::Jsonl => Box::new(JsonlWriter::open(path)?),
-            OutputFormat::Sqlite => Box::new(SqliteWriter::open(path)?),
-            OutputFormat::DsmV1 => Box::new(DsmWriter::open(path, Dsm::V1)?),
-            OutputFormat::DsmV2 => Box::new(DsmWriter::open(path, Dsm::V2)?),
-        })
+    pub fn open<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: OutputOptions,
+    ) -> Result<Box<dyn Writer + Sync>> {
+        let OutputOptions {
+            compression,
+            append,
+            envelope,
+            minify,
+            scope,
+            taxonomy,
+            ownership,
+            level,
+            root,
+            args,
+        } = options;
+
+        if compression.is_some() && !self.is_jsonl() && !self.is_jsonls() {
+            bail!("Compression is only supported with --format=jsonl or --format=jsonls");
+        }
+
+        if append && !self.is_sqlite() {
+            bail!("Appending is only supported with --format=sqlite");
+        }
+
+        if envelope && !self.is_jsonl() {
+            bail!("Envelopes are only supported with --format=jsonl");
+        }
+
+        if minify && !matches!(self, Self::DsmV1 | Self::DsmV2 | Self::Dv8) {
+            bail!("Minifying is only supported with --format=dsm-v1, dsm-v2, or dv8");
+        }
+
+        let is_dsm = matches!(self, Self::DsmV1 | Self::DsmV2 | Self::Dv8 | Self::Mtx);
+
+        match level {
+            Level::Team if !is_dsm => bail!("--level=team is only supported with DSM formats"),
+            Level::Dir(_) if !is_dsm => bail!("--dir-level is only supported with DSM formats"),
+            _ => {}
+        }
+
+        let dest = path.as_ref().to_path_buf();
+        let temp = temp_path(&dest);
+        let path = &temp;
+
+        if append && dest.exists() {
+            std::fs::copy(&dest, path).context("failed to copy existing output")?;
+        }
+
+        let inner: Box<dyn Writer + Sync> = match self {
+            OutputFormat::Csvs => Box::new(CsvsWriter::open(path, taxonomy)?),
+            OutputFormat::Jsonl => {
+                Box::new(JsonlWriter::open(path, compression, envelope, taxonomy)?)
+            }
+            OutputFormat::Jsonls => Box::new(JsonlsWriter::open(path, compression, taxonomy)?),
+            OutputFormat::Sqlite => Box::new(SqliteWriter::open(path, append, taxonomy, args)?),
+            OutputFormat::DsmV1 => {
+                Box::new(DsmWriter::open(path, Dsm::V1, taxonomy, ownership, level, minify)?)
+            }
+            OutputFormat::DsmV2 => {
+                Box::new(DsmWriter::open(path, Dsm::V2, taxonomy, ownership, level, minify)?)
+            }
+            OutputFormat::Dv8 => {
+                Box::new(DsmWriter::open(path, Dsm::Dv8, taxonomy, ownership, level, minify)?)
+            }
+            OutputFormat::Graphml => Box::new(GraphWriter::open(path, Graph::Graphml, taxonomy)?),
+            OutputFormat::Dot => Box::new(GraphWriter::open(path, Graph::Dot, taxonomy)?),
+            OutputFormat::Neo4j => Box::new(Neo4jWriter::open(path, taxonomy)?),
+            OutputFormat::Arrow => Box::new(ArrowWriter::open(path, taxonomy)?),
+            OutputFormat::Mtx => {
+                Box::new(DsmWriter::open(path, Dsm::Mtx, taxonomy, ownership, level, false)?)
+            }
+            OutputFormat::Xlsx => Box::new(XlsxWriter::open(path, taxonomy)?),
+            OutputFormat::Proto => Box::new(ProtoWriter::open(path, taxonomy)?),
+            OutputFormat::Scip => Box::new(ScipWriter::open(path, root, args)?),
+            OutputFormat::Structurizr => {
+                let name = root.file_name().unwrap_or_default().to_string_lossy().to_string();
+                Box::new(GraphWriter::open(path, Graph::Structurizr(name), taxonomy)?)
+            }
+            OutputFormat::Understand => Box::new(UnderstandWriter::open(path, taxonomy)?),
+            OutputFormat::Markdown => Box::new(MarkdownWriter::open(path, Report::Summary)?),
+            OutputFormat::Mermaid => Box::new(MarkdownWriter::open(path, Report::Mermaid)?),
+            OutputFormat::Plantuml => {
+                Box::new(GraphWriter::open(path, Graph::Plantuml(scope), taxonomy)?)
+            }
+        };
+
+        Ok(Box::new(AtomicWriter { inner, temp, dest, finalized: false }))
     }
 }
 
@@ -62,25 +321,252 @@ pub trait Writer {
     fn write_dep(&self, value: EntityDep) -> Result<()>;
     fn write_change(&self, value: Change) -> Result<()>;
     fn write_content(&self, value: Content) -> Result<()>;
+
+    fn write_dep_change(&self, _: DepChange) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_alignment(&self, _: Alignment) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_revspec(&self, _: Revspec) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_unresolved_ref(&self, _: UnresolvedRef) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_entity_match(&self, _: EntityMatch) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_metric(&self, _: Metric) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_external_dep(&self, _: ExternalDep) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_supertype(&self, _: Supertype) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_diagnostic(&self, _: Diagnostic) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_annotation(&self, _: Annotation) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_file(&self, _: CommitFile) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_commit(&self, _: Commit) -> Result<()> {
+        Ok(())
+    }
+
+    /// Write a record that was replaced by a `--post-script` hook.
+    ///
+    /// Such records no longer match the fixed schema of the resource, so only
+    /// schemaless formats can write them.
+    fn write_value(&self, resource: Resource, _: serde_json::Value) -> Result<()> {
+        bail!("cannot write modified {} records unless --format=jsonl or --format=jsonls", resource)
+    }
+
     fn finalize(&mut self) -> Result<()>;
 }
 
+/// A hidden path next to `dest` for output that is still being written.
+///
+/// Being in the same directory keeps the final rename on the same file system.
+fn temp_path(dest: &Path) -> PathBuf {
+    let filename = dest.file_name().unwrap_or_default().to_string_lossy();
+    dest.with_file_name(format!(".{}.tmp-{}", filename, std::process::id()))
+}
+
+/// A [Writer] that lets another writer build its output at a temporary path
+/// and then renames it into place once finalized.
+///
+/// A run that fails or is interrupted therefore never leaves a truncated file
+/// or half-built directory at the output path. If the writer is dropped without
+/// being finalized (e.g. after an error), the temporary output is removed.
+struct AtomicWriter {
+    inner: Box<dyn Writer + Sync>,
+    temp: PathBuf,
+    dest: PathBuf,
+    finalized: bool,
+}
+
+impl Writer for AtomicWriter {
+    fn supports(&self, resource: Resource) -> bool {
+        self.inner.supports(resource)
+    }
+
+    fn is_single_structure(&self) -> bool {
+        self.inner.is_single_structure()
+    }
+
+    fn write_entity(&self, value: Entity) -> Result<()> {
+        self.inner.write_entity(value)
+    }
+
+    fn write_dep(&self, value: EntityDep) -> Result<()> {
+        self.inner.write_dep(value)
+    }
+
+    fn write_change(&self, value: Change) -> Result<()> {
+        self.inner.write_change(value)
+    }
+
+    fn write_content(&self, value: Content) -> Result<()> {
+        self.inner.write_content(value)
+    }
+
+    fn write_dep_change(&self, value: DepChange) -> Result<()> {
+        self.inner.write_dep_change(value)
+    }
+
+    fn write_alignment(&self, value: Alignment) -> Result<()> {
+        self.inner.write_alignment(value)
+    }
+
+    fn write_revspec(&self, value: Revspec) -> Result<()> {
+        self.inner.write_revspec(value)
+    }
+
+    fn write_unresolved_ref(&self, value: UnresolvedRef) -> Result<()> {
+        self.inner.write_unresolved_ref(value)
+    }
+
+    fn write_entity_match(&self, value: EntityMatch) -> Result<()> {
+        self.inner.write_entity_match(value)
+    }
+
+    fn write_metric(&self, value: Metric) -> Result<()> {
+        self.inner.write_metric(value)
+    }
+
+    fn write_external_dep(&self, value: ExternalDep) -> Result<()> {
+        self.inner.write_external_dep(value)
+    }
+
+    fn write_supertype(&self, value: Supertype) -> Result<()> {
+        self.inner.write_supertype(value)
+    }
+
+    fn write_diagnostic(&self, value: Diagnostic) -> Result<()> {
+        self.inner.write_diagnostic(value)
+    }
+
+    fn write_annotation(&self, value: Annotation) -> Result<()> {
+        self.inner.write_annotation(value)
+    }
+
+    fn write_file(&self, value: CommitFile) -> Result<()> {
+        self.inner.write_file(value)
+    }
+
+    fn write_commit(&self, value: Commit) -> Result<()> {
+        self.inner.write_commit(value)
+    }
+
+    fn write_value(&self, resource: Resource, value: serde_json::Value) -> Result<()> {
+        self.inner.write_value(resource, value)
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.inner.finalize()?;
+        std::fs::rename(&self.temp, &self.dest).with_context(|| {
+            format!("failed to move {} to {}", self.temp.display(), self.dest.display())
+        })?;
+        self.finalized = true;
+        Ok(())
+    }
+}
+
+impl Drop for AtomicWriter {
+    fn drop(&mut self) {
+        if self.finalized {
+            return;
+        }
+
+        let _ = match self.temp.is_dir() {
+            true => std::fs::remove_dir_all(&self.temp),
+            false => std::fs::remove_file(&self.temp),
+        };
+    }
+}
+
 #[derive(Debug)]
 struct CsvsWriter {
     entities: Mutex<csv::Writer<File>>,
     deps: Mutex<csv::Writer<File>>,
     changes: Mutex<csv::Writer<File>>,
+    dep_changes: Mutex<csv::Writer<File>>,
     contents: Mutex<csv::Writer<File>>,
+    alignments: Mutex<csv::Writer<File>>,
+    revspecs: Mutex<csv::Writer<File>>,
+    unresolved_refs: Mutex<csv::Writer<File>>,
+    entity_matches: Mutex<csv::Writer<File>>,
+    metrics: Mutex<csv::Writer<File>>,
+    external_deps: Mutex<csv::Writer<File>>,
+    hierarchy: Mutex<csv::Writer<File>>,
+    diagnostics: Mutex<csv::Writer<File>>,
+    annotations: Mutex<csv::Writer<File>>,
+    files: Mutex<csv::Writer<File>>,
+    commits: Mutex<csv::Writer<File>>,
+    taxonomy: Taxonomy,
 }
 
 impl CsvsWriter {
-    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+    pub fn open<P: AsRef<Path>>(path: P, taxonomy: Taxonomy) -> Result<Self> {
         std::fs::create_dir_all(path.as_ref())?;
         let entities = Mutex::new(csv::Writer::from_path(path.as_ref().join("entities.csv"))?);
         let deps = Mutex::new(csv::Writer::from_path(path.as_ref().join("deps.csv"))?);
         let changes = Mutex::new(csv::Writer::from_path(path.as_ref().join("changes.csv"))?);
+        let dep_changes =
+            Mutex::new(csv::Writer::from_path(path.as_ref().join("dep_changes.csv"))?);
         let contents = Mutex::new(csv::Writer::from_path(path.as_ref().join("contents.csv"))?);
-        Ok(Self { entities, deps, changes, contents })
+        let alignments = Mutex::new(csv::Writer::from_path(path.as_ref().join("alignments.csv"))?);
+        let revspecs = Mutex::new(csv::Writer::from_path(path.as_ref().join("revspecs.csv"))?);
+        let unresolved_refs =
+            Mutex::new(csv::Writer::from_path(path.as_ref().join("unresolved_refs.csv"))?);
+        let entity_matches =
+            Mutex::new(csv::Writer::from_path(path.as_ref().join("entity_matches.csv"))?);
+        let metrics = Mutex::new(csv::Writer::from_path(path.as_ref().join("metrics.csv"))?);
+        let external_deps =
+            Mutex::new(csv::Writer::from_path(path.as_ref().join("external_deps.csv"))?);
+        let hierarchy = Mutex::new(csv::Writer::from_path(path.as_ref().join("hierarchy.csv"))?);
+        let diagnostics =
+            Mutex::new(csv::Writer::from_path(path.as_ref().join("diagnostics.csv"))?);
+        let annotations =
+            Mutex::new(csv::Writer::from_path(path.as_ref().join("annotations.csv"))?);
+        let files = Mutex::new(csv::Writer::from_path(path.as_ref().join("files.csv"))?);
+        let commits = Mutex::new(csv::Writer::from_path(path.as_ref().join("commits.csv"))?);
+        Ok(Self {
+            entities,
+            deps,
+            changes,
+            dep_changes,
+            contents,
+            alignments,
+            revspecs,
+            unresolved_refs,
+            entity_matches,
+            metrics,
+            external_deps,
+            hierarchy,
+            diagnostics,
+            annotations,
+            files,
+            commits,
+            taxonomy,
+        })
     }
 }
 
@@ -98,7 +584,7 @@ impl Writer for CsvsWriter {
     }
 
     fn write_dep(&self, value: EntityDep) -> Result<()> {
-        Ok(self.deps.lock().unwrap().serialize(EntityDepRow::from(value))?)
+        Ok(self.deps.lock().unwrap().serialize(EntityDepRow::from(value, &self.taxonomy))?)
     }
 
     fn write_change(&self, value: Change) -> Result<()> {
@@ -109,145 +595,1785 @@ impl Writer for CsvsWriter {
         Ok(self.contents.lock().unwrap().serialize(value)?)
     }
 
+    fn write_dep_change(&self, value: DepChange) -> Result<()> {
+        Ok(self.dep_changes.lock().unwrap().serialize(value)?)
+    }
+
+    fn write_alignment(&self, value: Alignment) -> Result<()> {
+        Ok(self.alignments.lock().unwrap().serialize(value)?)
+    }
+
+    fn write_revspec(&self, value: Revspec) -> Result<()> {
+        Ok(self.revspecs.lock().unwrap().serialize(value)?)
+    }
+
+    fn write_unresolved_ref(&self, value: UnresolvedRef) -> Result<()> {
+        Ok(self.unresolved_refs.lock().unwrap().serialize(value)?)
+    }
+
+    fn write_entity_match(&self, value: EntityMatch) -> Result<()> {
+        Ok(self.entity_matches.lock().unwrap().serialize(value)?)
+    }
+
+    fn write_metric(&self, value: Metric) -> Result<()> {
+        Ok(self.metrics.lock().unwrap().serialize(value)?)
+    }
+
+    fn write_external_dep(&self, value: ExternalDep) -> Result<()> {
+        Ok(self.external_deps.lock().unwrap().serialize(value)?)
+    }
+
+    fn write_supertype(&self, value: Supertype) -> Result<()> {
+        Ok(self.hierarchy.lock().unwrap().serialize(value)?)
+    }
+
+    fn write_diagnostic(&self, value: Diagnostic) -> Result<()> {
+        Ok(self.diagnostics.lock().unwrap().serialize(value)?)
+    }
+
+    fn write_annotation(&self, value: Annotation) -> Result<()> {
+        Ok(self.annotations.lock().unwrap().serialize(value)?)
+    }
+
+    fn write_file(&self, value: CommitFile) -> Result<()> {
+        Ok(self.files.lock().unwrap().serialize(value)?)
+    }
+
+    fn write_commit(&self, value: Commit) -> Result<()> {
+        Ok(self.commits.lock().unwrap().serialize(value)?)
+    }
+
     fn finalize(&mut self) -> Result<()> {
         self.entities.lock().unwrap().flush()?;
         self.deps.lock().unwrap().flush()?;
         self.changes.lock().unwrap().flush()?;
+        self.dep_changes.lock().unwrap().flush()?;
         self.contents.lock().unwrap().flush()?;
+        self.alignments.lock().unwrap().flush()?;
+        self.revspecs.lock().unwrap().flush()?;
+        self.unresolved_refs.lock().unwrap().flush()?;
+        self.entity_matches.lock().unwrap().flush()?;
+        self.metrics.lock().unwrap().flush()?;
+        self.external_deps.lock().unwrap().flush()?;
+        self.hierarchy.lock().unwrap().flush()?;
+        self.diagnostics.lock().unwrap().flush()?;
+        self.annotations.lock().unwrap().flush()?;
+        self.files.lock().unwrap().flush()?;
+        self.commits.lock().unwrap().flush()?;
         Ok(())
     }
 }
 
-#[derive(Debug)]
+/// The version of the rows written by [JsonlWriter], as given in each envelope.
+///
+/// Bump this whenever the fields of a resource change.
+const JSONL_SCHEMA_VERSION: u32 = 15;
+
+/// Writes every resource to a single file, one row per line.
+///
+/// If `envelope` is set, each row is wrapped as `{"type": ..., "v": ...,
+/// "data": ...}` so that consumers can tell which resource a line belongs to
+/// without guessing from its fields.
 struct JsonlWriter {
-    file: Mutex<LineWriter<File>>,
+    file: Mutex<JsonlFile>,
+    envelope: bool,
+    taxonomy: Taxonomy,
+}
+
+impl JsonlWriter {
+    fn open<P: AsRef<Path>>(
+        path: P,
+        compression: Option<Compression>,
+        envelope: bool,
+        taxonomy: Taxonomy,
+    ) -> Result<Self> {
+        let file = Mutex::new(JsonlFile::create(path, compression)?);
+        Ok(Self { file, envelope, taxonomy })
+    }
+
+    fn write<S: serde::Serialize>(&self, resource: Resource, value: S) -> Result<()> {
+        let line = match self.envelope {
+            true => serde_json::to_string(&JsonlEnvelope {
+                r#type: resource.singular(),
+                v: JSONL_SCHEMA_VERSION,
+                data: value,
+            })?,
+            false => serde_json::to_string(&value)?,
+        };
+
+        Ok(write!(self.file.lock().unwrap(), "{}\n", line)?)
+    }
+}
+
+#[derive(serde::Serialize)]
+struct JsonlEnvelope<S> {
+    r#type: &'static str,
+    v: u32,
+    data: S,
+}
+
+impl Writer for JsonlWriter {
+    fn supports(&self, _: Resource) -> bool {
+        true
+    }
+
+    fn is_single_structure(&self) -> bool {
+        false
+    }
+
+    fn write_entity(&self, value: Entity) -> Result<()> {
+        self.write(Resource::Entities, EntityRow::from(value))
+    }
+
+    fn write_dep(&self, value: EntityDep) -> Result<()> {
+        self.write(Resource::Deps, EntityDepRow::from(value, &self.taxonomy))
+    }
+
+    fn write_change(&self, value: Change) -> Result<()> {
+        self.write(Resource::Changes, value)
+    }
+
+    fn write_content(&self, value: Content) -> Result<()> {
+        self.write(Resource::Contents, value)
+    }
+
+    fn write_dep_change(&self, value: DepChange) -> Result<()> {
+        self.write(Resource::DepChanges, value)
+    }
+
+    fn write_alignment(&self, value: Alignment) -> Result<()> {
+        self.write(Resource::Alignments, value)
+    }
+
+    fn write_revspec(&self, value: Revspec) -> Result<()> {
+        self.write(Resource::Revspecs, value)
+    }
+
+    fn write_unresolved_ref(&self, value: UnresolvedRef) -> Result<()> {
+        self.write(Resource::UnresolvedRefs, value)
+    }
+
+    fn write_entity_match(&self, value: EntityMatch) -> Result<()> {
+        self.write(Resource::EntityMatches, value)
+    }
+
+    fn write_metric(&self, value: Metric) -> Result<()> {
+        self.write(Resource::Metrics, value)
+    }
+
+    fn write_external_dep(&self, value: ExternalDep) -> Result<()> {
+        self.write(Resource::ExternalDeps, value)
+    }
+
+    fn write_supertype(&self, value: Supertype) -> Result<()> {
+        self.write(Resource::Hierarchy, value)
+    }
+
+    fn write_diagnostic(&self, value: Diagnostic) -> Result<()> {
+        self.write(Resource::Diagnostics, value)
+    }
+
+    fn write_annotation(&self, value: Annotation) -> Result<()> {
+        self.write(Resource::Annotations, value)
+    }
+
+    fn write_file(&self, value: CommitFile) -> Result<()> {
+        self.write(Resource::Files, value)
+    }
+
+    fn write_commit(&self, value: Commit) -> Result<()> {
+        self.write(Resource::Commits, value)
+    }
+
+    fn write_value(&self, resource: Resource, value: serde_json::Value) -> Result<()> {
+        self.write(resource, value)
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.file.lock().unwrap().finish()
+    }
+}
+
+/// Like [JsonlWriter], but with a separate .jsonl file for each resource.
+///
+/// Every line of a file has the same fields, so consumers do not need to guess
+/// which resource a line belongs to.
+struct JsonlsWriter {
+    files: HashMap<Resource, Mutex<JsonlFile>>,
+    taxonomy: Taxonomy,
+}
+
+impl JsonlsWriter {
+    fn open<P: AsRef<Path>>(
+        path: P,
+        compression: Option<Compression>,
+        taxonomy: Taxonomy,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(path.as_ref())?;
+        let mut files = HashMap::new();
+
+        for resource in Resource::iter() {
+            let mut filename = format!("{}.jsonl", resource.to_string().replace('-', "_"));
+
+            if let Some(compression) = compression {
+                filename = format!("{}.{}", filename, compression.extension());
+            }
+
+            let file = JsonlFile::create(path.as_ref().join(filename), compression)?;
+            files.insert(resource, Mutex::new(file));
+        }
+
+        Ok(Self { files, taxonomy })
+    }
+
+    fn write<S: serde::Serialize>(&self, resource: Resource, value: S) -> Result<()> {
+        let mut file = self.files[&resource].lock().unwrap();
+        Ok(writeln!(file, "{}", serde_json::to_string(&value)?)?)
+    }
 }
 
-impl JsonlWriter {
-    fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        Ok(Self { file: Mutex::new(LineWriter::new(File::create(path)?)) })
+impl Writer for JsonlsWriter {
+    fn supports(&self, _: Resource) -> bool {
+        true
+    }
+
+    fn is_single_structure(&self) -> bool {
+        false
+    }
+
+    fn write_entity(&self, value: Entity) -> Result<()> {
+        self.write(Resource::Entities, EntityRow::from(value))
+    }
+
+    fn write_dep(&self, value: EntityDep) -> Result<()> {
+        self.write(Resource::Deps, EntityDepRow::from(value, &self.taxonomy))
+    }
+
+    fn write_change(&self, value: Change) -> Result<()> {
+        self.write(Resource::Changes, value)
+    }
+
+    fn write_content(&self, value: Content) -> Result<()> {
+        self.write(Resource::Contents, value)
+    }
+
+    fn write_dep_change(&self, value: DepChange) -> Result<()> {
+        self.write(Resource::DepChanges, value)
+    }
+
+    fn write_alignment(&self, value: Alignment) -> Result<()> {
+        self.write(Resource::Alignments, value)
+    }
+
+    fn write_revspec(&self, value: Revspec) -> Result<()> {
+        self.write(Resource::Revspecs, value)
+    }
+
+    fn write_unresolved_ref(&self, value: UnresolvedRef) -> Result<()> {
+        self.write(Resource::UnresolvedRefs, value)
+    }
+
+    fn write_entity_match(&self, value: EntityMatch) -> Result<()> {
+        self.write(Resource::EntityMatches, value)
+    }
+
+    fn write_metric(&self, value: Metric) -> Result<()> {
+        self.write(Resource::Metrics, value)
+    }
+
+    fn write_external_dep(&self, value: ExternalDep) -> Result<()> {
+        self.write(Resource::ExternalDeps, value)
+    }
+
+    fn write_supertype(&self, value: Supertype) -> Result<()> {
+        self.write(Resource::Hierarchy, value)
+    }
+
+    fn write_diagnostic(&self, value: Diagnostic) -> Result<()> {
+        self.write(Resource::Diagnostics, value)
+    }
+
+    fn write_annotation(&self, value: Annotation) -> Result<()> {
+        self.write(Resource::Annotations, value)
+    }
+
+    fn write_file(&self, value: CommitFile) -> Result<()> {
+        self.write(Resource::Files, value)
+    }
+
+    fn write_commit(&self, value: Commit) -> Result<()> {
+        self.write(Resource::Commits, value)
+    }
+
+    fn write_value(&self, resource: Resource, value: serde_json::Value) -> Result<()> {
+        self.write(resource, value)
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        for file in self.files.values() {
+            file.lock().unwrap().finish()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a stream of length-delimited Protocol Buffers messages.
+///
+/// Each message is a `Record` as defined by proto/neodepends.proto, which
+/// holds exactly one row of any resource.
+struct ProtoWriter {
+    file: Mutex<BufWriter<File>>,
+    taxonomy: Taxonomy,
+}
+
+impl ProtoWriter {
+    fn open<P: AsRef<Path>>(path: P, taxonomy: Taxonomy) -> Result<Self> {
+        Ok(Self { file: Mutex::new(BufWriter::new(File::create(path)?)), taxonomy })
+    }
+
+    fn write(&self, record: proto::record::Record) -> Result<()> {
+        let buf = proto::Record::new(record).encode_length_delimited_to_vec();
+        Ok(self.file.lock().unwrap().write_all(&buf)?)
+    }
+}
+
+impl Writer for ProtoWriter {
+    fn supports(&self, _: Resource) -> bool {
+        true
+    }
+
+    fn is_single_structure(&self) -> bool {
+        false
+    }
+
+    fn write_entity(&self, value: Entity) -> Result<()> {
+        self.write(proto::record::Record::Entity(proto::EntityMessage::from(value)))
+    }
+
+    fn write_dep(&self, value: EntityDep) -> Result<()> {
+        self.write(proto::record::Record::Dep(proto::DepMessage::from(value, &self.taxonomy)))
+    }
+
+    fn write_change(&self, value: Change) -> Result<()> {
+        self.write(proto::record::Record::Change(proto::ChangeMessage::from(value)))
+    }
+
+    fn write_content(&self, value: Content) -> Result<()> {
+        self.write(proto::record::Record::Content(proto::ContentMessage::from(value)))
+    }
+
+    fn write_dep_change(&self, value: DepChange) -> Result<()> {
+        self.write(proto::record::Record::DepChange(proto::DepChangeMessage::from(value)))
+    }
+
+    fn write_alignment(&self, value: Alignment) -> Result<()> {
+        self.write(proto::record::Record::Alignment(proto::AlignmentMessage::from(value)))
+    }
+
+    fn write_revspec(&self, value: Revspec) -> Result<()> {
+        self.write(proto::record::Record::Revspec(proto::RevspecMessage::from(value)))
+    }
+
+    fn write_unresolved_ref(&self, value: UnresolvedRef) -> Result<()> {
+        self.write(proto::record::Record::UnresolvedRef(proto::UnresolvedRefMessage::from(value)))
+    }
+
+    fn write_entity_match(&self, value: EntityMatch) -> Result<()> {
+        self.write(proto::record::Record::EntityMatch(proto::EntityMatchMessage::from(value)))
+    }
+
+    fn write_metric(&self, value: Metric) -> Result<()> {
+        self.write(proto::record::Record::Metric(proto::MetricMessage::from(value)))
+    }
+
+    fn write_external_dep(&self, value: ExternalDep) -> Result<()> {
+        self.write(proto::record::Record::ExternalDep(proto::ExternalDepMessage::from(value)))
+    }
+
+    fn write_supertype(&self, value: Supertype) -> Result<()> {
+        self.write(proto::record::Record::Supertype(proto::SupertypeMessage::from(value)))
+    }
+
+    fn write_diagnostic(&self, value: Diagnostic) -> Result<()> {
+        self.write(proto::record::Record::Diagnostic(proto::DiagnosticMessage::from(value)))
+    }
+
+    fn write_annotation(&self, value: Annotation) -> Result<()> {
+        self.write(proto::record::Record::Annotation(proto::AnnotationMessage::from(value)))
+    }
+
+    fn write_file(&self, value: CommitFile) -> Result<()> {
+        self.write(proto::record::Record::File(proto::FileMessage::from(value)))
+    }
+
+    fn write_commit(&self, value: Commit) -> Result<()> {
+        self.write(proto::record::Record::Commit(proto::CommitMessage::from(value)))
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        Ok(self.file.lock().unwrap().flush()?)
+    }
+}
+
+/// The file behind a [JsonlWriter], which may be compressed.
+///
+/// Lines are only flushed eagerly when uncompressed. Flushing an encoder after
+/// every line would ruin the compression ratio.
+enum JsonlFile {
+    Plain(LineWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl JsonlFile {
+    fn create<P: AsRef<Path>>(path: P, compression: Option<Compression>) -> Result<Self> {
+        let file = File::create(path)?;
+
+        Ok(match compression {
+            None => JsonlFile::Plain(LineWriter::new(file)),
+            Some(Compression::Gzip) => JsonlFile::Gzip(GzEncoder::new(
+                BufWriter::new(file),
+                flate2::Compression::default(),
+            )),
+            Some(Compression::Zstd) => {
+                JsonlFile::Zstd(zstd::Encoder::new(BufWriter::new(file), 0)?)
+            }
+        })
+    }
+
+    /// Write any trailing compressed frames and flush to disk.
+    fn finish(&mut self) -> Result<()> {
+        match self {
+            JsonlFile::Plain(file) => file.flush()?,
+            JsonlFile::Gzip(encoder) => {
+                encoder.try_finish()?;
+                encoder.get_mut().flush()?;
+            }
+            JsonlFile::Zstd(encoder) => {
+                encoder.do_finish()?;
+                encoder.get_mut().flush()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for JsonlFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            JsonlFile::Plain(file) => file.write(buf),
+            JsonlFile::Gzip(encoder) => encoder.write(buf),
+            JsonlFile::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            JsonlFile::Plain(file) => file.flush(),
+            JsonlFile::Gzip(encoder) => encoder.flush(),
+            JsonlFile::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Dsm {
+    V1,
+    V2,
+    Dv8,
+    Mtx,
+}
+
+/// Collects entities and a [Tally] of deps and changes, building the matrix
+/// once everything has been written. Only the entities are held in memory
+/// until then (see [Tally]).
+#[derive(Debug)]
+struct DsmWriter {
+    path: PathBuf,
+    dsm: Dsm,
+    entities: Mutex<Vec<Entity>>,
+    tally: Mutex<Tally>,
+    taxonomy: Taxonomy,
+    ownership: Ownership,
+    level: Level,
+    minify: bool,
+}
+
+impl DsmWriter {
+    fn open<P: AsRef<Path>>(
+        path: P,
+        dsm: Dsm,
+        taxonomy: Taxonomy,
+        ownership: Ownership,
+        level: Level,
+        minify: bool,
+    ) -> Result<Self> {
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            dsm,
+            entities: Default::default(),
+            tally: Mutex::new(Tally::new()?),
+            taxonomy,
+            ownership,
+            level,
+            minify,
+        })
+    }
+}
+
+impl Writer for DsmWriter {
+    fn supports(&self, resource: Resource) -> bool {
+        match resource {
+            Resource::Entities => true,
+            Resource::Deps => true,
+            Resource::Changes => true,
+            _ => false,
+        }
+    }
+
+    fn is_single_structure(&self) -> bool {
+        true
+    }
+
+    fn write_entity(&self, value: Entity) -> Result<()> {
+        self.entities.lock().unwrap().push(value);
+        Ok(())
+    }
+
+    fn write_dep(&self, value: EntityDep) -> Result<()> {
+        self.tally.lock().unwrap().add_dep(&value)
+    }
+
+    fn write_change(&self, value: Change) -> Result<()> {
+        self.tally.lock().unwrap().add_change(&value)
+    }
+
+    fn write_content(&self, _: Content) -> Result<()> {
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let mut entities = self.entities.lock().unwrap();
+        let tally = self.tally.get_mut().unwrap();
+        let no_owners = Ownership::default();
+        let mut ownership = &self.ownership;
+
+        // Every cell is then between two teams, so marking cells whose
+        // entities share a team would only mark the diagonal.
+        if self.level.is_team() {
+            let teams = self.ownership.teams(&entities);
+            *entities = aggregate_teams(&teams, &entities, tally)?;
+            ownership = &no_owners;
+        }
+
+        // Directories are merged just like teams, so the cells between
+        // directories owned by the same team are still marked
+        if let Level::Dir(depth) = self.level {
+            let dirs = directories(&entities, depth);
+            let dirs = dirs.iter().map(|(&id, dir)| (id, dir.as_str())).collect();
+            *entities = aggregate_teams(&dirs, &entities, tally)?;
+        }
+
+        let (taxonomy, minify) = (&self.taxonomy, self.minify);
+
+        match self.dsm {
+            Dsm::Mtx => write_mtx(&self.path, &entities, tally),
+            Dsm::V1 => {
+                let matrix = dsm_v1(&entities, tally, taxonomy, ownership)?;
+                write_json(&self.path, &matrix, minify)
+            }
+            Dsm::V2 => {
+                let matrix = dsm_v2(&entities, tally, taxonomy, ownership)?;
+                write_json(&self.path, &matrix, minify)
+            }
+            Dsm::Dv8 => {
+                let name = self.path.file_stem().unwrap_or_default().to_string_lossy();
+                let matrix = Dv8Matrix::build(&name, &entities, tally, taxonomy, ownership)?;
+                write_json(&self.path, &matrix, minify)
+            }
+        }
+    }
+}
+
+/// Serialize straight to a file, so a large matrix never has to be held in
+/// memory as a single string.
+fn write_json<S: serde::Serialize>(path: &Path, value: &S, minify: bool) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    match minify {
+        true => serde_json::to_writer(&mut file, value)?,
+        false => serde_json::to_writer_pretty(&mut file, value)?,
+    }
+
+    Ok(file.flush()?)
+}
+
+/// Write a directory with a deps.mtx, a cochanges.mtx (if there are any
+/// changes), and a variables.csv that maps each row and column of the matrices
+/// to an entity.
+fn write_mtx(path: &Path, entities: &[Entity], tally: &Tally) -> Result<()> {
+    std::fs::create_dir_all(path)?;
+    std::fs::write(path.join("deps.mtx"), mtx_deps(entities, tally)?)?;
+
+    if tally.has_changes()? {
+        std::fs::write(path.join("cochanges.mtx"), mtx_cochanges(entities, tally)?)?;
+    }
+
+    let mut variables = csv::Writer::from_path(path.join("variables.csv"))?;
+
+    for (i, entity) in entities.iter().enumerate() {
+        variables.serialize(MtxVariableRow {
+            index: i + 1,
+            id: entity.id,
+            parent_id: entity.parent_id,
+            name: &entity.name,
+            kind: entity.kind,
+        })?;
+    }
+
+    Ok(variables.flush()?)
+}
+
+#[derive(Debug)]
+#[derive(serde::Serialize)]
+struct MtxVariableRow<'a> {
+    index: usize,
+    id: EntityId,
+    parent_id: Option<EntityId>,
+    name: &'a str,
+    kind: EntityKind,
+}
+
+#[derive(Debug)]
+enum Graph {
+    Graphml,
+    Dot,
+    Structurizr(String),
+    Plantuml(Vec<String>),
+}
+
+#[derive(Debug)]
+struct GraphWriter {
+    path: PathBuf,
+    graph: Graph,
+    entities: Mutex<Vec<Entity>>,
+    deps: Mutex<Vec<EntityDep>>,
+    taxonomy: Taxonomy,
+}
+
+impl GraphWriter {
+    fn open<P: AsRef<Path>>(path: P, graph: Graph, taxonomy: Taxonomy) -> Result<Self> {
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            graph,
+            entities: Default::default(),
+            deps: Default::default(),
+            taxonomy,
+        })
+    }
+}
+
+impl Writer for GraphWriter {
+    fn supports(&self, resource: Resource) -> bool {
+        matches!(resource, Resource::Entities | Resource::Deps)
+    }
+
+    fn is_single_structure(&self) -> bool {
+        true
+    }
+
+    fn write_entity(&self, value: Entity) -> Result<()> {
+        self.entities.lock().unwrap().push(value);
+        Ok(())
+    }
+
+    fn write_dep(&self, value: EntityDep) -> Result<()> {
+        self.deps.lock().unwrap().push(value);
+        Ok(())
+    }
+
+    fn write_change(&self, _: Change) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_content(&self, _: Content) -> Result<()> {
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let entities = self.entities.lock().unwrap();
+        let deps = self.deps.lock().unwrap();
+
+        let text = match &self.graph {
+            Graph::Graphml => graphml(&entities, &deps, &self.taxonomy),
+            Graph::Dot => dot(&entities, &deps, &self.taxonomy),
+            Graph::Structurizr(name) => structurizr(name, &entities, &deps, &self.taxonomy),
+            Graph::Plantuml(scope) => plantuml(&entities, &deps, scope),
+        };
+
+        Ok(File::create(&self.path)?.write_all(text.as_bytes())?)
+    }
+}
+
+/// Writes a SCIP index of the entities and deps of a single commit.
+#[derive(Debug)]
+struct ScipWriter {
+    path: PathBuf,
+    root: PathBuf,
+    args: Vec<String>,
+    entities: Mutex<Vec<Entity>>,
+    deps: Mutex<Vec<EntityDep>>,
+}
+
+impl ScipWriter {
+    fn open<P: AsRef<Path>>(path: P, root: PathBuf, args: Vec<String>) -> Result<Self> {
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            root,
+            args,
+            entities: Default::default(),
+            deps: Default::default(),
+        })
+    }
+}
+
+impl Writer for ScipWriter {
+    fn supports(&self, resource: Resource) -> bool {
+        matches!(resource, Resource::Entities | Resource::Deps)
+    }
+
+    fn is_single_structure(&self) -> bool {
+        true
+    }
+
+    fn write_entity(&self, value: Entity) -> Result<()> {
+        self.entities.lock().unwrap().push(value);
+        Ok(())
+    }
+
+    fn write_dep(&self, value: EntityDep) -> Result<()> {
+        self.deps.lock().unwrap().push(value);
+        Ok(())
+    }
+
+    fn write_change(&self, _: Change) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_content(&self, _: Content) -> Result<()> {
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let entities = self.entities.lock().unwrap();
+        let deps = self.deps.lock().unwrap();
+        let index = build_index(&self.root, &self.args, &entities, &deps);
+        Ok(File::create(&self.path)?.write_all(&index.encode_to_vec())?)
+    }
+}
+
+#[derive(Debug)]
+enum Report {
+    Summary,
+    Mermaid,
+}
+
+/// Writes a Markdown report of the entities, deps, and changes.
+#[derive(Debug)]
+struct MarkdownWriter {
+    path: PathBuf,
+    report: Report,
+    entities: Mutex<Vec<Entity>>,
+    deps: Mutex<Vec<EntityDep>>,
+    changes: Mutex<Vec<Change>>,
+}
+
+impl MarkdownWriter {
+    fn open<P: AsRef<Path>>(path: P, report: Report) -> Result<Self> {
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            report,
+            entities: Default::default(),
+            deps: Default::default(),
+            changes: Default::default(),
+        })
+    }
+}
+
+impl Writer for MarkdownWriter {
+    fn supports(&self, resource: Resource) -> bool {
+        matches!(resource, Resource::Entities | Resource::Deps | Resource::Changes)
+    }
+
+    fn is_single_structure(&self) -> bool {
+        true
+    }
+
+    fn write_entity(&self, value: Entity) -> Result<()> {
+        self.entities.lock().unwrap().push(value);
+        Ok(())
+    }
+
+    fn write_dep(&self, value: EntityDep) -> Result<()> {
+        self.deps.lock().unwrap().push(value);
+        Ok(())
+    }
+
+    fn write_change(&self, value: Change) -> Result<()> {
+        self.changes.lock().unwrap().push(value);
+        Ok(())
+    }
+
+    fn write_content(&self, _: Content) -> Result<()> {
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let entities = self.entities.lock().unwrap();
+        let deps = self.deps.lock().unwrap();
+        let changes = self.changes.lock().unwrap();
+
+        let text = match self.report {
+            Report::Summary => report(&entities, &deps, &changes),
+            Report::Mermaid => mermaid(&entities, &deps, &changes),
+        };
+
+        Ok(File::create(&self.path)?.write_all(text.as_bytes())?)
+    }
+}
+
+/// Writes a directory with an entities.csv and a references.csv in the layout
+/// used by SciTools Understand.
+///
+/// References need the file and long name of both of their entities, so
+/// everything is buffered until all entities are known.
+#[derive(Debug)]
+struct UnderstandWriter {
+    path: PathBuf,
+    entities: Mutex<Vec<Entity>>,
+    deps: Mutex<Vec<EntityDep>>,
+    taxonomy: Taxonomy,
+}
+
+impl UnderstandWriter {
+    fn open<P: AsRef<Path>>(path: P, taxonomy: Taxonomy) -> Result<Self> {
+        std::fs::create_dir_all(path.as_ref())?;
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            entities: Default::default(),
+            deps: Default::default(),
+            taxonomy,
+        })
+    }
+}
+
+impl Writer for UnderstandWriter {
+    fn supports(&self, resource: Resource) -> bool {
+        matches!(resource, Resource::Entities | Resource::Deps)
+    }
+
+    fn is_single_structure(&self) -> bool {
+        true
+    }
+
+    fn write_entity(&self, value: Entity) -> Result<()> {
+        self.entities.lock().unwrap().push(value);
+        Ok(())
+    }
+
+    fn write_dep(&self, value: EntityDep) -> Result<()> {
+        self.deps.lock().unwrap().push(value);
+        Ok(())
+    }
+
+    fn write_change(&self, _: Change) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_content(&self, _: Content) -> Result<()> {
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let entities = self.entities.lock().unwrap();
+        let deps = self.deps.lock().unwrap();
+        let (entity_rows, reference_rows) = build_rows(&entities, &deps, &self.taxonomy);
+
+        let mut writer = csv::Writer::from_path(self.path.join("entities.csv"))?;
+        entity_rows.into_iter().try_for_each(|r| writer.serialize(r))?;
+        writer.flush()?;
+
+        let mut writer = csv::Writer::from_path(self.path.join("references.csv"))?;
+        reference_rows.into_iter().try_for_each(|r| writer.serialize(r))?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes a directory of CSV files in the format expected by `neo4j-admin
+/// database import`.
+///
+/// Entities and commits are written to nodes.csv. Containment, deps, and
+/// changes are written to relationships.csv. Changes refer to a
+/// [SimpleEntityId], so they are buffered until all entities are known and
+/// then attached to every entity with a matching `simple_id`.
+#[derive(Debug)]
+struct Neo4jWriter {
+    nodes: Mutex<csv::Writer<File>>,
+    relationships: Mutex<csv::Writer<File>>,
+    simple_ids: Mutex<HashMap<SimpleEntityId, Vec<EntityId>>>,
+    changes: Mutex<Vec<Change>>,
+    taxonomy: Taxonomy,
+}
+
+impl Neo4jWriter {
+    fn open<P: AsRef<Path>>(path: P, taxonomy: Taxonomy) -> Result<Self> {
+        std::fs::create_dir_all(path.as_ref())?;
+        let nodes = Mutex::new(csv::Writer::from_path(path.as_ref().join("nodes.csv"))?);
+        let relationships =
+            Mutex::new(csv::Writer::from_path(path.as_ref().join("relationships.csv"))?);
+        Ok(Self {
+            nodes,
+            relationships,
+            simple_ids: Default::default(),
+            changes: Default::default(),
+            taxonomy,
+        })
+    }
+}
+
+impl Writer for Neo4jWriter {
+    fn supports(&self, resource: Resource) -> bool {
+        matches!(resource, Resource::Entities | Resource::Deps | Resource::Changes)
+    }
+
+    fn is_single_structure(&self) -> bool {
+        false
+    }
+
+    fn write_entity(&self, value: Entity) -> Result<()> {
+        self.simple_ids.lock().unwrap().entry(value.simple_id).or_default().push(value.id);
+
+        if let Some(parent_id) = value.parent_id {
+            self.relationships.lock().unwrap().serialize(Neo4jRelationshipRow {
+                start: parent_id.0.to_string(),
+                end: value.id.0.to_string(),
+                r#type: "CONTAINS",
+                ..Default::default()
+            })?;
+        }
+
+        Ok(self.nodes.lock().unwrap().serialize(Neo4jNodeRow {
+            id: value.id.0.to_string(),
+            name: value.name,
+            kind: value.kind.as_ref().to_string(),
+            labels: format!("Entity;{}", value.kind.as_ref()),
+        })?)
+    }
+
+    fn write_dep(&self, value: EntityDep) -> Result<()> {
+        Ok(self.relationships.lock().unwrap().serialize(Neo4jRelationshipRow {
+            start: value.src.0.to_string(),
+            end: value.tgt.0.to_string(),
+            r#type: "DEPENDS_ON",
+            kind: Some(self.taxonomy.label(value.kind).to_string()),
+            row: Some(value.position.row()),
+            count: Some(value.count),
+            ..Default::default()
+        })?)
+    }
+
+    fn write_change(&self, value: Change) -> Result<()> {
+        self.changes.lock().unwrap().push(value);
+        Ok(())
+    }
+
+    fn write_content(&self, _: Content) -> Result<()> {
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let simple_ids = self.simple_ids.lock().unwrap();
+        let changes = self.changes.lock().unwrap();
+        let mut nodes = self.nodes.lock().unwrap();
+        let mut relationships = self.relationships.lock().unwrap();
+
+        let commit_ids: BTreeSet<CommitId> = changes.iter().map(|c| c.commit_id).collect();
+
+        for commit_id in commit_ids {
+            nodes.serialize(Neo4jNodeRow {
+                id: commit_id.to_string(),
+                name: commit_id.to_string(),
+                kind: "Commit".to_string(),
+                labels: "Commit".to_string(),
+            })?;
+        }
+
+        for change in changes.iter() {
+            for entity_id in simple_ids.get(&change.simple_id).into_iter().flatten() {
+                relationships.serialize(Neo4jRelationshipRow {
+                    start: change.commit_id.to_string(),
+                    end: entity_id.0.to_string(),
+                    r#type: "CHANGED",
+                    kind: Some(change.kind.as_ref().to_string()),
+                    adds: Some(change.adds),
+                    dels: Some(change.dels),
+                    ..Default::default()
+                })?;
+            }
+        }
+
+        nodes.flush()?;
+        relationships.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+#[derive(serde::Serialize)]
+struct Neo4jNodeRow {
+    #[serde(rename = "id:ID")]
+    id: String,
+    name: String,
+    kind: String,
+    #[serde(rename = ":LABEL")]
+    labels: String,
+}
+
+#[derive(Debug, Default)]
+#[derive(serde::Serialize)]
+struct Neo4jRelationshipRow {
+    #[serde(rename = ":START_ID")]
+    start: String,
+    #[serde(rename = ":END_ID")]
+    end: String,
+    #[serde(rename = ":TYPE")]
+    r#type: &'static str,
+    kind: Option<String>,
+    #[serde(rename = "row:int")]
+    row: Option<usize>,
+    #[serde(rename = "count:int")]
+    count: Option<usize>,
+    #[serde(rename = "adds:int")]
+    adds: Option<usize>,
+    #[serde(rename = "dels:int")]
+    dels: Option<usize>,
+}
+
+/// Writes a directory of Arrow IPC stream files, one per resource.
+///
+/// Rows are collected into record batches of [ARROW_BATCH_SIZE] rows, so the
+/// output can be streamed rather than held in memory. Each file can be loaded
+/// directly with `polars.read_ipc_stream` or `pyarrow.ipc.open_stream`.
+struct ArrowWriter {
+    entities: Mutex<ArrowStream>,
+    deps: Mutex<ArrowStream>,
+    changes: Mutex<ArrowStream>,
+    dep_changes: Mutex<ArrowStream>,
+    contents: Mutex<ArrowStream>,
+    alignments: Mutex<ArrowStream>,
+    revspecs: Mutex<ArrowStream>,
+    unresolved_refs: Mutex<ArrowStream>,
+    entity_matches: Mutex<ArrowStream>,
+    metrics: Mutex<ArrowStream>,
+    external_deps: Mutex<ArrowStream>,
+    hierarchy: Mutex<ArrowStream>,
+    diagnostics: Mutex<ArrowStream>,
+    annotations: Mutex<ArrowStream>,
+    files: Mutex<ArrowStream>,
+    commits: Mutex<ArrowStream>,
+    taxonomy: Taxonomy,
+}
+
+impl ArrowWriter {
+    fn open<P: AsRef<Path>>(path: P, taxonomy: Taxonomy) -> Result<Self> {
+        std::fs::create_dir_all(path.as_ref())?;
+        let open = |name: &str, fields| -> Result<_> {
+            Ok(Mutex::new(ArrowStream::open(path.as_ref().join(name), fields)?))
+        };
+        Ok(Self {
+            entities: open("entities.arrow", arrow_entity_fields())?,
+            deps: open("deps.arrow", arrow_dep_fields())?,
+            changes: open("changes.arrow", arrow_change_fields())?,
+            dep_changes: open("dep_changes.arrow", arrow_dep_change_fields())?,
+            contents: open("contents.arrow", arrow_content_fields())?,
+            alignments: open("alignments.arrow", arrow_alignment_fields())?,
+            revspecs: open("revspecs.arrow", arrow_revspec_fields())?,
+            unresolved_refs: open("unresolved_refs.arrow", arrow_unresolved_ref_fields())?,
+            entity_matches: open("entity_matches.arrow", arrow_entity_match_fields())?,
+            metrics: open("metrics.arrow", arrow_metric_fields())?,
+            external_deps: open("external_deps.arrow", arrow_external_dep_fields())?,
+            hierarchy: open("hierarchy.arrow", arrow_supertype_fields())?,
+            diagnostics: open("diagnostics.arrow", arrow_diagnostic_fields())?,
+            annotations: open("annotations.arrow", arrow_annotation_fields())?,
+            files: open("files.arrow", arrow_file_fields())?,
+            commits: open("commits.arrow", arrow_commit_fields())?,
+            taxonomy,
+        })
+    }
+}
+
+impl Writer for ArrowWriter {
+    fn supports(&self, _: Resource) -> bool {
+        true
+    }
+
+    fn is_single_structure(&self) -> bool {
+        false
+    }
+
+    fn write_entity(&self, value: Entity) -> Result<()> {
+        self.entities.lock().unwrap().push(EntityRow::from(value))
+    }
+
+    fn write_dep(&self, value: EntityDep) -> Result<()> {
+        self.deps.lock().unwrap().push(EntityDepRow::from(value, &self.taxonomy))
+    }
+
+    fn write_change(&self, value: Change) -> Result<()> {
+        self.changes.lock().unwrap().push(value)
+    }
+
+    fn write_content(&self, value: Content) -> Result<()> {
+        self.contents.lock().unwrap().push(value)
+    }
+
+    fn write_dep_change(&self, value: DepChange) -> Result<()> {
+        self.dep_changes.lock().unwrap().push(value)
+    }
+
+    fn write_alignment(&self, value: Alignment) -> Result<()> {
+        self.alignments.lock().unwrap().push(value)
+    }
+
+    fn write_revspec(&self, value: Revspec) -> Result<()> {
+        self.revspecs.lock().unwrap().push(value)
+    }
+
+    fn write_unresolved_ref(&self, value: UnresolvedRef) -> Result<()> {
+        self.unresolved_refs.lock().unwrap().push(value)
+    }
+
+    fn write_entity_match(&self, value: EntityMatch) -> Result<()> {
+        self.entity_matches.lock().unwrap().push(value)
+    }
+
+    fn write_metric(&self, value: Metric) -> Result<()> {
+        self.metrics.lock().unwrap().push(value)
+    }
+
+    fn write_external_dep(&self, value: ExternalDep) -> Result<()> {
+        self.external_deps.lock().unwrap().push(value)
+    }
+
+    fn write_supertype(&self, value: Supertype) -> Result<()> {
+        self.hierarchy.lock().unwrap().push(value)
+    }
+
+    fn write_diagnostic(&self, value: Diagnostic) -> Result<()> {
+        self.diagnostics.lock().unwrap().push(value)
+    }
+
+    fn write_annotation(&self, value: Annotation) -> Result<()> {
+        self.annotations.lock().unwrap().push(value)
+    }
+
+    fn write_file(&self, value: CommitFile) -> Result<()> {
+        self.files.lock().unwrap().push(value)
+    }
+
+    fn write_commit(&self, value: Commit) -> Result<()> {
+        self.commits.lock().unwrap().push(value)
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.entities.lock().unwrap().finish()?;
+        self.deps.lock().unwrap().finish()?;
+        self.changes.lock().unwrap().finish()?;
+        self.dep_changes.lock().unwrap().finish()?;
+        self.contents.lock().unwrap().finish()?;
+        self.alignments.lock().unwrap().finish()?;
+        self.revspecs.lock().unwrap().finish()?;
+        self.unresolved_refs.lock().unwrap().finish()?;
+        self.entity_matches.lock().unwrap().finish()?;
+        self.metrics.lock().unwrap().finish()?;
+        self.external_deps.lock().unwrap().finish()?;
+        self.hierarchy.lock().unwrap().finish()?;
+        self.diagnostics.lock().unwrap().finish()?;
+        self.annotations.lock().unwrap().finish()?;
+        self.files.lock().unwrap().finish()?;
+        self.commits.lock().unwrap().finish()?;
+        Ok(())
+    }
+}
+
+const ARROW_BATCH_SIZE: usize = 8192;
+
+struct ArrowStream {
+    builder: ArrayBuilder,
+    writer: StreamWriter<BufWriter<File>>,
+    len: usize,
+}
+
+impl ArrowStream {
+    fn open(path: PathBuf, fields: Vec<FieldRef>) -> Result<Self> {
+        let builder = ArrayBuilder::from_arrow(&fields)?;
+        let schema = Schema::new(fields);
+        let writer = StreamWriter::try_new(BufWriter::new(File::create(path)?), &schema)?;
+        Ok(Self { builder, writer, len: 0 })
+    }
+
+    fn push<S: serde::Serialize>(&mut self, value: S) -> Result<()> {
+        self.builder.push(value)?;
+        self.len += 1;
+
+        if self.len >= ARROW_BATCH_SIZE {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.len > 0 {
+            self.writer.write(&self.builder.to_record_batch()?)?;
+            self.len = 0;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.flush()?;
+        self.writer.finish()?;
+        Ok(self.writer.get_mut().flush()?)
+    }
+}
+
+fn arrow_field(name: &str, data_type: DataType, nullable: bool) -> FieldRef {
+    Arc::new(Field::new(name, data_type, nullable))
+}
+
+fn arrow_entity_fields() -> Vec<FieldRef> {
+    vec![
+        arrow_field("id", DataType::Utf8, false),
+        arrow_field("parent_id", DataType::Utf8, true),
+        arrow_field("name", DataType::Utf8, false),
+        arrow_field("kind", DataType::Utf8, false),
+        arrow_field("start_byte", DataType::UInt64, false),
+        arrow_field("start_row", DataType::UInt64, false),
+        arrow_field("start_column", DataType::UInt64, false),
+        arrow_field("end_byte", DataType::UInt64, false),
+        arrow_field("end_row", DataType::UInt64, false),
+        arrow_field("end_column", DataType::UInt64, false),
+        arrow_field("content_id", DataType::Utf8, false),
+        arrow_field("simple_id", DataType::Utf8, false),
+        arrow_field("ordinal", DataType::UInt64, false),
+        arrow_field("comment_start_byte", DataType::UInt64, true),
+        arrow_field("comment_start_row", DataType::UInt64, true),
+        arrow_field("comment_start_column", DataType::UInt64, true),
+        arrow_field("comment_end_byte", DataType::UInt64, true),
+        arrow_field("comment_end_row", DataType::UInt64, true),
+        arrow_field("comment_end_column", DataType::UInt64, true),
+        arrow_field("qualified_name", DataType::Utf8, false),
+        arrow_field("entity_content_id", DataType::Utf8, false),
+        arrow_field("is_test", DataType::Boolean, false),
+        arrow_field("byte_size", DataType::UInt64, false),
+        arrow_field("token_count", DataType::UInt64, true),
+        arrow_field("lang", DataType::Utf8, true),
+        arrow_field("filename", DataType::Utf8, true),
+    ]
+}
+
+fn arrow_dep_fields() -> Vec<FieldRef> {
+    vec![
+        arrow_field("src", DataType::Utf8, false),
+        arrow_field("tgt", DataType::Utf8, false),
+        arrow_field("kind", DataType::Utf8, false),
+        arrow_field("row", DataType::UInt64, false),
+        arrow_field("commit_id", DataType::Utf8, false),
+        arrow_field("confidence", DataType::Utf8, false),
+        arrow_field("resolver", DataType::Utf8, false),
+        arrow_field("count", DataType::UInt64, false),
+        arrow_field("src_filename", DataType::Utf8, true),
+        arrow_field("tgt_filename", DataType::Utf8, true),
+        arrow_field("tgt_row", DataType::UInt64, true),
+        arrow_field("tgt_column", DataType::UInt64, true),
+        arrow_field("src_name", DataType::Utf8, true),
+        arrow_field("tgt_name", DataType::Utf8, true),
+    ]
+}
+
+fn arrow_change_fields() -> Vec<FieldRef> {
+    vec![
+        arrow_field("simple_id", DataType::Utf8, false),
+        arrow_field("commit_id", DataType::Utf8, false),
+        arrow_field("kind", DataType::Utf8, false),
+        arrow_field("adds", DataType::UInt64, false),
+        arrow_field("dels", DataType::UInt64, false),
+        arrow_field("author_name", DataType::Utf8, false),
+        arrow_field("author_email", DataType::Utf8, false),
+    ]
+}
+
+fn arrow_dep_change_fields() -> Vec<FieldRef> {
+    vec![
+        arrow_field("src", DataType::Utf8, false),
+        arrow_field("tgt", DataType::Utf8, false),
+        arrow_field("kind", DataType::Utf8, false),
+        arrow_field("old_commit_id", DataType::Utf8, false),
+        arrow_field("new_commit_id", DataType::Utf8, false),
+        arrow_field("adds", DataType::UInt64, false),
+        arrow_field("dels", DataType::UInt64, false),
+    ]
+}
+
+fn arrow_content_fields() -> Vec<FieldRef> {
+    vec![arrow_field("id", DataType::Utf8, false), arrow_field("content", DataType::Utf8, false)]
+}
+
+fn arrow_alignment_fields() -> Vec<FieldRef> {
+    vec![
+        arrow_field("a", DataType::Utf8, false),
+        arrow_field("b", DataType::Utf8, false),
+        arrow_field("deps", DataType::UInt64, false),
+        arrow_field("cochanges", DataType::UInt64, false),
+        arrow_field("kind", DataType::Utf8, false),
+    ]
+}
+
+fn arrow_revspec_fields() -> Vec<FieldRef> {
+    vec![
+        arrow_field("revspec", DataType::Utf8, false),
+        arrow_field("commit_id", DataType::Utf8, false),
+        arrow_field("resolved_at", DataType::UInt64, false),
+    ]
+}
+
+fn arrow_unresolved_ref_fields() -> Vec<FieldRef> {
+    vec![
+        arrow_field("filename", DataType::Utf8, false),
+        arrow_field("row", DataType::UInt64, false),
+        arrow_field("column", DataType::UInt64, false),
+        arrow_field("text", DataType::Utf8, false),
+        arrow_field("commit_id", DataType::Utf8, false),
+    ]
+}
+
+fn arrow_entity_match_fields() -> Vec<FieldRef> {
+    vec![
+        arrow_field("old_id", DataType::Utf8, false),
+        arrow_field("new_id", DataType::Utf8, false),
+        arrow_field("old_commit_id", DataType::Utf8, false),
+        arrow_field("new_commit_id", DataType::Utf8, false),
+        arrow_field("kind", DataType::Utf8, false),
+    ]
+}
+
+fn arrow_metric_fields() -> Vec<FieldRef> {
+    vec![
+        arrow_field("entity_id", DataType::Utf8, false),
+        arrow_field("commit_id", DataType::Utf8, false),
+        arrow_field("fan_in", DataType::UInt64, false),
+        arrow_field("fan_out", DataType::UInt64, false),
+        arrow_field("lines", DataType::UInt64, false),
+        arrow_field("logical_lines", DataType::UInt64, false),
+        arrow_field("complexity", DataType::UInt64, false),
+        arrow_field("max_nesting", DataType::UInt64, false),
+        arrow_field("params", DataType::UInt64, true),
+    ]
+}
+
+fn arrow_external_dep_fields() -> Vec<FieldRef> {
+    vec![
+        arrow_field("src", DataType::Utf8, false),
+        arrow_field("symbol", DataType::Utf8, false),
+        arrow_field("row", DataType::UInt64, false),
+        arrow_field("commit_id", DataType::Utf8, false),
+    ]
+}
+
+fn arrow_supertype_fields() -> Vec<FieldRef> {
+    vec![
+        arrow_field("subtype", DataType::Utf8, false),
+        arrow_field("supertype", DataType::Utf8, false),
+        arrow_field("commit_id", DataType::Utf8, false),
+        arrow_field("kind", DataType::Utf8, false),
+        arrow_field("depth", DataType::UInt64, false),
+    ]
+}
+
+fn arrow_diagnostic_fields() -> Vec<FieldRef> {
+    vec![
+        arrow_field("filename", DataType::Utf8, true),
+        arrow_field("commit_id", DataType::Utf8, false),
+        arrow_field("stage", DataType::Utf8, false),
+        arrow_field("message", DataType::Utf8, false),
+    ]
+}
+
+fn arrow_annotation_fields() -> Vec<FieldRef> {
+    vec![
+        arrow_field("entity_id", DataType::Utf8, false),
+        arrow_field("name", DataType::Utf8, false),
+        arrow_field("text", DataType::Utf8, false),
+        arrow_field("row", DataType::UInt64, false),
+    ]
+}
+
+fn arrow_file_fields() -> Vec<FieldRef> {
+    vec![
+        arrow_field("filename", DataType::Utf8, false),
+        arrow_field("commit_id", DataType::Utf8, false),
+        arrow_field("content_id", DataType::Utf8, false),
+    ]
+}
+
+fn arrow_commit_fields() -> Vec<FieldRef> {
+    vec![
+        arrow_field("commit_id", DataType::Utf8, false),
+        arrow_field("author_name", DataType::Utf8, false),
+        arrow_field("author_email", DataType::Utf8, false),
+        arrow_field("author_date", DataType::Int64, false),
+        arrow_field("committer_name", DataType::Utf8, false),
+        arrow_field("committer_email", DataType::Utf8, false),
+        arrow_field("commit_date", DataType::Int64, false),
+        arrow_field("subject", DataType::Utf8, false),
+        arrow_field("parent_ids", DataType::Utf8, false),
+        arrow_field("ref_name", DataType::Utf8, true),
+    ]
+}
+
+/// Writes an Excel workbook with one worksheet per resource.
+///
+/// Each worksheet starts with a bold header row that is frozen in place.
+/// Worksheets are written in constant memory mode, so rows are flushed to disk
+/// as they are written. Excel limits a cell to [XLSX_MAX_STRING_LEN]
+/// characters, so longer contents are truncated.
+struct XlsxWriter {
+    path: PathBuf,
+    book: Mutex<XlsxBook>,
+    taxonomy: Taxonomy,
+}
+
+impl XlsxWriter {
+    fn open<P: AsRef<Path>>(path: P, taxonomy: Taxonomy) -> Result<Self> {
+        let mut workbook = Workbook::new();
+        let bold = Format::new().set_bold();
+
+        for resource in Resource::iter() {
+            let sheet = workbook.add_worksheet_with_constant_memory();
+            sheet.set_name(resource.to_string())?;
+            sheet.set_freeze_panes(1, 0)?;
+
+            for (col, header) in xlsx_headers(resource).iter().enumerate() {
+                sheet.write_string_with_format(0, col as u16, *header, &bold)?;
+            }
+        }
+
+        let rows = Resource::iter().map(|r| (r, 1)).collect();
+        let book = Mutex::new(XlsxBook { workbook, rows });
+        Ok(Self { path: path.as_ref().to_path_buf(), book, taxonomy })
+    }
+}
+
+impl Writer for XlsxWriter {
+    fn supports(&self, _: Resource) -> bool {
+        true
+    }
+
+    fn is_single_structure(&self) -> bool {
+        false
+    }
+
+    fn write_entity(&self, value: Entity) -> Result<()> {
+        self.book.lock().unwrap().push(Resource::Entities, EntityRow::from(value))
+    }
+
+    fn write_dep(&self, value: EntityDep) -> Result<()> {
+        self.book.lock().unwrap().push(Resource::Deps, EntityDepRow::from(value, &self.taxonomy))
+    }
+
+    fn write_change(&self, value: Change) -> Result<()> {
+        self.book.lock().unwrap().push(Resource::Changes, value)
+    }
+
+    fn write_content(&self, mut value: Content) -> Result<()> {
+        if let Some((i, _)) = value.content.char_indices().nth(XLSX_MAX_STRING_LEN) {
+            log::warn!("Truncating content {} to fit in a worksheet cell", value.id.0.to_string());
+            value.content.truncate(i);
+        }
+
+        self.book.lock().unwrap().push(Resource::Contents, value)
+    }
+
+    fn write_dep_change(&self, value: DepChange) -> Result<()> {
+        self.book.lock().unwrap().push(Resource::DepChanges, value)
+    }
+
+    fn write_alignment(&self, value: Alignment) -> Result<()> {
+        self.book.lock().unwrap().push(Resource::Alignments, value)
+    }
+
+    fn write_revspec(&self, value: Revspec) -> Result<()> {
+        self.book.lock().unwrap().push(Resource::Revspecs, value)
+    }
+
+    fn write_unresolved_ref(&self, value: UnresolvedRef) -> Result<()> {
+        self.book.lock().unwrap().push(Resource::UnresolvedRefs, value)
+    }
+
+    fn write_entity_match(&self, value: EntityMatch) -> Result<()> {
+        self.book.lock().unwrap().push(Resource::EntityMatches, value)
     }
 
-    fn write<S: serde::Serialize>(&self, value: S) -> Result<()> {
-        Ok(write!(self.file.lock().unwrap(), "{}\n", serde_json::to_string(&value)?)?)
+    fn write_metric(&self, value: Metric) -> Result<()> {
+        self.book.lock().unwrap().push(Resource::Metrics, value)
     }
-}
 
-impl Writer for JsonlWriter {
-    fn supports(&self, _: Resource) -> bool {
-        true
+    fn write_external_dep(&self, value: ExternalDep) -> Result<()> {
+        self.book.lock().unwrap().push(Resource::ExternalDeps, value)
     }
 
-    fn is_single_structure(&self) -> bool {
-        false
+    fn write_supertype(&self, value: Supertype) -> Result<()> {
+        self.book.lock().unwrap().push(Resource::Hierarchy, value)
     }
 
-    fn write_entity(&self, value: Entity) -> Result<()> {
-        self.write(EntityRow::from(value))
+    fn write_diagnostic(&self, value: Diagnostic) -> Result<()> {
+        self.book.lock().unwrap().push(Resource::Diagnostics, value)
     }
 
-    fn write_dep(&self, value: EntityDep) -> Result<()> {
-        self.write(EntityDepRow::from(value))
+    fn write_annotation(&self, value: Annotation) -> Result<()> {
+        self.book.lock().unwrap().push(Resource::Annotations, value)
     }
 
-    fn write_change(&self, value: Change) -> Result<()> {
-        self.write(value)
+    fn write_file(&self, value: CommitFile) -> Result<()> {
+        self.book.lock().unwrap().push(Resource::Files, value)
     }
 
-    fn write_content(&self, value: Content) -> Result<()> {
-        self.write(value)
+    fn write_commit(&self, value: Commit) -> Result<()> {
+        self.book.lock().unwrap().push(Resource::Commits, value)
     }
 
     fn finalize(&mut self) -> Result<()> {
-        Ok(self.file.lock().unwrap().flush()?)
+        Ok(self.book.lock().unwrap().workbook.save(&self.path)?)
     }
 }
 
-#[derive(Debug)]
-enum Dsm {
-    V1,
-    V2,
-}
+const XLSX_MAX_ROWS: u32 = 1_048_576;
+const XLSX_MAX_STRING_LEN: usize = 32_767;
 
-#[derive(Debug)]
-struct DsmWriter {
-    path: PathBuf,
-    dsm: Dsm,
-    entities: Mutex<Vec<Entity>>,
-    deps: Mutex<Vec<EntityDep>>,
-    changes: Mutex<Vec<Change>>,
+struct XlsxBook {
+    workbook: Workbook,
+    rows: HashMap<Resource, u32>,
 }
 
-impl DsmWriter {
-    fn open<P: AsRef<Path>>(path: P, dsm: Dsm) -> Result<Self> {
-        Ok(Self {
-            path: path.as_ref().to_path_buf(),
-            dsm,
-            entities: Default::default(),
-            deps: Default::default(),
-            changes: Default::default(),
-        })
-    }
-}
+impl XlsxBook {
+    fn push<S: serde::Serialize>(&mut self, resource: Resource, value: S) -> Result<()> {
+        let row = self.rows[&resource];
 
-impl Writer for DsmWriter {
-    fn supports(&self, resource: Resource) -> bool {
-        match resource {
-            Resource::Entities => true,
-            Resource::Deps => true,
-            Resource::Changes => true,
-            _ => false,
+        if row == XLSX_MAX_ROWS {
+            bail!("Too many {} for a worksheet. Use another --format.", resource);
         }
-    }
-
-    fn is_single_structure(&self) -> bool {
-        true
-    }
-
-    fn write_entity(&self, value: Entity) -> Result<()> {
-        self.entities.lock().unwrap().push(value);
-        Ok(())
-    }
 
-    fn write_dep(&self, value: EntityDep) -> Result<()> {
-        self.deps.lock().unwrap().push(value);
-        Ok(())
-    }
+        let value = serde_json::to_value(value)?;
+        let sheet = self.workbook.worksheet_from_name(&resource.to_string())?;
 
-    fn write_change(&self, value: Change) -> Result<()> {
-        self.changes.lock().unwrap().push(value);
-        Ok(())
-    }
+        for (col, header) in xlsx_headers(resource).iter().enumerate() {
+            match &value[header] {
+                serde_json::Value::Number(n) => {
+                    sheet.write_number(row, col as u16, n.as_f64().unwrap())?;
+                }
+                serde_json::Value::String(s) => {
+                    sheet.write_string(row, col as u16, s)?;
+                }
+                serde_json::Value::Bool(b) => {
+                    sheet.write_boolean(row, col as u16, *b)?;
+                }
+                _ => {}
+            };
+        }
 
-    fn write_content(&self, _: Content) -> Result<()> {
+        self.rows.insert(resource, row + 1);
         Ok(())
     }
+}
 
-    fn finalize(&mut self) -> Result<()> {
-        let entities = self.entities.lock().unwrap();
-        let deps = self.deps.lock().unwrap();
-        let changes = self.changes.lock().unwrap();
-
-        let text = match self.dsm {
-            Dsm::V1 => dsm_v1(&entities, &deps, &changes),
-            Dsm::V2 => dsm_v2(&entities, &deps, &changes),
-        };
-
-        Ok(File::create(&self.path)?.write_all(text.as_bytes())?)
+/// The columns of each worksheet, in the same order as the CSV output.
+fn xlsx_headers(resource: Resource) -> &'static [&'static str] {
+    match resource {
+        Resource::Entities => &[
+            "id",
+            "parent_id",
+            "name",
+            "kind",
+            "start_byte",
+            "start_row",
+            "start_column",
+            "end_byte",
+            "end_row",
+            "end_column",
+            "content_id",
+            "simple_id",
+            "ordinal",
+            "comment_start_byte",
+            "comment_start_row",
+            "comment_start_column",
+            "comment_end_byte",
+            "comment_end_row",
+            "comment_end_column",
+            "qualified_name",
+            "entity_content_id",
+            "is_test",
+            "byte_size",
+            "token_count",
+            "lang",
+            "filename",
+        ],
+        Resource::Deps => &[
+            "src",
+            "tgt",
+            "kind",
+            "row",
+            "commit_id",
+            "confidence",
+            "resolver",
+            "count",
+            "src_filename",
+            "tgt_filename",
+            "tgt_row",
+            "tgt_column",
+            "src_name",
+            "tgt_name",
+        ],
+        Resource::Changes => {
+            &["simple_id", "commit_id", "kind", "adds", "dels", "author_name", "author_email"]
+        }
+        Resource::DepChanges => {
+            &["src", "tgt", "kind", "old_commit_id", "new_commit_id", "adds", "dels"]
+        }
+        Resource::Contents => &["id", "content"],
+        Resource::Alignments => &["a", "b", "deps", "cochanges", "kind"],
+        Resource::Revspecs => &["revspec", "commit_id", "resolved_at"],
+        Resource::UnresolvedRefs => &["filename", "row", "column", "text", "commit_id"],
+        Resource::EntityMatches => &["old_id", "new_id", "old_commit_id", "new_commit_id", "kind"],
+        Resource::Metrics => &[
+            "entity_id",
+            "commit_id",
+            "fan_in",
+            "fan_out",
+            "lines",
+            "logical_lines",
+            "complexity",
+            "max_nesting",
+            "params",
+        ],
+        Resource::ExternalDeps => &["src", "symbol", "row", "commit_id"],
+        Resource::Hierarchy => &["subtype", "supertype", "commit_id", "kind", "depth"],
+        Resource::Diagnostics => &["filename", "commit_id", "stage", "message"],
+        Resource::Annotations => &["entity_id", "name", "text", "row"],
+        Resource::Files => &["filename", "commit_id", "content_id"],
+        Resource::Commits => &[
+            "commit_id",
+            "author_name",
+            "author_email",
+            "author_date",
+            "committer_name",
+            "committer_email",
+            "commit_date",
+            "subject",
+            "parent_ids",
+            "ref_name",
+        ],
     }
 }
 
+/// Writes a SQLite database with one table per resource.
+///
+/// Indices are only created once all rows have been inserted, which is much
+/// faster than maintaining them along the way. Foreign keys are declared so
+/// that tools can discover the relationships between tables, but they are not
+/// enforced, since a table may be left empty (see --resources).
+///
+/// When appending to an existing database, rows that are already present (by
+/// primary key) are skipped. Deps have no primary key, so the deps (and
+/// unresolved references) of any commit that already has deps in the database
+/// are skipped instead.
 #[derive(Debug)]
 struct SqliteWriter {
     conn: Mutex<Connection>,
+    scanned: HashSet<PseudoCommitId>,
+    taxonomy: Taxonomy,
 }
 
 impl SqliteWriter {
-    fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+    fn open<P: AsRef<Path>>(
+        path: P,
+        append: bool,
+        taxonomy: Taxonomy,
+        args: Vec<String>,
+    ) -> Result<Self> {
+        let exists = path.as_ref().exists();
         let conn = Connection::open(path)?;
+
+        if append && exists {
+            check_sqlite_schema(&conn)?;
+        }
+
         conn.execute_batch(SQLITE_INIT)?;
-        Ok(Self { conn: Mutex::new(conn) })
+
+        let scanned = match append {
+            true => sqlite_scanned_commits(&conn)?,
+            false => HashSet::new(),
+        };
+
+        let args = serde_json::to_string(&args)?;
+        let mut insert = conn.prepare("INSERT OR REPLACE INTO meta VALUES (?, ?)")?;
+        insert.execute(params!["schema_version", SQLITE_SCHEMA_VERSION])?;
+        insert.execute(params!["tool_version", env!("CARGO_PKG_VERSION")])?;
+        insert.execute(params!["args", args])?;
+        drop(insert);
+
+        Ok(Self { conn: Mutex::new(conn), scanned, taxonomy })
+    }
+}
+
+/// Make sure an existing database was written with the current schema.
+fn check_sqlite_schema(conn: &Connection) -> Result<()> {
+    let version: Option<String> = conn
+        .query_row("SELECT value FROM meta WHERE key = 'schema_version'", [], |r| r.get(0))
+        .optional()
+        .ok()
+        .flatten();
+
+    match version {
+        Some(version) if version == SQLITE_SCHEMA_VERSION => Ok(()),
+        Some(version) => bail!(
+            "Cannot append to a database with schema version {} (expected {}). Use --force \
+             instead.",
+            version,
+            SQLITE_SCHEMA_VERSION
+        ),
+        None => bail!("Cannot append to a database without a schema version. Use --force instead."),
+    }
+}
+
+/// Find the commits that already have deps in the database.
+fn sqlite_scanned_commits(conn: &Connection) -> Result<HashSet<PseudoCommitId>> {
+    let mut stmt = conn.prepare("SELECT DISTINCT commit_id FROM deps")?;
+    let rows = stmt.query_map([], |r| r.get::<_, Option<Vec<u8>>>(0))?;
+    let mut scanned = HashSet::new();
+
+    for row in rows {
+        scanned.insert(match row? {
+            Some(bytes) => PseudoCommitId::CommitId(git2::Oid::from_bytes(&bytes)?.into()),
+            None => PseudoCommitId::WorkDir,
+        });
     }
+
+    Ok(scanned)
 }
 
 impl Writer for SqliteWriter {
@@ -265,7 +2391,11 @@ impl Writer for SqliteWriter {
         self.conn
             .lock()
             .unwrap()
-            .prepare_cached("INSERT INTO entities VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")?
+            .prepare_cached(
+                "INSERT OR IGNORE INTO entities
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, \
+                 ?, ?)",
+            )?
             .execute(params![
                 &value.id,
                 &value.parent_id,
@@ -279,19 +2409,52 @@ impl Writer for SqliteWriter {
                 &value.end_column,
                 &value.content_id,
                 &value.simple_id,
+                &value.ordinal,
+                &value.comment_start_byte,
+                &value.comment_start_row,
+                &value.comment_start_column,
+                &value.comment_end_byte,
+                &value.comment_end_row,
+                &value.comment_end_column,
+                &value.qualified_name,
+                &value.entity_content_id,
+                &value.is_test,
+                &value.byte_size,
+                &value.token_count,
+                &value.lang,
+                &value.filename,
             ])?;
 
         Ok(())
     }
 
     fn write_dep(&self, value: EntityDep) -> Result<()> {
-        let value = EntityDepRow::from(value);
+        if self.scanned.contains(&value.commit_id) {
+            return Ok(());
+        }
+
+        let value = EntityDepRow::from(value, &self.taxonomy);
 
         self.conn
             .lock()
             .unwrap()
-            .prepare_cached("INSERT INTO deps VALUES (?, ?, ?, ?, ?)")?
-            .execute(params![&value.src, &value.tgt, &value.kind, &value.row, &value.commit_id])?;
+            .prepare_cached("INSERT INTO deps VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")?
+            .execute(params![
+                &value.src,
+                &value.tgt,
+                &value.kind,
+                &value.row,
+                &value.commit_id,
+                &value.confidence,
+                &value.resolver,
+                &value.count,
+                &value.src_filename,
+                &value.tgt_filename,
+                &value.tgt_row,
+                &value.tgt_column,
+                &value.src_name,
+                &value.tgt_name,
+            ])?;
 
         Ok(())
     }
@@ -300,13 +2463,15 @@ impl Writer for SqliteWriter {
         self.conn
             .lock()
             .unwrap()
-            .prepare_cached("INSERT INTO changes VALUES (?, ?, ?, ?, ?)")?
+            .prepare_cached("INSERT OR IGNORE INTO changes VALUES (?, ?, ?, ?, ?, ?, ?)")?
             .execute(params![
                 &value.simple_id,
                 &value.commit_id,
                 &value.kind,
                 &value.dels,
                 &value.adds,
+                &value.author_name,
+                &value.author_email,
             ])?;
 
         Ok(())
@@ -316,27 +2481,219 @@ impl Writer for SqliteWriter {
         self.conn
             .lock()
             .unwrap()
-            .prepare_cached("INSERT INTO contents VALUES (?, ?)")?
+            .prepare_cached("INSERT OR IGNORE INTO contents VALUES (?, ?)")?
             .execute(params![&value.id, &value.content])?;
 
         Ok(())
     }
 
+    fn write_dep_change(&self, value: DepChange) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .prepare_cached("INSERT OR IGNORE INTO dep_changes VALUES (?, ?, ?, ?, ?, ?, ?)")?
+            .execute(params![
+                &value.src,
+                &value.tgt,
+                &value.kind,
+                &value.old_commit_id,
+                &value.new_commit_id,
+                &value.adds,
+                &value.dels,
+            ])?;
+
+        Ok(())
+    }
+
+    fn write_alignment(&self, value: Alignment) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .prepare_cached("INSERT OR REPLACE INTO alignments VALUES (?, ?, ?, ?, ?)")?
+            .execute(params![&value.a, &value.b, &value.deps, &value.cochanges, &value.kind])?;
+
+        Ok(())
+    }
+
+    fn write_revspec(&self, value: Revspec) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .prepare_cached("INSERT INTO revspecs VALUES (?, ?, ?)")?
+            .execute(params![&value.revspec, &value.commit_id, &value.resolved_at])?;
+
+        Ok(())
+    }
+
+    fn write_unresolved_ref(&self, value: UnresolvedRef) -> Result<()> {
+        if self.scanned.contains(&value.commit_id) {
+            return Ok(());
+        }
+
+        self.conn
+            .lock()
+            .unwrap()
+            .prepare_cached("INSERT INTO unresolved_refs VALUES (?, ?, ?, ?, ?)")?
+            .execute(params![
+                &value.filename,
+                &value.row,
+                &value.column,
+                &value.text,
+                &value.commit_id
+            ])?;
+
+        Ok(())
+    }
+
+    fn write_entity_match(&self, value: EntityMatch) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .prepare_cached("INSERT OR IGNORE INTO entity_matches VALUES (?, ?, ?, ?, ?)")?
+            .execute(params![
+                &value.old_id,
+                &value.new_id,
+                &value.old_commit_id,
+                &value.new_commit_id,
+                &value.kind,
+            ])?;
+
+        Ok(())
+    }
+
+    fn write_metric(&self, value: Metric) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .prepare_cached("INSERT OR IGNORE INTO metrics VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)")?
+            .execute(params![
+                &value.entity_id,
+                &value.commit_id,
+                &value.fan_in,
+                &value.fan_out,
+                &value.lines,
+                &value.logical_lines,
+                &value.complexity,
+                &value.max_nesting,
+                &value.params,
+            ])?;
+
+        Ok(())
+    }
+
+    fn write_external_dep(&self, value: ExternalDep) -> Result<()> {
+        if self.scanned.contains(&value.commit_id) {
+            return Ok(());
+        }
+
+        self.conn
+            .lock()
+            .unwrap()
+            .prepare_cached("INSERT INTO external_deps VALUES (?, ?, ?, ?)")?
+            .execute(params![&value.src, &value.symbol, &value.row, &value.commit_id])?;
+
+        Ok(())
+    }
+
+    fn write_supertype(&self, value: Supertype) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .prepare_cached("INSERT OR IGNORE INTO hierarchy VALUES (?, ?, ?, ?, ?)")?
+            .execute(params![
+                &value.subtype,
+                &value.supertype,
+                &value.commit_id,
+                &value.kind,
+                &value.depth,
+            ])?;
+
+        Ok(())
+    }
+
+    fn write_diagnostic(&self, value: Diagnostic) -> Result<()> {
+        if self.scanned.contains(&value.commit_id) {
+            return Ok(());
+        }
+
+        self.conn
+            .lock()
+            .unwrap()
+            .prepare_cached("INSERT INTO diagnostics VALUES (?, ?, ?, ?)")?
+            .execute(params![&value.filename, &value.commit_id, &value.stage, &value.message])?;
+
+        Ok(())
+    }
+
+    fn write_annotation(&self, value: Annotation) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .prepare_cached("INSERT OR IGNORE INTO annotations VALUES (?, ?, ?, ?)")?
+            .execute(params![&value.entity_id, &value.name, &value.text, &value.row])?;
+
+        Ok(())
+    }
+
+    fn write_file(&self, value: CommitFile) -> Result<()> {
+        if self.scanned.contains(&value.commit_id) {
+            return Ok(());
+        }
+
+        self.conn
+            .lock()
+            .unwrap()
+            .prepare_cached("INSERT INTO files VALUES (?, ?, ?)")?
+            .execute(params![&value.filename, &value.commit_id, &value.content_id])?;
+
+        Ok(())
+    }
+
+    fn write_commit(&self, value: Commit) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .prepare_cached("INSERT OR IGNORE INTO commits VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")?
+            .execute(params![
+                &value.commit_id,
+                &value.author_name,
+                &value.author_email,
+                &value.author_date,
+                &value.committer_name,
+                &value.committer_email,
+                &value.commit_date,
+                &value.subject,
+                &value.parent_ids,
+                &value.ref_name,
+            ])?;
+
+        Ok(())
+    }
+
     fn finalize(&mut self) -> Result<()> {
-        self.conn.lock().unwrap().execute_batch("VACUUM;")?;
+        self.conn.lock().unwrap().execute_batch(SQLITE_FINISH)?;
 
         Ok(())
     }
 }
 
+/// Bump this whenever the tables or views of [SQLITE_INIT] change.
+const SQLITE_SCHEMA_VERSION: &str = "27";
+
 const SQLITE_INIT: &'static str = "
     PRAGMA journal_mode = WAL;
     PRAGMA synchronous = NORMAL;
     PRAGMA wal_checkpoint(TRUNCATE);
+    PRAGMA foreign_keys = OFF;
+
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT NOT NULL PRIMARY KEY,
+        value TEXT NOT NULL
+    );
 
     CREATE TABLE IF NOT EXISTS entities (
         id BLOB NOT NULL PRIMARY KEY,
-        parent_id BLOB,
+        parent_id BLOB REFERENCES entities (id),
         name TEXT NOT NULL,
         kind TEXT NOT NULL,
         start_byte INT NOT NULL,
@@ -345,16 +2702,39 @@ const SQLITE_INIT: &'static str = "
         end_byte INT NOT NULL,
         end_row INT NOT NULL,
         end_column INT NOT NULL,
-        content_id BLOB NOT NULL,
-        simple_id BLOB NOT NULL
+        content_id BLOB NOT NULL REFERENCES contents (content_id),
+        simple_id BLOB NOT NULL,
+        ordinal INT NOT NULL,
+        comment_start_byte INT,
+        comment_start_row INT,
+        comment_start_column INT,
+        comment_end_byte INT,
+        comment_end_row INT,
+        comment_end_column INT,
+        qualified_name TEXT NOT NULL,
+        entity_content_id BLOB NOT NULL,
+        is_test INT NOT NULL,
+        byte_size INT NOT NULL,
+        token_count INT,
+        lang TEXT,
+        filename TEXT
     );
 
     CREATE TABLE IF NOT EXISTS deps (
-        src BLOB NOT NULL,
-        tgt BLOB NOT NULL,
+        src BLOB NOT NULL REFERENCES entities (id),
+        tgt BLOB NOT NULL REFERENCES entities (id),
         kind TEXT NOT NULL,
         row INT NOT NULL,
-        commit_id BLOB
+        commit_id BLOB,
+        confidence TEXT NOT NULL,
+        resolver TEXT NOT NULL,
+        count INT NOT NULL,
+        src_filename TEXT,
+        tgt_filename TEXT,
+        tgt_row INT,
+        tgt_column INT,
+        src_name TEXT,
+        tgt_name TEXT
     );
 
     CREATE TABLE IF NOT EXISTS changes (
@@ -363,18 +2743,152 @@ const SQLITE_INIT: &'static str = "
         kind TEXT NOT NULL,
         dels INT NOT NULL,
         adds INT NOT NULL,
+        author_name TEXT NOT NULL,
+        author_email TEXT NOT NULL,
         PRIMARY KEY (simple_id, commit_id)
     );
 
+    CREATE TABLE IF NOT EXISTS dep_changes (
+        src BLOB NOT NULL,
+        tgt BLOB NOT NULL,
+        kind TEXT NOT NULL,
+        old_commit_id BLOB,
+        new_commit_id BLOB,
+        adds INT NOT NULL,
+        dels INT NOT NULL,
+        UNIQUE (src, tgt, kind, old_commit_id, new_commit_id)
+    );
+
     CREATE TABLE IF NOT EXISTS contents (
         content_id BLOB NOT NULL PRIMARY KEY,
         content TEXT NOT NULL
     );
+
+    CREATE TABLE IF NOT EXISTS alignments (
+        a BLOB NOT NULL,
+        b BLOB NOT NULL,
+        deps INT NOT NULL,
+        cochanges INT NOT NULL,
+        kind TEXT NOT NULL,
+        PRIMARY KEY (a, b)
+    );
+
+    CREATE TABLE IF NOT EXISTS revspecs (
+        revspec TEXT NOT NULL,
+        commit_id BLOB,
+        resolved_at INT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS unresolved_refs (
+        filename TEXT NOT NULL,
+        row INT NOT NULL,
+        column INT NOT NULL,
+        text TEXT NOT NULL,
+        commit_id BLOB
+    );
+
+    CREATE TABLE IF NOT EXISTS entity_matches (
+        old_id BLOB NOT NULL,
+        new_id BLOB NOT NULL,
+        old_commit_id BLOB,
+        new_commit_id BLOB,
+        kind TEXT NOT NULL,
+        UNIQUE (old_id, new_id, old_commit_id, new_commit_id)
+    );
+
+    CREATE TABLE IF NOT EXISTS metrics (
+        entity_id BLOB NOT NULL REFERENCES entities (id),
+        commit_id BLOB,
+        fan_in INT NOT NULL,
+        fan_out INT NOT NULL,
+        lines INT NOT NULL,
+        logical_lines INT NOT NULL,
+        complexity INT NOT NULL,
+        max_nesting INT NOT NULL,
+        params INT,
+        UNIQUE (entity_id, commit_id)
+    );
+
+    CREATE TABLE IF NOT EXISTS external_deps (
+        src BLOB NOT NULL REFERENCES entities (id),
+        symbol TEXT NOT NULL,
+        row INT NOT NULL,
+        commit_id BLOB
+    );
+
+    CREATE TABLE IF NOT EXISTS hierarchy (
+        subtype BLOB NOT NULL REFERENCES entities (id),
+        supertype BLOB NOT NULL REFERENCES entities (id),
+        commit_id BLOB,
+        kind TEXT NOT NULL,
+        depth INT NOT NULL,
+        UNIQUE (subtype, supertype, commit_id)
+    );
+
+    CREATE TABLE IF NOT EXISTS diagnostics (
+        filename TEXT,
+        commit_id BLOB,
+        stage TEXT NOT NULL,
+        message TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS annotations (
+        entity_id BLOB NOT NULL REFERENCES entities (id),
+        name TEXT NOT NULL,
+        text TEXT NOT NULL,
+        row INT NOT NULL,
+        UNIQUE (entity_id, row, text)
+    );
+
+    CREATE TABLE IF NOT EXISTS files (
+        filename TEXT NOT NULL,
+        commit_id BLOB,
+        content_id BLOB NOT NULL REFERENCES contents (content_id)
+    );
+
+    CREATE TABLE IF NOT EXISTS commits (
+        commit_id BLOB NOT NULL PRIMARY KEY,
+        author_name TEXT NOT NULL,
+        author_email TEXT NOT NULL,
+        author_date INT NOT NULL,
+        committer_name TEXT NOT NULL,
+        committer_email TEXT NOT NULL,
+        commit_date INT NOT NULL,
+        subject TEXT NOT NULL,
+        parent_ids TEXT NOT NULL,
+        ref_name TEXT
+    );
+
+    CREATE VIEW IF NOT EXISTS entity_paths (id, path) AS
+        WITH RECURSIVE paths (id, path) AS (
+            SELECT id, name FROM entities WHERE parent_id IS NULL
+            UNION ALL
+            SELECT e.id, p.path || '/' || e.name FROM entities e JOIN paths p ON e.parent_id = p.id
+        )
+        SELECT id, path FROM paths;
+
+    CREATE VIEW IF NOT EXISTS named_deps AS
+        SELECT d.*, s.path AS src_path, t.path AS tgt_path
+        FROM deps d
+        JOIN entity_paths s ON s.id = d.src
+        JOIN entity_paths t ON t.id = d.tgt;
+";
+
+const SQLITE_FINISH: &str = "
+    CREATE INDEX IF NOT EXISTS entities_parent_id ON entities (parent_id);
+    CREATE INDEX IF NOT EXISTS entities_simple_id ON entities (simple_id);
+    CREATE INDEX IF NOT EXISTS deps_src ON deps (src);
+    CREATE INDEX IF NOT EXISTS deps_tgt ON deps (tgt);
+    CREATE INDEX IF NOT EXISTS changes_commit_id ON changes (commit_id);
+    CREATE INDEX IF NOT EXISTS annotations_name ON annotations (name);
+    CREATE INDEX IF NOT EXISTS files_content_id ON files (content_id);
+    VACUUM;
+    PRAGMA journal_mode = DELETE;
 ";
 
 #[derive(Debug)]
 #[derive(serde::Serialize)]
-struct EntityRow {
+pub struct EntityRow {
     id: EntityId,
     parent_id: Option<EntityId>,
     name: String,
@@ -387,11 +2901,26 @@ struct EntityRow {
     end_column: usize,
     content_id: ContentId,
     simple_id: SimpleEntityId,
+    ordinal: usize,
+    comment_start_byte: Option<usize>,
+    comment_start_row: Option<usize>,
+    comment_start_column: Option<usize>,
+    comment_end_byte: Option<usize>,
+    comment_end_row: Option<usize>,
+    comment_end_column: Option<usize>,
+    qualified_name: String,
+    entity_content_id: EntityContentId,
+    is_test: bool,
+    byte_size: usize,
+    token_count: Option<usize>,
+    lang: Option<Lang>,
+    filename: Option<String>,
 }
 
 impl EntityRow {
-    fn from(entity: Entity) -> Self {
+    pub fn from(entity: Entity) -> Self {
         let location = entity.location();
+        let comment = entity.comment;
         Self {
             id: entity.id,
             parent_id: entity.parent_id,
@@ -405,28 +2934,65 @@ impl EntityRow {
             end_column: location.end.column,
             content_id: entity.content_id,
             simple_id: entity.simple_id,
+            ordinal: entity.ordinal,
+            comment_start_byte: comment.map(|c| c.start.byte),
+            comment_start_row: comment.map(|c| c.start.row),
+            comment_start_column: comment.map(|c| c.start.column),
+            comment_end_byte: comment.map(|c| c.end.byte),
+            comment_end_row: comment.map(|c| c.end.row),
+            comment_end_column: comment.map(|c| c.end.column),
+            qualified_name: entity.qualified_name,
+            entity_content_id: entity.entity_content_id,
+            is_test: entity.is_test,
+            byte_size: entity.byte_size,
+            token_count: entity.token_count,
+            lang: entity.lang,
+            filename: entity.filename,
         }
     }
 }
 
 #[derive(Debug)]
 #[derive(serde::Serialize)]
-struct EntityDepRow {
+pub struct EntityDepRow {
     src: EntityId,
     tgt: EntityId,
-    kind: DepKind,
+    kind: String,
     row: usize,
     commit_id: PseudoCommitId,
+    confidence: Confidence,
+    resolver: &'static str,
+    count: usize,
+    src_filename: Option<String>,
+    tgt_filename: Option<String>,
+    tgt_row: Option<usize>,
+    tgt_column: Option<usize>,
+    src_name: Option<String>,
+    tgt_name: Option<String>,
 }
 
 impl EntityDepRow {
-    fn from(entity_dep: EntityDep) -> Self {
+    pub fn from(entity_dep: EntityDep, taxonomy: &Taxonomy) -> Self {
+        let tgt_position = entity_dep.location.as_ref().map(|l| l.tgt_position);
+        let (src_filename, tgt_filename, src_name, tgt_name) = match entity_dep.location {
+            Some(l) => (Some(l.src_filename), Some(l.tgt_filename), l.src_name, l.tgt_name),
+            None => (None, None, None, None),
+        };
         Self {
             src: entity_dep.src,
             tgt: entity_dep.tgt,
-            kind: entity_dep.kind,
+            kind: taxonomy.label(entity_dep.kind).to_string(),
             row: entity_dep.position.row(),
             commit_id: entity_dep.commit_id,
+            confidence: entity_dep.confidence,
+            resolver: entity_dep.resolver,
+            count: entity_dep.count,
+            src_filename,
+            tgt_filename,
+            tgt_row: tgt_position.map(|p| p.row()),
+            tgt_column: tgt_position.and_then(|p| p.column()),
+            src_name,
+            tgt_name,
         }
     }
 }
@@ -0,0 +1,156 @@
+//! Assign entities to the teams that own them.
+//!
+//! An ownership file has one rule per line of the form `PATHSPEC -> TEAM`,
+//! where `PATHSPEC` is matched against the path of each entity (e.g.
+//! `src/Foo.java/Foo/bar`). As in a CODEOWNERS file, the last matching rule
+//! wins, so broad rules should come before narrow ones. Entities that match no
+//! rule have no owner. Blank lines and lines starting with `#` are ignored.
+//!
+//! Alternatively, a file ending in .yaml or .yml maps each team to a list of
+//! pathspecs, which are treated as rules in the order they appear:
+//!
+//! ```yaml
+//! payments:
+//!   - src/billing
+//!   - src/invoices
+//! platform:
+//!   - src/core
+//! ```
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use itertools::Itertools;
+
+use crate::core::ContentId;
+use crate::core::Entity;
+use crate::core::EntityId;
+use crate::core::EntityKind;
+use crate::core::Position;
+use crate::core::SimpleEntityId;
+use crate::core::Span;
+use crate::matrix::qualified_names;
+use crate::matrix::Tally;
+use crate::spec::Pathspec;
+
+#[derive(Debug, Clone, Default)]
+pub struct Ownership {
+    rules: Vec<(Pathspec, String)>,
+}
+
+impl Ownership {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read owners from {}", path.display()))?;
+
+        if matches!(path.extension().and_then(|e| e.to_str()), Some("yaml") | Some("yml")) {
+            return Self::parse_yaml(path, &text);
+        }
+
+        let mut rules = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((spec, team)) = line.split_once("->") else {
+                bail!("expected 'PATHSPEC -> TEAM' ({}:{})", path.display(), i + 1);
+            };
+
+            let spec = Pathspec::try_from_vec(vec![spec.trim().to_string()])
+                .with_context(|| format!("invalid pattern ({}:{})", path.display(), i + 1))?;
+            rules.push((spec, team.trim().to_string()));
+        }
+
+        Ok(Self { rules })
+    }
+
+    fn parse_yaml(path: &Path, text: &str) -> Result<Self> {
+        let teams: serde_yaml::Mapping = serde_yaml::from_str(text)
+            .with_context(|| format!("failed to parse owners from {}", path.display()))?;
+        let mut rules = Vec::new();
+
+        for (team, specs) in teams {
+            let (Some(team), Some(specs)) = (team.as_str(), specs.as_sequence()) else {
+                bail!("expected each team to map to a list of pathspecs ({})", path.display());
+            };
+
+            for spec in specs {
+                let Some(spec) = spec.as_str() else {
+                    bail!("expected the pathspecs of {} to be strings ({})", team, path.display());
+                };
+
+                let spec = Pathspec::try_from_vec(vec![spec.to_string()])
+                    .with_context(|| format!("invalid pattern ({})", path.display()))?;
+                rules.push((spec, team.to_string()));
+            }
+        }
+
+        Ok(Self { rules })
+    }
+
+    /// The team that owns the entity at `path`, if any.
+    pub fn team(&self, path: &str) -> Option<&str> {
+        self.rules.iter().rev().find(|(s, _)| s.matches(path)).map(|(_, t)| t.as_str())
+    }
+
+    /// Find the team that owns each of `entities`.
+    ///
+    /// Entities without an owner are left out.
+    pub fn teams(&self, entities: &[Entity]) -> HashMap<EntityId, &str> {
+        if self.rules.is_empty() {
+            return HashMap::new();
+        }
+
+        qualified_names(entities)
+            .into_iter()
+            .filter_map(|(id, path)| Some((id, self.team(&path)?)))
+            .collect()
+    }
+}
+
+/// Merge entities and the deps and changes of a [Tally] so that each team
+/// becomes a single entity.
+///
+/// Any other grouping of entities (e.g. by directory) can be merged the same
+/// way. Each team is represented by a file entity named after the team. Deps
+/// and changes of entities without an owner are dropped. A commit counts as a
+/// single change to a team no matter how many of its entities it touched.
+pub fn aggregate_teams(
+    teams: &HashMap<EntityId, &str>,
+    entities: &[Entity],
+    tally: &mut Tally,
+) -> Result<Vec<Entity>> {
+    let team_entities: HashMap<_, _> =
+        teams.values().unique().map(|&t| (t, team_entity(t))).collect();
+    let ids: HashMap<_, _> = teams.iter().map(|(e, t)| (*e, team_entities[t].id)).collect();
+    let simple_ids: HashMap<_, _> = entities
+        .iter()
+        .filter_map(|e| Some((e.simple_id, team_entities[teams.get(&e.id)?].simple_id)))
+        .collect();
+
+    tally.map(&ids, &simple_ids)?;
+    Ok(team_entities.into_values().sorted_by(|a, b| a.name.cmp(&b.name)).collect())
+}
+
+fn team_entity(team: &str) -> Entity {
+    let origin = Position::new(0, 0, 0);
+    let simple_id = SimpleEntityId::new(None, team, EntityKind::File, None);
+    let content_id = ContentId::from_content("");
+    let span = Span::new(origin, origin);
+    Entity::new(None, team.to_string(), EntityKind::File, span, None, content_id, simple_id)
+}
+
+/// Are `a` and `b` owned by the same team?
+///
+/// Returns `None` unless both have an owner.
+pub fn same_team(teams: &HashMap<EntityId, &str>, a: EntityId, b: EntityId) -> Option<bool> {
+    Some(teams.get(&a)? == teams.get(&b)?)
+}
@@ -0,0 +1,270 @@
+//! Run resolvers written by users as external processes.
+//!
+//! A plugin is any command that speaks JSON over stdio, one object per line.
+//! A process is started for each version and language. Neodepends sends it
+//! every file to resolve and then asks for the deps:
+//!
+//! ```text
+//! {"type": "add_file", "filename": "src/Foo.java", "content": "..."}
+//! {"type": "resolve"}
+//! ```
+//!
+//! After the resolve message, stdin is closed. The plugin then writes one dep
+//! per line to stdout and exits with a status of 0:
+//!
+//! ```text
+//! {"src": {"filename": "src/Foo.java", "row": 4}, "tgt": {"filename": "src/Bar.java", "row": 0}, "kind": "Call"}
+//! ```
+//!
+//! Rows start counting at 0. An endpoint may also give a `column` and a `byte`
+//! to point at an exact position rather than a whole row. A dep may give a
+//! `confidence` of "Resolved" (the default) or "Heuristic". Deps to files that
+//! were not sent are ignored. Anything the plugin writes to stderr is passed
+//! through.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::sync::Mutex;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use itertools::Itertools;
+use serde::Deserialize;
+use subprocess::Exec;
+use subprocess::Popen;
+use subprocess::Redirection;
+
+use crate::core::Confidence;
+use crate::core::DepKind;
+use crate::core::FileDep;
+use crate::core::FileKey;
+use crate::core::FileSet;
+use crate::core::FilenameDep;
+use crate::core::FilenameEndpoint;
+use crate::core::PartialPosition;
+use crate::core::Position;
+use crate::core::PseudoCommitId;
+use crate::languages::Lang;
+use crate::resolution::Resolver;
+use crate::resolution::ResolverFactory;
+
+/// A command to run as the resolver of a language, given as `LANG=CMD`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginCommand {
+    pub lang: Lang,
+    pub command: String,
+}
+
+impl PluginCommand {
+    pub fn parse(s: &str) -> Result<Self> {
+        let Some((lang, command)) = s.split_once('=') else {
+            bail!("expected LANG=CMD but found {:?}", s);
+        };
+
+        let lang = lang.parse().with_context(|| format!("unknown language {:?}", lang))?;
+        Ok(Self { lang, command: command.to_string() })
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request<'a> {
+    AddFile { filename: &'a str, content: &'a str },
+    Resolve,
+}
+
+#[derive(Debug, Deserialize)]
+struct PluginDep {
+    src: PluginEndpoint,
+    tgt: PluginEndpoint,
+    kind: DepKind,
+
+    #[serde(default)]
+    confidence: Confidence,
+}
+
+#[derive(Debug, Deserialize)]
+struct PluginEndpoint {
+    filename: String,
+    row: usize,
+    column: Option<usize>,
+    byte: Option<usize>,
+}
+
+impl PluginEndpoint {
+    fn into_filename_endpoint(self) -> FilenameEndpoint {
+        let position = match (self.byte, self.column) {
+            (Some(byte), Some(column)) => {
+                PartialPosition::Whole(Position::new(byte, self.row, column))
+            }
+            _ => PartialPosition::Row(self.row),
+        };
+        FilenameEndpoint::new(self.filename, position)
+    }
+}
+
+/// A running plugin and the pipe to its stdin.
+struct Process {
+    popen: Popen,
+    stdin: BufWriter<File>,
+}
+
+impl Process {
+    fn spawn(command: &str) -> Result<Self> {
+        let mut popen = Exec::shell(command)
+            .stdin(Redirection::Pipe)
+            .stdout(Redirection::Pipe)
+            .popen()
+            .with_context(|| format!("failed to run resolver command `{}`", command))?;
+        let stdin = BufWriter::new(popen.stdin.take().unwrap());
+        Ok(Self { popen, stdin })
+    }
+
+    fn send(&mut self, request: &Request) -> Result<()> {
+        serde_json::to_writer(&mut self.stdin, request)?;
+        self.stdin.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Ask for the deps, read them all, and wait for the plugin to exit.
+    fn resolve(mut self, command: &str, commit_id: PseudoCommitId) -> Result<Vec<FilenameDep>> {
+        self.send(&Request::Resolve)?;
+        self.stdin.flush()?;
+        drop(self.stdin);
+
+        let stdout = BufReader::new(self.popen.stdout.take().unwrap());
+        let mut deps = Vec::new();
+
+        for line in stdout.lines() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let dep: PluginDep = serde_json::from_str(&line)
+                .with_context(|| format!("resolver command `{}` wrote an invalid dep", command))?;
+            let src = dep.src.into_filename_endpoint();
+            let tgt = dep.tgt.into_filename_endpoint();
+            let position = src.position;
+            let confidence = dep.confidence;
+            let dep = FilenameDep::new(src, tgt, dep.kind, position, commit_id);
+            deps.push(dep.with_confidence(confidence));
+        }
+
+        let status = self.popen.wait()?;
+
+        if !status.success() {
+            bail!("resolver command `{}` terminated with {:?}", command, status);
+        }
+
+        Ok(deps)
+    }
+}
+
+/// A resolver that runs a [PluginCommand].
+///
+/// See [Resolver].
+pub struct PluginResolver {
+    commit_id: PseudoCommitId,
+    command: String,
+    process: Mutex<Option<Process>>,
+    files: Mutex<HashSet<FileKey>>,
+}
+
+impl PluginResolver {
+    fn new(commit_id: PseudoCommitId, command: String) -> Self {
+        Self { commit_id, command, process: Default::default(), files: Default::default() }
+    }
+
+    fn try_add_file(&self, filename: &str, content: &str) -> Result<()> {
+        let mut process = self.process.lock().unwrap();
+
+        if process.is_none() {
+            *process = Some(Process::spawn(&self.command)?);
+        }
+
+        process
+            .as_mut()
+            .unwrap()
+            .send(&Request::AddFile { filename, content })
+            .with_context(|| format!("failed to send {} to `{}`", filename, self.command))?;
+        self.files.lock().unwrap().insert(FileKey::from_content(filename.to_string(), content));
+        Ok(())
+    }
+
+    fn try_resolve(&self) -> Result<Vec<FileDep>> {
+        let Some(process) = self.process.lock().unwrap().take() else {
+            return Ok(Vec::new());
+        };
+
+        let file_set = FileSet::new(self.files.lock().unwrap().iter().cloned());
+        let deps = process.resolve(&self.command, self.commit_id)?;
+        Ok(deps.into_iter().filter_map(|d| d.into_file_dep(&file_set)).collect())
+    }
+}
+
+impl Resolver for PluginResolver {
+    fn add_file(&self, filename: &str, content: &str) {
+        self.try_add_file(filename, content).unwrap();
+    }
+
+    fn resolve(&self) -> Result<Vec<FileDep>> {
+        log::info!("Running resolver command `{}`...", &self.command);
+        self.try_resolve()
+    }
+}
+
+impl std::fmt::Debug for PluginResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PluginResolver")
+            .field("commit_id", &self.commit_id)
+            .field("command", &self.command)
+            .field("files", &self.files)
+            .finish()
+    }
+}
+
+/// A plugin resolver factory.
+///
+/// See [ResolverFactory].
+#[derive(Debug, Clone)]
+pub struct PluginResolverFactory {
+    commands: HashMap<Lang, String>,
+}
+
+impl PluginResolverFactory {
+    /// Create a factory from a list of commands. If a language is given more
+    /// than once, the last command wins.
+    pub fn new(commands: Vec<PluginCommand>) -> Self {
+        Self { commands: commands.into_iter().map(|c| (c.lang, c.command)).collect() }
+    }
+}
+
+impl ResolverFactory for PluginResolverFactory {
+    fn name(&self) -> &'static str {
+        "plugin"
+    }
+
+    fn supports(&self, lang: Lang) -> bool {
+        self.commands.contains_key(&lang)
+    }
+
+    /// The commands themselves, as plugins have no common way to report a
+    /// version.
+    fn version(&self) -> Result<String> {
+        Ok(self.commands.iter().sorted().map(|(l, c)| format!("{}={}", l, c)).join(", "))
+    }
+
+    fn try_create(&self, commit_id: PseudoCommitId, lang: Lang) -> Option<Box<dyn Resolver>> {
+        self.commands
+            .get(&lang)
+            .map(|c| Box::new(PluginResolver::new(commit_id, c.clone())) as Box<dyn Resolver>)
+    }
+}
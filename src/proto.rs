@@ -0,0 +1,597 @@
+//! Protocol Buffers messages for `--format=proto`.
+//!
+//! These are written by hand to match proto/neodepends.proto (so building does
+//! not require `protoc`) and must be kept in sync with it.
+
+use crate::core::Alignment;
+use crate::core::Annotation;
+use crate::core::Change;
+use crate::core::Commit;
+use crate::core::CommitFile;
+use crate::core::Content;
+use crate::core::DepChange;
+use crate::core::Diagnostic;
+use crate::core::Entity;
+use crate::core::EntityDep;
+use crate::core::EntityMatch;
+use crate::core::ExternalDep;
+use crate::core::Metric;
+use crate::core::PseudoCommitId;
+use crate::core::Revspec;
+use crate::core::Supertype;
+use crate::core::UnresolvedRef;
+use crate::taxonomy::Taxonomy;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Record {
+    #[prost(oneof = "record::Record", tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12")]
+    pub record: Option<record::Record>,
+}
+
+pub mod record {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Record {
+        #[prost(message, tag = "1")]
+        Entity(super::EntityMessage),
+        #[prost(message, tag = "2")]
+        Dep(super::DepMessage),
+        #[prost(message, tag = "3")]
+        Change(super::ChangeMessage),
+        #[prost(message, tag = "4")]
+        DepChange(super::DepChangeMessage),
+        #[prost(message, tag = "5")]
+        Content(super::ContentMessage),
+        #[prost(message, tag = "6")]
+        Alignment(super::AlignmentMessage),
+        #[prost(message, tag = "7")]
+        Revspec(super::RevspecMessage),
+        #[prost(message, tag = "8")]
+        UnresolvedRef(super::UnresolvedRefMessage),
+        #[prost(message, tag = "9")]
+        EntityMatch(super::EntityMatchMessage),
+        #[prost(message, tag = "10")]
+        Metric(super::MetricMessage),
+        #[prost(message, tag = "11")]
+        ExternalDep(super::ExternalDepMessage),
+        #[prost(message, tag = "12")]
+        Supertype(super::SupertypeMessage),
+        #[prost(message, tag = "13")]
+        Diagnostic(super::DiagnosticMessage),
+        #[prost(message, tag = "14")]
+        Annotation(super::AnnotationMessage),
+        #[prost(message, tag = "15")]
+        File(super::FileMessage),
+        #[prost(message, tag = "16")]
+        Commit(super::CommitMessage),
+    }
+}
+
+impl Record {
+    pub fn new(record: record::Record) -> Self {
+        Self { record: Some(record) }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EntityMessage {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(string, optional, tag = "2")]
+    pub parent_id: Option<String>,
+    #[prost(string, tag = "3")]
+    pub name: String,
+    #[prost(string, tag = "4")]
+    pub kind: String,
+    #[prost(uint64, tag = "5")]
+    pub start_byte: u64,
+    #[prost(uint64, tag = "6")]
+    pub start_row: u64,
+    #[prost(uint64, tag = "7")]
+    pub start_column: u64,
+    #[prost(uint64, tag = "8")]
+    pub end_byte: u64,
+    #[prost(uint64, tag = "9")]
+    pub end_row: u64,
+    #[prost(uint64, tag = "10")]
+    pub end_column: u64,
+    #[prost(string, tag = "11")]
+    pub content_id: String,
+    #[prost(string, tag = "12")]
+    pub simple_id: String,
+    #[prost(uint64, tag = "13")]
+    pub ordinal: u64,
+    #[prost(uint64, optional, tag = "14")]
+    pub comment_start_byte: Option<u64>,
+    #[prost(uint64, optional, tag = "15")]
+    pub comment_start_row: Option<u64>,
+    #[prost(uint64, optional, tag = "16")]
+    pub comment_start_column: Option<u64>,
+    #[prost(uint64, optional, tag = "17")]
+    pub comment_end_byte: Option<u64>,
+    #[prost(uint64, optional, tag = "18")]
+    pub comment_end_row: Option<u64>,
+    #[prost(uint64, optional, tag = "19")]
+    pub comment_end_column: Option<u64>,
+    #[prost(string, tag = "20")]
+    pub qualified_name: String,
+    #[prost(string, tag = "21")]
+    pub entity_content_id: String,
+    #[prost(bool, tag = "22")]
+    pub is_test: bool,
+    #[prost(uint64, tag = "23")]
+    pub byte_size: u64,
+    #[prost(uint64, optional, tag = "24")]
+    pub token_count: Option<u64>,
+    #[prost(string, optional, tag = "25")]
+    pub lang: Option<String>,
+    #[prost(string, optional, tag = "26")]
+    pub filename: Option<String>,
+}
+
+impl EntityMessage {
+    pub fn from(entity: Entity) -> Self {
+        let location = entity.location();
+        Self {
+            id: entity.id.0.to_string(),
+            parent_id: entity.parent_id.map(|p| p.0.to_string()),
+            name: entity.name,
+            kind: entity.kind.as_ref().to_string(),
+            start_byte: location.start.byte as u64,
+            start_row: location.start.row as u64,
+            start_column: location.start.column as u64,
+            end_byte: location.end.byte as u64,
+            end_row: location.end.row as u64,
+            end_column: location.end.column as u64,
+            content_id: entity.content_id.0.to_string(),
+            simple_id: entity.simple_id.0.to_string(),
+            ordinal: entity.ordinal as u64,
+            comment_start_byte: entity.comment.map(|c| c.start.byte as u64),
+            comment_start_row: entity.comment.map(|c| c.start.row as u64),
+            comment_start_column: entity.comment.map(|c| c.start.column as u64),
+            comment_end_byte: entity.comment.map(|c| c.end.byte as u64),
+            comment_end_row: entity.comment.map(|c| c.end.row as u64),
+            comment_end_column: entity.comment.map(|c| c.end.column as u64),
+            qualified_name: entity.qualified_name,
+            entity_content_id: entity.entity_content_id.0.to_string(),
+            is_test: entity.is_test,
+            byte_size: entity.byte_size as u64,
+            token_count: entity.token_count.map(|t| t as u64),
+            lang: entity.lang.map(|l| l.to_string()),
+            filename: entity.filename,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DepMessage {
+    #[prost(string, tag = "1")]
+    pub src: String,
+    #[prost(string, tag = "2")]
+    pub tgt: String,
+    #[prost(string, tag = "3")]
+    pub kind: String,
+    #[prost(uint64, tag = "4")]
+    pub row: u64,
+    #[prost(string, tag = "5")]
+    pub commit_id: String,
+    #[prost(string, tag = "6")]
+    pub confidence: String,
+    #[prost(string, tag = "7")]
+    pub resolver: String,
+    #[prost(uint64, tag = "8")]
+    pub count: u64,
+    #[prost(string, optional, tag = "9")]
+    pub src_filename: Option<String>,
+    #[prost(string, optional, tag = "10")]
+    pub tgt_filename: Option<String>,
+    #[prost(uint64, optional, tag = "11")]
+    pub tgt_row: Option<u64>,
+    #[prost(uint64, optional, tag = "12")]
+    pub tgt_column: Option<u64>,
+    #[prost(string, optional, tag = "13")]
+    pub src_name: Option<String>,
+    #[prost(string, optional, tag = "14")]
+    pub tgt_name: Option<String>,
+}
+
+impl DepMessage {
+    pub fn from(dep: EntityDep, taxonomy: &Taxonomy) -> Self {
+        Self {
+            src: dep.src.0.to_string(),
+            tgt: dep.tgt.0.to_string(),
+            kind: taxonomy.label(dep.kind).to_string(),
+            row: dep.position.row() as u64,
+            commit_id: commit_str(dep.commit_id),
+            confidence: dep.confidence.as_ref().to_string(),
+            resolver: dep.resolver.to_string(),
+            count: dep.count as u64,
+            src_filename: dep.location.as_ref().map(|l| l.src_filename.clone()),
+            tgt_filename: dep.location.as_ref().map(|l| l.tgt_filename.clone()),
+            tgt_row: dep.location.as_ref().map(|l| l.tgt_position.row() as u64),
+            tgt_column: dep
+                .location
+                .as_ref()
+                .and_then(|l| l.tgt_position.column())
+                .map(|c| c as u64),
+            src_name: dep.location.as_ref().and_then(|l| l.src_name.clone()),
+            tgt_name: dep.location.as_ref().and_then(|l| l.tgt_name.clone()),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ChangeMessage {
+    #[prost(string, tag = "1")]
+    pub simple_id: String,
+    #[prost(string, tag = "2")]
+    pub commit_id: String,
+    #[prost(string, tag = "3")]
+    pub kind: String,
+    #[prost(uint64, tag = "4")]
+    pub adds: u64,
+    #[prost(uint64, tag = "5")]
+    pub dels: u64,
+    #[prost(string, tag = "6")]
+    pub author_name: String,
+    #[prost(string, tag = "7")]
+    pub author_email: String,
+}
+
+impl ChangeMessage {
+    pub fn from(change: Change) -> Self {
+        Self {
+            simple_id: change.simple_id.0.to_string(),
+            commit_id: change.commit_id.to_string(),
+            kind: change.kind.as_ref().to_string(),
+            adds: change.adds as u64,
+            dels: change.dels as u64,
+            author_name: change.author_name,
+            author_email: change.author_email,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DepChangeMessage {
+    #[prost(string, tag = "1")]
+    pub src: String,
+    #[prost(string, tag = "2")]
+    pub tgt: String,
+    #[prost(string, tag = "3")]
+    pub kind: String,
+    #[prost(string, tag = "4")]
+    pub old_commit_id: String,
+    #[prost(string, tag = "5")]
+    pub new_commit_id: String,
+    #[prost(uint64, tag = "6")]
+    pub adds: u64,
+    #[prost(uint64, tag = "7")]
+    pub dels: u64,
+}
+
+impl DepChangeMessage {
+    pub fn from(dep_change: DepChange) -> Self {
+        Self {
+            src: dep_change.src.0.to_string(),
+            tgt: dep_change.tgt.0.to_string(),
+            kind: dep_change.kind.as_ref().to_string(),
+            old_commit_id: commit_str(dep_change.old_commit_id),
+            new_commit_id: commit_str(dep_change.new_commit_id),
+            adds: dep_change.adds as u64,
+            dels: dep_change.dels as u64,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ContentMessage {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(string, tag = "2")]
+    pub content: String,
+}
+
+impl ContentMessage {
+    pub fn from(content: Content) -> Self {
+        Self { id: content.id.0.to_string(), content: content.content }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AlignmentMessage {
+    #[prost(string, tag = "1")]
+    pub a: String,
+    #[prost(string, tag = "2")]
+    pub b: String,
+    #[prost(uint64, tag = "3")]
+    pub deps: u64,
+    #[prost(uint64, tag = "4")]
+    pub cochanges: u64,
+    #[prost(string, tag = "5")]
+    pub kind: String,
+}
+
+impl AlignmentMessage {
+    pub fn from(alignment: Alignment) -> Self {
+        Self {
+            a: alignment.a.0.to_string(),
+            b: alignment.b.0.to_string(),
+            deps: alignment.deps as u64,
+            cochanges: alignment.cochanges as u64,
+            kind: alignment.kind.as_ref().to_string(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RevspecMessage {
+    #[prost(string, tag = "1")]
+    pub revspec: String,
+    #[prost(string, tag = "2")]
+    pub commit_id: String,
+    #[prost(uint64, tag = "3")]
+    pub resolved_at: u64,
+}
+
+impl RevspecMessage {
+    pub fn from(revspec: Revspec) -> Self {
+        Self {
+            revspec: revspec.revspec,
+            commit_id: commit_str(revspec.commit_id),
+            resolved_at: revspec.resolved_at,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct UnresolvedRefMessage {
+    #[prost(string, tag = "1")]
+    pub filename: String,
+    #[prost(uint64, tag = "2")]
+    pub row: u64,
+    #[prost(uint64, tag = "3")]
+    pub column: u64,
+    #[prost(string, tag = "4")]
+    pub text: String,
+    #[prost(string, tag = "5")]
+    pub commit_id: String,
+}
+
+impl UnresolvedRefMessage {
+    pub fn from(unresolved_ref: UnresolvedRef) -> Self {
+        Self {
+            filename: unresolved_ref.filename,
+            row: unresolved_ref.row as u64,
+            column: unresolved_ref.column as u64,
+            text: unresolved_ref.text,
+            commit_id: commit_str(unresolved_ref.commit_id),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EntityMatchMessage {
+    #[prost(string, tag = "1")]
+    pub old_id: String,
+    #[prost(string, tag = "2")]
+    pub new_id: String,
+    #[prost(string, tag = "3")]
+    pub old_commit_id: String,
+    #[prost(string, tag = "4")]
+    pub new_commit_id: String,
+    #[prost(string, tag = "5")]
+    pub kind: String,
+}
+
+impl EntityMatchMessage {
+    pub fn from(entity_match: EntityMatch) -> Self {
+        Self {
+            old_id: entity_match.old_id.0.to_string(),
+            new_id: entity_match.new_id.0.to_string(),
+            old_commit_id: commit_str(entity_match.old_commit_id),
+            new_commit_id: commit_str(entity_match.new_commit_id),
+            kind: entity_match.kind.as_ref().to_string(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MetricMessage {
+    #[prost(string, tag = "1")]
+    pub entity_id: String,
+    #[prost(string, tag = "2")]
+    pub commit_id: String,
+    #[prost(uint64, tag = "3")]
+    pub fan_in: u64,
+    #[prost(uint64, tag = "4")]
+    pub fan_out: u64,
+    #[prost(uint64, tag = "5")]
+    pub lines: u64,
+    #[prost(uint64, tag = "6")]
+    pub logical_lines: u64,
+    #[prost(uint64, tag = "7")]
+    pub complexity: u64,
+    #[prost(uint64, tag = "8")]
+    pub max_nesting: u64,
+    #[prost(uint64, optional, tag = "9")]
+    pub params: Option<u64>,
+}
+
+impl MetricMessage {
+    pub fn from(metric: Metric) -> Self {
+        Self {
+            entity_id: metric.entity_id.0.to_string(),
+            commit_id: commit_str(metric.commit_id),
+            fan_in: metric.fan_in as u64,
+            fan_out: metric.fan_out as u64,
+            lines: metric.lines as u64,
+            logical_lines: metric.logical_lines as u64,
+            complexity: metric.complexity as u64,
+            max_nesting: metric.max_nesting as u64,
+            params: metric.params.map(|p| p as u64),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExternalDepMessage {
+    #[prost(string, tag = "1")]
+    pub src: String,
+    #[prost(string, tag = "2")]
+    pub symbol: String,
+    #[prost(uint64, tag = "3")]
+    pub row: u64,
+    #[prost(string, tag = "4")]
+    pub commit_id: String,
+}
+
+impl ExternalDepMessage {
+    pub fn from(external_dep: ExternalDep) -> Self {
+        Self {
+            src: external_dep.src.0.to_string(),
+            symbol: external_dep.symbol,
+            row: external_dep.row as u64,
+            commit_id: commit_str(external_dep.commit_id),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SupertypeMessage {
+    #[prost(string, tag = "1")]
+    pub subtype: String,
+    #[prost(string, tag = "2")]
+    pub supertype: String,
+    #[prost(string, tag = "3")]
+    pub commit_id: String,
+    #[prost(string, tag = "4")]
+    pub kind: String,
+    #[prost(uint64, tag = "5")]
+    pub depth: u64,
+}
+
+impl SupertypeMessage {
+    pub fn from(supertype: Supertype) -> Self {
+        Self {
+            subtype: supertype.subtype.0.to_string(),
+            supertype: supertype.supertype.0.to_string(),
+            commit_id: commit_str(supertype.commit_id),
+            kind: supertype.kind.as_ref().to_string(),
+            depth: supertype.depth as u64,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DiagnosticMessage {
+    #[prost(string, optional, tag = "1")]
+    pub filename: Option<String>,
+    #[prost(string, tag = "2")]
+    pub commit_id: String,
+    #[prost(string, tag = "3")]
+    pub stage: String,
+    #[prost(string, tag = "4")]
+    pub message: String,
+}
+
+impl DiagnosticMessage {
+    pub fn from(diagnostic: Diagnostic) -> Self {
+        Self {
+            filename: diagnostic.filename,
+            commit_id: commit_str(diagnostic.commit_id),
+            stage: diagnostic.stage.as_ref().to_string(),
+            message: diagnostic.message,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AnnotationMessage {
+    #[prost(string, tag = "1")]
+    pub entity_id: String,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(string, tag = "3")]
+    pub text: String,
+    #[prost(uint64, tag = "4")]
+    pub row: u64,
+}
+
+impl AnnotationMessage {
+    pub fn from(annotation: Annotation) -> Self {
+        Self {
+            entity_id: annotation.entity_id.0.to_string(),
+            name: annotation.name,
+            text: annotation.text,
+            row: annotation.row as u64,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FileMessage {
+    #[prost(string, tag = "1")]
+    pub filename: String,
+    #[prost(string, tag = "2")]
+    pub commit_id: String,
+    #[prost(string, tag = "3")]
+    pub content_id: String,
+}
+
+impl FileMessage {
+    pub fn from(file: CommitFile) -> Self {
+        Self {
+            filename: file.filename,
+            commit_id: commit_str(file.commit_id),
+            content_id: file.content_id.0.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CommitMessage {
+    #[prost(string, tag = "1")]
+    pub commit_id: String,
+    #[prost(string, tag = "2")]
+    pub author_name: String,
+    #[prost(string, tag = "3")]
+    pub author_email: String,
+    #[prost(int64, tag = "4")]
+    pub author_date: i64,
+    #[prost(string, tag = "5")]
+    pub committer_name: String,
+    #[prost(string, tag = "6")]
+    pub committer_email: String,
+    #[prost(int64, tag = "7")]
+    pub commit_date: i64,
+    #[prost(string, tag = "8")]
+    pub subject: String,
+    #[prost(string, repeated, tag = "9")]
+    pub parent_ids: Vec<String>,
+    #[prost(string, optional, tag = "10")]
+    pub ref_name: Option<String>,
+}
+
+impl CommitMessage {
+    pub fn from(commit: Commit) -> Self {
+        Self {
+            commit_id: commit.commit_id.to_string(),
+            author_name: commit.author_name,
+            author_email: commit.author_email,
+            author_date: commit.author_date,
+            committer_name: commit.committer_name,
+            committer_email: commit.committer_email,
+            commit_date: commit.commit_date,
+            subject: commit.subject,
+            parent_ids: commit.parent_ids.split_whitespace().map(String::from).collect(),
+            ref_name: commit.ref_name,
+        }
+    }
+}
+
+fn commit_str(commit_id: PseudoCommitId) -> String {
+    match commit_id {
+        PseudoCommitId::CommitId(commit_id) => commit_id.to_string(),
+        PseudoCommitId::WorkDir => "WORKDIR".to_string(),
+    }
+}
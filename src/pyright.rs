@@ -0,0 +1,612 @@
+//! Resolve Python with Pyright (or any other language server).
+//!
+//! Stack Graphs and Depends both lose track of a reference as soon as its
+//! receiver needs type inference to be known (e.g. `self.repo.save()` where
+//! `repo` is assigned in `__init__`). Pyright does that inference. It is run as
+//! a language server over stdio in a temporary copy of each version, and every
+//! reference found by a syntactic pass is sent to it as a go-to-definition
+//! request:
+//!
+//! - The function of a call is a "Call" dep.
+//! - A superclass of a class is an "Extend" dep.
+//! - A name imported by an import statement is an "Import" dep.
+//! - Any other attribute or type annotation is a "Use" dep.
+//!
+//! Definitions outside of the project (like the standard library) are ignored.
+//! Any server that supports `textDocument/definition` will work, as only the
+//! parts of the Language Server Protocol shared by all servers are used.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use serde_json::json;
+use serde_json::Value;
+use subprocess::Exec;
+use subprocess::Popen;
+use subprocess::Redirection;
+use tempfile::TempDir;
+use tree_sitter::Parser;
+use tree_sitter::Query;
+use tree_sitter::QueryCursor;
+
+use crate::core::Dep;
+use crate::core::DepKind;
+use crate::core::FileDep;
+use crate::core::FileEndpoint;
+use crate::core::FileKey;
+use crate::core::PartialPosition;
+use crate::core::Position;
+use crate::core::PseudoCommitId;
+use crate::core::Span;
+use crate::languages::Lang;
+use crate::resolution::Resolver;
+use crate::resolution::ResolverFactory;
+
+/// The command used when `--pyright-cmd` is not given.
+pub const DEFAULT_PYRIGHT_CMD: &str = "pyright-langserver --stdio";
+
+/// How long to wait for the next response before giving up on the server.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(300);
+
+lazy_static! {
+    static ref PYTHON_QUERY: Query = Query::new(
+        tree_sitter_python::language(),
+        "(call function: (identifier) @call)
+         (call function: (attribute attribute: (identifier) @call))
+         (class_definition superclasses: (argument_list (identifier) @extend))
+         (class_definition
+           superclasses: (argument_list (attribute attribute: (identifier) @extend)))
+         (import_statement name: (dotted_name (identifier) @import .))
+         (import_statement name: (aliased_import name: (dotted_name (identifier) @import .)))
+         (import_from_statement name: (dotted_name (identifier) @import .))
+         (import_from_statement
+           name: (aliased_import name: (dotted_name (identifier) @import .)))
+         (attribute attribute: (identifier) @use)
+         (type (identifier) @use)"
+    )
+    .unwrap();
+}
+
+/// A reference to ask the server about.
+#[derive(Debug, Clone)]
+struct Reference {
+    filename: String,
+    position: Position,
+    kind: DepKind,
+}
+
+/// A Pyright resolver.
+///
+/// Works by using a temporary directory, as the server needs every file on
+/// disk to follow imports.
+///
+/// See [Resolver].
+#[derive(Debug)]
+pub struct PyrightResolver {
+    commit_id: PseudoCommitId,
+    command: String,
+    temp_dir: TempDir,
+    files: Mutex<HashMap<String, String>>,
+}
+
+impl PyrightResolver {
+    fn new(commit_id: PseudoCommitId, command: String) -> Self {
+        let temp_dir = TempDir::new().unwrap();
+        Self { commit_id, command, temp_dir, files: Default::default() }
+    }
+
+    fn try_resolve(&self) -> Result<Vec<FileDep>> {
+        let files = std::mem::take(&mut *self.files.lock().unwrap());
+
+        if files.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let root = self.temp_dir.path().canonicalize()?;
+        let references = files
+            .iter()
+            .sorted()
+            .flat_map(|(filename, content)| find_references(filename, content))
+            .collect_vec();
+
+        let mut server = Server::spawn(&self.command, &root)?;
+        server.initialize(&root)?;
+
+        for (filename, content) in files.iter().sorted() {
+            let document = json!({
+                "uri": to_uri(&root.join(filename)),
+                "languageId": "python",
+                "version": 1,
+                "text": content,
+            });
+            server.notify("textDocument/didOpen", json!({ "textDocument": document }))?;
+        }
+
+        let params = references.iter().map(|r| {
+            let content = &files[&r.filename];
+            json!({
+                "textDocument": { "uri": to_uri(&root.join(&r.filename)) },
+                "position": to_lsp_position(content, r.position),
+            })
+        });
+        let results = server.request_many("textDocument/definition", params.collect())?;
+        server.shutdown(&self.command)?;
+
+        // A reference the server could not answer for is treated like one
+        // without a definition rather than failing every other reference
+        let results = results.into_iter().map(|result| {
+            result.unwrap_or_else(|err| {
+                log::debug!("{:#}", err);
+                Value::Null
+            })
+        });
+
+        let lines: HashMap<_, _> =
+            files.iter().map(|(f, c)| (f.as_str(), line_starts(c))).collect();
+        let mut deps = Vec::new();
+
+        for (reference, result) in references.into_iter().zip(results) {
+            let Some((uri, line, character)) = first_location(&result) else {
+                continue;
+            };
+
+            let Some(filename) = from_uri(&uri, &root) else {
+                continue;
+            };
+
+            let Some(content) = files.get(&filename) else {
+                continue;
+            };
+
+            let tgt_position =
+                from_lsp_position(content, &lines[filename.as_str()], line, character);
+
+            let Some(tgt_position) = tgt_position else {
+                continue;
+            };
+
+            if filename == reference.filename && tgt_position == reference.position {
+                continue;
+            }
+
+            let position = PartialPosition::Whole(reference.position);
+            let src_key =
+                FileKey::from_content(reference.filename.clone(), &files[&reference.filename]);
+            let tgt_key = FileKey::from_content(filename, content);
+            let src = FileEndpoint::new(src_key, position);
+            let tgt = FileEndpoint::new(tgt_key, PartialPosition::Whole(tgt_position));
+            deps.push(Dep::new(src, tgt, reference.kind, position, self.commit_id));
+        }
+
+        Ok(deps)
+    }
+}
+
+impl Resolver for PyrightResolver {
+    fn add_file(&self, filename: &str, content: &str) {
+        let path = self.temp_dir.as_ref().join(filename);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+        self.files.lock().unwrap().insert(filename.to_string(), content.to_string());
+    }
+
+    fn resolve(&self) -> Result<Vec<FileDep>> {
+        log::info!("Running Pyright on {} file(s)...", self.files.lock().unwrap().len());
+        self.try_resolve()
+    }
+}
+
+/// A Pyright resolver factory.
+///
+/// See [ResolverFactory].
+#[derive(Debug, Clone)]
+pub struct PyrightResolverFactory {
+    command: String,
+}
+
+impl PyrightResolverFactory {
+    pub fn new(command: String) -> Self {
+        Self { command }
+    }
+}
+
+impl ResolverFactory for PyrightResolverFactory {
+    fn name(&self) -> &'static str {
+        "pyright"
+    }
+
+    fn supports(&self, lang: Lang) -> bool {
+        lang == Lang::Python
+    }
+
+    fn is_heavyweight(&self) -> bool {
+        true
+    }
+
+    /// The command itself, as a language server only reports its version once
+    /// it has been started.
+    fn version(&self) -> Result<String> {
+        Ok(self.command.clone())
+    }
+
+    fn try_create(&self, commit_id: PseudoCommitId, lang: Lang) -> Option<Box<dyn Resolver>> {
+        match self.supports(lang) {
+            true => Some(Box::new(PyrightResolver::new(commit_id, self.command.clone()))),
+            false => None,
+        }
+    }
+}
+
+/// Find the references in a Python file that may have a definition in the
+/// project.
+///
+/// A reference captured more than once (e.g. the attribute of a call) is only
+/// kept as the kind that comes first in [PYTHON_QUERY].
+fn find_references(filename: &str, content: &str) -> Vec<Reference> {
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_python::language()).unwrap();
+
+    let Some(tree) = parser.parse(content, None) else {
+        return Vec::new();
+    };
+
+    let names = PYTHON_QUERY.capture_names();
+    let mut references: HashMap<usize, (usize, Reference)> = HashMap::new();
+    let mut cursor = QueryCursor::new();
+
+    for m in cursor.matches(&PYTHON_QUERY, tree.root_node(), content.as_bytes()) {
+        for capture in m.captures {
+            let kind = match names[capture.index as usize].as_str() {
+                "call" => DepKind::Call,
+                "extend" => DepKind::Extend,
+                "import" => DepKind::Import,
+                _ => DepKind::Use,
+            };
+            let position = Span::from_ts(capture.node.range()).start;
+            let reference = Reference { filename: filename.to_string(), position, kind };
+
+            match references.get(&position.byte) {
+                Some((pattern, _)) if *pattern <= m.pattern_index => {}
+                _ => {
+                    references.insert(position.byte, (m.pattern_index, reference));
+                }
+            }
+        }
+    }
+
+    references.into_values().map(|(_, r)| r).sorted_by_key(|r| r.position.byte).collect()
+}
+
+/// A running language server.
+struct Server {
+    popen: Popen,
+    stdin: Arc<Mutex<Option<BufWriter<File>>>>,
+    responses: Receiver<(u64, Result<Value>)>,
+    next_id: u64,
+}
+
+impl Server {
+    fn spawn(command: &str, root: &Path) -> Result<Self> {
+        let mut popen = Exec::shell(command)
+            .cwd(root)
+            .stdin(Redirection::Pipe)
+            .stdout(Redirection::Pipe)
+            .popen()
+            .with_context(|| format!("failed to run language server `{}`", command))?;
+        let stdin = Arc::new(Mutex::new(Some(BufWriter::new(popen.stdin.take().unwrap()))));
+        let stdout = BufReader::new(popen.stdout.take().unwrap());
+        let (sender, responses) = mpsc::channel();
+        let replies = stdin.clone();
+
+        // The server may ask things of the client at any time (and wait for an
+        // answer), so its output is read on a thread of its own
+        std::thread::spawn(move || {
+            let mut stdout = stdout;
+
+            while let Ok(Some(message)) = read_message(&mut stdout) {
+                let id = message.get("id").and_then(|i| i.as_u64());
+
+                match (message.get("method"), id) {
+                    (Some(method), Some(_)) => {
+                        let reply = json!({
+                            "jsonrpc": "2.0",
+                            "id": message["id"],
+                            "result": default_reply(method.as_str(), &message["params"]),
+                        });
+
+                        let mut replies = replies.lock().unwrap();
+                        let Some(replies) = replies.as_mut() else {
+                            break;
+                        };
+
+                        if write_message(replies, &reply).is_err() || replies.flush().is_err() {
+                            break;
+                        }
+                    }
+                    (Some(_), None) => {}
+                    (None, Some(id)) => {
+                        let result = match message.get("error") {
+                            Some(error) => Err(anyhow::anyhow!("{}", error)),
+                            None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+                        };
+
+                        if sender.send((id, result)).is_err() {
+                            break;
+                        }
+                    }
+                    (None, None) => {}
+                }
+            }
+        });
+
+        Ok(Self { popen, stdin, responses, next_id: 0 })
+    }
+
+    fn initialize(&mut self, root: &Path) -> Result<()> {
+        let uri = to_uri(root);
+        let params = json!({
+            "processId": std::process::id(),
+            "rootUri": uri,
+            "capabilities": {
+                "textDocument": { "definition": { "linkSupport": false } },
+                "workspace": { "configuration": true },
+            },
+            "workspaceFolders": [{ "uri": uri, "name": "neodepends" }],
+        });
+        self.request("initialize", params)?;
+        self.notify("initialized", json!({}))
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Result<Value> {
+        self.request_many(method, vec![params])?.remove(0)
+    }
+
+    /// Send a request for each of `params` and wait for all of the results,
+    /// returned in the same order.
+    ///
+    /// Only fails if the server stops answering. An error response to one of
+    /// the requests is returned as the result of that request.
+    fn request_many(&mut self, method: &str, params: Vec<Value>) -> Result<Vec<Result<Value>>> {
+        let first_id = self.next_id;
+
+        for params in params.iter() {
+            let request = json!({
+                "jsonrpc": "2.0",
+                "id": self.next_id,
+                "method": method,
+                "params": params,
+            });
+            self.write(&request)?;
+            self.next_id += 1;
+        }
+
+        self.flush()?;
+        let mut results = HashMap::new();
+
+        while results.len() < params.len() {
+            let (id, result) =
+                self.responses.recv_timeout(RESPONSE_TIMEOUT).with_context(|| {
+                    format!("language server exited or stopped answering during {}", method)
+                })?;
+
+            if (first_id..self.next_id).contains(&id) {
+                results.insert(id, result.with_context(|| format!("{} failed", method)));
+            }
+        }
+
+        Ok((first_id..self.next_id).map(|id| results.remove(&id).unwrap()).collect())
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> Result<()> {
+        let notification = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        self.write(&notification)?;
+        self.flush()
+    }
+
+    fn write(&self, message: &Value) -> Result<()> {
+        match self.stdin.lock().unwrap().as_mut() {
+            Some(stdin) => write_message(stdin, message),
+            None => bail!("language server stdin is closed"),
+        }
+    }
+
+    fn flush(&self) -> Result<()> {
+        match self.stdin.lock().unwrap().as_mut() {
+            Some(stdin) => Ok(stdin.flush()?),
+            None => bail!("language server stdin is closed"),
+        }
+    }
+
+    /// Ask the server to exit and wait for it to do so.
+    fn shutdown(mut self, command: &str) -> Result<()> {
+        self.request("shutdown", Value::Null)?;
+        self.notify("exit", Value::Null)?;
+        self.stdin.lock().unwrap().take();
+        let status = self.popen.wait_timeout(RESPONSE_TIMEOUT)?;
+        let Some(status) = status else {
+            bail!("language server `{}` did not exit after shutdown", command);
+        };
+
+        if !status.success() {
+            bail!("language server `{}` terminated with {:?}", command, status);
+        }
+
+        Ok(())
+    }
+}
+
+/// Make sure the server does not outlive a resolver that failed part way.
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stdin.lock().unwrap().take();
+
+        if self.popen.poll().is_none() {
+            let _ = self.popen.kill();
+            let _ = self.popen.wait();
+        }
+    }
+}
+
+/// The answer to a request from the server. Every setting is left as its
+/// default, and anything else is declined.
+fn default_reply(method: Option<&str>, params: &Value) -> Value {
+    match method {
+        Some("workspace/configuration") => {
+            let items = params["items"].as_array().map_or(0, |i| i.len());
+            Value::Array(vec![Value::Null; items])
+        }
+        _ => Value::Null,
+    }
+}
+
+/// Read a message with its Content-Length header. Returns None at the end of
+/// the stream.
+fn read_message<R: BufRead>(reader: &mut R) -> Result<Option<Value>> {
+    let mut length = None;
+
+    loop {
+        let mut line = String::new();
+
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+
+    let mut buffer = vec![0; length.context("message has no Content-Length")?];
+    reader.read_exact(&mut buffer)?;
+    Ok(Some(serde_json::from_slice(&buffer)?))
+}
+
+fn write_message<W: Write>(writer: &mut W, message: &Value) -> Result<()> {
+    let body = serde_json::to_vec(message)?;
+    write!(writer, "Content-Length: {}\r\n\r\n", body.len())?;
+    writer.write_all(&body)?;
+    Ok(())
+}
+
+/// The URI, line, and character of the first location in a definition result,
+/// which may be a location, a list of locations, or a list of location links.
+fn first_location(result: &Value) -> Option<(String, u64, u64)> {
+    let location = match result {
+        Value::Array(locations) => locations.first()?,
+        location => location,
+    };
+
+    let uri = location.get("uri").or_else(|| location.get("targetUri"))?.as_str()?;
+    let range = location.get("range").or_else(|| location.get("targetSelectionRange"))?;
+    let start = &range["start"];
+    Some((uri.to_string(), start["line"].as_u64()?, start["character"].as_u64()?))
+}
+
+/// A position as the server expects it, where the character counts UTF-16 code
+/// units from the start of the line.
+fn to_lsp_position(content: &str, position: Position) -> Value {
+    let line_start = position.byte - position.column;
+    let character = content[line_start..position.byte].encode_utf16().count();
+    json!({ "line": position.row, "character": character })
+}
+
+/// The byte offset of the start of each line of `content`.
+fn line_starts(content: &str) -> Vec<usize> {
+    std::iter::once(0).chain(content.match_indices('\n').map(|(i, _)| i + 1)).collect()
+}
+
+/// The inverse of [to_lsp_position]. Returns None if the position is not in
+/// `content`.
+fn from_lsp_position(
+    content: &str,
+    line_starts: &[usize],
+    line: u64,
+    character: u64,
+) -> Option<Position> {
+    let row = line as usize;
+    let line_start = *line_starts.get(row)?;
+    let line_end = line_starts.get(row + 1).copied().unwrap_or(content.len());
+    let mut units = 0;
+
+    for (i, c) in content[line_start..line_end].char_indices() {
+        if units >= character as usize {
+            return Some(Position::new(line_start + i, row, i));
+        }
+
+        units += c.len_utf16();
+    }
+
+    Some(Position::new(line_end, row, line_end - line_start))
+}
+
+/// A `file` URI for an absolute path, with the characters that are not allowed
+/// in a URI percent-encoded.
+fn to_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let encoded = path
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .join("");
+
+    match encoded.starts_with('/') {
+        true => format!("file://{}", encoded),
+        false => format!("file:///{}", encoded),
+    }
+}
+
+/// The filename (relative to `root`) of a `file` URI, if it is under `root`.
+fn from_uri(uri: &str, root: &Path) -> Option<String> {
+    let encoded = uri.strip_prefix("file://")?;
+    let mut bytes = Vec::new();
+    let mut iter = encoded.bytes();
+
+    while let Some(b) = iter.next() {
+        match b {
+            b'%' => {
+                let hex = [iter.next()?, iter.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b => bytes.push(b),
+        }
+    }
+
+    let path = String::from_utf8(bytes).ok()?;
+
+    // Windows paths come as /C:/...
+    let path = match path.as_bytes() {
+        [b'/', _, b':', ..] => PathBuf::from(&path[1..]),
+        _ => PathBuf::from(path),
+    };
+
+    let relative = path.strip_prefix(root).ok()?;
+    Some(relative.components().map(|c| c.as_os_str().to_string_lossy()).join("/"))
+}
@@ -0,0 +1,352 @@
+//! Guess the deps that Java code makes through reflection.
+//!
+//! Frameworks like Spring wire classes together by name, so no resolver sees a
+//! dep from `ctx.getBean("billingService")` to `BillingService`. A syntactic
+//! pass over each file finds three kinds of references:
+//!
+//! - `Class.forName("com.foo.Bar")` and `loader.loadClass("com.foo.Bar")`.
+//! - `getBean("bar")`, matched to the class annotated with `@Component`,
+//!   `@Service`, `@Repository`, `@Controller`, `@RestController`,
+//!   `@Configuration`, or `@Named` (or the `@Bean` method) whose bean has that
+//!   name. Without an explicit name, a class is named like Spring does (e.g.
+//!   `barService` for `BarService`) and a method by itself.
+//! - Any other string literal that is exactly the qualified name of a class.
+//!
+//! Nested classes may be given with `$` or `.` (e.g. `com.foo.Bar$Baz`). A
+//! reference is only matched if exactly one class (or bean) in the version has
+//! that name. Deps found this way are "Use" deps marked
+//! [Confidence::Heuristic].
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use rayon::prelude::*;
+use tree_sitter::Node;
+use tree_sitter::Parser;
+
+use crate::core::Confidence;
+use crate::core::Dep;
+use crate::core::DepKind;
+use crate::core::FileDep;
+use crate::core::FileEndpoint;
+use crate::core::FileKey;
+use crate::core::MultiFileSet;
+use crate::core::PartialPosition;
+use crate::core::Position;
+use crate::core::PseudoCommitId;
+use crate::core::Span;
+use crate::filesystem::FileReader;
+use crate::languages::Lang;
+
+/// The kinds of nodes that declare a class (or something like one).
+const CLASS_DECLARATIONS: &[&str] = &[
+    "annotation_type_declaration",
+    "class_declaration",
+    "enum_declaration",
+    "interface_declaration",
+    "record_declaration",
+];
+
+/// The annotations that make a class a Spring (or CDI) bean.
+const BEAN_ANNOTATIONS: &[&str] = &[
+    "Component",
+    "Configuration",
+    "Controller",
+    "Named",
+    "Repository",
+    "RestController",
+    "Service",
+];
+
+/// The methods whose first argument is the name of a class.
+const CLASS_LOADERS: &[&str] = &["forName", "loadClass"];
+
+/// A string literal that may name a class or bean.
+#[derive(Debug)]
+enum Reference {
+    Class(String),
+    Bean(String),
+    Literal(String),
+}
+
+/// The classes, beans, and references of a Java file.
+#[derive(Debug, Default)]
+struct Scan {
+    /// Each class under both its qualified and binary name.
+    classes: Vec<(String, Position)>,
+    beans: Vec<(String, Position)>,
+    references: Vec<(Reference, Position)>,
+}
+
+/// Find the deps made through reflection in the Java files of each version.
+pub fn resolve<R: FileReader>(reader: &R, files: &MultiFileSet) -> Vec<FileDep> {
+    files
+        .iter()
+        .flat_map(|(&commit_id, file_set)| {
+            let scans: Vec<_> = file_set
+                .iter()
+                .filter(|f| Lang::of(&f.filename) == Some(Lang::Java))
+                .collect_vec()
+                .into_par_iter()
+                .filter_map(|f| Some((f, scan(&reader.read(f.content_id).ok()?)?)))
+                .collect();
+
+            let classes =
+                unique(scans.iter().flat_map(|(f, s)| s.classes.iter().map(move |c| (*f, c))));
+            let beans =
+                unique(scans.iter().flat_map(|(f, s)| s.beans.iter().map(move |b| (*f, b))));
+
+            scans
+                .iter()
+                .flat_map(|(f, s)| s.references.iter().map(move |(r, p)| (*f, r, *p)))
+                .filter_map(|(f, reference, p)| {
+                    let &(tgt, tgt_p) = match reference {
+                        Reference::Class(name) | Reference::Literal(name) => {
+                            classes.get(name.as_str())?
+                        }
+                        Reference::Bean(name) => beans.get(name.as_str())?,
+                    };
+                    Some(to_dep(f, p, tgt, tgt_p, commit_id))
+                })
+                .collect_vec()
+        })
+        .collect()
+}
+
+/// Map each name to where it is declared, leaving out names declared more than
+/// once.
+fn unique<'a, I>(names: I) -> HashMap<&'a str, (&'a FileKey, Position)>
+where
+    I: Iterator<Item = (&'a FileKey, &'a (String, Position))>,
+{
+    names
+        .map(|(f, (n, p))| (n.as_str(), (f, *p)))
+        .unique()
+        .into_group_map()
+        .into_iter()
+        .filter_map(|(n, defs)| match defs.as_slice() {
+            [def] => Some((n, *def)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn to_dep(
+    src: &FileKey,
+    src_position: Position,
+    tgt: &FileKey,
+    tgt_position: Position,
+    commit_id: PseudoCommitId,
+) -> FileDep {
+    let position = PartialPosition::Whole(src_position);
+    let src = FileEndpoint::new(src.clone(), position);
+    let tgt = FileEndpoint::new(tgt.clone(), PartialPosition::Whole(tgt_position));
+    Dep::new(src, tgt, DepKind::Use, position, commit_id)
+        .with_confidence(Confidence::Heuristic)
+        .with_resolver("reflection")
+}
+
+/// Find the classes and beans declared in a file and the string literals that
+/// may refer to them.
+///
+/// Returns None if the file could not be parsed.
+fn scan(content: &str) -> Option<Scan> {
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_java::language()).ok()?;
+    let tree = parser.parse(content, None)?;
+
+    let root = tree.root_node();
+    let package = root
+        .named_children(&mut root.walk())
+        .find(|c| c.kind() == "package_declaration")
+        .and_then(|p| {
+            let mut cursor = p.walk();
+            let name = p
+                .named_children(&mut cursor)
+                .find(|c| matches!(c.kind(), "identifier" | "scoped_identifier"));
+            name
+        })
+        .and_then(|n| n.utf8_text(content.as_bytes()).ok());
+
+    let mut result = Scan::default();
+    visit(root, content, package, &mut Vec::new(), &mut result);
+    Some(result)
+}
+
+/// Add the classes, beans, and references of `node` and its descendants to
+/// `scan`.
+///
+/// `outer` holds the names of the classes that enclose `node`.
+fn visit<'a>(
+    node: Node,
+    content: &'a str,
+    package: Option<&'a str>,
+    outer: &mut Vec<&'a str>,
+    scan: &mut Scan,
+) {
+    let text = |n: Node| n.utf8_text(content.as_bytes()).unwrap_or_default();
+    let start = |n: Node| Span::from_ts(n.range()).start;
+    let qualify = |name: String| match package {
+        Some(package) => format!("{}.{}", package, name),
+        None => name,
+    };
+
+    match node.kind() {
+        kind if CLASS_DECLARATIONS.contains(&kind) => {
+            if let Some(name) = node.child_by_field_name("name") {
+                let (name, position) = (text(name), start(name));
+                let mut names = outer.iter().copied().chain([name]);
+                scan.classes.push((qualify(names.clone().join(".")), position));
+
+                if !outer.is_empty() {
+                    scan.classes.push((qualify(names.join("$")), position));
+                }
+
+                for bean in bean_names(node, content, BEAN_ANNOTATIONS) {
+                    scan.beans.push((bean.unwrap_or_else(|| decapitalize(name)), position));
+                }
+
+                outer.push(name);
+                visit_children(node, content, package, outer, scan);
+                outer.pop();
+                return;
+            }
+        }
+        "method_declaration" => {
+            if let Some(name) = node.child_by_field_name("name") {
+                for bean in bean_names(node, content, &["Bean"]) {
+                    scan.beans.push((bean.unwrap_or_else(|| text(name).to_string()), start(name)));
+                }
+            }
+        }
+        "string_literal" => {
+            if let Some(literal) = unquote(text(node)) {
+                let reference = match invoked_method(node).map(text) {
+                    Some(method) if CLASS_LOADERS.contains(&method) => Reference::Class(literal),
+                    Some("getBean") => Reference::Bean(literal),
+                    _ if literal.contains('.') => Reference::Literal(literal),
+                    _ => return,
+                };
+                scan.references.push((reference, start(node)));
+            }
+        }
+        _ => {}
+    }
+
+    visit_children(node, content, package, outer, scan);
+}
+
+fn visit_children<'a>(
+    node: Node,
+    content: &'a str,
+    package: Option<&'a str>,
+    outer: &mut Vec<&'a str>,
+    scan: &mut Scan,
+) {
+    let mut cursor = node.walk();
+
+    for child in node.named_children(&mut cursor) {
+        visit(child, content, package, outer, scan);
+    }
+}
+
+/// The name of the method that `literal` is the first argument of, if any.
+fn invoked_method(literal: Node) -> Option<Node> {
+    let args = literal.parent().filter(|p| p.kind() == "argument_list")?;
+
+    if args.named_child(0) != Some(literal) {
+        return None;
+    }
+
+    args.parent().filter(|p| p.kind() == "method_invocation")?.child_by_field_name("name")
+}
+
+/// The beans declared by the given annotations on a declaration.
+///
+/// Each bean is None if it takes the default name. A declaration without any of
+/// the annotations declares no beans.
+fn bean_names(declaration: Node, content: &str, annotations: &[&str]) -> Vec<Option<String>> {
+    let Some(modifiers) =
+        declaration.named_children(&mut declaration.walk()).find(|c| c.kind() == "modifiers")
+    else {
+        return Vec::new();
+    };
+
+    let mut cursor = modifiers.walk();
+    let annotation = modifiers
+        .named_children(&mut cursor)
+        .filter(|a| matches!(a.kind(), "annotation" | "marker_annotation"))
+        .find(|a| {
+            let name =
+                a.child_by_field_name("name").and_then(|n| n.utf8_text(content.as_bytes()).ok());
+            name.is_some_and(|n| annotations.contains(&n.rsplit('.').next().unwrap_or(n)))
+        });
+
+    let Some(annotation) = annotation else {
+        return Vec::new();
+    };
+
+    let names = annotation
+        .child_by_field_name("arguments")
+        .map(|args| explicit_names(args, content))
+        .unwrap_or_default();
+
+    match names.is_empty() {
+        true => vec![None],
+        false => names.into_iter().map(Some).collect(),
+    }
+}
+
+/// The names given as the value (or `name`) of an annotation, like `"a"` in
+/// `@Service("a")` or `@Bean(name = {"a", "b"})`.
+fn explicit_names(args: Node, content: &str) -> Vec<String> {
+    let mut cursor = args.walk();
+    let values = args.named_children(&mut cursor).filter_map(|arg| match arg.kind() {
+        "element_value_pair" => {
+            let key = arg.child_by_field_name("key")?.utf8_text(content.as_bytes()).ok()?;
+            matches!(key, "value" | "name").then(|| arg.child_by_field_name("value")).flatten()
+        }
+        _ => Some(arg),
+    });
+
+    let mut names = Vec::new();
+
+    for value in values.collect_vec() {
+        let literals = match value.kind() {
+            "element_value_array_initializer" => value.named_children(&mut value.walk()).collect(),
+            _ => vec![value],
+        };
+
+        names.extend(
+            literals
+                .into_iter()
+                .filter(|l| l.kind() == "string_literal")
+                .filter_map(|l| unquote(l.utf8_text(content.as_bytes()).ok()?)),
+        );
+    }
+
+    names
+}
+
+/// The content of a plain string literal. None for text blocks, empty strings,
+/// and strings with escapes.
+fn unquote(literal: &str) -> Option<String> {
+    let inner = literal.strip_prefix('"')?.strip_suffix('"')?;
+
+    match inner.is_empty() || inner.starts_with('"') || inner.contains('\\') {
+        true => None,
+        false => Some(inner.to_string()),
+    }
+}
+
+/// Name a bean after its class like Spring does: the first letter is lowered,
+/// unless the first two letters are both capitals (e.g. `URLParser`).
+fn decapitalize(name: &str) -> String {
+    let mut chars = name.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(a), Some(b)) if a.is_uppercase() && b.is_uppercase() => name.to_string(),
+        (Some(a), _) => a.to_lowercase().chain(name.chars().skip(1)).collect(),
+        _ => String::new(),
+    }
+}
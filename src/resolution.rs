@@ -1,14 +1,28 @@
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt::Debug;
+use std::path::Path;
+use std::sync::Mutex;
 
+use anyhow::bail;
+use anyhow::Result;
 use rayon::prelude::*;
 
+use crate::bridges;
+use crate::core::Diagnostic;
+use crate::core::DiagnosticStage;
 use crate::core::FileDep;
 use crate::core::FileKey;
 use crate::core::MultiFileSet;
 use crate::core::PseudoCommitId;
-use crate::languages::Lang;
+use crate::core::UnresolvedRef;
+use crate::duck;
 use crate::filesystem::FileReader;
+use crate::intrafile;
+use crate::languages::Lang;
+use crate::manifests;
+use crate::reflection;
+use crate::table;
 
 /// Used to extract the file-level dependencies between given source
 /// files.
@@ -20,16 +34,69 @@ pub trait Resolver: Debug + Send + Sync {
     /// language.
     fn add_file(&self, filename: &str, content: &str);
 
+    /// Add a source file that already exists on disk at `path`.
+    ///
+    /// Resolvers that work on a copy of the project can link to `path` rather
+    /// than write out the content again. Returns false if the file was not
+    /// added, in which case the caller should fall back to
+    /// [Resolver::add_file].
+    fn add_file_on_disk(&self, _file: &FileKey, _path: &Path) -> bool {
+        false
+    }
+
     /// Resolve file-level dependencies between source files.
     ///
     /// Only considers files added with [Resolver::add_file]. It is undefined
     /// behavior to call [Resolver::add_file] while [Resolver::resolve] is
     /// running. It is undefined behavior to call this function more than once.
-    fn resolve(&self) -> Vec<FileDep>;
+    ///
+    /// Fails if the resolver as a whole could not run (e.g. an external tool
+    /// crashed or could not be started).
+    fn resolve(&self) -> Result<Vec<FileDep>>;
+
+    /// The references that [Resolver::resolve] could not match to a
+    /// definition.
+    ///
+    /// Only meaningful after [Resolver::resolve] has run. Resolvers that do not
+    /// keep track of these return nothing.
+    fn unresolved(&self) -> Vec<UnresolvedRef> {
+        Vec::new()
+    }
+
+    /// The files (or other inputs) that [Resolver::resolve] had to skip.
+    ///
+    /// Only meaningful after [Resolver::resolve] has run. Failures of the
+    /// resolver as a whole are recorded by the [ResolverManager], so this is
+    /// only for failures that still let the rest of the files be resolved.
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        Vec::new()
+    }
 }
 
 /// Used to create a [Resolver] as a trait object.
 pub trait ResolverFactory: Debug + Send + Sync + 'static {
+    /// A short name for the underlying Resolver (e.g. "depends").
+    fn name(&self) -> &'static str;
+
+    /// Does the underlying Resolver support this language?
+    fn supports(&self, lang: Lang) -> bool;
+
+    /// Is the underlying Resolver expensive to start (e.g. it runs a JVM)?
+    ///
+    /// Heavyweight resolvers are skipped for shards with a single file, as
+    /// they would only find deps within that file. The next resolver that
+    /// supports the language (if any) is used instead.
+    fn is_heavyweight(&self) -> bool {
+        false
+    }
+
+    /// The version of the underlying Resolver.
+    ///
+    /// Resolvers built into Neodepends share its version.
+    fn version(&self) -> Result<String> {
+        Ok(env!("CARGO_PKG_VERSION").to_string())
+    }
+
     /// Attempt to create a Resolver for the given commit and lang.
     ///
     /// Will return [None] if the underlying Resolver does not support this
@@ -37,11 +104,57 @@ pub trait ResolverFactory: Debug + Send + Sync + 'static {
     fn try_create(&self, commit_id: PseudoCommitId, lang: Lang) -> Option<Box<dyn Resolver>>;
 }
 
+/// When the files of each shard (a version and language) are written out.
+///
+/// Only matters for resolvers that copy files to disk, like Depends.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(strum::Display, strum::EnumIs, strum::EnumString, strum::VariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ScratchPolicy {
+    /// Add the files of every shard before resolving any of them.
+    ///
+    /// Fastest, but every shard occupies scratch space at the same time.
+    #[default]
+    Batch,
+
+    /// Add, resolve, and clean up one shard at a time.
+    ///
+    /// Bounds scratch space to roughly the size of the largest shard.
+    Stream,
+}
+
+/// Which resolvers are run for a language that several of them support.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(strum::Display, strum::EnumIs, strum::EnumString, strum::VariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ResolverMode {
+    /// Only run the resolver with the highest priority.
+    #[default]
+    Priority,
+
+    /// Run every resolver and keep all of their deps.
+    ///
+    /// Each dep records which resolver found it, so the resolvers can be
+    /// compared.
+    Union,
+}
+
 /// Used to resolve the dependencies of different versions and languages
 /// simultaneously
 #[derive(Debug)]
 pub struct ResolverManager {
     resolvers: Vec<Box<dyn ResolverFactory>>,
+    policy: ScratchPolicy,
+    mode: ResolverMode,
+    duck_typing: bool,
+    intra_file: bool,
+    cross_language: bool,
+    reflection: bool,
+    build_manifests: bool,
+    diagnostics: Mutex<Vec<Diagnostic>>,
+
+    /// The resolvers that failed as a whole (also in `diagnostics`).
+    failures: Mutex<Vec<Diagnostic>>,
 }
 
 impl ResolverManager {
@@ -49,12 +162,63 @@ impl ResolverManager {
     ///
     /// The list should be sorted in order of decreasing priority.
     pub fn new(resolvers: Vec<Box<dyn ResolverFactory>>) -> Self {
-        Self { resolvers }
+        Self {
+            resolvers,
+            policy: ScratchPolicy::default(),
+            mode: ResolverMode::default(),
+            duck_typing: false,
+            intra_file: false,
+            cross_language: false,
+            reflection: false,
+            build_manifests: false,
+            diagnostics: Default::default(),
+            failures: Default::default(),
+        }
     }
 
     /// Create a [ResolverManager] without any resolvers.
     pub fn empty() -> Self {
-        Self { resolvers: Vec::new() }
+        Self::new(Vec::new())
+    }
+
+    pub fn set_scratch_policy(&mut self, policy: ScratchPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn set_mode(&mut self, mode: ResolverMode) {
+        self.mode = mode;
+    }
+
+    /// Also guess the deps of Python attributes that could not be resolved.
+    /// See [crate::duck].
+    pub fn set_duck_typing(&mut self, duck_typing: bool) {
+        self.duck_typing = duck_typing;
+    }
+
+    /// Also find the deps between the members of the same Java file with a
+    /// syntactic pass. These replace the deps within the same file that a
+    /// resolver only found the row of. See [crate::intrafile].
+    pub fn set_intra_file(&mut self, intra_file: bool) {
+        self.intra_file = intra_file;
+    }
+
+    /// Also find the deps from one language to another (e.g. from a Java
+    /// native method to the C function that implements it). See
+    /// [crate::bridges].
+    pub fn set_cross_language(&mut self, cross_language: bool) {
+        self.cross_language = cross_language;
+    }
+
+    /// Also guess the deps that Java code makes through reflection (e.g.
+    /// `Class.forName`). See [crate::reflection].
+    pub fn set_reflection(&mut self, reflection: bool) {
+        self.reflection = reflection;
+    }
+
+    /// Also find the deps between the modules of build manifests. See
+    /// [crate::manifests].
+    pub fn set_build_manifests(&mut self, build_manifests: bool) {
+        self.build_manifests = build_manifests;
     }
 
     /// Resolve the file-level dependencies for each version contained within
@@ -63,52 +227,266 @@ impl ResolverManager {
     /// The [crate::core::FileSet] associated with each version is split into
     /// disjoint subsets of files where each subset contains all the files
     /// written in a particular language. Each of these subsets is considered
-    /// independently and has its dependencies resolved in parallel.
-    pub fn resolve<R: FileReader>(&self, reader: &R, files: &MultiFileSet) -> Vec<FileDep> {
+    /// independently and has its dependencies resolved in parallel. Also
+    /// returns the references that could not be resolved (see
+    /// [Resolver::unresolved]).
+    pub fn resolve<R: FileReader>(
+        &self,
+        reader: &R,
+        files: &MultiFileSet,
+    ) -> (Vec<FileDep>, Vec<UnresolvedRef>) {
+        let (mut deps, mut unresolved) = self.resolve_shards(reader, files);
+
+        if self.duck_typing {
+            let guessed = duck::resolve(reader, files, &deps);
+            let guessed_at: HashSet<_> = guessed
+                .iter()
+                .map(|d| (&d.src.file_key.filename, d.position.row(), d.position.column()))
+                .collect();
+            unresolved.retain(|r| !guessed_at.contains(&(&r.filename, r.row, Some(r.column))));
+            deps.extend(guessed);
+        }
+
+        if self.intra_file {
+            let found = intrafile::resolve(reader, files, &deps);
+            let found_rows: HashSet<_> =
+                found.iter().map(|d| (d.commit_id, &d.src.file_key, d.position.row())).collect();
+            let found_at: HashSet<_> = found
+                .iter()
+                .map(|d| (&d.src.file_key.filename, d.position.row(), d.position.column()))
+                .collect();
+            deps.retain(|d| {
+                d.src.file_key != d.tgt.file_key
+                    || d.position.byte().is_some()
+                    || !found_rows.contains(&(d.commit_id, &d.src.file_key, d.position.row()))
+            });
+            unresolved.retain(|r| !found_at.contains(&(&r.filename, r.row, Some(r.column))));
+            deps.extend(found);
+        }
+
+        if self.cross_language {
+            deps.extend(bridges::resolve(reader, files));
+        }
+
+        if self.reflection {
+            deps.extend(reflection::resolve(reader, files));
+        }
+
+        if self.build_manifests {
+            deps.extend(manifests::resolve(reader, files));
+        }
+
+        (deps, unresolved)
+    }
+
+    fn resolve_shards<R: FileReader>(
+        &self,
+        reader: &R,
+        files: &MultiFileSet,
+    ) -> (Vec<FileDep>, Vec<UnresolvedRef>) {
         // Save some work if we know there are no resolvers
         if self.resolvers.is_empty() {
-            return Vec::new();
+            return (Vec::new(), Vec::new());
         }
-        
+
         // Create a list of resolvers and an associated list (of lists) of files
         let (resolvers, files): (Vec<_>, Vec<_>) = to_map(files)
             .into_par_iter()
-            .filter_map(|((commit_id, lang), files)| {
-                self.resolver_for(commit_id, lang).map(|r| (r, files))
+            .flat_map_iter(|((commit_id, lang), files)| {
+                let resolvers = self.resolvers_for(commit_id, lang, files.len());
+                resolvers.into_iter().map(move |(n, r)| ((n, commit_id, r), files.clone()))
             })
             .collect();
 
+        if self.policy.is_stream() {
+            let (deps, unresolved): (Vec<_>, Vec<_>) = resolvers
+                .into_iter()
+                .zip(files)
+                .map(|((name, commit_id, resolver), files)| {
+                    files.into_par_iter().for_each(|f| add_file(reader, f, &[resolver.as_ref()]));
+                    self.run(name, commit_id, resolver.as_ref())
+                })
+                .unzip();
+            return (deps.concat(), unresolved.concat());
+        }
+
         // Organize files and resolvers so we only have to load each file once
-        let mut lookup: HashMap<&FileKey, Vec<&Box<dyn Resolver>>> = HashMap::new();
+        let mut lookup: HashMap<&FileKey, Vec<&dyn Resolver>> = HashMap::new();
         for (i, inner_files) in files.iter().enumerate() {
             for &file in inner_files {
-                lookup.entry(file).or_default().push(&resolvers[i]);
+                lookup.entry(file).or_default().push(resolvers[i].2.as_ref());
             }
         }
 
         // Iterate through the files and add each one to their associated resolvers
-        lookup.into_par_iter().for_each(|(f, resolvers)| {
-            let content = reader.read(f.content_id).unwrap();
-            resolvers.into_par_iter().for_each(|r| r.add_file(&f.filename, &content));
-        });
+        lookup.into_par_iter().for_each(|(f, resolvers)| add_file(reader, f, &resolvers));
 
         // Resolve everything
-        resolvers.into_par_iter().flat_map(|r| r.resolve()).collect()
+        let (deps, unresolved): (Vec<_>, Vec<_>) =
+            resolvers.into_par_iter().map(|(n, c, r)| self.run(n, c, r.as_ref())).unzip();
+        (deps.concat(), unresolved.concat())
+    }
+
+    /// Resolve the files already added to a resolver.
+    ///
+    /// If the resolver fails, a [Diagnostic] is recorded and nothing is
+    /// returned.
+    fn run(
+        &self,
+        name: &'static str,
+        commit_id: PseudoCommitId,
+        resolver: &dyn Resolver,
+    ) -> (Vec<FileDep>, Vec<UnresolvedRef>) {
+        match resolver.resolve() {
+            Ok(deps) => {
+                self.diagnostics.lock().unwrap().extend(resolver.diagnostics());
+                (tag(name, deps), resolver.unresolved())
+            }
+            Err(err) => {
+                log::error!("Resolver {} failed at {}: {:#}", name, commit_id, err);
+                let message = format!("{}: {:#}", name, err);
+                let diagnostic =
+                    Diagnostic::new(None, commit_id, DiagnosticStage::Resolve, message);
+                self.failures.lock().unwrap().push(diagnostic.clone());
+                self.diagnostics.lock().unwrap().push(diagnostic);
+                (Vec::new(), Vec::new())
+            }
+        }
+    }
+
+    /// Take the failures of the resolvers (see [Resolver::diagnostics]).
+    ///
+    /// A resolver that fails is recorded here (rather than bringing down the
+    /// whole run) and contributes no deps.
+    pub fn take_diagnostics(&self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics.lock().unwrap())
+    }
+
+    /// Fail if any resolver failed as a whole so far.
+    ///
+    /// Files that a resolver skipped (see [Resolver::diagnostics]) are not
+    /// counted.
+    pub fn check_failures(&self) -> Result<()> {
+        match self.failures.lock().unwrap().first() {
+            Some(failure) => {
+                bail!("Failed to resolve deps at {}: {}", failure.commit_id, failure.message)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Are there no resolvers at all?
+    pub fn is_empty(&self) -> bool {
+        self.resolvers.is_empty()
     }
 
-    /// Try to create a resolver for a particular version and language
-    fn resolver_for(&self, commit_id: PseudoCommitId, lang: Lang) -> Option<Box<dyn Resolver>> {
-        self.resolvers.iter().filter_map(|f| f.try_create(commit_id, lang)).next()
+    /// The name and version of each enabled resolver, in order of priority.
+    pub fn versions(&self) -> Result<Vec<(&'static str, String)>> {
+        self.resolvers.iter().map(|f| Ok((f.name(), f.version()?))).collect()
     }
+
+    /// The names of the resolvers that will be used for this language.
+    ///
+    /// Holds at most one name unless the mode is [ResolverMode::Union].
+    pub fn resolver_names(&self, lang: Lang) -> Vec<&'static str> {
+        let names = self.resolvers.iter().filter(|f| f.supports(lang)).map(|f| f.name());
+
+        match self.mode {
+            ResolverMode::Priority => names.take(1).collect(),
+            ResolverMode::Union => names.collect(),
+        }
+    }
+
+    /// Render a table showing which resolvers support which languages.
+    ///
+    /// The last column shows which resolvers will actually be used.
+    pub fn coverage_table(&self, langs: &[Lang]) -> String {
+        let mut header = vec!["lang"];
+        header.extend(self.resolvers.iter().map(|f| f.name()));
+        header.push("used");
+
+        let rows = langs
+            .iter()
+            .map(|&lang| {
+                let mut row = vec![lang.to_string()];
+                row.extend(self.resolvers.iter().map(|f| table::yes_or_no(f.supports(lang))));
+                let used = self.resolver_names(lang);
+                row.push(if used.is_empty() { "NONE".to_string() } else { used.join("+") });
+                row
+            })
+            .collect::<Vec<_>>();
+
+        table::render(&header, &rows)
+    }
+
+    /// Try to create the resolvers for a particular version and language
+    /// (along with their names).
+    ///
+    /// Only the first resolver is created unless the mode is
+    /// [ResolverMode::Union]. Heavyweight resolvers are passed over if there is
+    /// only one file (see [ResolverFactory::is_heavyweight]).
+    fn resolvers_for(
+        &self,
+        commit_id: PseudoCommitId,
+        lang: Lang,
+        file_count: usize,
+    ) -> Vec<(&'static str, Box<dyn Resolver>)> {
+        let resolvers = self
+            .resolvers
+            .iter()
+            .filter(|f| file_count > 1 || !f.is_heavyweight())
+            .filter_map(|f| Some((f.name(), f.try_create(commit_id, lang)?)));
+
+        match self.mode {
+            ResolverMode::Priority => resolvers.take(1).collect(),
+            ResolverMode::Union => resolvers.collect(),
+        }
+    }
+}
+
+/// Record that these deps were found by the resolver named `name`.
+fn tag(name: &'static str, deps: Vec<FileDep>) -> Vec<FileDep> {
+    deps.into_iter().map(|d| d.with_resolver(name)).collect()
+}
+
+/// Add a file to each of the given resolvers.
+///
+/// Resolvers are first offered the copy of the file on disk (if any) so the
+/// content is only read for those that need it. Files that cannot be read are
+/// skipped (see [DiagnosticStage::Read]).
+fn add_file<R: FileReader>(reader: &R, file: &FileKey, resolvers: &[&dyn Resolver]) {
+    let path = reader.path_on_disk(file);
+
+    let remaining = resolvers
+        .iter()
+        .filter(|r| path.as_ref().is_none_or(|p| !r.add_file_on_disk(file, p)))
+        .collect::<Vec<_>>();
+
+    if remaining.is_empty() {
+        return;
+    }
+
+    let content = match reader.read(file.content_id) {
+        Ok(content) => content,
+        Err(err) => {
+            log::warn!("Skipping {} as it could not be read: {}", file.filename, err);
+            return;
+        }
+    };
+    remaining.into_par_iter().for_each(|r| r.add_file(&file.filename, &content));
 }
 
 /// Group the given files by their version and language.
+///
+/// C files are grouped with C++ files because headers (which are given to C)
+/// are shared by both, so neither can be resolved without the other.
 fn to_map<'a>(files: &'a MultiFileSet) -> HashMap<(PseudoCommitId, Lang), Vec<&'a FileKey>> {
     let mut map: HashMap<_, Vec<_>> = HashMap::new();
 
     for (&commit_id, file_set) in files.iter() {
         for file in file_set.iter() {
             if let Some(lang) = Lang::of(&file.filename) {
+                let lang = if lang == Lang::C { Lang::Cpp } else { lang };
                 map.entry((commit_id, lang)).or_default().push(file);
             }
         }
@@ -0,0 +1,257 @@
+//! Export entities and deps as a SCIP index for code-navigation tools.
+//!
+//! See https://github.com/sourcegraph/scip. Each file becomes a document. Every
+//! other entity becomes a global symbol with a definition occurrence spanning
+//! its code, and every dep becomes a reference occurrence (in the file of its
+//! source) of the symbol of its target.
+//!
+//! Symbols are built from the names of an entity and its ancestors, so
+//! overloaded methods share a symbol. Definition ranges cover the whole entity
+//! rather than just its name, since the location of the name is not known. The
+//! messages below are written by hand to match the parts of scip.proto that are
+//! used and must keep the same field numbers.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use itertools::Itertools;
+
+use crate::core::Entity;
+use crate::core::EntityDep;
+use crate::core::EntityId;
+use crate::core::EntityKind;
+use crate::core::Span;
+use crate::languages::Lang;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Index {
+    #[prost(message, optional, tag = "1")]
+    pub metadata: Option<Metadata>,
+    #[prost(message, repeated, tag = "2")]
+    pub documents: Vec<Document>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Metadata {
+    #[prost(int32, tag = "1")]
+    pub version: i32,
+    #[prost(message, optional, tag = "2")]
+    pub tool_info: Option<ToolInfo>,
+    #[prost(string, tag = "3")]
+    pub project_root: String,
+    #[prost(int32, tag = "4")]
+    pub text_document_encoding: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ToolInfo {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub version: String,
+    #[prost(string, repeated, tag = "3")]
+    pub arguments: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Document {
+    #[prost(string, tag = "1")]
+    pub relative_path: String,
+    #[prost(message, repeated, tag = "2")]
+    pub occurrences: Vec<Occurrence>,
+    #[prost(message, repeated, tag = "3")]
+    pub symbols: Vec<SymbolInformation>,
+    #[prost(string, tag = "4")]
+    pub language: String,
+    #[prost(int32, tag = "6")]
+    pub position_encoding: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Occurrence {
+    #[prost(int32, repeated, tag = "1")]
+    pub range: Vec<i32>,
+    #[prost(string, tag = "2")]
+    pub symbol: String,
+    #[prost(int32, tag = "3")]
+    pub symbol_roles: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SymbolInformation {
+    #[prost(string, tag = "1")]
+    pub symbol: String,
+    #[prost(string, tag = "6")]
+    pub display_name: String,
+    #[prost(string, tag = "8")]
+    pub enclosing_symbol: String,
+}
+
+/// `TextEncoding.UTF8` in scip.proto.
+const TEXT_ENCODING_UTF8: i32 = 1;
+
+/// `PositionEncoding.UTF8CodeUnitOffsetFromLineStart` in scip.proto.
+const POSITION_ENCODING_UTF8: i32 = 1;
+
+/// `SymbolRole.Definition` in scip.proto.
+const SYMBOL_ROLE_DEFINITION: i32 = 1;
+
+/// Build an index of a single version of a project found at `root`.
+///
+/// `args` is the full command line (including the program name) recorded as
+/// the tool arguments.
+///
+/// Deps with an endpoint that is not among `entities` are skipped.
+pub fn build_index(root: &Path, args: &[String], entities: &[Entity], deps: &[EntityDep]) -> Index {
+    let by_id: HashMap<_, _> = entities.iter().map(|e| (e.id, e)).collect();
+    let symbols: HashMap<_, _> = entities.iter().map(|e| (e.id, symbol(&by_id, e))).collect();
+    let files: HashMap<_, _> = entities.iter().map(|e| (e.id, find_file(&by_id, e).id)).collect();
+    let mut documents: HashMap<EntityId, Document> = entities
+        .iter()
+        .filter(|e| e.kind.is_file())
+        .map(|e| (e.id, Document::new(&e.name)))
+        .collect();
+
+    for entity in entities.iter().filter(|e| !e.kind.is_file()) {
+        let Some(document) = documents.get_mut(&files[&entity.id]) else {
+            continue;
+        };
+
+        let enclosing_symbol = match entity.parent_id.and_then(|id| by_id.get(&id)) {
+            Some(parent) if !parent.kind.is_file() => symbols[&parent.id].clone(),
+            _ => String::new(),
+        };
+
+        document.occurrences.push(Occurrence {
+            range: to_range(entity.code),
+            symbol: symbols[&entity.id].clone(),
+            symbol_roles: SYMBOL_ROLE_DEFINITION,
+        });
+
+        document.symbols.push(SymbolInformation {
+            symbol: symbols[&entity.id].clone(),
+            display_name: entity.name.clone(),
+            enclosing_symbol,
+        });
+    }
+
+    for dep in deps.iter().filter(|d| by_id.contains_key(&d.src) && by_id.contains_key(&d.tgt)) {
+        let Some(document) = documents.get_mut(&files[&dep.src]) else {
+            continue;
+        };
+
+        let (row, column) = (dep.position.row() as i32, dep.position.column().unwrap_or(0) as i32);
+        document.occurrences.push(Occurrence {
+            range: vec![row, column, column],
+            symbol: symbols[&dep.tgt].clone(),
+            symbol_roles: 0,
+        });
+    }
+
+    let metadata = Metadata {
+        version: 0,
+        tool_info: Some(ToolInfo {
+            name: "neodepends".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            arguments: args.iter().skip(1).cloned().collect(),
+        }),
+        project_root: format!("file://{}", root.to_string_lossy()),
+        text_document_encoding: TEXT_ENCODING_UTF8,
+    };
+
+    let documents = documents.into_values().sorted_by(|a, b| a.relative_path.cmp(&b.relative_path));
+    Index { metadata: Some(metadata), documents: documents.collect() }
+}
+
+impl Document {
+    fn new(filename: &str) -> Self {
+        Self {
+            relative_path: filename.to_string(),
+            occurrences: Vec::new(),
+            symbols: Vec::new(),
+            language: Lang::of(filename).map(language).unwrap_or_default().to_string(),
+            position_encoding: POSITION_ENCODING_UTF8,
+        }
+    }
+}
+
+/// The name of a language as given by the `Language` enum of scip.proto.
+fn language(lang: Lang) -> &'static str {
+    match lang {
+        Lang::C => "C",
+        Lang::Cpp => "CPP",
+        Lang::Go => "Go",
+        Lang::Java => "Java",
+        Lang::JavaScript => "JavaScript",
+        Lang::Kotlin => "Kotlin",
+        Lang::Python => "Python",
+        Lang::Ruby => "Ruby",
+        Lang::TypeScript => "TypeScript",
+    }
+}
+
+/// Build a global symbol by appending a descriptor for each ancestor.
+///
+/// The components of a filename become namespaces (e.g. `src/Foo.java/`).
+fn symbol(by_id: &HashMap<EntityId, &Entity>, entity: &Entity) -> String {
+    let mut descriptors = Vec::new();
+    let mut curr = Some(entity);
+
+    while let Some(entity) = curr {
+        let name = &entity.name;
+
+        descriptors.push(match entity.kind {
+            EntityKind::File => name.split('/').map(|n| format!("{}/", escape(n))).join(""),
+            EntityKind::Method
+            | EntityKind::Constructor
+            | EntityKind::Function
+            | EntityKind::Lambda => format!("{}().", escape(name)),
+            EntityKind::Field => format!("{}.", escape(name)),
+            _ => format!("{}#", escape(name)),
+        });
+
+        // Packages (see --packages) are left out
+        curr = match entity.kind.is_file() {
+            true => None,
+            false => entity.parent_id.and_then(|id| by_id.get(&id).copied()),
+        };
+    }
+
+    format!("neodepends . . . {}", descriptors.into_iter().rev().join(""))
+}
+
+/// Quote a name with backticks unless it is a simple identifier.
+fn escape(name: &str) -> String {
+    if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || "_+-$".contains(c)) {
+        name.to_string()
+    } else {
+        format!("`{}`", name.replace('`', "``"))
+    }
+}
+
+/// Find the file that contains an entity (or the entity itself).
+fn find_file<'a>(by_id: &HashMap<EntityId, &'a Entity>, entity: &'a Entity) -> &'a Entity {
+    let mut curr = entity;
+
+    while let Some(parent) = curr.parent_id.and_then(|id| by_id.get(&id)) {
+        // Files may be inside packages (see --packages)
+        if curr.kind.is_file() {
+            break;
+        }
+        curr = parent;
+    }
+
+    curr
+}
+
+/// Convert a span into a SCIP range, which has three elements if the span
+/// starts and ends on the same line.
+fn to_range(span: Span) -> Vec<i32> {
+    let (start, end) = (span.start, span.end);
+
+    if start.row == end.row {
+        vec![start.row as i32, start.column as i32, end.column as i32]
+    } else {
+        vec![start.row as i32, start.column as i32, end.row as i32, end.column as i32]
+    }
+}
@@ -0,0 +1,261 @@
+//! Run the records of every resource through a user-provided script.
+//!
+//! Scripts are written in Rhai (see https://rhai.rs). A script may define a
+//! function for any resource, each taking a single record as an object map
+//! with the same fields as the jsonl output:
+//!
+//! ```text
+//! fn entity(e) { e.kind != "Field" }
+//! fn dep(d) { d.kind = "Use"; d }
+//! ```
+//!
+//! A hook returns `true` to keep the record, `false` (or nothing) to drop it,
+//! or a map to write in its place. Resources without a hook pass through
+//! untouched.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use rhai::Dynamic;
+use rhai::Engine;
+use rhai::Scope;
+use rhai::AST;
+use serde::Serialize;
+
+use crate::core::Alignment;
+use crate::core::Annotation;
+use crate::core::Change;
+use crate::core::Commit;
+use crate::core::CommitFile;
+use crate::core::Content;
+use crate::core::DepChange;
+use crate::core::Diagnostic;
+use crate::core::Entity;
+use crate::core::EntityDep;
+use crate::core::EntityMatch;
+use crate::core::ExternalDep;
+use crate::core::Metric;
+use crate::core::Revspec;
+use crate::core::Supertype;
+use crate::core::UnresolvedRef;
+use crate::output::EntityDepRow;
+use crate::output::EntityRow;
+use crate::output::Resource;
+use crate::output::Writer;
+use crate::taxonomy::Taxonomy;
+
+/// What a hook decided to do with a record.
+enum Outcome {
+    Keep,
+    Drop,
+    Replace(serde_json::Value),
+}
+
+/// A [Writer] that passes each record through the hooks of a script before
+/// handing it to another writer.
+pub struct ScriptedWriter {
+    inner: Box<dyn Writer + Sync>,
+    engine: Engine,
+    ast: AST,
+    hooks: HashSet<String>,
+    taxonomy: Taxonomy,
+}
+
+impl ScriptedWriter {
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        inner: Box<dyn Writer + Sync>,
+        taxonomy: Taxonomy,
+    ) -> Result<Self> {
+        let engine = Engine::new();
+        let ast = engine
+            .compile_file(path.as_ref().to_path_buf())
+            .with_context(|| format!("failed to compile {}", path.as_ref().display()))?;
+        let hooks = ast
+            .iter_functions()
+            .filter(|f| f.params.len() == 1)
+            .map(|f| f.name.to_string())
+            .collect();
+        Ok(Self { inner, engine, ast, hooks, taxonomy })
+    }
+
+    /// Call the hook of `resource` on the record built by `row`.
+    ///
+    /// The record is only built if the script defines the hook.
+    fn call<S: Serialize, F: FnOnce() -> S>(&self, resource: Resource, row: F) -> Result<Outcome> {
+        let name = resource.singular();
+
+        if !self.hooks.contains(name) {
+            return Ok(Outcome::Keep);
+        }
+
+        let arg = rhai::serde::to_dynamic(row())?;
+        let result: Dynamic = self
+            .engine
+            .call_fn(&mut Scope::new(), &self.ast, name, (arg,))
+            .with_context(|| format!("failed to run {}() of --post-script", name))?;
+
+        if result.is_unit() {
+            Ok(Outcome::Drop)
+        } else if let Ok(keep) = result.as_bool() {
+            Ok(if keep { Outcome::Keep } else { Outcome::Drop })
+        } else if result.is_map() {
+            Ok(Outcome::Replace(rhai::serde::from_dynamic(&result)?))
+        } else {
+            bail!(
+                "{}() of --post-script returned a {}, not a bool or map",
+                name,
+                result.type_name()
+            )
+        }
+    }
+}
+
+impl Writer for ScriptedWriter {
+    fn supports(&self, resource: Resource) -> bool {
+        self.inner.supports(resource)
+    }
+
+    fn is_single_structure(&self) -> bool {
+        self.inner.is_single_structure()
+    }
+
+    fn write_entity(&self, value: Entity) -> Result<()> {
+        match self.call(Resource::Entities, || EntityRow::from(value.clone()))? {
+            Outcome::Keep => self.inner.write_entity(value),
+            Outcome::Drop => Ok(()),
+            Outcome::Replace(v) => self.inner.write_value(Resource::Entities, v),
+        }
+    }
+
+    fn write_dep(&self, value: EntityDep) -> Result<()> {
+        match self.call(Resource::Deps, || EntityDepRow::from(value.clone(), &self.taxonomy))? {
+            Outcome::Keep => self.inner.write_dep(value),
+            Outcome::Drop => Ok(()),
+            Outcome::Replace(v) => self.inner.write_value(Resource::Deps, v),
+        }
+    }
+
+    fn write_change(&self, value: Change) -> Result<()> {
+        match self.call(Resource::Changes, || &value)? {
+            Outcome::Keep => self.inner.write_change(value),
+            Outcome::Drop => Ok(()),
+            Outcome::Replace(v) => self.inner.write_value(Resource::Changes, v),
+        }
+    }
+
+    fn write_content(&self, value: Content) -> Result<()> {
+        match self.call(Resource::Contents, || &value)? {
+            Outcome::Keep => self.inner.write_content(value),
+            Outcome::Drop => Ok(()),
+            Outcome::Replace(v) => self.inner.write_value(Resource::Contents, v),
+        }
+    }
+
+    fn write_dep_change(&self, value: DepChange) -> Result<()> {
+        match self.call(Resource::DepChanges, || &value)? {
+            Outcome::Keep => self.inner.write_dep_change(value),
+            Outcome::Drop => Ok(()),
+            Outcome::Replace(v) => self.inner.write_value(Resource::DepChanges, v),
+        }
+    }
+
+    fn write_alignment(&self, value: Alignment) -> Result<()> {
+        match self.call(Resource::Alignments, || &value)? {
+            Outcome::Keep => self.inner.write_alignment(value),
+            Outcome::Drop => Ok(()),
+            Outcome::Replace(v) => self.inner.write_value(Resource::Alignments, v),
+        }
+    }
+
+    fn write_revspec(&self, value: Revspec) -> Result<()> {
+        match self.call(Resource::Revspecs, || &value)? {
+            Outcome::Keep => self.inner.write_revspec(value),
+            Outcome::Drop => Ok(()),
+            Outcome::Replace(v) => self.inner.write_value(Resource::Revspecs, v),
+        }
+    }
+
+    fn write_unresolved_ref(&self, value: UnresolvedRef) -> Result<()> {
+        match self.call(Resource::UnresolvedRefs, || &value)? {
+            Outcome::Keep => self.inner.write_unresolved_ref(value),
+            Outcome::Drop => Ok(()),
+            Outcome::Replace(v) => self.inner.write_value(Resource::UnresolvedRefs, v),
+        }
+    }
+
+    fn write_entity_match(&self, value: EntityMatch) -> Result<()> {
+        match self.call(Resource::EntityMatches, || &value)? {
+            Outcome::Keep => self.inner.write_entity_match(value),
+            Outcome::Drop => Ok(()),
+            Outcome::Replace(v) => self.inner.write_value(Resource::EntityMatches, v),
+        }
+    }
+
+    fn write_metric(&self, value: Metric) -> Result<()> {
+        match self.call(Resource::Metrics, || &value)? {
+            Outcome::Keep => self.inner.write_metric(value),
+            Outcome::Drop => Ok(()),
+            Outcome::Replace(v) => self.inner.write_value(Resource::Metrics, v),
+        }
+    }
+
+    fn write_external_dep(&self, value: ExternalDep) -> Result<()> {
+        match self.call(Resource::ExternalDeps, || &value)? {
+            Outcome::Keep => self.inner.write_external_dep(value),
+            Outcome::Drop => Ok(()),
+            Outcome::Replace(v) => self.inner.write_value(Resource::ExternalDeps, v),
+        }
+    }
+
+    fn write_supertype(&self, value: Supertype) -> Result<()> {
+        match self.call(Resource::Hierarchy, || &value)? {
+            Outcome::Keep => self.inner.write_supertype(value),
+            Outcome::Drop => Ok(()),
+            Outcome::Replace(v) => self.inner.write_value(Resource::Hierarchy, v),
+        }
+    }
+
+    fn write_diagnostic(&self, value: Diagnostic) -> Result<()> {
+        match self.call(Resource::Diagnostics, || &value)? {
+            Outcome::Keep => self.inner.write_diagnostic(value),
+            Outcome::Drop => Ok(()),
+            Outcome::Replace(v) => self.inner.write_value(Resource::Diagnostics, v),
+        }
+    }
+
+    fn write_annotation(&self, value: Annotation) -> Result<()> {
+        match self.call(Resource::Annotations, || &value)? {
+            Outcome::Keep => self.inner.write_annotation(value),
+            Outcome::Drop => Ok(()),
+            Outcome::Replace(v) => self.inner.write_value(Resource::Annotations, v),
+        }
+    }
+
+    fn write_file(&self, value: CommitFile) -> Result<()> {
+        match self.call(Resource::Files, || &value)? {
+            Outcome::Keep => self.inner.write_file(value),
+            Outcome::Drop => Ok(()),
+            Outcome::Replace(v) => self.inner.write_value(Resource::Files, v),
+        }
+    }
+
+    fn write_commit(&self, value: Commit) -> Result<()> {
+        match self.call(Resource::Commits, || &value)? {
+            Outcome::Keep => self.inner.write_commit(value),
+            Outcome::Drop => Ok(()),
+            Outcome::Replace(v) => self.inner.write_value(Resource::Commits, v),
+        }
+    }
+
+    fn write_value(&self, resource: Resource, value: serde_json::Value) -> Result<()> {
+        self.inner.write_value(resource, value)
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.inner.finalize()
+    }
+}
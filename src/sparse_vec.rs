@@ -98,11 +98,12 @@ impl<T: Copy + Eq + Hash> SparseVec<T> {
         let entry_z = self.entries[indices.end - 1];
 
         let replacements = [
-            (entry_a.key.start, start - 1, entry_a.value),
-            (start, end, value),
-            (end + 1, entry_z.key.end, entry_z.value),
+            start.checked_sub(1).map(|before| (entry_a.key.start, before, entry_a.value)),
+            Some((start, end, value)),
+            end.checked_add(1).map(|after| (after, entry_z.key.end, entry_z.value)),
         ]
         .into_iter()
+        .flatten()
         .filter_map(Entry::try_from_triple);
 
         self.entries.splice(indices, replacements);
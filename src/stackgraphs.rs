@@ -5,10 +5,16 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fmt::Debug;
+use std::ops::Range;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::RwLock;
 
 use anyhow::bail;
+use anyhow::Result;
+use itertools::Itertools;
+use sha2::Digest;
+use sha2::Sha256;
 use stack_graphs::arena::Handle;
 use stack_graphs::graph::Node;
 use stack_graphs::graph::StackGraph;
@@ -24,29 +30,51 @@ use tree_sitter_stack_graphs::StackGraphLanguage;
 
 use crate::core::Dep;
 use crate::core::DepKind;
+use crate::core::Diagnostic;
+use crate::core::DiagnosticStage;
 use crate::core::FileDep;
 use crate::core::FileEndpoint;
 use crate::core::FileKey;
 use crate::core::PartialPosition;
 use crate::core::PseudoCommitId;
 use crate::core::Span;
+use crate::core::UnresolvedRef;
 use crate::languages::Lang;
 use crate::resolution::Resolver;
 use crate::resolution::ResolverFactory;
+use crate::storage::Storage;
+use crate::tagging::find_dep_kind;
+use crate::tagging::DepTagger;
 
 /// A Stack Graphs resolver.
 ///
 /// See [Resolver].
 pub struct StackGraphsResolver {
     commit_id: PseudoCommitId,
+    lang: Lang,
     sgl: Arc<StackGraphLanguage>,
     cache: Arc<SgCache>,
+    recipe: String,
     files: RwLock<HashSet<FileKey>>,
+    unresolved: Mutex<Vec<UnresolvedRef>>,
 }
 
 impl StackGraphsResolver {
-    fn new(commit_id: PseudoCommitId, sgl: Arc<StackGraphLanguage>, cache: Arc<SgCache>) -> Self {
-        Self { commit_id, sgl, cache, files: Default::default() }
+    fn new(
+        commit_id: PseudoCommitId,
+        lang: Lang,
+        sgl: Arc<StackGraphLanguage>,
+        cache: Arc<SgCache>,
+    ) -> Self {
+        Self {
+            commit_id,
+            lang,
+            sgl,
+            cache,
+            recipe: recipe(lang),
+            files: Default::default(),
+            unresolved: Default::default(),
+        }
     }
 }
 
@@ -55,16 +83,47 @@ impl Resolver for StackGraphsResolver {
         let file = FileKey::from_content(filename.to_string(), content);
 
         if !self.cache.contains(&file) {
-            self.cache.insert(file.clone(), build(&self.sgl, filename, content));
+            match self.cache.load(&file, &self.recipe) {
+                Some(data) => self.cache.insert(file.clone(), data),
+                None => {
+                    let data = build(&self.sgl, self.lang.dep_tagger(), filename, content);
+                    self.cache.store(&file, &self.recipe, &data);
+                    self.cache.insert(file.clone(), data);
+                }
+            }
         }
 
         self.files.write().unwrap().insert(file);
     }
 
-    fn resolve(&self) -> Vec<FileDep> {
+    fn resolve(&self) -> Result<Vec<FileDep>> {
         let files = self.files.read().unwrap();
         let data = files.iter().filter_map(|f| self.cache.get(f).unwrap());
-        resolve(data, self.commit_id)
+        let (deps, unresolved) = resolve(data, self.commit_id);
+        *self.unresolved.lock().unwrap() = unresolved;
+        Ok(deps)
+    }
+
+    fn unresolved(&self) -> Vec<UnresolvedRef> {
+        self.unresolved.lock().unwrap().clone()
+    }
+
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        let files = self.files.read().unwrap();
+        files
+            .iter()
+            .filter(|f| self.cache.failed(f))
+            .sorted()
+            .map(|f| {
+                let message = "failed to build the stack graph".to_string();
+                Diagnostic::new(
+                    Some(f.filename.clone()),
+                    self.commit_id,
+                    DiagnosticStage::Resolve,
+                    message,
+                )
+            })
+            .collect()
     }
 }
 
@@ -72,6 +131,7 @@ impl Debug for StackGraphsResolver {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("StackGraphsResolver")
             .field("commit_id", &self.commit_id)
+            .field("lang", &self.lang)
             .field("tsg_path", &self.sgl.tsg_path())
             .field("cache", &self.cache)
             .field("files", &self.files)
@@ -88,29 +148,85 @@ pub struct StackGraphsResolverFactory {
 }
 
 impl StackGraphsResolverFactory {
-    pub fn new() -> Self {
-        Self { cache: Arc::new(SgCache::new()) }
+    /// Create a factory whose stack graphs are also kept in `storage` (if
+    /// given), so later runs do not have to build them again.
+    pub fn new(storage: Option<Arc<Storage>>) -> Self {
+        Self { cache: Arc::new(SgCache::new(storage)) }
     }
 }
 
 impl ResolverFactory for StackGraphsResolverFactory {
+    fn name(&self) -> &'static str {
+        "stackgraphs"
+    }
+
+    fn supports(&self, lang: Lang) -> bool {
+        lang.sgl().is_some()
+    }
+
     fn try_create(&self, commit_id: PseudoCommitId, lang: Lang) -> Option<Box<dyn Resolver>> {
         lang.sgl().map(|sgl| {
-            Box::new(StackGraphsResolver::new(commit_id, sgl, self.cache.clone()))
+            Box::new(StackGraphsResolver::new(commit_id, lang, sgl, self.cache.clone()))
                 as Box<dyn Resolver>
         })
     }
 }
 
 /// Used to avoid duplicate stack graph calculations.
+///
+/// Stack graphs are kept in memory for the length of a run and, if there is a
+/// [Storage], on disk between runs. Any failure to use the storage is logged
+/// and otherwise ignored, as the stack graph can always be built again.
 #[derive(Debug)]
 struct SgCache {
     map: RwLock<HashMap<FileKey, Option<StackGraphData>>>,
+    storage: Option<Arc<Storage>>,
 }
 
 impl SgCache {
-    fn new() -> Self {
-        Self { map: Default::default() }
+    fn new(storage: Option<Arc<Storage>>) -> Self {
+        Self { map: Default::default(), storage }
+    }
+
+    /// Load a stack graph (or the failure to build one) from the storage.
+    ///
+    /// Stack graphs are stored by filename and not just content, as the name
+    /// of the file is part of the graph.
+    fn load(&self, key: &FileKey, recipe: &str) -> Option<Option<StackGraphData>> {
+        let data = match self.storage.as_ref()?.get("stackgraph", key, recipe) {
+            Ok(Some(Some(bytes))) => StackGraphData::decode(key.clone(), &bytes).map(Some),
+            Ok(Some(None)) => Ok(None),
+            Ok(None) => return None,
+            Err(e) => Err(e),
+        };
+
+        match data {
+            Ok(data) => Some(data),
+            Err(e) => {
+                log::warn!(
+                    "Failed to load the stack graph of {} from the cache: {}",
+                    key.filename,
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    fn store(&self, key: &FileKey, recipe: &str, data: &Option<StackGraphData>) {
+        let Some(storage) = &self.storage else {
+            return;
+        };
+
+        let result = data
+            .as_ref()
+            .map(|d| d.encode())
+            .transpose()
+            .and_then(|b| storage.put("stackgraph", key, recipe, b.as_deref()));
+
+        if let Err(e) = result {
+            log::warn!("Failed to store the stack graph of {} in the cache: {}", key.filename, e);
+        }
     }
 
     fn contains(&self, key: &FileKey) -> bool {
@@ -121,6 +237,11 @@ impl SgCache {
         self.map.read().unwrap().get(key).cloned()
     }
 
+    /// Did the stack graph of this file fail to build?
+    fn failed(&self, key: &FileKey) -> bool {
+        self.map.read().unwrap().get(key).is_some_and(|d| d.is_none())
+    }
+
     fn insert(&self, key: FileKey, value: Option<StackGraphData>) {
         self.map.write().unwrap().insert(key, value);
     }
@@ -129,12 +250,14 @@ impl SgCache {
 /// A stack graph representation that is able to be cloned and moved around for
 /// caching purposes.
 ///
-/// Intended to contain the stack graph of a single file.
+/// Intended to contain the stack graph of a single file. Also holds the ranges
+/// found by the [DepTagger] of the language of the file (if it has one).
 #[derive(Debug, Clone)]
 struct StackGraphData {
     file_key: FileKey,
     graph: stack_graphs::serde::StackGraph,
     paths: Vec<stack_graphs::serde::PartialPath>,
+    dep_kinds: Vec<(Range<usize>, DepKind)>,
 }
 
 impl StackGraphData {
@@ -143,14 +266,53 @@ impl StackGraphData {
         graph: StackGraph,
         mut partials: PartialPaths,
         paths: Vec<PartialPath>,
+        dep_kinds: Vec<(Range<usize>, DepKind)>,
     ) -> Self {
         let paths = paths
             .iter()
             .map(|p| stack_graphs::serde::PartialPath::from_partial_path(&graph, &mut partials, p))
             .collect::<Vec<_>>();
         let graph = stack_graphs::serde::StackGraph::from_graph(&graph);
-        Self { file_key, graph, paths }
+        Self { file_key, graph, paths, dep_kinds }
+    }
+
+    fn encode(&self) -> Result<Vec<u8>> {
+        let dep_kinds =
+            self.dep_kinds.iter().map(|(r, k)| (r.start, r.end, k.to_string())).collect_vec();
+        let value = (&self.graph, &self.paths, dep_kinds);
+        Ok(bincode::encode_to_vec(value, bincode::config::standard())?)
+    }
+
+    fn decode(file_key: FileKey, bytes: &[u8]) -> Result<Self> {
+        type Value = (
+            stack_graphs::serde::StackGraph,
+            Vec<stack_graphs::serde::PartialPath>,
+            Vec<(usize, usize, String)>,
+        );
+        let ((graph, paths, dep_kinds), _): (Value, _) =
+            bincode::decode_from_slice(bytes, bincode::config::standard())?;
+        let dep_kinds = dep_kinds
+            .into_iter()
+            .map(|(start, end, k)| Ok((start..end, DepKind::try_from(k.as_str())?)))
+            .collect::<Result<_>>()?;
+        Ok(Self { file_key, graph, paths, dep_kinds })
+    }
+}
+
+/// A hash of everything that the stack graph of a file of `lang` depends on
+/// besides the file itself.
+fn recipe(lang: Lang) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(env!("CARGO_PKG_VERSION"));
+
+    for (filename, query) in lang.queries() {
+        if matches!(*filename, "stack-graphs.tsg" | "deps.scm") {
+            hasher.update(filename);
+            hasher.update(query);
+        }
     }
+
+    hex::encode(hasher.finalize())
 }
 
 /// A stack graph representation that can be used to resolve dependencies.
@@ -158,6 +320,7 @@ impl StackGraphData {
 /// Intended to contain the stack graphs of many files.
 struct StackGraphEval {
     file_keys: HashMap<String, FileKey>,
+    dep_kinds: HashMap<String, Vec<(Range<usize>, DepKind)>>,
     graph: StackGraph,
     partials: PartialPaths,
     paths: Vec<PartialPath>,
@@ -169,6 +332,7 @@ impl StackGraphEval {
         I: IntoIterator<Item = StackGraphData>,
     {
         let mut file_keys = HashMap::new();
+        let mut dep_kinds = HashMap::new();
         let mut graph = StackGraph::new();
         let mut partials = PartialPaths::new();
         let mut paths = Vec::new();
@@ -179,6 +343,7 @@ impl StackGraphEval {
             }
 
             file_keys.insert(portable.file_key.filename.clone(), portable.file_key.clone());
+            dep_kinds.insert(portable.file_key.filename.clone(), portable.dep_kinds);
             portable.graph.load_into(&mut graph)?;
 
             for path in &portable.paths {
@@ -186,14 +351,19 @@ impl StackGraphEval {
             }
         }
 
-        Ok(StackGraphEval { file_keys, graph, partials, paths })
+        Ok(StackGraphEval { file_keys, dep_kinds, graph, partials, paths })
     }
 }
 
 /// Attempt to build a stack graph from a source file.
 ///
 /// Returns None if a stack graph could not be built.
-fn build(sgl: &StackGraphLanguage, filename: &str, content: &str) -> Option<StackGraphData> {
+fn build(
+    sgl: &StackGraphLanguage,
+    dep_tagger: Option<&DepTagger>,
+    filename: &str,
+    content: &str,
+) -> Option<StackGraphData> {
     let mut graph = StackGraph::new();
     let mut partials = PartialPaths::new();
     let mut paths = Vec::new();
@@ -216,11 +386,14 @@ fn build(sgl: &StackGraphLanguage, filename: &str, content: &str) -> Option<Stac
     )
     .ok()?;
 
-    Some(StackGraphData::new(file_key, graph, partials, paths))
+    let dep_kinds = dep_tagger.map(|t| t.tag(content)).unwrap_or_default();
+    Some(StackGraphData::new(file_key, graph, partials, paths, dep_kinds))
 }
 
 /// Resolve file-level dependencies given for a collection of files.
-fn resolve<I>(data: I, commit_id: PseudoCommitId) -> Vec<FileDep>
+///
+/// Also returns each reference that could not be stitched to a definition.
+fn resolve<I>(data: I, commit_id: PseudoCommitId) -> (Vec<FileDep>, Vec<UnresolvedRef>)
 where
     I: IntoIterator<Item = StackGraphData>,
 {
@@ -248,18 +421,36 @@ where
     let position = |n: Handle<Node>| {
         PartialPosition::Whole(Span::from_lsp(&eval.graph.source_info(n).unwrap().span).start)
     };
+    let kind = |n: Handle<Node>, position: PartialPosition| {
+        let ranges = eval.dep_kinds.get(&filename(n))?;
+        find_dep_kind(ranges, position.byte()?)
+    };
 
-    references
+    let resolved: HashSet<_> = references.iter().map(|r| r.start_node).collect();
+    let unresolved = eval
+        .graph
+        .iter_nodes()
+        .filter(|&n| eval.graph[n].is_reference() && !resolved.contains(&n))
+        .filter_map(|n| {
+            let start = Span::from_lsp(&eval.graph.source_info(n)?.span).start;
+            let text = eval.graph[eval.graph[n].symbol()?].to_string();
+            Some(UnresolvedRef::new(filename(n), start.row, start.column, text, commit_id))
+        })
+        .collect();
+
+    let deps = references
         .into_iter()
         .map(|r| {
             let start_node_pos = position(r.start_node);
             Dep::new(
                 FileEndpoint::new(file_key(r.start_node), start_node_pos),
                 FileEndpoint::new(file_key(r.end_node), position(r.end_node)),
-                DepKind::Use,
+                kind(r.start_node, start_node_pos).unwrap_or(DepKind::Use),
                 start_node_pos,
                 commit_id,
             )
         })
-        .collect()
+        .collect();
+
+    (deps, unresolved)
 }
@@ -0,0 +1,98 @@
+//! Summarize which languages make up a project.
+//!
+//! Intended as a quick sanity check that a pathspec selects the files a user
+//! expects. The output mimics [linguist](https://github.com/github-linguist/linguist).
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use anyhow::Result;
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use crate::filesystem::FileSystem;
+use crate::languages::Lang;
+use crate::spec::Filespec;
+
+/// The number of files and bytes written in a particular language.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LangStats {
+    pub lang: Lang,
+    pub files: usize,
+    pub bytes: usize,
+}
+
+/// Count the files and bytes of each language reachable from a [Filespec].
+///
+/// Files that do not belong to a supported language are ignored. The results
+/// are sorted by decreasing size.
+pub fn lang_stats(fs: &FileSystem, spec: &Filespec) -> Result<Vec<LangStats>> {
+    let files = fs.list(spec).into_files();
+
+    let sizes = files
+        .into_par_iter()
+        .filter_map(|f| Lang::of(&f.filename).map(|l| (l, f.content_id)))
+        .map(|(lang, content_id)| Ok((lang, fs.size(content_id)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let stats = sizes
+        .into_iter()
+        .into_group_map()
+        .into_iter()
+        .map(|(lang, sizes)| LangStats { lang, files: sizes.len(), bytes: sizes.iter().sum() })
+        .sorted_by(|a, b| b.bytes.cmp(&a.bytes).then(a.lang.cmp(&b.lang)))
+        .collect();
+
+    Ok(stats)
+}
+
+/// Render stats as a plain-text table in the style of `github-linguist`.
+pub fn to_text(stats: &[LangStats]) -> String {
+    let total = stats.iter().map(|s| s.bytes).sum();
+    let mut text = String::new();
+
+    for s in stats {
+        let percentage = format!("{:.2}%", percentage(s.bytes, total));
+        let name = s.lang.linguist_name();
+        writeln!(text, "{:<8}{:<12}{:<8}{}", percentage, s.bytes, s.files, name).unwrap();
+    }
+
+    text
+}
+
+/// Render stats as JSON in the style of `github-linguist --json`.
+///
+/// Each language maps to its size in bytes, its number of files, and its
+/// percentage of the total size.
+pub fn to_json(stats: &[LangStats]) -> String {
+    let total = stats.iter().map(|s| s.bytes).sum();
+
+    let map: BTreeMap<_, _> = stats
+        .iter()
+        .map(|s| {
+            let row = JsonRow {
+                size: s.bytes,
+                files: s.files,
+                percentage: format!("{:.2}", percentage(s.bytes, total)),
+            };
+            (s.lang.linguist_name(), row)
+        })
+        .collect();
+
+    serde_json::to_string_pretty(&map).unwrap()
+}
+
+#[derive(Debug)]
+#[derive(serde::Serialize)]
+struct JsonRow {
+    size: usize,
+    files: usize,
+    percentage: String,
+}
+
+fn percentage(part: usize, total: usize) -> f64 {
+    match total {
+        0 => 0.0,
+        _ => 100.0 * part as f64 / total as f64,
+    }
+}
@@ -0,0 +1,84 @@
+//! Keep the results of expensive per-file work between runs.
+//!
+//! Scanning many versions of a project mostly sees files that were already
+//! scanned, either earlier in the same run or in a previous one. A [Storage]
+//! is a SQLite database under `--cache-dir` that maps a file and a "recipe" (a
+//! hash of everything that the result depends on, like the rules that were
+//! used) to the bytes of the result. A result of None (e.g. the file could not
+//! be parsed) is kept as well, so that it is not retried.
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::Context;
+use anyhow::Result;
+use rusqlite::params;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+
+use crate::core::FileKey;
+
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    PRAGMA synchronous = NORMAL;
+
+    CREATE TABLE IF NOT EXISTS results (
+        kind TEXT NOT NULL,
+        filename TEXT NOT NULL,
+        content_id BLOB NOT NULL,
+        recipe TEXT NOT NULL,
+        value BLOB,
+        PRIMARY KEY (kind, filename, content_id, recipe)
+    ) WITHOUT ROWID;
+";
+
+/// A persistent cache of the results of per-file work.
+///
+/// Safe to share between threads and between processes.
+#[derive(Debug)]
+pub struct Storage {
+    conn: Mutex<Connection>,
+}
+
+impl Storage {
+    /// Open (or create) the storage in `dir`.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir).context("failed to create cache directory")?;
+        let path = dir.join("cache.sqlite");
+        let conn = Connection::open(&path)
+            .with_context(|| format!("failed to open cache at {}", path.display()))?;
+        conn.busy_timeout(std::time::Duration::from_secs(60))?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    /// Get the result of `kind` for a file, if there is one.
+    ///
+    /// The outer option is None if no result was stored.
+    pub fn get(&self, kind: &str, file: &FileKey, recipe: &str) -> Result<Option<Option<Vec<u8>>>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(
+            "SELECT value FROM results
+             WHERE kind = ? AND filename = ? AND content_id = ? AND recipe = ?",
+        )?;
+        let value = stmt
+            .query_row(params![kind, &file.filename, &file.content_id, recipe], |r| r.get(0))
+            .optional()?;
+        Ok(value)
+    }
+
+    /// Store the result of `kind` for a file, replacing any that was there.
+    pub fn put(
+        &self,
+        kind: &str,
+        file: &FileKey,
+        recipe: &str,
+        value: Option<&[u8]>,
+    ) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.prepare_cached("INSERT OR REPLACE INTO results VALUES (?, ?, ?, ?, ?)")?
+            .execute(params![kind, &file.filename, &file.content_id, recipe, value])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+//! Hide coupling that has already been reviewed and accepted.
+//!
+//! A suppressions file has one rule per line of the form `SRC -> TGT`, where
+//! `SRC` and `TGT` are pathspecs matched against the path of each entity (e.g.
+//! `src/Foo.java/Foo/bar`). Since a pathspec also matches everything beneath
+//! it, a rule like `src/legacy -> src/core` suppresses every dep from an entity
+//! in `src/legacy` to an entity in `src/core`. Blank lines and lines starting
+//! with `#` are ignored.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::path::Path;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::spec::Pathspec;
+
+#[derive(Debug, Clone, Default)]
+pub struct Suppressions {
+    rules: Vec<(Pathspec, Pathspec)>,
+}
+
+impl Suppressions {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read suppressions from {}", path.display()))?;
+        let mut rules = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((src, tgt)) = line.split_once("->") else {
+                bail!("expected 'SRC -> TGT' ({}:{})", path.display(), i + 1);
+            };
+
+            let parse = |s: &str| {
+                Pathspec::try_from_vec(vec![s.trim().to_string()])
+                    .with_context(|| format!("invalid pattern ({}:{})", path.display(), i + 1))
+            };
+
+            rules.push((parse(src)?, parse(tgt)?));
+        }
+
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Is coupling from `src` to `tgt` suppressed?
+    ///
+    /// `paths` maps each entity to its path. Entities without a path are never
+    /// suppressed.
+    pub fn suppresses<K: Eq + Hash>(&self, paths: &HashMap<K, String>, src: &K, tgt: &K) -> bool {
+        let (Some(src), Some(tgt)) = (paths.get(src), paths.get(tgt)) else {
+            return false;
+        };
+
+        self.rules.iter().any(|(s, t)| s.matches(src) && t.matches(tgt))
+    }
+}
@@ -0,0 +1,38 @@
+//! Render small plain-text tables for the terminal.
+
+use std::fmt::Write;
+
+/// Render a table with left-aligned columns separated by two spaces.
+///
+/// Every row should have the same number of cells as the header.
+pub fn render(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths = header.iter().map(|h| h.len()).collect::<Vec<_>>();
+
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = usize::max(*width, cell.len());
+        }
+    }
+
+    let mut text = String::new();
+    write_row(&mut text, &widths, header.iter().copied());
+
+    for row in rows {
+        write_row(&mut text, &widths, row.iter().map(|c| c.as_str()));
+    }
+
+    text
+}
+
+/// Render a boolean cell as "yes" or "-".
+pub fn yes_or_no(value: bool) -> String {
+    match value {
+        true => "yes".to_string(),
+        false => "-".to_string(),
+    }
+}
+
+fn write_row<'a, I: Iterator<Item = &'a str>>(text: &mut String, widths: &[usize], cells: I) {
+    let line = cells.zip(widths).map(|(c, &w)| format!("{:<w$}", c, w = w)).collect::<Vec<_>>();
+    writeln!(text, "{}", line.join("  ").trim_end()).unwrap();
+}
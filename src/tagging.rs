@@ -1,6 +1,9 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::ops::Range;
 
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 use counter::Counter;
@@ -10,36 +13,66 @@ use tree_sitter::Node;
 use tree_sitter::Parser;
 use tree_sitter::Query;
 use tree_sitter::QueryCursor;
+use tree_sitter::Tree;
 
 use crate::core::ContentId;
-use crate::core::Dep;
+use crate::core::DepKind;
+use crate::core::DepLocation;
 use crate::core::Entity;
+use crate::core::EntityContentId;
 use crate::core::EntityDep;
 use crate::core::EntityId;
 use crate::core::EntityKind;
 use crate::core::FileDep;
+use crate::core::FileEndpoint;
 use crate::core::FileKey;
 use crate::core::PartialPosition;
 use crate::core::PartialSpan;
 use crate::core::Position;
 use crate::core::SimpleEntityId;
 use crate::core::Span;
+use crate::languages::Lang;
 use crate::sparse_vec::SparseVec;
 
+thread_local! {
+    /// Each thread keeps one of each around for all the files it tags rather
+    /// than creating them per file. Creating them was not a measurable cost
+    /// (parsing and query matching dominate), but reusing them is no harder.
+    static PARSER: RefCell<Parser> = RefCell::new(Parser::new());
+    static CURSOR: RefCell<QueryCursor> = RefCell::new(QueryCursor::new());
+}
+
+/// Parse `content` with the parser of this thread.
+pub fn parse(language: Language, content: &str) -> Result<Tree> {
+    PARSER.with(|parser| {
+        let mut parser = parser.borrow_mut();
+        parser.set_language(language)?;
+        parser.parse(content, None).context("failed to parse")
+    })
+}
+
+/// Call `f` with the query cursor of this thread.
+fn with_cursor<T, F: FnOnce(&mut QueryCursor) -> T>(f: F) -> T {
+    CURSOR.with(|cursor| f(&mut cursor.borrow_mut()))
+}
+
 /// The ordered collection of entities contained within a particular [FileKey].
 #[derive(Debug, Clone)]
 pub struct EntitySet {
     entities: HashMap<EntityId, Entity>,
     table: LocationTable,
+    names: HashMap<String, Vec<EntityId>>,
 }
 
 impl EntitySet {
     /// Create an [EntitySet] from a topologically ordered list of [Entity]s.
     ///
     /// In particular, an entity must appear later in the list than its parent.
-    fn from_topo_vec(tags: Vec<Entity>) -> Self {
+    fn from_topo_vec(mut tags: Vec<Entity>) -> Self {
+        assign_ordinals(&mut tags);
         let table = LocationTable::from_topo_slice(&tags);
-        Self { entities: tags.into_iter().map(|e| (e.id, e)).collect(), table }
+        let names = index_names(&tags);
+        Self { entities: tags.into_iter().map(|e| (e.id, e)).collect(), table, names }
     }
 
     pub fn into_entities_vec(self) -> Vec<Entity> {
@@ -48,10 +81,77 @@ impl EntitySet {
         self.entities.into_values().sorted_by_key(|e| indices[&e.id]).collect()
     }
 
+    pub fn get(&self, id: EntityId) -> Option<&Entity> {
+        self.entities.get(&id)
+    }
+
+    /// Set [Entity::is_test] on each entity for which `is_test` holds and on
+    /// everything inside of it.
+    pub fn mark_tests<F: Fn(&Entity) -> bool>(&mut self, is_test: F) {
+        for id in &self.table.ids {
+            let parent = self.entities[id].parent_id.and_then(|p| self.entities.get(&p));
+            let inherited = parent.is_some_and(|p| p.is_test);
+            let entity = self.entities.get_mut(id).unwrap();
+            entity.is_test = inherited || is_test(entity);
+        }
+    }
+
+    /// The names of an entity and its ancestors joined with "/", starting with
+    /// the file (e.g. `src/Foo.java/Foo/bar`).
+    pub fn path(&self, id: EntityId) -> Option<String> {
+        let mut entity = self.get(id)?;
+        let mut parts = vec![entity.name.as_str()];
+
+        while let Some(parent) = entity.parent_id.and_then(|id| self.get(id)) {
+            parts.push(&parent.name);
+            entity = parent;
+        }
+
+        Some(parts.into_iter().rev().join("/"))
+    }
+
+    pub fn iter_entities(&self) -> impl Iterator<Item = &Entity> {
+        self.entities.values()
+    }
+
     pub fn find_id(&self, position: PartialPosition) -> Option<EntityId> {
         self.table.find_id(position)
     }
 
+    /// Find the entity with the qualified name reported by a resolver.
+    ///
+    /// A qualified name like `com.foo.Bar.baz` is matched against the names of
+    /// an entity and its ancestors below the file (`Bar.baz`), so leading parts
+    /// that are not entities (like the package) are ignored. If several
+    /// entities match (e.g. overloaded methods), the one that encloses
+    /// `position` is chosen, or none if no such entity exists.
+    pub fn find_id_by_name(&self, name: &str, position: PartialPosition) -> Option<EntityId> {
+        let parts = name.split(['.', '/']).collect_vec();
+        let candidates = (0..parts.len()).find_map(|i| self.names.get(&parts[i..].join(".")))?;
+
+        if let [id] = candidates.as_slice() {
+            return Some(*id);
+        }
+
+        let mut curr = self.find_id(position);
+
+        while let Some(id) = curr {
+            if candidates.contains(&id) {
+                return Some(id);
+            }
+            curr = self.entities[&id].parent_id;
+        }
+
+        None
+    }
+
+    /// Find the entity of a dep endpoint, first by name and then by position.
+    fn find_endpoint(&self, endpoint: &FileEndpoint) -> Option<EntityId> {
+        let by_name =
+            endpoint.name.as_ref().and_then(|n| self.find_id_by_name(n, endpoint.position));
+        by_name.or_else(|| self.find_id(endpoint.position))
+    }
+
     pub fn count_simple_ids<I>(&self, spans: I) -> Counter<SimpleEntityId>
     where
         I: IntoIterator<Item = PartialSpan>,
@@ -66,13 +166,43 @@ impl EntitySet {
             })
             .collect()
     }
+
+    /// Count the rows on which each entity overlaps one of `spans`.
+    ///
+    /// Unlike [Self::count_simple_ids] with [PartialSpan::Row], several
+    /// entities can be counted for the same row. Each span must lie on a
+    /// single row.
+    pub fn count_simple_ids_per_row<I>(&self, spans: I) -> Counter<SimpleEntityId>
+    where
+        I: IntoIterator<Item = Span>,
+    {
+        spans
+            .into_iter()
+            .flat_map(|span| {
+                self.table
+                    .find_ids(PartialSpan::Whole(span))
+                    .keys()
+                    .map(|id| (span.start.row, self.entities[id].simple_id))
+                    .collect_vec()
+            })
+            .unique()
+            .map(|(_, simple_id)| simple_id)
+            .collect()
+    }
 }
 
 impl FileDep {
     pub fn to_entity_dep(&self, entity_sets: &HashMap<FileKey, EntitySet>) -> Option<EntityDep> {
-        let src = entity_sets.get(&self.src.file_key)?.find_id(self.src.position)?;
-        let tgt = entity_sets.get(&self.tgt.file_key)?.find_id(self.tgt.position)?;
-        Some(Dep::new(src, tgt, self.kind, self.position, self.commit_id))
+        let src = entity_sets.get(&self.src.file_key)?.find_endpoint(&self.src)?;
+        let tgt = entity_sets.get(&self.tgt.file_key)?.find_endpoint(&self.tgt)?;
+        let location = DepLocation {
+            src_filename: self.src.file_key.filename.clone(),
+            tgt_filename: self.tgt.file_key.filename.clone(),
+            tgt_position: self.tgt.position,
+            src_name: self.src.name.clone(),
+            tgt_name: self.tgt.name.clone(),
+        };
+        Some(self.clone().with_endpoints(src, tgt).with_location(location))
     }
 }
 
@@ -121,7 +251,27 @@ impl LocationTable {
     }
 }
 
+/// How fine-grained the entities found by a [Tagger] are.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(strum::Display, strum::EnumIs, strum::EnumString, strum::VariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Granularity {
+    /// A single entity for each file.
+    File,
+
+    /// Files and the types within them (e.g. classes and interfaces). Members
+    /// (e.g. methods and fields) are folded into their type, so their deps and
+    /// changes count for the type. Anything inside a member (e.g. an anonymous
+    /// class) is folded along with it.
+    Type,
+
+    /// Every entity the tag query finds.
+    #[default]
+    Member,
+}
+
 #[derive(Debug)]
+#[derive(strum::EnumIs)]
 pub enum Tagger {
     EntityLevel(EntityTagger),
     FileLevel,
@@ -135,21 +285,57 @@ impl Tagger {
         }
     }
 
-    pub fn tag(&self, filename: &str, content: &str, file_level: bool) -> EntitySet {
-        if file_level {
+    pub fn tag(&self, filename: &str, content: &str, granularity: Granularity) -> EntitySet {
+        if granularity.is_file() {
             return to_singleton_entity_set(filename, content);
         }
 
+        self.try_tag(filename, content, false, granularity)
+            .unwrap_or_else(|_| to_singleton_entity_set(filename, content))
+    }
+
+    /// Like [Self::tag], but fail rather than fall back to a single file-level
+    /// entity when the file cannot be parsed or the language has no tag query.
+    ///
+    /// A file only parses if its tree has no syntax errors, even though
+    /// tree-sitter recovers from them.
+    ///
+    /// If `signatures`, the parameter types of each method and constructor are
+    /// part of its [SimpleEntityId], so overloads are told apart.
+    pub fn try_tag(
+        &self,
+        filename: &str,
+        content: &str,
+        signatures: bool,
+        granularity: Granularity,
+    ) -> Result<EntitySet> {
+        if granularity.is_file() {
+            return Ok(to_singleton_entity_set(filename, content));
+        }
+
         match self {
-            Tagger::EntityLevel(tagger) => match tagger.tag(filename, content) {
-                Ok(entity_set) => entity_set,
-                Err(_) => to_singleton_entity_set(filename, content),
-            },
-            Tagger::FileLevel => to_singleton_entity_set(filename, content),
+            Tagger::EntityLevel(tagger) => tagger.tag(filename, content, signatures, granularity),
+            Tagger::FileLevel => bail!("entities are not supported for this language"),
         }
     }
 }
 
+/// The first node (in document order) that tree-sitter inserted or skipped to
+/// recover from a syntax error.
+fn find_error(node: Node) -> Option<Node> {
+    if node.is_error() || node.is_missing() {
+        return Some(node);
+    }
+
+    if !node.has_error() {
+        return None;
+    }
+
+    let mut cursor = node.walk();
+    let children = node.children(&mut cursor).collect_vec();
+    children.into_iter().find_map(find_error)
+}
+
 #[derive(Debug)]
 pub struct EntityTagger {
     language: Language,
@@ -174,27 +360,63 @@ impl EntityTagger {
         Self { language, query, kinds, ix_name, ix_comment }
     }
 
-    fn tag(&self, filename: &str, content: &str) -> Result<EntitySet> {
-        let mut parser = Parser::new();
-        parser.set_language(self.language)?;
-        let tree = parser.parse(content, None).context("failed to parse")?;
+    fn tag(
+        &self,
+        filename: &str,
+        content: &str,
+        signatures: bool,
+        granularity: Granularity,
+    ) -> Result<EntitySet> {
+        let tree = parse(self.language, content)?;
         let root = tree.root_node();
-        let mut cursor = QueryCursor::new();
+
+        if let Some(error) = find_error(root) {
+            let start = error.start_position();
+            bail!("syntax error at line {}, column {}", start.row + 1, start.column + 1);
+        }
 
         let mut captures = HashMap::new();
         let root_capture = Capture::from_root_node(filename, &root);
         captures.insert(root_capture.id, root_capture);
 
+        with_cursor(|cursor| self.capture(cursor, root, content, &mut captures))?;
+        if granularity.is_type() {
+            fold_members(&mut captures);
+        }
+        let package = find_package(root, content);
+        let tokens = find_tokens(root);
+        Ok(into_entity_set(filename, captures, content, package, Some(&tokens), signatures))
+    }
+
+    fn capture(
+        &self,
+        cursor: &mut QueryCursor,
+        root: Node,
+        content: &str,
+        captures: &mut HashMap<CaptureId, Capture>,
+    ) -> Result<()> {
+        let mut patterns = HashMap::new();
+
         for r#match in cursor.matches(&self.query, root, content.as_bytes()) {
             let mut builder: CaptureBuilder = CaptureBuilder::default();
-            builder.comment(None);
+            let mut comment = None;
+            let mut tagged = None;
+            let mut named = false;
 
             for capture in r#match.captures {
                 if capture.index == self.ix_name {
                     builder.name(capture.node.utf8_text(content.as_bytes()).unwrap().to_string());
+                    named = true;
                 } else if Some(capture.index) == self.ix_comment {
-                    builder.comment(Some(Span::from_ts(capture.node.range())));
+                    comment = Some(capture.node);
                 } else if let Some(kind) = self.kinds[capture.index as usize] {
+                    tagged = Some(capture.node);
+                    builder.signature(match kind {
+                        EntityKind::Constructor | EntityKind::Method => {
+                            signature(capture.node, content)
+                        }
+                        _ => None,
+                    });
                     builder.id(CaptureId(capture.node.id()));
                     builder.ancestor_ids(collect_ancestor_ids(&capture.node));
                     builder.kind(kind);
@@ -202,12 +424,83 @@ impl EntityTagger {
                 }
             }
 
+            // Lambdas and anonymous classes have no @name
+            if !named {
+                builder.name(match builder.kind {
+                    Some(EntityKind::Lambda) => "<lambda>".to_string(),
+                    _ => "<anonymous>".to_string(),
+                });
+            }
+
+            // The query does not require the comment to be right before the
+            // entity, so a comment may be matched with any later sibling
+            let comment = comment.filter(|c| tagged.is_some() && c.next_sibling() == tagged);
+            builder.comment(comment.map(|c| Span::from_ts(c.range())));
             let capture = builder.build()?;
-            captures.insert(capture.id, capture);
+
+            // The same entity is matched both with and without its comment. An
+            // entity matched by several patterns (e.g. a Python function that
+            // is also a method) takes the kind of the first pattern.
+            match (captures.get(&capture.id), patterns.get(&capture.id)) {
+                (Some(existing), _) if existing.comment.is_some() && capture.comment.is_none() => {}
+                (_, Some(&pattern)) if pattern < r#match.pattern_index => {}
+                _ => {
+                    patterns.insert(capture.id, r#match.pattern_index);
+                    captures.insert(capture.id, capture);
+                }
+            }
         }
 
-        Ok(into_entity_set(captures, ContentId::from_content(content)))
+        Ok(())
+    }
+}
+
+/// Finds the kind of dep implied by the syntax surrounding a reference.
+///
+/// Resolvers like Stack Graphs know where a reference points but not how it is
+/// used. Each capture of the query is named `dep.KIND` (e.g. `@dep.Cast`) and a
+/// reference takes the kind of the smallest captured node that contains it.
+#[derive(Debug)]
+pub struct DepTagger {
+    language: Language,
+    query: Query,
+    kinds: Vec<DepKind>,
+}
+
+impl DepTagger {
+    pub fn new(language: Language, dep_query: &str) -> Self {
+        let query = Query::new(language, dep_query).unwrap();
+
+        let kinds = query
+            .capture_names()
+            .iter()
+            .map(|c| DepKind::try_from(c.strip_prefix("dep.").unwrap()).unwrap())
+            .collect::<Vec<_>>();
+
+        Self { language, query, kinds }
     }
+
+    /// Find the byte range and kind of each captured node, smallest first.
+    pub fn tag(&self, content: &str) -> Vec<(Range<usize>, DepKind)> {
+        let Ok(tree) = parse(self.language, content) else {
+            return Vec::new();
+        };
+
+        with_cursor(|cursor| {
+            cursor
+                .captures(&self.query, tree.root_node(), content.as_bytes())
+                .map(|(m, i)| m.captures[i])
+                .map(|c| (c.node.byte_range(), self.kinds[c.index as usize]))
+                .sorted_by_key(|(r, _)| r.len())
+                .collect()
+        })
+    }
+}
+
+/// Find the kind of the smallest range (as returned by [DepTagger::tag]) that
+/// contains `byte`.
+pub fn find_dep_kind(ranges: &[(Range<usize>, DepKind)], byte: usize) -> Option<DepKind> {
+    ranges.iter().find(|(r, _)| r.contains(&byte)).map(|(_, k)| *k)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -221,6 +514,9 @@ struct Capture {
     kind: EntityKind,
     code: Span,
     comment: Option<Span>,
+
+    /// The parameter types of a method or constructor, like `(int,String)`.
+    signature: Option<String>,
 }
 
 impl Capture {
@@ -232,6 +528,7 @@ impl Capture {
             kind: EntityKind::File,
             code: Span::new(Position::new(0, 0, 0), end_position),
             comment: None,
+            signature: None,
         }
     }
 
@@ -243,6 +540,7 @@ impl Capture {
             kind: EntityKind::File,
             code: root.range().into(),
             comment: None,
+            signature: None,
         }
     }
 
@@ -273,7 +571,77 @@ fn collect_ancestor_ids(node: &Node) -> Vec<CaptureId> {
     ids
 }
 
+/// Remove the captures of members (anything but files and types) along with
+/// everything inside of them.
+fn fold_members(captures: &mut HashMap<CaptureId, Capture>) {
+    let members: HashSet<_> = captures
+        .values()
+        .filter(|c| c.kind != EntityKind::File && !c.kind.is_type())
+        .map(|c| c.id)
+        .collect();
+
+    captures.retain(|id, c| {
+        !members.contains(id) && !c.ancestor_ids.iter().any(|a| members.contains(a))
+    });
+}
+
 fn to_singleton_entity_set(filename: &str, content: &str) -> EntitySet {
+    let capture = Capture::singleton(filename, end_position(content));
+    let mut captures = HashMap::with_capacity(1);
+    captures.insert(capture.id, capture);
+    into_entity_set(filename, captures, content, None, None, false)
+}
+
+/// Create an [EntitySet] of a file entity holding a single module entity that
+/// spans the whole file (see [crate::manifests]).
+pub fn to_module_entity_set(filename: &str, content: &str, module: &str) -> EntitySet {
+    let file = Capture::singleton(filename, end_position(content));
+    let module = Capture {
+        id: CaptureId(1),
+        ancestor_ids: vec![file.id],
+        name: module.to_string(),
+        kind: EntityKind::Module,
+        code: file.code,
+        comment: None,
+        signature: None,
+    };
+    let captures = [file, module].into_iter().map(|c| (c.id, c)).collect();
+    into_entity_set(filename, captures, content, None, None, false)
+}
+
+/// Create a package entity for each part of a package name, so that
+/// `com.foo` becomes `com` holding `foo`. Outermost first.
+///
+/// Package entities are shared by every file (of any version) in the package,
+/// so they do not depend on any content.
+pub fn to_package_entities(package: &str) -> Vec<Entity> {
+    let origin = Position::new(0, 0, 0);
+    let content_id = ContentId::from_content("");
+    let mut entities: Vec<Entity> = Vec::new();
+
+    for (i, name) in package.split('.').enumerate() {
+        let parent = entities.last();
+        let simple_id =
+            SimpleEntityId::new(parent.map(|p| p.simple_id), name, EntityKind::Package, None);
+        let span = Span::new(origin, origin);
+        let kind = EntityKind::Package;
+        let mut entity = Entity::new(
+            parent.map(|p| p.id),
+            name.to_string(),
+            kind,
+            span,
+            None,
+            content_id,
+            simple_id,
+        );
+        entity.qualified_name = package.split('.').take(i + 1).join(".");
+        entities.push(entity);
+    }
+
+    entities
+}
+
+fn end_position(content: &str) -> Position {
     let last = content.split_inclusive('\n').enumerate().last();
 
     let (end_row, end_col) = if let Some((end_row, end_line)) = last {
@@ -282,28 +650,125 @@ fn to_singleton_entity_set(filename: &str, content: &str) -> EntitySet {
         (0, 0)
     };
 
-    let end_position = Position::new(content.len(), end_row, end_col);
-    let capture = Capture::singleton(filename, end_position);
-    let mut captures = HashMap::with_capacity(1);
-    captures.insert(capture.id, capture);
-    into_entity_set(captures, ContentId::from_content(content))
+    Position::new(content.len(), end_row, end_col)
+}
+
+/// The name of the package (or namespace) declared at the top of a file, like
+/// `package com.foo;` in Java, `package com.foo` in Kotlin, or `package foo` in
+/// Go.
+pub fn parse_package(language: Language, content: &str) -> Option<String> {
+    let tree = parse(language, content).ok()?;
+    find_package(tree.root_node(), content).map(|p| p.to_string())
 }
 
-fn into_entity_set(captures: HashMap<CaptureId, Capture>, content_id: ContentId) -> EntitySet {
+fn find_package<'a>(root: Node, content: &'a str) -> Option<&'a str> {
+    let mut cursor = root.walk();
+    let declaration = root.named_children(&mut cursor).find(|c| c.kind().starts_with("package"))?;
+    let mut cursor = declaration.walk();
+    let name = declaration.named_children(&mut cursor).find(|c| c.kind().contains("identifier"))?;
+    name.utf8_text(content.as_bytes()).ok()
+}
+
+/// The parameter types of a function-like node, like `(int,String)`.
+///
+/// Parameters without a type (e.g. in Python) are given by name instead.
+/// Whitespace is collapsed, so `Map<K, V>` is given as `Map<K,V>`.
+fn signature(node: Node, content: &str) -> Option<String> {
+    let params = node.child_by_field_name("parameters")?;
+    let text = |n: Node| n.utf8_text(content.as_bytes()).unwrap_or_default().to_string();
+    let mut cursor = params.walk();
+
+    let types = params
+        .named_children(&mut cursor)
+        .filter(|p| !p.kind().contains("comment"))
+        .map(|p| {
+            let mut cursor = p.walk();
+            let param = p
+                .child_by_field_name("type")
+                .or_else(|| p.named_children(&mut cursor).find(|c| c.kind().contains("type")))
+                .or_else(|| p.child_by_field_name("name"));
+            let mut param = text(param.unwrap_or(p)).split_whitespace().join(" ");
+
+            // Varargs like `String... args` in Java
+            if param != text(p) && text(p).contains("...") {
+                param.push_str("...");
+            }
+
+            param.replace(", ", ",")
+        })
+        .join(",");
+
+    Some(format!("({})", types))
+}
+
+/// The start bytes of the tokens under `root` in order. Tokens are the leaves
+/// of the syntax tree, leaving out comments and nodes inserted by error
+/// recovery.
+fn find_tokens(root: Node) -> Vec<usize> {
+    let mut tokens = Vec::new();
+    let mut cursor = root.walk();
+
+    loop {
+        let node = cursor.node();
+
+        if node.is_extra() || node.is_missing() || !cursor.goto_first_child() {
+            if node.child_count() == 0 && !node.is_extra() && !node.is_missing() {
+                tokens.push(node.start_byte());
+            }
+
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    return tokens;
+                }
+            }
+        }
+    }
+}
+
+/// `tokens` are the start bytes of the tokens of the file in order (see
+/// [find_tokens]), or None if the file was not parsed.
+fn into_entity_set(
+    filename: &str,
+    captures: HashMap<CaptureId, Capture>,
+    content: &str,
+    package: Option<&str>,
+    tokens: Option<&[usize]>,
+    signatures: bool,
+) -> EntitySet {
+    let content_id = ContentId::from_content(content);
+    let lang = Lang::of(filename);
     let mut entities = Vec::with_capacity(captures.len());
     let mut simple_ids = HashMap::with_capacity(captures.len());
     let mut entity_ids = HashMap::with_capacity(captures.len());
+    let mut qualified_names: HashMap<CaptureId, String> = HashMap::with_capacity(captures.len());
     let capture_ids = captures.keys().map(|&k| k).collect::<HashSet<_>>();
 
     for capture in captures.into_values().sorted_by_cached_key(|c| c.topo_key()) {
         let parent_capture_id = capture.find_parent_id(&capture_ids);
 
         let parent_simple_id = parent_capture_id.map(|id| *simple_ids.get(&id).unwrap());
-        let simple_id = SimpleEntityId::new(parent_simple_id, &capture.name, capture.kind);
+        let signature = capture.signature.as_deref().filter(|_| signatures);
+        let simple_id =
+            SimpleEntityId::new(parent_simple_id, &capture.name, capture.kind, signature);
         simple_ids.insert(capture.id, simple_id);
 
         let parent_entity_id = parent_capture_id.map(|id| *entity_ids.get(&id).unwrap());
-        let entity = Entity::new(
+
+        // The file itself is left out of the qualified names of its entities
+        let outer = match parent_capture_id {
+            Some(id) => qualified_names.get(&id).map(String::as_str).or(package),
+            None => None,
+        };
+        let mut qualified_name = match outer {
+            Some(outer) => format!("{}.{}", outer, capture.name),
+            None => capture.name.clone(),
+        };
+        if capture.kind != EntityKind::File {
+            qualified_names.insert(capture.id, qualified_name.clone());
+        }
+        qualified_name.extend(capture.signature);
+
+        let mut entity = Entity::new(
             parent_entity_id,
             capture.name,
             capture.kind,
@@ -312,6 +777,15 @@ fn into_entity_set(captures: HashMap<CaptureId, Capture>, content_id: ContentId)
             content_id,
             simple_id,
         );
+        entity.qualified_name = qualified_name;
+        let code = content.get(entity.code.start.byte..entity.code.end.byte);
+        entity.entity_content_id = EntityContentId::from_code(code.unwrap_or_default());
+        entity.lang = lang;
+        entity.filename = Some(filename.to_string());
+        entity.token_count = tokens.map(|t| {
+            t.partition_point(|&b| b < entity.code.end.byte)
+                - t.partition_point(|&b| b < entity.code.start.byte)
+        });
         entity_ids.insert(capture.id, entity.id);
 
         entities.push(entity);
@@ -319,3 +793,36 @@ fn into_entity_set(captures: HashMap<CaptureId, Capture>, content_id: ContentId)
 
     EntitySet::from_topo_vec(entities)
 }
+
+/// Number the children of each entity in the order their code starts.
+fn assign_ordinals(entities: &mut [Entity]) {
+    let siblings = (0..entities.len())
+        .into_group_map_by(|&i| entities[i].parent_id)
+        .into_values()
+        .flat_map(|g| g.into_iter().sorted_by_key(|&i| entities[i].code).enumerate())
+        .collect_vec();
+
+    for (ordinal, i) in siblings {
+        entities[i].ordinal = ordinal;
+    }
+}
+
+/// Map the qualified name of each entity below the file (its name and the names
+/// of its ancestors joined with ".") to the entities with that name.
+///
+/// Entities must be topologically ordered.
+fn index_names(entities: &[Entity]) -> HashMap<String, Vec<EntityId>> {
+    let mut qualified: HashMap<EntityId, String> = HashMap::with_capacity(entities.len());
+    let mut names: HashMap<String, Vec<EntityId>> = HashMap::with_capacity(entities.len());
+
+    for entity in entities.iter().filter(|e| e.parent_id.is_some()) {
+        let name = match entity.parent_id.and_then(|id| qualified.get(&id)) {
+            Some(parent) => format!("{}.{}", parent, entity.name),
+            None => entity.name.clone(),
+        };
+        names.entry(name.clone()).or_default().push(entity.id);
+        qualified.insert(entity.id, name);
+    }
+
+    names
+}
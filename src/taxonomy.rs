@@ -0,0 +1,66 @@
+//! Rename and merge dep kinds before they are written.
+//!
+//! A taxonomy file has one rule per line of the form `KIND, ... -> LABEL`,
+//! where each `KIND` is the name of a [DepKind] (e.g. `Create, Call, Use ->
+//! Runtime`). Deps of a listed kind are written with `LABEL` as their kind, so
+//! kinds that share a label are merged in DSM cells. Kinds without a rule keep
+//! their own name. Blank lines and lines starting with `#` are ignored.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::core::DepKind;
+
+#[derive(Debug, Clone, Default)]
+pub struct Taxonomy {
+    labels: HashMap<DepKind, String>,
+}
+
+impl Taxonomy {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read dep kinds from {}", path.display()))?;
+        let mut labels = HashMap::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((kinds, label)) = line.split_once("->") else {
+                bail!("expected 'KIND, ... -> LABEL' ({}:{})", path.display(), i + 1);
+            };
+
+            let label = label.trim();
+
+            if label.is_empty() {
+                bail!("missing label ({}:{})", path.display(), i + 1);
+            }
+
+            for kind in kinds.split(',').map(|k| k.trim()) {
+                let kind = DepKind::from_str(kind).with_context(|| {
+                    format!("unknown dep kind '{}' ({}:{})", kind, path.display(), i + 1)
+                })?;
+
+                if labels.insert(kind, label.to_string()).is_some() {
+                    bail!("{} is mapped more than once ({}:{})", kind, path.display(), i + 1);
+                }
+            }
+        }
+
+        Ok(Self { labels })
+    }
+
+    /// The name that deps of this kind should be written with.
+    pub fn label(&self, kind: DepKind) -> &str {
+        self.labels.get(&kind).map_or(kind.into(), |l| l.as_str())
+    }
+}
@@ -0,0 +1,99 @@
+//! Flag the entities that are test code.
+//!
+//! Coupling between tests and the code they exercise is expected, so analyses
+//! often want to separate it from coupling within production code. An entity is
+//! test code if its file matches one of the test patterns (e.g. `*/src/test/*`
+//! or `*_test.go`), if it is inside test code, or if it looks like a test:
+//!
+//! - A method or function annotated with `@Test` (or another JUnit annotation
+//!   that declares a test) or `#[test]`.
+//! - A method or function named like `test_foo` or `testFoo`.
+//! - A class named like `FooTest`, `FooTests`, or `TestFoo`.
+
+use anyhow::Context;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::core::Entity;
+use crate::core::EntityKind;
+use crate::spec::Pathspec;
+use crate::tagging::EntitySet;
+
+lazy_static! {
+    static ref TEST_ATTRIBUTE: Regex = Regex::new(
+        r"@(?:org\.junit\.(?:jupiter\.api\.)?)?(?:Test|ParameterizedTest|RepeatedTest|TestFactory|TestTemplate)\b|#\[(?:\w+::)?test\]"
+    )
+    .unwrap();
+}
+
+/// The default patterns of test files. See
+/// https://git-scm.com/docs/gitglossary#def_pathspec.
+pub const DEFAULT_TEST_PATTERNS: &[&str] = &[
+    "src/test/*",
+    "*/src/test/*",
+    "test/*",
+    "*/test/*",
+    "tests/*",
+    "*/tests/*",
+    "spec/*",
+    "*/spec/*",
+    "test_*.py",
+    "*/test_*.py",
+    "*_test.py",
+    "*_test.go",
+    "*.test.js",
+    "*.spec.js",
+    "*.test.ts",
+    "*.spec.ts",
+    "*_spec.rb",
+];
+
+#[derive(Debug, Default)]
+pub struct TestDetector {
+    files: Option<Pathspec>,
+}
+
+impl TestDetector {
+    /// Treat the files matching any of `patterns` as test code. Empty patterns
+    /// are ignored.
+    pub fn new(mut patterns: Vec<String>) -> Result<Self> {
+        patterns.retain(|p| !p.is_empty());
+
+        if patterns.is_empty() {
+            return Ok(Self::default());
+        }
+
+        let files = Pathspec::try_from_vec(patterns).context("invalid test pattern")?;
+        Ok(Self { files: Some(files) })
+    }
+
+    /// Set [Entity::is_test] on each entity of the file `filename`.
+    pub fn mark(&self, filename: &str, content: &str, entity_set: &mut EntitySet) {
+        let test_file = self.files.as_ref().is_some_and(|f| f.matches(filename));
+        entity_set.mark_tests(|e| test_file || looks_like_test(e, content));
+    }
+}
+
+fn looks_like_test(entity: &Entity, content: &str) -> bool {
+    match entity.kind {
+        EntityKind::Function | EntityKind::Method => {
+            let name = &entity.name;
+            let code = content.get(entity.code.start.byte..entity.code.end.byte);
+
+            // Only look at the annotations (and other modifiers) before the body
+            let head = code.unwrap_or_default().split(['{', ':']).next().unwrap_or_default();
+
+            name.starts_with("test_")
+                || name.strip_prefix("test").is_some_and(|r| r.starts_with(char::is_uppercase))
+                || TEST_ATTRIBUTE.is_match(head)
+        }
+        EntityKind::Class => {
+            let name = &entity.name;
+            name.ends_with("Test")
+                || name.ends_with("Tests")
+                || name.strip_prefix("Test").is_some_and(|r| r.starts_with(char::is_uppercase))
+        }
+        _ => false,
+    }
+}
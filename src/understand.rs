@@ -0,0 +1,181 @@
+//! Export entities and deps in the CSV layout of SciTools Understand.
+//!
+//! Scripts written against Understand usually walk a table of entities and a
+//! table of references, where each reference has a scope (the entity making
+//! the reference), an ent (the entity being referenced), a kind, and the file
+//! and line where it occurs. Entities are identified by their long name and
+//! kinds are prefixed with the name of the language (e.g. "Java Method Member"
+//! or "Java Call"), so that the usual kind filters keep working. As in
+//! Understand, lines are numbered from 1 and each entity has a "Define"
+//! reference from its parent.
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use crate::core::Entity;
+use crate::core::EntityDep;
+use crate::core::EntityId;
+use crate::core::EntityKind;
+use crate::languages::Lang;
+use crate::taxonomy::Taxonomy;
+
+#[derive(Debug)]
+#[derive(serde::Serialize)]
+pub struct UnderstandEntityRow {
+    #[serde(rename = "Id")]
+    id: String,
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "LongName")]
+    long_name: String,
+    #[serde(rename = "Kind")]
+    kind: String,
+    #[serde(rename = "File")]
+    file: String,
+    #[serde(rename = "Line")]
+    line: usize,
+    #[serde(rename = "Column")]
+    column: usize,
+}
+
+#[derive(Debug)]
+#[derive(serde::Serialize)]
+pub struct UnderstandReferenceRow {
+    #[serde(rename = "Scope")]
+    scope: String,
+    #[serde(rename = "ScopeKind")]
+    scope_kind: String,
+    #[serde(rename = "Ent")]
+    ent: String,
+    #[serde(rename = "EntKind")]
+    ent_kind: String,
+    #[serde(rename = "Kind")]
+    kind: String,
+    #[serde(rename = "File")]
+    file: String,
+    #[serde(rename = "Line")]
+    line: usize,
+    #[serde(rename = "Column")]
+    column: usize,
+}
+
+/// Build the rows of entities.csv and references.csv.
+///
+/// Deps with an endpoint that is not among `entities` are skipped.
+pub fn build_rows(
+    entities: &[Entity],
+    deps: &[EntityDep],
+    taxonomy: &Taxonomy,
+) -> (Vec<UnderstandEntityRow>, Vec<UnderstandReferenceRow>) {
+    let by_id: HashMap<_, _> = entities.iter().map(|e| (e.id, e)).collect();
+    let files: HashMap<_, _> = entities.iter().map(|e| (e.id, find_file(&by_id, e))).collect();
+    let long_names: HashMap<_, _> = entities.iter().map(|e| (e.id, long_name(&by_id, e))).collect();
+    let kinds: HashMap<_, _> = entities
+        .iter()
+        .map(|e| (e.id, format!("{} {}", language(&files[&e.id].name), kind(e.kind))))
+        .collect();
+
+    let entity_rows = entities
+        .iter()
+        .map(|e| UnderstandEntityRow {
+            id: e.id.0.to_string(),
+            name: e.name.rsplit('/').next().unwrap_or_default().to_string(),
+            long_name: long_names[&e.id].clone(),
+            kind: kinds[&e.id].clone(),
+            file: files[&e.id].name.clone(),
+            line: e.code.start.row + 1,
+            column: e.code.start.column,
+        })
+        .collect();
+
+    let defines = entities.iter().filter_map(|e| {
+        let parent = by_id.get(&e.parent_id?)?;
+        Some((parent.id, e.id, "Define", e.code.start.row, e.code.start.column))
+    });
+
+    let references =
+        deps.iter().filter(|d| by_id.contains_key(&d.src) && by_id.contains_key(&d.tgt)).map(|d| {
+            let (row, column) = (d.position.row(), d.position.column().unwrap_or(0));
+            (d.src, d.tgt, taxonomy.label(d.kind), row, column)
+        });
+
+    let reference_rows = defines
+        .chain(references)
+        .map(|(scope, ent, kind, row, column)| UnderstandReferenceRow {
+            scope: long_names[&scope].clone(),
+            scope_kind: kinds[&scope].clone(),
+            ent: long_names[&ent].clone(),
+            ent_kind: kinds[&ent].clone(),
+            kind: format!("{} {}", language(&files[&scope].name), kind),
+            file: files[&scope].name.clone(),
+            line: row + 1,
+            column,
+        })
+        .sorted_by(|a, b| (&a.file, a.line, a.column).cmp(&(&b.file, b.line, b.column)))
+        .collect();
+
+    (entity_rows, reference_rows)
+}
+
+/// The long name of a file is its path. The long name of any other entity is
+/// the names of it and its ancestors (except the file) joined by dots.
+fn long_name(by_id: &HashMap<EntityId, &Entity>, entity: &Entity) -> String {
+    let mut parts = Vec::new();
+    let mut curr = Some(entity);
+
+    while let Some(entity) = curr.filter(|e| !e.kind.is_file() || parts.is_empty()) {
+        parts.push(entity.name.as_str());
+        curr = entity.parent_id.and_then(|id| by_id.get(&id).copied());
+    }
+
+    parts.into_iter().rev().join(".")
+}
+
+/// The prefix Understand gives to the kinds of a language.
+fn language(filename: &str) -> &'static str {
+    match Lang::of(filename) {
+        Some(Lang::C) | Some(Lang::Cpp) => "C",
+        Some(Lang::Go) => "Go",
+        Some(Lang::Java) => "Java",
+        Some(Lang::JavaScript) | Some(Lang::TypeScript) => "Web",
+        Some(Lang::Kotlin) => "Kotlin",
+        Some(Lang::Python) => "Python",
+        Some(Lang::Ruby) => "Ruby",
+        None => "Unknown",
+    }
+}
+
+/// The closest Understand kind (without the language prefix).
+fn kind(kind: EntityKind) -> &'static str {
+    match kind {
+        EntityKind::File => "File",
+        EntityKind::Annotation => "Annotation",
+        EntityKind::Constructor => "Method Constructor Member",
+        EntityKind::Class => "Class Type",
+        EntityKind::Enum => "Enum Type",
+        EntityKind::Field => "Variable Member",
+        EntityKind::Function => "Function",
+        EntityKind::Interface => "Interface Type",
+        EntityKind::Lambda => "Lambda",
+        EntityKind::Method => "Method Member",
+        EntityKind::Module => "Module",
+        EntityKind::Package => "Package",
+        EntityKind::Record => "Record Type",
+    }
+}
+
+/// Follow the parents of an entity until reaching its file entity.
+fn find_file<'a>(by_id: &HashMap<EntityId, &'a Entity>, entity: &'a Entity) -> &'a Entity {
+    let mut curr = entity;
+
+    while let Some(parent) = curr.parent_id.and_then(|id| by_id.get(&id)) {
+        // Files may be inside packages (see --packages)
+        if curr.kind.is_file() {
+            break;
+        }
+        curr = parent;
+    }
+
+    curr
+}
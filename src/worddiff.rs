@@ -0,0 +1,131 @@
+//! Narrow the hunks of a line-based diff down to the bytes that changed.
+//!
+//! Like `git diff --word-diff`, the lines of each hunk are split into tokens
+//! (runs of word characters, runs of whitespace, line breaks, and single
+//! punctuation characters) which are then diffed with a longest common
+//! subsequence. Tokens outside of the subsequence are the ones that changed.
+//! Hunks that are too large to diff this way are left as a whole.
+
+use crate::core::Hunk;
+use crate::core::PartialSpan;
+use crate::core::Position;
+use crate::core::Span;
+
+/// The most cells in the table used to diff the tokens of a single hunk.
+const MAX_CELLS: usize = 4_000_000;
+
+/// A token of a file. Never spans more than one row.
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    span: Span,
+}
+
+/// Find the tokens that were deleted from `old` and added to `new`.
+///
+/// Returns the spans of these tokens (each on a single row), first for `old`
+/// and then for `new`. Hunks must use [PartialSpan::Row].
+pub fn changed_spans(hunks: &[Hunk], old: &str, new: &str) -> (Vec<Span>, Vec<Span>) {
+    let (old_lines, new_lines) = (line_starts(old), line_starts(new));
+    let mut old_spans = Vec::new();
+    let mut new_spans = Vec::new();
+
+    for hunk in hunks {
+        let old_tokens = tokenize(old, &old_lines, hunk.old);
+        let new_tokens = tokenize(new, &new_lines, hunk.new);
+        let (old_changed, new_changed) = diff(&old_tokens, &new_tokens);
+        old_spans
+            .extend(old_tokens.iter().zip(old_changed).filter(|(_, c)| *c).map(|(t, _)| t.span));
+        new_spans
+            .extend(new_tokens.iter().zip(new_changed).filter(|(_, c)| *c).map(|(t, _)| t.span));
+    }
+
+    (old_spans, new_spans)
+}
+
+/// Mark the tokens of each side that are not part of a longest common
+/// subsequence.
+fn diff(old: &[Token], new: &[Token]) -> (Vec<bool>, Vec<bool>) {
+    let (n, m) = (old.len(), new.len());
+
+    if (n + 1) * (m + 1) > MAX_CELLS {
+        return (vec![true; n], vec![true; m]);
+    }
+
+    // lengths[i][j] is the length of the LCS of old[i..] and new[j..]
+    let mut lengths = vec![0u32; (n + 1) * (m + 1)];
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[at(i, j)] = match old[i].text == new[j].text {
+                true => lengths[at(i + 1, j + 1)] + 1,
+                false => lengths[at(i + 1, j)].max(lengths[at(i, j + 1)]),
+            };
+        }
+    }
+
+    let mut old_changed = vec![true; n];
+    let mut new_changed = vec![true; m];
+    let (mut i, mut j) = (0, 0);
+
+    while i < n && j < m {
+        if old[i].text == new[j].text {
+            old_changed[i] = false;
+            new_changed[j] = false;
+            i += 1;
+            j += 1;
+        } else if lengths[at(i + 1, j)] >= lengths[at(i, j + 1)] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    (old_changed, new_changed)
+}
+
+/// Split the rows of `content` covered by `rows` into tokens.
+fn tokenize<'a>(content: &'a str, line_starts: &[usize], rows: PartialSpan) -> Vec<Token<'a>> {
+    let PartialSpan::Row(start_row, end_row) = rows else {
+        panic!("expected a span of rows");
+    };
+
+    let mut tokens = Vec::new();
+
+    for row in start_row..end_row.min(line_starts.len()) {
+        let line_start = line_starts[row];
+        let line_end = line_starts.get(row + 1).copied().unwrap_or(content.len());
+        let line = &content[line_start..line_end];
+        let mut column = 0;
+
+        while column < line.len() {
+            let rest = &line[column..];
+            let first = rest.chars().next().unwrap();
+            let len = match first {
+                c if is_word(c) => rest.find(|c| !is_word(c)).unwrap_or(rest.len()),
+                '\n' => 1,
+                c if c.is_whitespace() => {
+                    rest.find(|c: char| c == '\n' || !c.is_whitespace()).unwrap_or(rest.len())
+                }
+                c => c.len_utf8(),
+            };
+
+            let start = Position::new(line_start + column, row, column);
+            let end = Position::new(line_start + column + len - 1, row, column + len - 1);
+            tokens.push(Token { text: &rest[..len], span: Span::new(start, end) });
+            column += len;
+        }
+    }
+
+    tokens
+}
+
+/// Find the byte at which each row starts.
+fn line_starts(content: &str) -> Vec<usize> {
+    std::iter::once(0).chain(content.match_indices('\n').map(|(i, _)| i + 1)).collect()
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}